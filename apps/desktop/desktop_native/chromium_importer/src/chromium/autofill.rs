@@ -0,0 +1,473 @@
+//! Autofill data of the "Web Data" database: payment cards, addresses and IBANs.
+
+use itertools::Itertools;
+use rusqlite::Connection;
+
+use super::{hex_to_bytes, table_exist, CryptoService};
+
+//
+// Public API
+//
+
+#[derive(Debug)]
+pub struct PaymentCard {
+    pub name_on_card: String,
+    pub number: String,
+    pub expiration_month: u32,
+    pub expiration_year: u32,
+    /// Security code, only stored by recent browser versions. Empty when unavailable.
+    pub code: String,
+    pub nickname: String,
+}
+
+#[derive(Debug)]
+pub struct PaymentCardImportFailure {
+    pub name_on_card: String,
+    pub error: String,
+}
+
+#[derive(Debug)]
+pub enum PaymentCardImportResult {
+    Success(PaymentCard),
+    Failure(PaymentCardImportFailure),
+}
+
+/// An address. Fields the browser doesn't have a value for are empty.
+#[derive(Debug, Default, PartialEq, Eq)]
+pub struct Address {
+    pub full_name: String,
+    pub company_name: String,
+    pub street_address: String,
+    pub city: String,
+    pub state: String,
+    pub zip_code: String,
+    pub country_code: String,
+    pub email: String,
+    pub phone_number: String,
+}
+
+#[derive(Debug)]
+pub struct Iban {
+    pub value: String,
+    pub nickname: String,
+}
+
+#[derive(Debug)]
+pub struct IbanImportFailure {
+    pub nickname: String,
+    pub error: String,
+}
+
+#[derive(Debug)]
+pub enum IbanImportResult {
+    Success(Iban),
+    Failure(IbanImportFailure),
+}
+
+#[derive(Debug, Default)]
+pub struct AutofillImport {
+    pub cards: Vec<PaymentCardImportResult>,
+    pub addresses: Vec<Address>,
+    pub ibans: Vec<IbanImportResult>,
+}
+
+//
+// Private
+//
+
+pub(super) struct EncryptedCard {
+    name_on_card: String,
+    encrypted_number: Vec<u8>,
+    expiration_month: u32,
+    expiration_year: u32,
+    encrypted_code: Vec<u8>,
+    nickname: String,
+}
+
+pub(super) struct EncryptedIban {
+    encrypted_value: Vec<u8>,
+    nickname: String,
+}
+
+/// Field types of address tokens, as numbered by Chromium's `FieldType`.
+mod field_type {
+    pub(super) const NAME_FULL: i64 = 7;
+    pub(super) const EMAIL_ADDRESS: i64 = 9;
+    pub(super) const PHONE_HOME_WHOLE_NUMBER: i64 = 14;
+    pub(super) const ADDRESS_HOME_CITY: i64 = 33;
+    pub(super) const ADDRESS_HOME_STATE: i64 = 34;
+    pub(super) const ADDRESS_HOME_ZIP: i64 = 35;
+    pub(super) const ADDRESS_HOME_COUNTRY: i64 = 36;
+    pub(super) const COMPANY_NAME: i64 = 60;
+    pub(super) const ADDRESS_HOME_STREET_ADDRESS: i64 = 77;
+}
+
+pub(super) fn query_cards(conn: &Connection) -> Result<Vec<EncryptedCard>, rusqlite::Error> {
+    if !table_exist(conn, "credit_cards")? {
+        return Ok(vec![]);
+    }
+
+    // The security codes are kept in a separate table by recent versions
+    let code_column = if table_exist(conn, "local_stored_cvc")? {
+        "(SELECT hex(value_encrypted) FROM local_stored_cvc WHERE guid = c.guid)"
+    } else {
+        "NULL"
+    };
+
+    let mut stmt = conn.prepare(&format!(
+        r#"
+        SELECT
+          c.name_on_card                AS nameOnCard,
+          hex(c.card_number_encrypted)  AS encryptedNumberHex,
+          c.expiration_month            AS expirationMonth,
+          c.expiration_year             AS expirationYear,
+          {code_column}                 AS encryptedCodeHex,
+          c.nickname                    AS nickname
+        FROM
+          credit_cards c
+        "#
+    ))?;
+
+    let cards_iter = stmt.query_map((), |row| {
+        Ok(EncryptedCard {
+            name_on_card: row
+                .get::<_, Option<String>>("nameOnCard")?
+                .unwrap_or_default(),
+            encrypted_number: hex_to_bytes(&row.get::<_, String>("encryptedNumberHex")?),
+            expiration_month: row
+                .get::<_, Option<u32>>("expirationMonth")?
+                .unwrap_or_default(),
+            expiration_year: row
+                .get::<_, Option<u32>>("expirationYear")?
+                .unwrap_or_default(),
+            encrypted_code: row
+                .get::<_, Option<String>>("encryptedCodeHex")?
+                .map(|hex| hex_to_bytes(&hex))
+                .unwrap_or_default(),
+            nickname: row
+                .get::<_, Option<String>>("nickname")?
+                .unwrap_or_default(),
+        })
+    })?;
+
+    cards_iter.collect()
+}
+
+pub(super) fn query_addresses(conn: &Connection) -> Result<Vec<Address>, rusqlite::Error> {
+    // Addresses are stored as typed tokens by recent versions, in tables that were renamed over
+    // time, and in a table per kind of value by older versions.
+    for tokens_table in ["address_type_tokens", "local_addresses_type_tokens"] {
+        if table_exist(conn, tokens_table)? {
+            return query_address_tokens(conn, tokens_table);
+        }
+    }
+    if table_exist(conn, "autofill_profiles")? {
+        return query_legacy_addresses(conn);
+    }
+    Ok(vec![])
+}
+
+fn query_address_tokens(
+    conn: &Connection,
+    tokens_table: &str,
+) -> Result<Vec<Address>, rusqlite::Error> {
+    let mut stmt = conn.prepare(&format!(
+        "SELECT guid, type, value FROM {tokens_table} ORDER BY guid"
+    ))?;
+
+    let tokens = stmt
+        .query_map((), |row| {
+            Ok((
+                row.get::<_, String>("guid")?,
+                row.get::<_, i64>("type")?,
+                row.get::<_, Option<String>>("value")?.unwrap_or_default(),
+            ))
+        })?
+        .collect::<Result<Vec<_>, _>>()?;
+
+    let addresses = tokens
+        .into_iter()
+        .chunk_by(|(guid, _, _)| guid.clone())
+        .into_iter()
+        .map(|(_, tokens)| address_from_tokens(tokens.map(|(_, kind, value)| (kind, value))))
+        .filter(|address| *address != Address::default())
+        .collect();
+
+    Ok(addresses)
+}
+
+fn address_from_tokens(tokens: impl IntoIterator<Item = (i64, String)>) -> Address {
+    let mut address = Address::default();
+    for (kind, value) in tokens {
+        let field = match kind {
+            field_type::NAME_FULL => &mut address.full_name,
+            field_type::COMPANY_NAME => &mut address.company_name,
+            field_type::ADDRESS_HOME_STREET_ADDRESS => &mut address.street_address,
+            field_type::ADDRESS_HOME_CITY => &mut address.city,
+            field_type::ADDRESS_HOME_STATE => &mut address.state,
+            field_type::ADDRESS_HOME_ZIP => &mut address.zip_code,
+            field_type::ADDRESS_HOME_COUNTRY => &mut address.country_code,
+            field_type::EMAIL_ADDRESS => &mut address.email,
+            field_type::PHONE_HOME_WHOLE_NUMBER => &mut address.phone_number,
+            _ => continue,
+        };
+        *field = value;
+    }
+    address
+}
+
+fn query_legacy_addresses(conn: &Connection) -> Result<Vec<Address>, rusqlite::Error> {
+    let mut stmt = conn.prepare(
+        r#"
+        SELECT
+          (SELECT full_name FROM autofill_profile_names n WHERE n.guid = p.guid)  AS fullName,
+          p.company_name                                                          AS companyName,
+          p.street_address                                                        AS streetAddress,
+          p.city                                                                  AS city,
+          p.state                                                                 AS state,
+          p.zipcode                                                               AS zipCode,
+          p.country_code                                                          AS countryCode,
+          (SELECT email FROM autofill_profile_emails e WHERE e.guid = p.guid)     AS email,
+          (SELECT number FROM autofill_profile_phones ph WHERE ph.guid = p.guid)  AS phoneNumber
+        FROM
+          autofill_profiles p
+        "#,
+    )?;
+
+    let addresses_iter = stmt.query_map((), |row| {
+        let text = |column: &str| -> Result<String, rusqlite::Error> {
+            Ok(row.get::<_, Option<String>>(column)?.unwrap_or_default())
+        };
+        Ok(Address {
+            full_name: text("fullName")?,
+            company_name: text("companyName")?,
+            street_address: text("streetAddress")?,
+            city: text("city")?,
+            state: text("state")?,
+            zip_code: text("zipCode")?,
+            country_code: text("countryCode")?,
+            email: text("email")?,
+            phone_number: text("phoneNumber")?,
+        })
+    })?;
+
+    addresses_iter.collect()
+}
+
+pub(super) fn query_ibans(conn: &Connection) -> Result<Vec<EncryptedIban>, rusqlite::Error> {
+    if !table_exist(conn, "local_ibans")? {
+        return Ok(vec![]);
+    }
+
+    let mut stmt = conn.prepare(
+        r#"
+        SELECT
+          hex(value_encrypted)  AS encryptedValueHex,
+          nickname              AS nickname
+        FROM
+          local_ibans
+        "#,
+    )?;
+
+    let ibans_iter = stmt.query_map((), |row| {
+        Ok(EncryptedIban {
+            encrypted_value: hex_to_bytes(&row.get::<_, String>("encryptedValueHex")?),
+            nickname: row
+                .get::<_, Option<String>>("nickname")?
+                .unwrap_or_default(),
+        })
+    })?;
+
+    ibans_iter.collect()
+}
+
+pub(super) async fn decrypt_cards(
+    encrypted_cards: Vec<EncryptedCard>,
+    crypto_service: &mut Box<dyn CryptoService>,
+) -> Vec<PaymentCardImportResult> {
+    let mut results = Vec::with_capacity(encrypted_cards.len());
+    for card in encrypted_cards {
+        let result = match crypto_service
+            .decrypt_to_string(&card.encrypted_number)
+            .await
+        {
+            Ok(number) => {
+                let code = if card.encrypted_code.is_empty() {
+                    String::new()
+                } else {
+                    crypto_service
+                        .decrypt_to_string(&card.encrypted_code)
+                        .await
+                        .unwrap_or_default()
+                };
+
+                PaymentCardImportResult::Success(PaymentCard {
+                    name_on_card: card.name_on_card,
+                    number,
+                    expiration_month: card.expiration_month,
+                    expiration_year: card.expiration_year,
+                    code,
+                    nickname: card.nickname,
+                })
+            }
+            Err(e) => PaymentCardImportResult::Failure(PaymentCardImportFailure {
+                name_on_card: card.name_on_card,
+                error: e.to_string(),
+            }),
+        };
+        results.push(result);
+    }
+    results
+}
+
+pub(super) async fn decrypt_ibans(
+    encrypted_ibans: Vec<EncryptedIban>,
+    crypto_service: &mut Box<dyn CryptoService>,
+) -> Vec<IbanImportResult> {
+    let mut results = Vec::with_capacity(encrypted_ibans.len());
+    for iban in encrypted_ibans {
+        let result = match crypto_service
+            .decrypt_to_string(&iban.encrypted_value)
+            .await
+        {
+            Ok(value) => IbanImportResult::Success(Iban {
+                value,
+                nickname: iban.nickname,
+            }),
+            Err(e) => IbanImportResult::Failure(IbanImportFailure {
+                nickname: iban.nickname,
+                error: e.to_string(),
+            }),
+        };
+        results.push(result);
+    }
+    results
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn make_connection(schema: &str) -> Connection {
+        let conn = Connection::open_in_memory().unwrap();
+        conn.execute_batch(schema).unwrap();
+        conn
+    }
+
+    #[test]
+    fn test_query_cards() {
+        let conn = make_connection(
+            r#"
+            CREATE TABLE credit_cards (guid VARCHAR PRIMARY KEY, name_on_card VARCHAR,
+              expiration_month INTEGER, expiration_year INTEGER, card_number_encrypted BLOB,
+              nickname VARCHAR);
+            CREATE TABLE local_stored_cvc (guid VARCHAR PRIMARY KEY, value_encrypted VARCHAR);
+            INSERT INTO credit_cards VALUES ('1', 'Jane Doe', 4, 2030, X'763130AABB', 'Travel');
+            INSERT INTO credit_cards VALUES ('2', NULL, 12, 2031, X'763130CC', NULL);
+            INSERT INTO local_stored_cvc VALUES ('1', X'763130DD');
+            "#,
+        );
+
+        let cards = query_cards(&conn).unwrap();
+        assert_eq!(cards.len(), 2);
+        assert_eq!(cards[0].name_on_card, "Jane Doe");
+        assert_eq!(cards[0].encrypted_number, b"v10\xaa\xbb");
+        assert_eq!(cards[0].expiration_month, 4);
+        assert_eq!(cards[0].expiration_year, 2030);
+        assert_eq!(cards[0].encrypted_code, b"v10\xdd");
+        assert_eq!(cards[0].nickname, "Travel");
+        assert_eq!(cards[1].name_on_card, "");
+        assert!(cards[1].encrypted_code.is_empty());
+    }
+
+    #[test]
+    fn test_query_cards_without_tables() {
+        let conn = make_connection("");
+        assert!(query_cards(&conn).unwrap().is_empty());
+        assert!(query_addresses(&conn).unwrap().is_empty());
+        assert!(query_ibans(&conn).unwrap().is_empty());
+    }
+
+    #[test]
+    fn test_query_address_tokens() {
+        let conn = make_connection(
+            r#"
+            CREATE TABLE address_type_tokens (guid VARCHAR, type INTEGER, value VARCHAR,
+              verification_status INTEGER DEFAULT 0);
+            INSERT INTO address_type_tokens VALUES ('a', 7, 'Jane Doe', 0);
+            INSERT INTO address_type_tokens VALUES ('a', 77, '1 Main St', 0);
+            INSERT INTO address_type_tokens VALUES ('a', 33, 'Springfield', 0);
+            INSERT INTO address_type_tokens VALUES ('a', 36, 'US', 0);
+            INSERT INTO address_type_tokens VALUES ('a', 3, 'Jane', 0);
+            INSERT INTO address_type_tokens VALUES ('b', 9, 'john@example.com', 0);
+            INSERT INTO address_type_tokens VALUES ('c', 3, 'Ignored', 0);
+            "#,
+        );
+
+        assert_eq!(
+            query_addresses(&conn).unwrap(),
+            vec![
+                Address {
+                    full_name: "Jane Doe".to_string(),
+                    street_address: "1 Main St".to_string(),
+                    city: "Springfield".to_string(),
+                    country_code: "US".to_string(),
+                    ..Default::default()
+                },
+                Address {
+                    email: "john@example.com".to_string(),
+                    ..Default::default()
+                },
+            ]
+        );
+    }
+
+    #[test]
+    fn test_query_legacy_addresses() {
+        let conn = make_connection(
+            r#"
+            CREATE TABLE autofill_profiles (guid VARCHAR PRIMARY KEY, company_name VARCHAR,
+              street_address VARCHAR, city VARCHAR, state VARCHAR, zipcode VARCHAR,
+              country_code VARCHAR);
+            CREATE TABLE autofill_profile_names (guid VARCHAR, full_name VARCHAR);
+            CREATE TABLE autofill_profile_emails (guid VARCHAR, email VARCHAR);
+            CREATE TABLE autofill_profile_phones (guid VARCHAR, number VARCHAR);
+            INSERT INTO autofill_profiles VALUES ('a', 'ACME', '1 Main St', 'Springfield', 'IL',
+              '62701', 'US');
+            INSERT INTO autofill_profile_names VALUES ('a', 'Jane Doe');
+            INSERT INTO autofill_profile_phones VALUES ('a', '+1 555 0100');
+            "#,
+        );
+
+        assert_eq!(
+            query_addresses(&conn).unwrap(),
+            vec![Address {
+                full_name: "Jane Doe".to_string(),
+                company_name: "ACME".to_string(),
+                street_address: "1 Main St".to_string(),
+                city: "Springfield".to_string(),
+                state: "IL".to_string(),
+                zip_code: "62701".to_string(),
+                country_code: "US".to_string(),
+                email: String::new(),
+                phone_number: "+1 555 0100".to_string(),
+            }]
+        );
+    }
+
+    #[test]
+    fn test_query_ibans() {
+        let conn = make_connection(
+            r#"
+            CREATE TABLE local_ibans (guid VARCHAR PRIMARY KEY, use_count INTEGER,
+              use_date INTEGER, value_encrypted VARCHAR, nickname VARCHAR);
+            INSERT INTO local_ibans VALUES ('1', 0, 0, X'763130EE', 'Savings');
+            "#,
+        );
+
+        let ibans = query_ibans(&conn).unwrap();
+        assert_eq!(ibans.len(), 1);
+        assert_eq!(ibans[0].encrypted_value, b"v10\xee");
+        assert_eq!(ibans[0].nickname, "Savings");
+    }
+}
@@ -1,5 +1,5 @@
 use std::{
-    collections::HashMap,
+    collections::{hash_map::Entry, HashMap},
     path::{Path, PathBuf},
     sync::LazyLock,
 };
@@ -11,8 +11,16 @@ use hex::decode;
 use itertools::Itertools;
 use rusqlite::{params, Connection};
 
+use crate::util;
+
+mod autofill;
 mod platform;
 
+pub use autofill::{
+    Address, AutofillImport, Iban, IbanImportFailure, IbanImportResult, PaymentCard,
+    PaymentCardImportFailure, PaymentCardImportResult,
+};
+
 pub(crate) use platform::SUPPORTED_BROWSERS as PLATFORM_SUPPORTED_BROWSERS;
 #[cfg(target_os = "windows")]
 pub use platform::*;
@@ -51,6 +59,20 @@ pub enum LoginImportResult {
     Failure(LoginImportFailure),
 }
 
+/// A login that was merged into another with the same signon realm and username.
+#[derive(Debug)]
+pub struct DuplicateLogin {
+    pub url: String,
+    pub username: String,
+}
+
+#[derive(Debug)]
+pub struct LoginImport {
+    pub logins: Vec<LoginImportResult>,
+    /// The logins left out of `logins` because they duplicate one of them.
+    pub duplicates: Vec<DuplicateLogin>,
+}
+
 pub trait InstalledBrowserRetriever {
     fn get_installed_browsers(mas_build: bool) -> Vec<String>;
 }
@@ -75,6 +97,7 @@ impl InstalledBrowserRetriever for DefaultInstalledBrowserRetriever {
                     .filter(|data_dir| data_dir.exists())
                     .map(|_| (*browser).to_string())
             })
+            .chain(crate::firefox::get_installed_browsers())
             .collect()
     }
 }
@@ -94,8 +117,35 @@ pub async fn get_available_profiles(
         return read_result;
     }
 
-    let (_, local_state) = load_local_state_for_browser(browser_name)?;
-    Ok(get_profile_info(&local_state))
+    let installations = get_installations(get_browser_config(browser_name)?)?;
+
+    // With a single installation the profile IDs are the bare profile folders. With several, e.g.
+    // a native package and a Flatpak, they are qualified by the installation they belong to.
+    if let [installation] = installations.as_slice() {
+        let local_state = load_local_state(&installation.data_dir)?;
+        return Ok(get_profile_info(&local_state));
+    }
+
+    let mut profiles = Vec::new();
+    let mut first_error = None;
+    for installation in &installations {
+        match load_local_state(&installation.data_dir) {
+            Ok(local_state) => profiles.extend(
+                get_profile_info(&local_state)
+                    .into_iter()
+                    .map(|profile| installation.kind.qualify_profile(profile)),
+            ),
+            // Data directories can be left behind by uninstalled browsers
+            Err(e) => {
+                first_error.get_or_insert(e);
+            }
+        }
+    }
+
+    match first_error {
+        Some(e) if profiles.is_empty() => Err(e),
+        _ => Ok(profiles),
+    }
 }
 
 /// Pre-translated picker dialog strings supplied by the renderer (which has the
@@ -127,61 +177,63 @@ pub async fn import_logins(
     browser_name: &str,
     profile_id: &str,
     mas_build: bool,
-) -> Result<Vec<LoginImportResult>> {
-    // MAS builds resolve the data dir from the security-scoped bookmark — `dirs::home_dir()`
-    // returns the sandbox container path under the App Sandbox, not the user's real $HOME.
-    // `ScopedBrowserAccess::close()` is awaited on the success path so the security scope
-    // is released before the function returns; `Drop` is a defensive backstop on the error path.
-    #[cfg(target_os = "macos")]
-    let access = if mas_build {
-        Some(platform::sandbox::ScopedBrowserAccess::resume(browser_name).await?)
-    } else {
-        None
-    };
+) -> Result<LoginImport> {
+    let mut profile = OpenProfile::open(browser_name, profile_id, mas_build).await?;
 
-    #[cfg(target_os = "macos")]
-    let (data_dir, local_state) = match access.as_ref() {
-        Some(a) => {
-            let data_dir = a.path().to_path_buf();
-            let local_state = load_local_state(&data_dir)?;
-            (data_dir, local_state)
-        }
-        None => load_local_state_for_browser(browser_name)?,
-    };
-
-    #[cfg(not(target_os = "macos"))]
-    let (data_dir, local_state) = load_local_state_for_browser(browser_name)?;
-
-    validate_profile_id(&local_state, profile_id)?;
-
-    let mut crypto_service = platform::get_crypto_service(browser_name, &local_state)
-        .map_err(|e| anyhow!("Failed to get crypto service: {}", e))?;
-
-    let local_logins = get_logins(&data_dir, profile_id, "Login Data")
+    let local_logins = get_logins(&profile.data_dir, profile.profile_id, "Login Data")
         .map_err(|e| anyhow!("Failed to query logins: {}", e))?;
 
     // This is not available in all browsers, but there's no harm in trying. If the file doesn't
     // exist we just get an empty vector.
-    let account_logins = get_logins(&data_dir, profile_id, "Login Data For Account")
-        .map_err(|e| anyhow!("Failed to query logins: {}", e))?;
-
-    // TODO: Do we need a better merge strategy? Maybe ignore duplicates at least?
+    let account_logins = get_logins(
+        &profile.data_dir,
+        profile.profile_id,
+        "Login Data For Account",
+    )
+    .map_err(|e| anyhow!("Failed to query logins: {}", e))?;
+
+    // Synced logins are usually stored in both databases, so only the most recent copy is kept.
     // TODO: Should we also ignore an error from one of the two imports? If one is successful and
     // the other fails, should we still return the successful ones? At the moment it
     // doesn't fail for a missing file, only when something goes really wrong.
-    let all_logins = local_logins
-        .into_iter()
-        .chain(account_logins)
-        .collect::<Vec<_>>();
+    let (all_logins, duplicates) = merge_logins(local_logins.into_iter().chain(account_logins));
 
-    let results = decrypt_logins(all_logins, &mut crypto_service).await;
+    let logins = decrypt_logins(all_logins, &mut profile.crypto_service).await;
 
-    #[cfg(target_os = "macos")]
-    if let Some(a) = access {
-        a.close().await?;
-    }
+    profile.close().await?;
 
-    Ok(results)
+    Ok(LoginImport { logins, duplicates })
+}
+
+/// Imports the payment cards, addresses and IBANs the browser autofills, stored in the "Web Data"
+/// database of the profile.
+pub async fn import_autofill(
+    browser_name: &str,
+    profile_id: &str,
+    mas_build: bool,
+) -> Result<AutofillImport> {
+    let mut profile = OpenProfile::open(browser_name, profile_id, mas_build).await?;
+
+    let (cards, addresses, ibans) =
+        query_database(&profile.data_dir, profile.profile_id, "Web Data", |conn| {
+            Ok((
+                autofill::query_cards(conn)?,
+                autofill::query_addresses(conn)?,
+                autofill::query_ibans(conn)?,
+            ))
+        })
+        .map_err(|e| anyhow!("Failed to query autofill data: {}", e))?
+        .unwrap_or_default();
+
+    let result = AutofillImport {
+        cards: autofill::decrypt_cards(cards, &mut profile.crypto_service).await,
+        addresses,
+        ibans: autofill::decrypt_ibans(ibans, &mut profile.crypto_service).await,
+    };
+
+    profile.close().await?;
+
+    Ok(result)
 }
 
 //
@@ -207,19 +259,209 @@ pub(crate) static SUPPORTED_BROWSER_MAP: LazyLock<
         .collect::<std::collections::HashMap<_, _>>()
 });
 
-fn get_and_validate_data_dir(config: &BrowserConfig) -> Result<PathBuf> {
+/// How a browser was installed, derived from the location of its user data directory.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+enum InstallationKind {
+    Native,
+    Snap,
+    Flatpak,
+}
+
+impl InstallationKind {
+    const ALL: [InstallationKind; 3] = [Self::Native, Self::Snap, Self::Flatpak];
+
+    /// Separates the installation from the profile folder in qualified profile IDs.
+    const PROFILE_ID_SEPARATOR: char = ':';
+
+    /// # Arguments
+    ///
+    /// * `data_dir` - A user data directory relative to the home directory.
+    fn from_data_dir(data_dir: &str) -> Self {
+        if data_dir.starts_with(".var/app/") {
+            Self::Flatpak
+        } else if data_dir.starts_with("snap/") {
+            Self::Snap
+        } else {
+            Self::Native
+        }
+    }
+
+    fn id(self) -> &'static str {
+        match self {
+            Self::Native => "native",
+            Self::Snap => "snap",
+            Self::Flatpak => "flatpak",
+        }
+    }
+
+    fn label(self) -> Option<&'static str> {
+        match self {
+            Self::Native => None,
+            Self::Snap => Some("Snap"),
+            Self::Flatpak => Some("Flatpak"),
+        }
+    }
+
+    /// Prefixes the profile ID with the installation, and suffixes the name of packaged
+    /// installations with their packaging format, so that they can be told apart in the picker.
+    fn qualify_profile(self, profile: ProfileInfo) -> ProfileInfo {
+        ProfileInfo {
+            name: match self.label() {
+                Some(label) => format!("{} ({label})", profile.name),
+                None => profile.name,
+            },
+            folder: format!(
+                "{}{}{}",
+                self.id(),
+                Self::PROFILE_ID_SEPARATOR,
+                profile.folder
+            ),
+            ..profile
+        }
+    }
+
+    /// Splits a profile ID qualified by [`Self::qualify_profile`].
+    ///
+    /// # Returns
+    ///
+    /// The installation and the profile folder, or `None` if the ID is not qualified.
+    fn split_profile_id(profile_id: &str) -> Option<(Self, &str)> {
+        let (id, folder) = profile_id.split_once(Self::PROFILE_ID_SEPARATOR)?;
+        Self::ALL
+            .into_iter()
+            .find(|kind| kind.id() == id)
+            .map(|kind| (kind, folder))
+    }
+}
+
+/// An installation of a browser found on disk.
+#[derive(Debug)]
+struct Installation {
+    kind: InstallationKind,
+    data_dir: PathBuf,
+}
+
+fn get_browser_config(browser_name: &str) -> Result<&'static BrowserConfig> {
+    SUPPORTED_BROWSER_MAP
+        .get(browser_name)
+        .copied()
+        .ok_or_else(|| anyhow!("Unsupported browser: {}", browser_name))
+}
+
+/// # Returns
+///
+/// The installations of the browser whose user data directory exists, in the order of
+/// [`BrowserConfig::data_dir`]. Only the first directory of each [`InstallationKind`] is used.
+fn get_installations(config: &BrowserConfig) -> Result<Vec<Installation>> {
+    let home_dir = dirs::home_dir().ok_or_else(|| anyhow!("Home directory not found"))?;
+
+    let mut installations: Vec<Installation> = Vec::new();
     for data_dir in config.data_dir.iter() {
-        let dir = dirs::home_dir()
-            .ok_or_else(|| anyhow!("Home directory not found"))?
-            .join(data_dir);
-        if dir.exists() {
-            return Ok(dir);
+        let kind = InstallationKind::from_data_dir(data_dir);
+        let dir = home_dir.join(data_dir);
+        if dir.exists() && !installations.iter().any(|i| i.kind == kind) {
+            installations.push(Installation {
+                kind,
+                data_dir: dir,
+            });
+        }
+    }
+
+    if installations.is_empty() {
+        return Err(anyhow!(
+            "Browser user data directory '{:?}' not found",
+            config.data_dir
+        ));
+    }
+    Ok(installations)
+}
+
+fn get_and_validate_data_dir(config: &BrowserConfig) -> Result<PathBuf> {
+    get_installations(config)?
+        .into_iter()
+        .next()
+        .map(|installation| installation.data_dir)
+        .ok_or_else(|| anyhow!("Browser user data directory not found"))
+}
+
+/// Resolves a profile ID returned by [`get_available_profiles`].
+///
+/// # Returns
+///
+/// The user data directory of the installation the profile belongs to, and the profile folder.
+fn resolve_profile<'a>(browser_name: &str, profile_id: &'a str) -> Result<(PathBuf, &'a str)> {
+    let installations = get_installations(get_browser_config(browser_name)?)?;
+
+    match InstallationKind::split_profile_id(profile_id) {
+        Some((kind, folder)) => installations
+            .into_iter()
+            .find(|installation| installation.kind == kind)
+            .map(|installation| (installation.data_dir, folder))
+            .ok_or_else(|| anyhow!("Browser installation not found: {}", kind.id())),
+        None => installations
+            .into_iter()
+            .next()
+            .map(|installation| (installation.data_dir, profile_id))
+            .ok_or_else(|| anyhow!("Browser user data directory not found")),
+    }
+}
+
+/// A validated profile of a browser, with the crypto service decrypting its data.
+struct OpenProfile<'a> {
+    data_dir: PathBuf,
+    profile_id: &'a str,
+    crypto_service: Box<dyn CryptoService>,
+    #[cfg(target_os = "macos")]
+    access: Option<platform::sandbox::ScopedBrowserAccess>,
+}
+
+impl<'a> OpenProfile<'a> {
+    #[allow(unused_variables, clippy::unused_async)]
+    async fn open(browser_name: &str, profile_id: &'a str, mas_build: bool) -> Result<Self> {
+        // MAS builds resolve the data dir from the security-scoped bookmark — `dirs::home_dir()`
+        // returns the sandbox container path under the App Sandbox, not the user's real $HOME.
+        #[cfg(target_os = "macos")]
+        let access = if mas_build {
+            Some(platform::sandbox::ScopedBrowserAccess::resume(browser_name).await?)
+        } else {
+            None
+        };
+
+        #[cfg(target_os = "macos")]
+        let (data_dir, profile_id) = match access.as_ref() {
+            Some(a) => (a.path().to_path_buf(), profile_id),
+            None => resolve_profile(browser_name, profile_id)?,
+        };
+
+        #[cfg(not(target_os = "macos"))]
+        let (data_dir, profile_id) = resolve_profile(browser_name, profile_id)?;
+
+        let local_state = load_local_state(&data_dir)?;
+
+        validate_profile_id(&local_state, profile_id)?;
+
+        let crypto_service = platform::get_crypto_service(browser_name, &local_state)
+            .map_err(|e| anyhow!("Failed to get crypto service: {}", e))?;
+
+        Ok(Self {
+            data_dir,
+            profile_id,
+            crypto_service,
+            #[cfg(target_os = "macos")]
+            access,
+        })
+    }
+
+    /// `ScopedBrowserAccess::close()` is awaited on the success path so the security scope is
+    /// released before the import returns; `Drop` is a defensive backstop on the error path.
+    #[allow(clippy::unused_async)]
+    async fn close(self) -> Result<()> {
+        #[cfg(target_os = "macos")]
+        if let Some(a) = self.access {
+            a.close().await?;
         }
+        Ok(())
     }
-    Err(anyhow!(
-        "Browser user data directory '{:?}' not found",
-        config.data_dir
-    ))
 }
 
 //
@@ -258,18 +500,6 @@ struct OsCrypt {
     app_bound_encrypted_key: Option<String>,
 }
 
-fn load_local_state_for_browser(browser_name: &str) -> Result<(PathBuf, LocalState)> {
-    let config = SUPPORTED_BROWSER_MAP
-        .get(browser_name)
-        .ok_or_else(|| anyhow!("Unsupported browser: {}", browser_name))?;
-
-    let data_dir = get_and_validate_data_dir(config)?;
-
-    let local_state = load_local_state(&data_dir)?;
-
-    Ok((data_dir, local_state))
-}
-
 fn load_local_state(browser_dir: &Path) -> Result<LocalState> {
     let local_state = std::fs::read_to_string(browser_dir.join("Local State"))
         .map_err(|e| anyhow!("Failed to read local state file: {}", e))?;
@@ -313,48 +543,31 @@ fn validate_profile_id(local_state: &LocalState, profile_id: &str) -> Result<()>
 
 struct EncryptedLogin {
     url: String,
+    signon_realm: String,
     username: String,
     encrypted_password: Vec<u8>,
     encrypted_note: Vec<u8>,
+    /// Microseconds since 1601-01-01, the epoch of Chromium timestamps.
+    date_password_modified: i64,
 }
 
 fn get_logins(browser_dir: &Path, profile_id: &str, filename: &str) -> Result<Vec<EncryptedLogin>> {
-    let login_data_path = browser_dir.join(profile_id).join(filename);
+    Ok(query_database(browser_dir, profile_id, filename, query_logins)?.unwrap_or_default())
+}
 
+/// Runs `query` against a copy of a database of the profile.
+///
+/// # Returns
+///
+/// The result of the query, or `None` if the database doesn't exist.
+fn query_database<T>(
+    browser_dir: &Path,
+    profile_id: &str,
+    filename: &str,
+    query: impl FnOnce(&Connection) -> Result<T, rusqlite::Error>,
+) -> Result<Option<T>> {
     // Sometimes database files are not present, so nothing to import
-    if !login_data_path.exists() {
-        return Ok(vec![]);
-    }
-
-    // When the browser with the current profile is open the database file is locked.
-    // To access it we need to copy it to a temporary location.
-    let tmp_db_path = std::env::temp_dir().join(format!(
-        "tmp-logins-{}-{}.db",
-        std::time::SystemTime::now()
-            .duration_since(std::time::UNIX_EPOCH)
-            .map_err(|e| anyhow!("Failed to retrieve system time: {}", e))?
-            .as_millis(),
-        rand::random::<u32>()
-    ));
-
-    std::fs::copy(&login_data_path, &tmp_db_path).map_err(|e| {
-        anyhow!(
-            "Failed to copy the password database file at {:?}: {}",
-            login_data_path,
-            e
-        )
-    })?;
-
-    let tmp_db_path = tmp_db_path
-        .to_str()
-        .ok_or_else(|| anyhow!("Failed to locate database."))?;
-    let maybe_logins =
-        query_logins(tmp_db_path).map_err(|e| anyhow!("Failed to query logins: {}", e))?;
-
-    // Clean up temp file
-    let _ = std::fs::remove_file(tmp_db_path);
-
-    Ok(maybe_logins)
+    util::query_database_copy(&browser_dir.join(profile_id).join(filename), query)
 }
 
 fn hex_to_bytes(hex: &str) -> Vec<u8> {
@@ -366,22 +579,39 @@ fn table_exist(conn: &Connection, table_name: &str) -> Result<bool, rusqlite::Er
         .exists(params![table_name])
 }
 
-fn query_logins(db_path: &str) -> Result<Vec<EncryptedLogin>, rusqlite::Error> {
-    let conn = Connection::open(db_path)?;
+fn column_exist(
+    conn: &Connection,
+    table_name: &str,
+    column_name: &str,
+) -> Result<bool, rusqlite::Error> {
+    conn.prepare("SELECT name FROM pragma_table_info(?1) WHERE name=?2")?
+        .exists(params![table_name, column_name])
+}
 
-    let have_logins = table_exist(&conn, "logins")?;
-    let have_password_notes = table_exist(&conn, "password_notes")?;
+fn query_logins(conn: &Connection) -> Result<Vec<EncryptedLogin>, rusqlite::Error> {
+    let have_logins = table_exist(conn, "logins")?;
+    let have_password_notes = table_exist(conn, "password_notes")?;
     if !have_logins || !have_password_notes {
         return Ok(vec![]);
     }
 
-    let mut stmt = conn.prepare(
+    // Older databases don't track when the password was modified, and logins migrated from them
+    // store 0 instead, so fall back to when the login was created
+    let modified_column = if column_exist(conn, "logins", "date_password_modified")? {
+        "COALESCE(NULLIF(l.date_password_modified, 0), l.date_created)"
+    } else {
+        "l.date_created"
+    };
+
+    let mut stmt = conn.prepare(&format!(
         r#"
         SELECT
           l.origin_url          AS url,
+          l.signon_realm        AS signonRealm,
           l.username_value      AS username,
           hex(l.password_value) AS encryptedPasswordHex,
-          hex(pn.value)         AS encryptedNoteHex
+          hex(pn.value)         AS encryptedNoteHex,
+          {modified_column}     AS datePasswordModified
         FROM
           logins l
         LEFT JOIN
@@ -389,18 +619,22 @@ fn query_logins(db_path: &str) -> Result<Vec<EncryptedLogin>, rusqlite::Error> {
         WHERE
           l.blacklisted_by_user = 0
         "#,
-    )?;
+    ))?;
 
     let logins_iter = stmt.query_map((), |row| {
         let url: String = row.get("url")?;
+        let signon_realm: String = row.get("signonRealm")?;
         let username: String = row.get("username")?;
         let encrypted_password_hex: String = row.get("encryptedPasswordHex")?;
         let encrypted_note_hex: String = row.get("encryptedNoteHex")?;
+        let date_password_modified: Option<i64> = row.get("datePasswordModified")?;
         Ok(EncryptedLogin {
             url,
+            signon_realm,
             username,
             encrypted_password: hex_to_bytes(&encrypted_password_hex),
             encrypted_note: hex_to_bytes(&encrypted_note_hex),
+            date_password_modified: date_password_modified.unwrap_or_default(),
         })
     })?;
 
@@ -409,6 +643,82 @@ fn query_logins(db_path: &str) -> Result<Vec<EncryptedLogin>, rusqlite::Error> {
     Ok(logins)
 }
 
+/// Merges the logins with the same normalized signon realm and username into the one whose
+/// password was modified most recently, or the first one if they were modified at the same time.
+///
+/// # Returns
+///
+/// The merged logins, in the order they were first seen, and the duplicates merged into them.
+fn merge_logins(
+    logins: impl IntoIterator<Item = EncryptedLogin>,
+) -> (Vec<EncryptedLogin>, Vec<DuplicateLogin>) {
+    let mut merged: Vec<EncryptedLogin> = Vec::new();
+    let mut indices: HashMap<(String, String), usize> = HashMap::new();
+    let mut duplicates = Vec::new();
+
+    for login in logins {
+        let realm = if login.signon_realm.is_empty() {
+            &login.url
+        } else {
+            &login.signon_realm
+        };
+        let key = (normalize_signon_realm(realm), login.username.clone());
+
+        match indices.entry(key) {
+            Entry::Vacant(entry) => {
+                entry.insert(merged.len());
+                merged.push(login);
+            }
+            Entry::Occupied(entry) => {
+                let kept = &mut merged[*entry.get()];
+                let mut duplicate = if login.date_password_modified > kept.date_password_modified {
+                    std::mem::replace(kept, login)
+                } else {
+                    login
+                };
+                // Don't lose a note that was only added to one of the copies
+                if kept.encrypted_note.is_empty() {
+                    kept.encrypted_note = std::mem::take(&mut duplicate.encrypted_note);
+                }
+                duplicates.push(DuplicateLogin {
+                    url: duplicate.url,
+                    username: duplicate.username,
+                });
+            }
+        }
+    }
+
+    (merged, duplicates)
+}
+
+/// Normalizes a signon realm for comparison, e.g. "HTTPS://Example.com/" to
+/// "https://example.com". Only the scheme and host are lowercased, as the realm of HTTP
+/// authentication, e.g. "https://example.com/Admin Area", and the certificate hash of Android
+/// apps, e.g. "android://<hash>@com.example.app/", are case-sensitive.
+fn normalize_signon_realm(realm: &str) -> String {
+    let realm = realm.trim();
+    let Some((scheme, rest)) = realm.split_once("://") else {
+        return realm.trim_end_matches('/').to_string();
+    };
+    let (authority, path) = rest.split_once('/').unwrap_or((rest, ""));
+    let (user_info, host) = match authority.rsplit_once('@') {
+        Some((user_info, host)) => (Some(user_info), host),
+        None => (None, authority),
+    };
+
+    let mut normalized = format!("{}://", scheme.to_lowercase());
+    if let Some(user_info) = user_info {
+        normalized.push_str(user_info);
+        normalized.push('@');
+    }
+    normalized.push_str(&host.to_lowercase());
+    if !path.trim_end_matches('/').is_empty() {
+        normalized.push('/');
+        normalized.push_str(path);
+    }
+    normalized
+}
+
 async fn decrypt_logins(
     encrypted_logins: Vec<EncryptedLogin>,
     crypto_service: &mut Box<dyn CryptoService>,
@@ -581,6 +891,59 @@ mod tests {
         );
     }
 
+    #[test]
+    fn test_installation_kind_from_data_dir() {
+        assert_eq!(
+            InstallationKind::from_data_dir(".config/google-chrome"),
+            InstallationKind::Native
+        );
+        assert_eq!(
+            InstallationKind::from_data_dir("snap/chromium/common/chromium"),
+            InstallationKind::Snap
+        );
+        assert_eq!(
+            InstallationKind::from_data_dir(".var/app/com.google.Chrome/config/google-chrome"),
+            InstallationKind::Flatpak
+        );
+    }
+
+    #[test]
+    fn test_qualify_profile() {
+        let profile = ProfileInfo {
+            name: "Work".to_string(),
+            folder: "Profile 1".to_string(),
+            account_name: Some("Account".to_string()),
+            account_email: None,
+        };
+        let flatpak = InstallationKind::Flatpak.qualify_profile(profile);
+        assert_eq!(flatpak.name, "Work (Flatpak)");
+        assert_eq!(flatpak.folder, "flatpak:Profile 1");
+        assert_eq!(flatpak.account_name.as_deref(), Some("Account"));
+
+        let native = InstallationKind::Native.qualify_profile(ProfileInfo {
+            name: "Work".to_string(),
+            folder: "Default".to_string(),
+            account_name: None,
+            account_email: None,
+        });
+        assert_eq!(native.name, "Work");
+        assert_eq!(native.folder, "native:Default");
+    }
+
+    #[test]
+    fn test_split_profile_id() {
+        assert_eq!(
+            InstallationKind::split_profile_id("flatpak:Profile 1"),
+            Some((InstallationKind::Flatpak, "Profile 1"))
+        );
+        assert_eq!(
+            InstallationKind::split_profile_id("native:Default"),
+            Some((InstallationKind::Native, "Default"))
+        );
+        assert_eq!(InstallationKind::split_profile_id("Default"), None);
+        assert_eq!(InstallationKind::split_profile_id("appimage:Default"), None);
+    }
+
     #[test]
     fn test_validate_profile_id_accepts_known_profile() {
         let local_state = make_local_state(vec![
@@ -601,4 +964,143 @@ mod tests {
             );
         }
     }
+
+    fn make_login(
+        url: &str,
+        signon_realm: &str,
+        username: &str,
+        note: &[u8],
+        date_password_modified: i64,
+    ) -> EncryptedLogin {
+        EncryptedLogin {
+            url: url.to_string(),
+            signon_realm: signon_realm.to_string(),
+            username: username.to_string(),
+            encrypted_password: date_password_modified.to_be_bytes().to_vec(),
+            encrypted_note: note.to_vec(),
+            date_password_modified,
+        }
+    }
+
+    #[test]
+    fn test_merge_logins_keeps_most_recently_modified() {
+        let (logins, duplicates) = merge_logins([
+            make_login("https://a.com/login", "https://a.com/", "jane", b"", 1),
+            make_login("https://b.com/", "https://b.com/", "jane", b"", 1),
+            make_login("https://A.com/signin", "https://A.com", "jane", b"note", 3),
+            make_login("https://a.com/", "https://a.com/", "jane", b"", 2),
+            make_login("https://b.com/", "https://b.com/", "jane", b"", 1),
+        ]);
+
+        assert_eq!(
+            logins
+                .iter()
+                .map(|login| (login.url.as_str(), login.date_password_modified))
+                .collect::<Vec<_>>(),
+            vec![("https://A.com/signin", 3), ("https://b.com/", 1)]
+        );
+        assert_eq!(logins[0].encrypted_password, 3i64.to_be_bytes());
+        assert_eq!(
+            duplicates
+                .iter()
+                .map(|duplicate| duplicate.url.as_str())
+                .collect::<Vec<_>>(),
+            vec!["https://a.com/login", "https://a.com/", "https://b.com/"]
+        );
+    }
+
+    #[test]
+    fn test_normalize_signon_realm() {
+        for (realm, expected) in [
+            ("HTTPS://Example.com/", "https://example.com"),
+            ("https://example.com", "https://example.com"),
+            (
+                "https://Example.com/Admin Area",
+                "https://example.com/Admin Area",
+            ),
+            (
+                "android://AbC123==@Com.Example.App/",
+                "android://AbC123==@com.example.app",
+            ),
+            ("Example.com/", "Example.com"),
+        ] {
+            assert_eq!(normalize_signon_realm(realm), expected, "{realm}");
+        }
+    }
+
+    #[test]
+    fn test_merge_logins_keeps_case_sensitive_realms_apart() {
+        let (logins, duplicates) = merge_logins([
+            make_login("https://a.com/", "https://a.com/Admin", "jane", b"", 1),
+            make_login("https://a.com/", "https://A.com/admin", "jane", b"", 1),
+            make_login("", "android://AbC==@com.example.app/", "jane", b"", 1),
+            make_login("", "android://aBc==@com.example.app/", "jane", b"", 1),
+            make_login("", "android://AbC==@Com.Example.App/", "jane", b"", 1),
+        ]);
+
+        assert_eq!(logins.len(), 4);
+        assert_eq!(duplicates.len(), 1);
+    }
+
+    #[test]
+    fn test_merge_logins_keeps_distinct_usernames_and_notes() {
+        let (logins, duplicates) = merge_logins([
+            make_login("https://a.com/", "https://a.com/", "jane", b"", 2),
+            make_login("https://a.com/", "https://a.com/", "Jane", b"", 1),
+            make_login("https://a.com/", "https://a.com/", "jane", b"note", 1),
+            make_login("https://c.com/", "", "jane", b"", 1),
+            make_login("https://c.com", "", "jane", b"", 1),
+        ]);
+
+        assert_eq!(logins.len(), 3);
+        assert_eq!(logins[0].date_password_modified, 2);
+        assert_eq!(logins[0].encrypted_note, b"note");
+        assert_eq!(logins[1].username, "Jane");
+        assert_eq!(duplicates.len(), 2);
+    }
+
+    #[test]
+    fn test_query_logins_without_date_password_modified() {
+        let conn = Connection::open_in_memory().unwrap();
+        conn.execute_batch(
+            r#"
+            CREATE TABLE logins (id INTEGER PRIMARY KEY, origin_url TEXT, signon_realm TEXT,
+                username_value TEXT, password_value BLOB, blacklisted_by_user INTEGER,
+                date_created INTEGER);
+            CREATE TABLE password_notes (parent_id INTEGER, value BLOB);
+            INSERT INTO logins VALUES (1, 'https://a.com/login', 'https://a.com/', 'jane', x'01', 0, 42);
+            INSERT INTO logins VALUES (2, 'https://b.com/', 'https://b.com/', 'jane', x'02', 1, 43);
+            "#,
+        )
+        .unwrap();
+
+        let logins = query_logins(&conn).unwrap();
+
+        assert_eq!(logins.len(), 1);
+        assert_eq!(logins[0].signon_realm, "https://a.com/");
+        assert_eq!(logins[0].date_password_modified, 42);
+        assert!(logins[0].encrypted_note.is_empty());
+    }
+
+    #[test]
+    fn test_query_logins_with_zero_date_password_modified() {
+        let conn = Connection::open_in_memory().unwrap();
+        conn.execute_batch(
+            r#"
+            CREATE TABLE logins (id INTEGER PRIMARY KEY, origin_url TEXT, signon_realm TEXT,
+                username_value TEXT, password_value BLOB, blacklisted_by_user INTEGER,
+                date_created INTEGER, date_password_modified INTEGER);
+            CREATE TABLE password_notes (parent_id INTEGER, value BLOB);
+            INSERT INTO logins VALUES (1, 'https://a.com/', 'https://a.com/', 'jane', x'01', 0, 42, 0);
+            INSERT INTO logins VALUES (2, 'https://b.com/', 'https://b.com/', 'jane', x'02', 0, 43, 44);
+            "#,
+        )
+        .unwrap();
+
+        let logins = query_logins(&conn).unwrap();
+
+        assert_eq!(logins.len(), 2);
+        assert_eq!(logins[0].date_password_modified, 42);
+        assert_eq!(logins[1].date_password_modified, 44);
+    }
 }
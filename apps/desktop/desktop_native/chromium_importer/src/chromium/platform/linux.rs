@@ -1,4 +1,4 @@
-use std::collections::HashMap;
+use std::collections::{HashMap, VecDeque};
 
 use anyhow::{anyhow, Result};
 use async_trait::async_trait;
@@ -13,17 +13,24 @@ use crate::{
 // Public API
 //
 
-// TODO: It's possible that there might be multiple possible data directories, depending on the
-// installation method (e.g., snap, flatpak, etc.).
+// A browser can be installed natively, as a snap and as a Flatpak at the same time. Flatpak data
+// directories live under `~/.var/app/<app-id>`.
 pub(crate) const SUPPORTED_BROWSERS: &[BrowserConfig] = &[
     BrowserConfig {
         name: "Chrome",
-        data_dir: &[".config/google-chrome", "snap/chromium/common/chromium"],
+        data_dir: &[
+            ".config/google-chrome",
+            ".var/app/com.google.Chrome/config/google-chrome",
+        ],
         bundle_id: None,
     },
     BrowserConfig {
         name: "Chromium",
-        data_dir: &["snap/chromium/common/chromium"],
+        data_dir: &[
+            ".config/chromium",
+            "snap/chromium/common/chromium",
+            ".var/app/org.chromium.Chromium/config/chromium",
+        ],
         bundle_id: None,
     },
     BrowserConfig {
@@ -31,12 +38,35 @@ pub(crate) const SUPPORTED_BROWSERS: &[BrowserConfig] = &[
         data_dir: &[
             "snap/brave/current/.config/BraveSoftware/Brave-Browser",
             ".config/BraveSoftware/Brave-Browser",
+            ".var/app/com.brave.Browser/config/BraveSoftware/Brave-Browser",
         ],
         bundle_id: None,
     },
     BrowserConfig {
         name: "Opera",
-        data_dir: &["snap/opera/current/.config/opera", ".config/opera"],
+        data_dir: &[
+            "snap/opera/current/.config/opera",
+            ".config/opera",
+            ".var/app/com.opera.Opera/config/opera",
+        ],
+        bundle_id: None,
+    },
+    // Edge is not published as a snap.
+    BrowserConfig {
+        name: "Microsoft Edge",
+        data_dir: &[
+            ".config/microsoft-edge",
+            ".var/app/com.microsoft.Edge/config/microsoft-edge",
+        ],
+        bundle_id: None,
+    },
+    BrowserConfig {
+        name: "Vivaldi",
+        data_dir: &[
+            ".config/vivaldi",
+            "snap/vivaldi/current/.config/vivaldi",
+            ".var/app/com.vivaldi.Vivaldi/config/vivaldi",
+        ],
         bundle_id: None,
     },
 ];
@@ -60,43 +90,129 @@ pub(crate) fn get_crypto_service(
 #[derive(Debug)]
 struct KeyringConfig {
     browser: &'static str,
+    /// The `application` attribute of the Secret Service item.
     application_id: &'static str,
+    /// The KWallet folder is `<name> Keys` and the entry in it `<name> Safe Storage`.
+    kwallet_name: &'static str,
 }
 
 const KEYRING_CONFIG: [KeyringConfig; SUPPORTED_BROWSERS.len()] = [
     KeyringConfig {
         browser: "Chrome",
         application_id: "chrome",
+        kwallet_name: "Chrome",
     },
     KeyringConfig {
         browser: "Chromium",
         application_id: "chromium",
+        kwallet_name: "Chromium",
     },
     KeyringConfig {
         browser: "Brave",
         application_id: "brave",
+        kwallet_name: "Brave",
     },
     KeyringConfig {
         browser: "Opera",
         application_id: "opera",
+        kwallet_name: "Opera",
+    },
+    KeyringConfig {
+        browser: "Microsoft Edge",
+        application_id: "microsoft-edge",
+        kwallet_name: "Microsoft Edge",
+    },
+    KeyringConfig {
+        browser: "Vivaldi",
+        application_id: "vivaldi",
+        kwallet_name: "Vivaldi",
     },
 ];
 
 const IV: [u8; 16] = [0x20; 16];
+/// The hard-coded password used by `--password-store=basic` and for v10 passwords.
+const BASIC_PASSWORD: &[u8] = b"peanuts";
+/// The key derived from [`BASIC_PASSWORD`].
 const V10_KEY: [u8; 16] = [
     0xfd, 0x62, 0x1f, 0xe5, 0xa2, 0xb4, 0x02, 0x53, 0x9d, 0xfa, 0x14, 0x7c, 0xa9, 0x27, 0x27, 0x78,
 ];
 
+/// Stores Chromium keeps the password of v11 keys in, chosen with `--password-store`.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+enum PasswordStore {
+    Libsecret,
+    KWallet5,
+    KWallet6,
+    Basic,
+}
+
+impl PasswordStore {
+    /// The flag isn't persisted by the browser, so the stores are probed. Like Chromium, KWallet
+    /// is preferred on KDE and libsecret on other desktops.
+    ///
+    /// # Arguments
+    ///
+    /// * `current_desktop` - The value of `XDG_CURRENT_DESKTOP`.
+    /// * `kde_session_version` - The value of `KDE_SESSION_VERSION`.
+    ///
+    /// # Returns
+    ///
+    /// All stores, in the order they are probed.
+    fn probe_order(current_desktop: &str, kde_session_version: Option<&str>) -> Vec<Self> {
+        let is_kde = current_desktop
+            .split(':')
+            .any(|desktop| desktop.eq_ignore_ascii_case("KDE"));
+        let kwallets = if kde_session_version == Some("5") {
+            [Self::KWallet5, Self::KWallet6]
+        } else {
+            [Self::KWallet6, Self::KWallet5]
+        };
+
+        if is_kde {
+            vec![kwallets[0], kwallets[1], Self::Libsecret, Self::Basic]
+        } else {
+            vec![Self::Libsecret, kwallets[0], kwallets[1], Self::Basic]
+        }
+    }
+
+    fn from_environment() -> Vec<Self> {
+        let current_desktop = std::env::var("XDG_CURRENT_DESKTOP").unwrap_or_default();
+        let kde_session_version = std::env::var("KDE_SESSION_VERSION").ok();
+        Self::probe_order(&current_desktop, kde_session_version.as_deref())
+    }
+
+    async fn get_password(self, config: &KeyringConfig) -> Result<Vec<u8>> {
+        match self {
+            Self::Libsecret => get_libsecret_password(config.application_id).await,
+            Self::KWallet5 => {
+                get_kwallet_password("org.kde.kwalletd5", "/modules/kwalletd5", config).await
+            }
+            Self::KWallet6 => {
+                get_kwallet_password("org.kde.kwalletd6", "/modules/kwalletd6", config).await
+            }
+            Self::Basic => Ok(BASIC_PASSWORD.to_vec()),
+        }
+    }
+}
+
 struct LinuxCryptoService {
     config: &'static KeyringConfig,
-    v11_key: Option<Vec<u8>>,
+    /// Stores that haven't been probed yet, in probe order.
+    unprobed_stores: VecDeque<PasswordStore>,
+    /// Keys derived from the passwords of the probed stores. The key that decrypted last is
+    /// first.
+    v11_keys: Vec<Vec<u8>>,
+    /// Why probing the stores failed, reported if no key decrypts a password.
+    store_errors: Vec<String>,
 }
 
 impl LinuxCryptoService {
     fn new(config: &'static KeyringConfig) -> Self {
         Self {
             config,
-            v11_key: None,
+            unprobed_stores: PasswordStore::from_environment().into(),
+            v11_keys: Vec::new(),
+            store_errors: Vec::new(),
         }
     }
 
@@ -104,17 +220,41 @@ impl LinuxCryptoService {
         decrypt(&V10_KEY, encrypted)
     }
 
+    /// Decrypts with the keys of the stores probed so far, and probes further stores until one
+    /// holds a key that decrypts the password.
     async fn decrypt_v11(&mut self, encrypted: &[u8]) -> Result<String> {
-        if self.v11_key.is_none() {
-            let master_password = get_master_password(self.config.application_id).await?;
-            self.v11_key = Some(util::derive_saltysalt(&master_password, 1)?);
+        if let Some(plaintext) = self.decrypt_with_known_keys(encrypted) {
+            return Ok(plaintext);
         }
 
-        let key = self
-            .v11_key
-            .as_ref()
-            .ok_or_else(|| anyhow!("Failed to retrieve key"))?;
-        decrypt(key, encrypted)
+        while let Some(store) = self.unprobed_stores.pop_front() {
+            let key = match store.get_password(self.config).await {
+                Ok(password) => util::derive_saltysalt(&password, 1)?,
+                Err(e) => {
+                    self.store_errors.push(format!("{store:?}: {e}"));
+                    continue;
+                }
+            };
+            self.v11_keys.push(key);
+            if let Some(plaintext) = self.decrypt_with_known_keys(encrypted) {
+                return Ok(plaintext);
+            }
+        }
+
+        Err(anyhow!(
+            "No password store holds the key for this password ({})",
+            self.store_errors.join(", ")
+        ))
+    }
+
+    fn decrypt_with_known_keys(&mut self, encrypted: &[u8]) -> Option<String> {
+        let (index, plaintext) = self
+            .v11_keys
+            .iter()
+            .enumerate()
+            .find_map(|(index, key)| Some((index, decrypt(key, encrypted).ok()?)))?;
+        self.v11_keys[..=index].rotate_right(1);
+        Some(plaintext)
     }
 }
 
@@ -139,9 +279,13 @@ fn decrypt(key: &[u8], encrypted: &[u8]) -> Result<String> {
     String::from_utf8(plaintext).map_err(|e| anyhow!("UTF-8 error: {:?}", e))
 }
 
-async fn get_master_password(application_tag: &str) -> Result<Vec<u8>> {
-    let keyring = oo7::Keyring::new().await?;
-    keyring.unlock().await?;
+// Sandboxed browsers store the password in the Secret Service of the host like native ones do. The
+// host service is queried directly, since `oo7::Keyring` would use a file keyring private to this
+// application when it is sandboxed itself.
+async fn get_libsecret_password(application_tag: &str) -> Result<Vec<u8>> {
+    let service = oo7::dbus::Service::new().await?;
+    let keyring = service.default_collection().await?;
+    keyring.unlock(None).await?;
 
     let attributes = HashMap::from([
         (
@@ -160,3 +304,133 @@ async fn get_master_password(application_tag: &str) -> Result<Vec<u8>> {
         None => Err(anyhow!("The master password not found in the keyring")),
     }
 }
+
+#[zbus::proxy(interface = "org.kde.KWallet", gen_blocking = false)]
+trait KWallet {
+    #[zbus(name = "networkWallet")]
+    fn network_wallet(&self) -> zbus::Result<String>;
+
+    #[zbus(name = "open")]
+    fn open(&self, wallet: &str, w_id: i64, appid: &str) -> zbus::Result<i32>;
+
+    #[zbus(name = "readPassword")]
+    fn read_password(
+        &self,
+        handle: i32,
+        folder: &str,
+        key: &str,
+        appid: &str,
+    ) -> zbus::Result<String>;
+
+    #[zbus(name = "close")]
+    fn close(&self, handle: i32, force: bool, appid: &str) -> zbus::Result<i32>;
+}
+
+/// Identifies the importer to KWallet, which shows it when asking the user to open the wallet.
+const KWALLET_APP_ID: &str = "Bitwarden";
+
+async fn get_kwallet_password(
+    destination: &'static str,
+    path: &'static str,
+    config: &KeyringConfig,
+) -> Result<Vec<u8>> {
+    let connection = zbus::Connection::session().await?;
+    let kwallet = KWalletProxy::builder(&connection)
+        .destination(destination)?
+        .path(path)?
+        .build()
+        .await?;
+
+    let wallet = kwallet.network_wallet().await?;
+    let handle = kwallet.open(&wallet, 0, KWALLET_APP_ID).await?;
+    if handle < 0 {
+        return Err(anyhow!("Failed to open the wallet {}", wallet));
+    }
+
+    let password = kwallet
+        .read_password(
+            handle,
+            &format!("{} Keys", config.kwallet_name),
+            &format!("{} Safe Storage", config.kwallet_name),
+            KWALLET_APP_ID,
+        )
+        .await;
+    let _ = kwallet.close(handle, false, KWALLET_APP_ID).await;
+
+    match password? {
+        password if password.is_empty() => {
+            Err(anyhow!("The master password not found in the wallet"))
+        }
+        password => Ok(password.into_bytes()),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_v10_key_is_derived_from_basic_password() {
+        assert_eq!(
+            util::derive_saltysalt(BASIC_PASSWORD, 1).unwrap(),
+            V10_KEY.to_vec()
+        );
+    }
+
+    #[test]
+    fn test_probe_order_prefers_kwallet_on_kde() {
+        assert_eq!(
+            PasswordStore::probe_order("KDE", Some("5")),
+            vec![
+                PasswordStore::KWallet5,
+                PasswordStore::KWallet6,
+                PasswordStore::Libsecret,
+                PasswordStore::Basic,
+            ]
+        );
+        assert_eq!(
+            PasswordStore::probe_order("KDE", Some("6"))[0],
+            PasswordStore::KWallet6
+        );
+    }
+
+    #[test]
+    fn test_probe_order_prefers_libsecret_elsewhere() {
+        for desktop in ["GNOME", "ubuntu:GNOME", "XFCE", ""] {
+            assert_eq!(
+                PasswordStore::probe_order(desktop, None),
+                vec![
+                    PasswordStore::Libsecret,
+                    PasswordStore::KWallet6,
+                    PasswordStore::KWallet5,
+                    PasswordStore::Basic,
+                ],
+                "{desktop}"
+            );
+        }
+    }
+
+    #[test]
+    fn test_decrypt_with_known_keys_moves_matching_key_first() {
+        use aes::cipher::{block_padding::Pkcs7, BlockEncryptMut, KeyIvInit};
+
+        let key = util::derive_saltysalt(BASIC_PASSWORD, 1).unwrap();
+        let encrypted = cbc::Encryptor::<aes::Aes128>::new_from_slices(&key, &IV)
+            .unwrap()
+            .encrypt_padded_vec_mut::<Pkcs7>(b"secret");
+
+        let mut service = LinuxCryptoService {
+            config: &KEYRING_CONFIG[0],
+            unprobed_stores: VecDeque::new(),
+            v11_keys: vec![vec![0; 16], vec![1; 16], key.clone()],
+            store_errors: Vec::new(),
+        };
+
+        assert_eq!(
+            service.decrypt_with_known_keys(&encrypted).as_deref(),
+            Some("secret")
+        );
+        assert_eq!(service.v11_keys, vec![key, vec![0; 16], vec![1; 16]]);
+        assert_eq!(service.decrypt_with_known_keys(b"0123456789abcdef"), None);
+    }
+}
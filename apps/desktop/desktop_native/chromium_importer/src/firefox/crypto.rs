@@ -0,0 +1,357 @@
+//! Decryption of Gecko logins with the key kept in the NSS database of the profile, `key4.db`.
+
+use aes::cipher::{block_padding::Pkcs7, BlockDecryptMut, KeyIvInit};
+use anyhow::{anyhow, Result};
+use der::{
+    asn1::{AnyRef, ObjectIdentifier, OctetStringRef},
+    Decode as _, Reader as _,
+};
+use pbkdf2::hmac::{Hmac, KeyInit, Mac};
+use rusqlite::{params, Connection, OptionalExtension};
+use sha1::{Digest, Sha1};
+
+const PBES2_OID: ObjectIdentifier = ObjectIdentifier::new_unwrap("1.2.840.113549.1.5.13");
+const PBKDF2_OID: ObjectIdentifier = ObjectIdentifier::new_unwrap("1.2.840.113549.1.5.12");
+const HMAC_WITH_SHA256_OID: ObjectIdentifier = ObjectIdentifier::new_unwrap("1.2.840.113549.2.9");
+const AES_256_CBC_OID: ObjectIdentifier = ObjectIdentifier::new_unwrap("2.16.840.1.101.3.4.1.42");
+const DES_EDE3_CBC_OID: ObjectIdentifier = ObjectIdentifier::new_unwrap("1.2.840.113549.3.7");
+/// `pbeWithSha1AndTripleDES-CBC`, used by profiles created before Firefox 75.
+const PBE_SHA1_3DES_OID: ObjectIdentifier =
+    ObjectIdentifier::new_unwrap("1.2.840.113549.1.12.5.1.3");
+
+/// `CKA_ID` of the key that encrypts the logins.
+const LOGINS_KEY_ID: [u8; 16] = [0xf8, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 1];
+/// Plaintext of the entry that verifies the primary password.
+const PASSWORD_CHECK: &[u8] = b"password-check";
+/// NSS derives keys with 10,000 iterations, the cap keeps a crafted `key4.db` from stalling the
+/// import.
+const MAX_PBKDF2_ITERATIONS: u32 = 1_000_000;
+
+/// The outcome of reading the logins key with a primary password.
+pub(super) enum LoginsKey {
+    Key(Vec<u8>),
+    IncorrectPassword,
+}
+
+/// Reads the key that encrypts the logins from `key4.db`.
+///
+/// # Arguments
+///
+/// * `primary_password` - The primary password of the profile, empty if it has none.
+pub(super) fn read_logins_key(conn: &Connection, primary_password: &str) -> Result<LoginsKey> {
+    let (global_salt, password_check): (Vec<u8>, Vec<u8>) = conn
+        .query_row(
+            "SELECT item1, item2 FROM metadata WHERE id = 'password'",
+            (),
+            |row| Ok((row.get(0)?, row.get(1)?)),
+        )
+        .map_err(|e| anyhow!("Failed to read the key database: {}", e))?;
+
+    // Decryption with an incorrect password fails, or rarely yields garbage.
+    match decrypt_pbe(&password_check, &global_salt, primary_password.as_bytes()) {
+        Ok(plaintext) if plaintext == PASSWORD_CHECK => {}
+        _ => return Ok(LoginsKey::IncorrectPassword),
+    }
+
+    let encrypted_key: Vec<u8> = conn
+        .query_row(
+            "SELECT a11 FROM nssPrivate WHERE a102 = ?1",
+            params![LOGINS_KEY_ID.as_slice()],
+            |row| row.get(0),
+        )
+        .optional()
+        .map_err(|e| anyhow!("Failed to read the key database: {}", e))?
+        .ok_or_else(|| anyhow!("The logins key was not found in the key database"))?;
+
+    decrypt_pbe(&encrypted_key, &global_salt, primary_password.as_bytes()).map(LoginsKey::Key)
+}
+
+/// Decrypts a base64-decoded `encryptedUsername` or `encryptedPassword` of `logins.json`.
+pub(super) fn decrypt_field(key: &[u8], encrypted: &[u8]) -> Result<String> {
+    let invalid = |e| anyhow!("Failed to parse encrypted value: {}", e);
+
+    let (cipher, iv, ciphertext) = AnyRef::from_der(encrypted)
+        .and_then(|value| {
+            value.sequence(|reader| {
+                let _key_id: OctetStringRef = reader.decode()?;
+                let (cipher, iv) = reader.sequence(|reader| {
+                    let cipher: ObjectIdentifier = reader.decode()?;
+                    let iv: OctetStringRef = reader.decode()?;
+                    Ok((cipher, iv))
+                })?;
+                let ciphertext: OctetStringRef = reader.decode()?;
+                Ok((cipher, iv, ciphertext))
+            })
+        })
+        .map_err(invalid)?;
+
+    let plaintext = match cipher {
+        DES_EDE3_CBC_OID => decrypt_3des_cbc(
+            key.get(..24)
+                .ok_or_else(|| anyhow!("The logins key is too short"))?,
+            iv.as_bytes(),
+            ciphertext.as_bytes(),
+        ),
+        AES_256_CBC_OID => decrypt_aes_256_cbc(
+            key.get(..32)
+                .ok_or_else(|| anyhow!("The logins key is too short"))?,
+            iv.as_bytes(),
+            ciphertext.as_bytes(),
+        ),
+        oid => Err(anyhow!("Unsupported encryption {}", oid)),
+    }?;
+
+    String::from_utf8(plaintext).map_err(|e| anyhow!("UTF-8 error: {:?}", e))
+}
+
+/// Decrypts an entry of `key4.db`, encrypted with a key derived from the global salt and the
+/// primary password.
+fn decrypt_pbe(encrypted: &[u8], global_salt: &[u8], password: &[u8]) -> Result<Vec<u8>> {
+    let invalid = |e| anyhow!("Failed to parse key database entry: {}", e);
+
+    let (algorithm, parameters, ciphertext) = AnyRef::from_der(encrypted)
+        .and_then(|value| {
+            value.sequence(|reader| {
+                let (algorithm, parameters) = reader.sequence(|reader| {
+                    let algorithm: ObjectIdentifier = reader.decode()?;
+                    let parameters: AnyRef = reader.decode()?;
+                    Ok((algorithm, parameters))
+                })?;
+                let ciphertext: OctetStringRef = reader.decode()?;
+                Ok((algorithm, parameters, ciphertext))
+            })
+        })
+        .map_err(invalid)?;
+
+    // NSS hashes the password with the global salt before deriving keys from it
+    let hashed_password = Sha1::new()
+        .chain_update(global_salt)
+        .chain_update(password)
+        .finalize();
+
+    match algorithm {
+        PBES2_OID => decrypt_pbes2(parameters, &hashed_password, ciphertext.as_bytes()),
+        PBE_SHA1_3DES_OID => {
+            decrypt_pbe_sha1_3des(parameters, &hashed_password, ciphertext.as_bytes())
+        }
+        oid => Err(anyhow!("Unsupported key database encryption {}", oid)),
+    }
+}
+
+/// PBES2 with PBKDF2-HMAC-SHA256 and AES-256-CBC, as defined in RFC 8018.
+fn decrypt_pbes2(parameters: AnyRef, hashed_password: &[u8], ciphertext: &[u8]) -> Result<Vec<u8>> {
+    let invalid = |e| anyhow!("Failed to parse PBES2 parameters: {}", e);
+
+    let (salt, iterations, prf, cipher, iv) = parameters
+        .sequence(|reader| {
+            let (salt, iterations, prf) = reader.sequence(|reader| {
+                let kdf: ObjectIdentifier = reader.decode()?;
+                if kdf != PBKDF2_OID {
+                    return Err(reader.error(der::ErrorKind::OidUnknown { oid: kdf }));
+                }
+                reader.sequence(|reader| {
+                    let salt: OctetStringRef = reader.decode()?;
+                    let iterations: u32 = reader.decode()?;
+                    let _key_len: Option<u32> = reader.decode()?;
+                    let prf: ObjectIdentifier = reader.sequence(|reader| {
+                        let prf = reader.decode()?;
+                        // The parameters of the PRF are NULL, if present
+                        let _: Option<AnyRef> = reader.decode()?;
+                        Ok(prf)
+                    })?;
+                    Ok((salt, iterations, prf))
+                })
+            })?;
+            let (cipher, iv) = reader.sequence(|reader| {
+                let cipher: ObjectIdentifier = reader.decode()?;
+                let iv: OctetStringRef = reader.decode()?;
+                Ok((cipher, iv))
+            })?;
+            Ok((salt, iterations, prf, cipher, iv))
+        })
+        .map_err(invalid)?;
+    if prf != HMAC_WITH_SHA256_OID {
+        return Err(anyhow!("Unsupported PBKDF2 function {}", prf));
+    }
+    if cipher != AES_256_CBC_OID {
+        return Err(anyhow!("Unsupported PBES2 encryption {}", cipher));
+    }
+    if iterations > MAX_PBKDF2_ITERATIONS {
+        return Err(anyhow!(
+            "PBKDF2 iteration count {} exceeds the maximum of {}",
+            iterations,
+            MAX_PBKDF2_ITERATIONS
+        ));
+    }
+
+    let mut key = [0u8; 32];
+    pbkdf2::pbkdf2_hmac::<sha2::Sha256>(hashed_password, salt.as_bytes(), iterations, &mut key);
+
+    // NSS stores the IV without the OCTET STRING header it was encrypted with
+    let mut full_iv = vec![0x04, 0x0e];
+    full_iv.extend_from_slice(iv.as_bytes());
+    decrypt_aes_256_cbc(&key, &full_iv, ciphertext)
+}
+
+/// `pbeWithSha1AndTripleDES-CBC` with the key derivation of NSS.
+fn decrypt_pbe_sha1_3des(
+    parameters: AnyRef,
+    hashed_password: &[u8],
+    ciphertext: &[u8],
+) -> Result<Vec<u8>> {
+    let entry_salt = parameters
+        .sequence(|reader| {
+            let entry_salt: OctetStringRef = reader.decode()?;
+            let _iterations: u32 = reader.decode()?;
+            Ok(entry_salt)
+        })
+        .map_err(|e| anyhow!("Failed to parse PBE parameters: {}", e))?;
+    let entry_salt = entry_salt.as_bytes();
+
+    let hmac_sha1 = |key: &[u8], parts: &[&[u8]]| -> Result<Vec<u8>> {
+        let mut mac = Hmac::<Sha1>::new_from_slice(key)
+            .map_err(|e| anyhow!("Failed to derive key: {}", e))?;
+        for part in parts {
+            mac.update(part);
+        }
+        Ok(mac.finalize().into_bytes().to_vec())
+    };
+
+    let mut padded_salt = entry_salt.to_vec();
+    padded_salt.resize(padded_salt.len().max(20), 0);
+    let combined_hash = Sha1::new()
+        .chain_update(hashed_password)
+        .chain_update(entry_salt)
+        .finalize();
+
+    let mut key = hmac_sha1(&combined_hash, &[&padded_salt, entry_salt])?;
+    let intermediate = hmac_sha1(&combined_hash, &[&padded_salt])?;
+    key.extend(hmac_sha1(&combined_hash, &[&intermediate, entry_salt])?);
+
+    decrypt_3des_cbc(&key[..24], &key[key.len() - 8..], ciphertext)
+}
+
+fn decrypt_aes_256_cbc(key: &[u8], iv: &[u8], ciphertext: &[u8]) -> Result<Vec<u8>> {
+    cbc::Decryptor::<aes::Aes256>::new_from_slices(key, iv)?
+        .decrypt_padded_vec_mut::<Pkcs7>(ciphertext)
+        .map_err(|e| anyhow!("Failed to decrypt: {}", e))
+}
+
+fn decrypt_3des_cbc(key: &[u8], iv: &[u8], ciphertext: &[u8]) -> Result<Vec<u8>> {
+    cbc::Decryptor::<des::TdesEde3>::new_from_slices(key, iv)?
+        .decrypt_padded_vec_mut::<Pkcs7>(ciphertext)
+        .map_err(|e| anyhow!("Failed to decrypt: {}", e))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const GLOBAL_SALT: &str = "0102030405060708090a0b0c0d0e0f1011121314";
+    /// Encrypted with PBES2 and the primary password "hunter2".
+    const PBES2_PASSWORD_CHECK: &str = "308182306e06092a864886f70d01050d3061304206092a864886f70d01050c30350420101112131415161718191a1b1c1d1e1f202122232425262728292a2b2c2d2e2f020203e8020120300a06082a864886f70d0209301b060960864801650304012a040ea0a1a2a3a4a5a6a7a8a9aaabacad0410e3dec9b3e28150bccf10e7bc23fbf701";
+    const PBES2_LOGINS_KEY: &str = "3081a2306e06092a864886f70d01050d3061304206092a864886f70d01050c30350420303132333435363738393a3b3c3d3e3f404142434445464748494a4b4c4d4e4f020203e8020120300a06082a864886f70d0209301b060960864801650304012a040eb0b1b2b3b4b5b6b7b8b9babbbcbd0430176bd089c823ea80a4a27be01f5dbfaa50dec6329d1287668455eb6a2d6824616ab07141cf692c5fa5f67445a90b9b6a";
+    /// `PBES2_PASSWORD_CHECK` with 10,000,000 PBKDF2 iterations.
+    const PBES2_EXCESSIVE_ITERATIONS: &str = "308184307006092a864886f70d01050d3063304406092a864886f70d01050c30370420101112131415161718191a1b1c1d1e1f202122232425262728292a2b2c2d2e2f020400989680020120300a06082a864886f70d0209301b060960864801650304012a040ea0a1a2a3a4a5a6a7a8a9aaabacad0410e3dec9b3e28150bccf10e7bc23fbf701";
+    /// Encrypted with `pbeWithSha1AndTripleDES-CBC` and no primary password.
+    const LEGACY_PASSWORD_CHECK: &str = "303c3028060b2a864886f70d010c05010330190414505152535455565758595a5b5c5d5e5f606162630201010410f6e66b61603508e80e094b9f7371fd0a";
+    const LEGACY_LOGINS_KEY: &str = "304c3028060b2a864886f70d010c050103301904146465666768696a6b6c6d6e6f70717273747576770201010420a2b384bebe1194076dfd0e6f2b13a63373588b61a6416455ef23ca53568fe3dd";
+    /// "jane@example.com" encrypted with 3DES-CBC.
+    const FIELD_3DES: &str = "30420410f8000000000000000000000000000001301406082a864886f70d03070408000102030405060704189461db25e3ef3d7a0f84b0407e85248f2d29cca3201be975";
+    /// "correct horse" encrypted with AES-256-CBC.
+    const FIELD_AES: &str = "30430410f8000000000000000000000000000001301d060960864801650304012a0410000102030405060708090a0b0c0d0e0f04101fc9393e25544acac4bb4316105b9074";
+
+    fn logins_key() -> Vec<u8> {
+        (0x40..0x60).collect()
+    }
+
+    fn make_key_db(password_check: &str, logins_key: &str) -> Connection {
+        let conn = Connection::open_in_memory().unwrap();
+        conn.execute_batch(
+            "CREATE TABLE metadata (id PRIMARY KEY UNIQUE ON CONFLICT REPLACE, item1, item2);
+             CREATE TABLE nssPrivate (id PRIMARY KEY UNIQUE ON CONFLICT ABORT, a11, a102);",
+        )
+        .unwrap();
+        conn.execute(
+            "INSERT INTO metadata VALUES ('password', ?1, ?2)",
+            params![
+                hex::decode(GLOBAL_SALT).unwrap(),
+                hex::decode(password_check).unwrap()
+            ],
+        )
+        .unwrap();
+        conn.execute(
+            "INSERT INTO nssPrivate VALUES (1, ?1, ?2)",
+            params![hex::decode(logins_key).unwrap(), LOGINS_KEY_ID.as_slice()],
+        )
+        .unwrap();
+        conn
+    }
+
+    fn unwrap_key(key: LoginsKey) -> Vec<u8> {
+        match key {
+            LoginsKey::Key(key) => key,
+            LoginsKey::IncorrectPassword => panic!("incorrect password"),
+        }
+    }
+
+    #[test]
+    fn test_read_logins_key_pbes2() {
+        let conn = make_key_db(PBES2_PASSWORD_CHECK, PBES2_LOGINS_KEY);
+
+        let key = unwrap_key(read_logins_key(&conn, "hunter2").unwrap());
+        assert_eq!(key, logins_key());
+    }
+
+    #[test]
+    fn test_read_logins_key_incorrect_password() {
+        let conn = make_key_db(PBES2_PASSWORD_CHECK, PBES2_LOGINS_KEY);
+
+        for password in ["", "hunter3"] {
+            assert!(matches!(
+                read_logins_key(&conn, password).unwrap(),
+                LoginsKey::IncorrectPassword
+            ));
+        }
+    }
+
+    #[test]
+    fn test_decrypt_pbe_excessive_iterations() {
+        let error = decrypt_pbe(
+            &hex::decode(PBES2_EXCESSIVE_ITERATIONS).unwrap(),
+            &hex::decode(GLOBAL_SALT).unwrap(),
+            b"hunter2",
+        )
+        .unwrap_err();
+
+        assert!(error.to_string().contains("exceeds the maximum"));
+    }
+
+    #[test]
+    fn test_read_logins_key_legacy() {
+        let conn = make_key_db(LEGACY_PASSWORD_CHECK, LEGACY_LOGINS_KEY);
+
+        let key = unwrap_key(read_logins_key(&conn, "").unwrap());
+        assert_eq!(key, logins_key()[..24]);
+        assert!(matches!(
+            read_logins_key(&conn, "hunter2").unwrap(),
+            LoginsKey::IncorrectPassword
+        ));
+    }
+
+    #[test]
+    fn test_decrypt_field() {
+        let key = logins_key();
+
+        assert_eq!(
+            decrypt_field(&key, &hex::decode(FIELD_3DES).unwrap()).unwrap(),
+            "jane@example.com"
+        );
+        assert_eq!(
+            decrypt_field(&key, &hex::decode(FIELD_AES).unwrap()).unwrap(),
+            "correct horse"
+        );
+        assert!(decrypt_field(&key[..24], &hex::decode(FIELD_AES).unwrap()).is_err());
+        assert!(decrypt_field(&key, b"not der").is_err());
+    }
+}
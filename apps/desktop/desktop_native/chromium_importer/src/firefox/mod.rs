@@ -0,0 +1,427 @@
+//! Imports logins from Firefox and the browsers based on it.
+
+use std::path::{Path, PathBuf};
+
+use anyhow::{anyhow, Result};
+use base64::{engine::general_purpose::STANDARD, Engine as _};
+use itertools::Itertools;
+
+use crate::{
+    chromium::{Login, LoginImportFailure, LoginImportResult, ProfileInfo},
+    util,
+};
+
+mod crypto;
+
+//
+// Public API
+//
+
+/// Error returned when the profile is protected by a primary password and none was given. The
+/// renderer matches it to prompt the user for the password.
+pub const PRIMARY_PASSWORD_REQUIRED: &str = "firefoxImporterPrimaryPasswordRequired";
+/// Error returned when the given primary password is incorrect.
+pub const INCORRECT_PRIMARY_PASSWORD: &str = "firefoxImporterIncorrectPrimaryPassword";
+
+/// # Returns
+///
+/// The names of the supported Gecko browsers that have profiles on this device.
+pub fn get_installed_browsers() -> Vec<String> {
+    SUPPORTED_BROWSERS
+        .iter()
+        .filter(|config| get_data_dir(config).is_ok())
+        .map(|config| config.name.to_string())
+        .collect()
+}
+
+/// # Returns
+///
+/// The profiles listed in `profiles.ini`. Their IDs are the paths of the profiles.
+pub fn get_available_profiles(browser_name: &str) -> Result<Vec<ProfileInfo>> {
+    let (_, profiles) = load_profiles(browser_name)?;
+
+    Ok(profiles
+        .into_iter()
+        .map(|profile| ProfileInfo {
+            name: if !profile.name.trim().is_empty() {
+                profile.name
+            } else {
+                profile.path.clone()
+            },
+            folder: profile.path,
+            account_name: None,
+            account_email: None,
+        })
+        .sorted_by(|a, b| {
+            a.name
+                .to_lowercase()
+                .cmp(&b.name.to_lowercase())
+                .then_with(|| a.folder.cmp(&b.folder))
+        })
+        .collect())
+}
+
+/// Imports the logins of `logins.json`, decrypted with the key in `key4.db`.
+///
+/// # Arguments
+///
+/// * `primary_password` - The primary password of the profile, if the user entered one.
+///
+/// # Errors
+///
+/// [`PRIMARY_PASSWORD_REQUIRED`] if the profile has a primary password and `primary_password` is
+/// `None`, and [`INCORRECT_PRIMARY_PASSWORD`] if `primary_password` is incorrect.
+pub fn import_logins(
+    browser_name: &str,
+    profile_id: &str,
+    primary_password: Option<&str>,
+) -> Result<Vec<LoginImportResult>> {
+    let (data_dir, profiles) = load_profiles(browser_name)?;
+    let profile = profiles
+        .iter()
+        .find(|profile| profile.path == profile_id)
+        .ok_or_else(|| anyhow!("Unknown browser profile: {}", profile_id))?;
+    let profile_dir = profile.resolve(&data_dir);
+
+    let logins = match std::fs::read_to_string(profile_dir.join("logins.json")) {
+        Ok(contents) => {
+            serde_json::from_str::<LoginsFile>(&contents)
+                .map_err(|e| anyhow!("Failed to parse logins JSON: {}", e))?
+                .logins
+        }
+        // Profiles without saved logins don't have the file
+        Err(e) if e.kind() == std::io::ErrorKind::NotFound => return Ok(vec![]),
+        Err(e) => return Err(anyhow!("Failed to read logins file: {}", e)),
+    };
+
+    let key = util::query_database_copy(&profile_dir.join("key4.db"), |conn| {
+        Ok(crypto::read_logins_key(
+            conn,
+            primary_password.unwrap_or_default(),
+        ))
+    })?
+    .ok_or_else(|| anyhow!("The key database of the profile was not found"))??;
+    let key = match key {
+        crypto::LoginsKey::Key(key) => key,
+        crypto::LoginsKey::IncorrectPassword if primary_password.is_none() => {
+            return Err(anyhow!(PRIMARY_PASSWORD_REQUIRED))
+        }
+        crypto::LoginsKey::IncorrectPassword => return Err(anyhow!(INCORRECT_PRIMARY_PASSWORD)),
+    };
+
+    Ok(logins
+        .into_iter()
+        .map(|login| decrypt_login(login, &key))
+        .collect())
+}
+
+//
+// Private
+//
+
+#[derive(Debug, Clone, Copy)]
+pub(crate) struct BrowserConfig {
+    pub name: &'static str,
+    /// Directories containing `profiles.ini`, relative to the home directory. The first that
+    /// exists is used.
+    pub data_dir: &'static [&'static str],
+}
+
+#[cfg(target_os = "linux")]
+pub(crate) const SUPPORTED_BROWSERS: &[BrowserConfig] = &[
+    BrowserConfig {
+        name: "Firefox",
+        data_dir: &[
+            ".mozilla/firefox",
+            ".config/mozilla/firefox",
+            "snap/firefox/common/.mozilla/firefox",
+            ".var/app/org.mozilla.firefox/.mozilla/firefox",
+        ],
+    },
+    BrowserConfig {
+        name: "LibreWolf",
+        data_dir: &[
+            ".librewolf",
+            ".var/app/io.gitlab.librewolf-community/.librewolf",
+        ],
+    },
+    BrowserConfig {
+        name: "Waterfox",
+        data_dir: &[".waterfox", ".var/app/net.waterfox.waterfox/.waterfox"],
+    },
+    BrowserConfig {
+        name: "Zen",
+        data_dir: &[".zen", ".var/app/app.zen_browser.zen/.zen"],
+    },
+];
+
+#[cfg(target_os = "macos")]
+pub(crate) const SUPPORTED_BROWSERS: &[BrowserConfig] = &[
+    BrowserConfig {
+        name: "Firefox",
+        data_dir: &["Library/Application Support/Firefox"],
+    },
+    BrowserConfig {
+        name: "LibreWolf",
+        data_dir: &["Library/Application Support/librewolf"],
+    },
+    BrowserConfig {
+        name: "Waterfox",
+        data_dir: &["Library/Application Support/Waterfox"],
+    },
+    BrowserConfig {
+        name: "Zen",
+        data_dir: &["Library/Application Support/zen"],
+    },
+];
+
+#[cfg(target_os = "windows")]
+pub(crate) const SUPPORTED_BROWSERS: &[BrowserConfig] = &[
+    BrowserConfig {
+        name: "Firefox",
+        data_dir: &["AppData/Roaming/Mozilla/Firefox"],
+    },
+    BrowserConfig {
+        name: "LibreWolf",
+        data_dir: &["AppData/Roaming/librewolf"],
+    },
+    BrowserConfig {
+        name: "Waterfox",
+        data_dir: &["AppData/Roaming/Waterfox"],
+    },
+    BrowserConfig {
+        name: "Zen",
+        data_dir: &["AppData/Roaming/zen"],
+    },
+];
+
+fn get_data_dir(config: &BrowserConfig) -> Result<PathBuf> {
+    let home_dir = dirs::home_dir().ok_or_else(|| anyhow!("Home directory not found"))?;
+    config
+        .data_dir
+        .iter()
+        .map(|data_dir| home_dir.join(data_dir))
+        .find(|dir| dir.join("profiles.ini").exists())
+        .ok_or_else(|| anyhow!("Browser profiles file in '{:?}' not found", config.data_dir))
+}
+
+/// # Returns
+///
+/// The data directory of the browser and the profiles listed in its `profiles.ini`.
+fn load_profiles(browser_name: &str) -> Result<(PathBuf, Vec<GeckoProfile>)> {
+    let config = SUPPORTED_BROWSERS
+        .iter()
+        .find(|config| config.name == browser_name)
+        .ok_or_else(|| anyhow!("Unsupported browser: {}", browser_name))?;
+    let data_dir = get_data_dir(config)?;
+
+    let profiles_ini = std::fs::read_to_string(data_dir.join("profiles.ini"))
+        .map_err(|e| anyhow!("Failed to read profiles file: {}", e))?;
+
+    Ok((data_dir, parse_profiles_ini(&profiles_ini)))
+}
+
+#[derive(Debug, PartialEq, Eq)]
+struct GeckoProfile {
+    name: String,
+    path: String,
+    is_relative: bool,
+}
+
+impl GeckoProfile {
+    fn resolve(&self, data_dir: &Path) -> PathBuf {
+        if self.is_relative {
+            data_dir.join(&self.path)
+        } else {
+            PathBuf::from(&self.path)
+        }
+    }
+}
+
+/// Parses the `[ProfileN]` sections of `profiles.ini`. Sections without a path are skipped.
+fn parse_profiles_ini(contents: &str) -> Vec<GeckoProfile> {
+    let mut profiles = Vec::new();
+    let mut current: Option<GeckoProfile> = None;
+
+    for line in contents.lines().map(str::trim) {
+        if line.is_empty() || line.starts_with(';') || line.starts_with('#') {
+            continue;
+        }
+        if let Some(section) = line.strip_prefix('[').and_then(|l| l.strip_suffix(']')) {
+            profiles.extend(current.take().filter(|p| !p.path.is_empty()));
+            if section.starts_with("Profile") {
+                current = Some(GeckoProfile {
+                    name: String::new(),
+                    path: String::new(),
+                    is_relative: true,
+                });
+            }
+            continue;
+        }
+        let (Some(profile), Some((key, value))) = (current.as_mut(), line.split_once('=')) else {
+            continue;
+        };
+        match key.trim() {
+            "Name" => profile.name = value.trim().to_string(),
+            "Path" => profile.path = value.trim().to_string(),
+            "IsRelative" => profile.is_relative = value.trim() != "0",
+            _ => {}
+        }
+    }
+    profiles.extend(current.filter(|p| !p.path.is_empty()));
+
+    profiles
+}
+
+#[derive(serde::Deserialize)]
+struct LoginsFile {
+    #[serde(default)]
+    logins: Vec<GeckoLogin>,
+}
+
+#[derive(serde::Deserialize)]
+#[serde(rename_all = "camelCase")]
+struct GeckoLogin {
+    hostname: String,
+    encrypted_username: String,
+    encrypted_password: String,
+}
+
+fn decrypt_login(login: GeckoLogin, key: &[u8]) -> LoginImportResult {
+    let decrypt = |encrypted: &str| -> Result<String> {
+        let encrypted = STANDARD
+            .decode(encrypted)
+            .map_err(|e| anyhow!("Failed to decode encrypted value: {}", e))?;
+        crypto::decrypt_field(key, &encrypted)
+    };
+
+    let result = decrypt(&login.encrypted_username).and_then(|username| {
+        decrypt(&login.encrypted_password).map(|password| (username, password))
+    });
+    match result {
+        Ok((username, password)) => LoginImportResult::Success(Login {
+            url: login.hostname,
+            username,
+            password,
+            note: String::new(),
+        }),
+        Err(e) => LoginImportResult::Failure(LoginImportFailure {
+            url: login.hostname,
+            username: String::new(),
+            error: e.to_string(),
+        }),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_parse_profiles_ini() {
+        let profiles = parse_profiles_ini(
+            r#"
+[Install4F96D1932A9F858E]
+Default=Profiles/abcd1234.default-release
+Locked=1
+
+[Profile1]
+Name=default
+IsRelative=1
+Path=Profiles/efgh5678.default
+
+[Profile0]
+Name=default-release
+IsRelative=1
+Path=Profiles/abcd1234.default-release
+Default=1
+
+[Profile2]
+Name=Work
+IsRelative=0
+Path=/mnt/work/firefox
+
+[Profile3]
+Name=Broken
+
+[General]
+StartWithLastProfile=1
+Version=2
+"#,
+        );
+
+        assert_eq!(
+            profiles,
+            vec![
+                GeckoProfile {
+                    name: "default".to_string(),
+                    path: "Profiles/efgh5678.default".to_string(),
+                    is_relative: true,
+                },
+                GeckoProfile {
+                    name: "default-release".to_string(),
+                    path: "Profiles/abcd1234.default-release".to_string(),
+                    is_relative: true,
+                },
+                GeckoProfile {
+                    name: "Work".to_string(),
+                    path: "/mnt/work/firefox".to_string(),
+                    is_relative: false,
+                },
+            ]
+        );
+    }
+
+    #[test]
+    fn test_resolve_profile() {
+        let data_dir = Path::new("/home/user/.mozilla/firefox");
+        let relative = GeckoProfile {
+            name: "default".to_string(),
+            path: "Profiles/abcd.default".to_string(),
+            is_relative: true,
+        };
+        let absolute = GeckoProfile {
+            name: "Work".to_string(),
+            path: "/mnt/work/firefox".to_string(),
+            is_relative: false,
+        };
+
+        assert_eq!(
+            relative.resolve(data_dir),
+            data_dir.join("Profiles/abcd.default")
+        );
+        assert_eq!(
+            absolute.resolve(data_dir),
+            PathBuf::from("/mnt/work/firefox")
+        );
+    }
+
+    #[test]
+    fn test_decrypt_login_reports_failure() {
+        let login: LoginsFile = serde_json::from_str(
+            r#"{
+                "nextId": 2,
+                "logins": [{
+                    "id": 1,
+                    "hostname": "https://example.com",
+                    "httpRealm": null,
+                    "formSubmitURL": "https://example.com",
+                    "encryptedUsername": "bm90IGRlcg==",
+                    "encryptedPassword": "bm90IGRlcg==",
+                    "encType": 1
+                }]
+            }"#,
+        )
+        .unwrap();
+
+        let results = login
+            .logins
+            .into_iter()
+            .map(|login| decrypt_login(login, &[0; 32]))
+            .collect::<Vec<_>>();
+        assert!(matches!(
+            results.as_slice(),
+            [LoginImportResult::Failure(LoginImportFailure { url, .. })] if url == "https://example.com"
+        ));
+    }
+}
@@ -5,5 +5,6 @@ pub mod config {
 }
 
 pub mod chromium;
+pub mod firefox;
 pub mod metadata;
 mod util;
@@ -1,6 +1,9 @@
 use std::collections::{HashMap, HashSet};
 
-use crate::chromium::{InstalledBrowserRetriever, PLATFORM_SUPPORTED_BROWSERS};
+use crate::{
+    chromium::{InstalledBrowserRetriever, PLATFORM_SUPPORTED_BROWSERS},
+    firefox,
+};
 
 /// Mechanisms that load data into the importer
 pub struct NativeImporterMetadata {
@@ -14,7 +17,9 @@ pub struct NativeImporterMetadata {
 
 /// Returns a map of supported importers based on the current platform.
 ///
-/// Only browsers listed in PLATFORM_SUPPORTED_BROWSERS will have the "chromium" loader.
+/// Only browsers listed in PLATFORM_SUPPORTED_BROWSERS will have the "chromium" loader, and only
+/// browsers listed in `firefox::SUPPORTED_BROWSERS` the "firefox" loader. The latter isn't offered
+/// by MAS builds, which can't access the profiles outside of their sandbox.
 /// All importers will have the "file" loader.
 pub fn get_supported_importers<T: InstalledBrowserRetriever>(
     mas_build: bool,
@@ -30,18 +35,27 @@ pub fn get_supported_importers<T: InstalledBrowserRetriever>(
         ("chromecsv", "Chrome"),
         ("chromiumcsv", "Chromium"),
         ("edgecsv", "Microsoft Edge"),
+        ("firefoxcsv", "Firefox"),
+        ("librewolfcsv", "LibreWolf"),
         ("operacsv", "Opera"),
         ("vivaldicsv", "Vivaldi"),
+        ("waterfoxcsv", "Waterfox"),
+        ("zencsv", "Zen"),
     ];
 
     let supported: HashSet<&'static str> =
         PLATFORM_SUPPORTED_BROWSERS.iter().map(|b| b.name).collect();
+    let gecko_supported: HashSet<&'static str> =
+        firefox::SUPPORTED_BROWSERS.iter().map(|b| b.name).collect();
 
     for (id, browser_name) in IMPORTERS {
         let mut loaders: Vec<String> = vec!["file".to_string()];
         if supported.contains(browser_name) {
             loaders.push("chromium".to_string());
         }
+        if !mas_build && gecko_supported.contains(browser_name) {
+            loaders.push("firefox".to_string());
+        }
 
         if installed_browsers.contains(&browser_name.to_string()) {
             map.insert(
@@ -73,10 +87,26 @@ mod tests {
             SUPPORTED_BROWSER_MAP
                 .keys()
                 .map(|browser| browser.to_string())
+                .chain(
+                    firefox::SUPPORTED_BROWSERS
+                        .iter()
+                        .map(|browser| browser.name.to_string()),
+                )
                 .collect()
         }
     }
 
+    const FIREFOX_IMPORTERS: [&str; 4] = ["firefoxcsv", "librewolfcsv", "waterfoxcsv", "zencsv"];
+
+    #[test]
+    fn mas_build_has_no_firefox_loader() {
+        let map = get_supported_importers::<MockInstalledBrowserRetriever>(true);
+
+        for id in FIREFOX_IMPORTERS {
+            assert_eq!(get_loaders(&map, id), HashSet::from(["file".to_string()]));
+        }
+    }
+
     fn map_keys(map: &HashMap<String, NativeImporterMetadata>) -> HashSet<String> {
         map.keys().cloned().collect()
     }
@@ -100,6 +130,10 @@ mod tests {
             "operacsv".to_string(),
             "vivaldicsv".to_string(),
             "edgecsv".to_string(),
+            "firefoxcsv".to_string(),
+            "librewolfcsv".to_string(),
+            "waterfoxcsv".to_string(),
+            "zencsv".to_string(),
         ]);
         assert_eq!(map.len(), expected.len());
         assert_eq!(map_keys(&map), expected);
@@ -129,6 +163,13 @@ mod tests {
             assert!(loaders.contains("file"));
             assert!(loaders.contains("chromium"), "missing chromium for {id}");
         }
+
+        for id in FIREFOX_IMPORTERS {
+            let loaders = get_loaders(&map, id);
+            assert!(loaders.contains("file"));
+            assert!(loaders.contains("firefox"), "missing firefox for {id}");
+            assert!(!loaders.contains("chromium"));
+        }
     }
 
     #[cfg(target_os = "linux")]
@@ -141,6 +182,12 @@ mod tests {
             "chromiumcsv".to_string(),
             "bravecsv".to_string(),
             "operacsv".to_string(),
+            "edgecsv".to_string(),
+            "vivaldicsv".to_string(),
+            "firefoxcsv".to_string(),
+            "librewolfcsv".to_string(),
+            "waterfoxcsv".to_string(),
+            "zencsv".to_string(),
         ]);
         assert_eq!(map.len(), expected.len());
         assert_eq!(map_keys(&map), expected);
@@ -156,13 +203,27 @@ mod tests {
     #[test]
     fn linux_specific_loaders_match_const_array() {
         let map = get_supported_importers::<MockInstalledBrowserRetriever>(false);
-        let ids = ["chromecsv", "chromiumcsv", "bravecsv", "operacsv"];
+        let ids = [
+            "chromecsv",
+            "chromiumcsv",
+            "bravecsv",
+            "operacsv",
+            "edgecsv",
+            "vivaldicsv",
+        ];
 
         for id in ids {
             let loaders = get_loaders(&map, id);
             assert!(loaders.contains("file"));
             assert!(loaders.contains("chromium"), "missing chromium for {id}");
         }
+
+        for id in FIREFOX_IMPORTERS {
+            let loaders = get_loaders(&map, id);
+            assert!(loaders.contains("file"));
+            assert!(loaders.contains("firefox"), "missing firefox for {id}");
+            assert!(!loaders.contains("chromium"));
+        }
     }
 
     #[cfg(target_os = "windows")]
@@ -175,8 +236,12 @@ mod tests {
             "chromecsv".to_string(),
             "chromiumcsv".to_string(),
             "edgecsv".to_string(),
+            "firefoxcsv".to_string(),
+            "librewolfcsv".to_string(),
             "operacsv".to_string(),
             "vivaldicsv".to_string(),
+            "waterfoxcsv".to_string(),
+            "zencsv".to_string(),
         ]);
         assert_eq!(map.len(), expected.len());
         assert_eq!(map_keys(&map), expected);
@@ -206,5 +271,12 @@ mod tests {
             assert!(loaders.contains("file"));
             assert!(loaders.contains("chromium"), "missing chromium for {id}");
         }
+
+        for id in FIREFOX_IMPORTERS {
+            let loaders = get_loaders(&map, id);
+            assert!(loaders.contains("file"));
+            assert!(loaders.contains("firefox"), "missing firefox for {id}");
+            assert!(!loaders.contains("chromium"));
+        }
     }
 }
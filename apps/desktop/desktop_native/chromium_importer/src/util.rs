@@ -1,4 +1,7 @@
+use std::path::Path;
+
 use anyhow::{anyhow, Result};
+use rusqlite::Connection;
 
 fn split_encrypted_string(encrypted: &[u8]) -> Result<(&str, &[u8])> {
     if encrypted.len() < 3 {
@@ -54,6 +57,47 @@ pub(crate) fn derive_saltysalt(password: &[u8], iterations: u32) -> Result<Vec<u
     Ok(key)
 }
 
+/// Runs `query` against a copy of the SQLite database at `db_path`.
+///
+/// # Returns
+///
+/// The result of the query, or `None` if the database doesn't exist.
+pub(crate) fn query_database_copy<T>(
+    db_path: &Path,
+    query: impl FnOnce(&Connection) -> Result<T, rusqlite::Error>,
+) -> Result<Option<T>> {
+    if !db_path.exists() {
+        return Ok(None);
+    }
+
+    // When the browser with the current profile is open the database file is locked.
+    // To access it we need to copy it to a temporary location.
+    let tmp_db_path = std::env::temp_dir().join(format!(
+        "tmp-db-{}-{}.db",
+        std::time::SystemTime::now()
+            .duration_since(std::time::UNIX_EPOCH)
+            .map_err(|e| anyhow!("Failed to retrieve system time: {}", e))?
+            .as_millis(),
+        rand::random::<u32>()
+    ));
+
+    std::fs::copy(db_path, &tmp_db_path)
+        .map_err(|e| anyhow!("Failed to copy the database file at {:?}: {}", db_path, e))?;
+
+    let tmp_db_path = tmp_db_path
+        .to_str()
+        .ok_or_else(|| anyhow!("Failed to locate database."))?;
+    let result = Connection::open(tmp_db_path).and_then(|conn| query(&conn));
+
+    // Clean up temp file
+    let _ = std::fs::remove_file(tmp_db_path);
+
+    let filename = db_path.file_name().unwrap_or_default().to_string_lossy();
+    Ok(Some(result.map_err(|e| {
+        anyhow!("Failed to query {}: {}", filename, e)
+    })?))
+}
+
 #[cfg(test)]
 mod tests {
     const LENGTH10: usize = 10;
@@ -280,16 +280,62 @@ export declare namespace biometrics {
 }
 
 export declare namespace chromium_importer {
+  export interface Address {
+    fullName: string
+    companyName: string
+    streetAddress: string
+    city: string
+    state: string
+    zipCode: string
+    countryCode: string
+    email: string
+    phoneNumber: string
+  }
+  export interface AutofillImportResult {
+    cards: Array<PaymentCardImportResult>
+    addresses: Array<Address>
+    ibans: Array<IbanImportResult>
+  }
+  export interface DuplicateLogin {
+    url: string
+    username: string
+  }
   export function getAvailableProfiles(browser: string, masBuild: boolean): Promise<Array<ProfileInfo>>
+  /** Returns the profiles of a Firefox based browser. */
+  export function getFirefoxProfiles(browser: string): Promise<Array<ProfileInfo>>
   /** Returns OS aware metadata describing supported Chromium based importers as a JSON string. */
   export function getMetadata(masBuild: boolean): Record<string, NativeImporterMetadata>
-  export function importLogins(browser: string, profileId: string, masBuild: boolean): Promise<Array<LoginImportResult>>
+  export interface Iban {
+    value: string
+    nickname: string
+  }
+  export interface IbanImportFailure {
+    nickname: string
+    error: string
+  }
+  export interface IbanImportResult {
+    iban?: Iban
+    failure?: IbanImportFailure
+  }
+  export function importAutofill(browser: string, profileId: string, masBuild: boolean): Promise<AutofillImportResult>
+  /**
+   * Imports the logins of a Firefox based browser profile. Fails with
+   * "firefoxImporterPrimaryPasswordRequired" if the profile has a primary password that wasn't
+   * given. Runs off the main thread, as deriving the key from the primary password is slow.
+   */
+  export function importFirefoxLogins(browser: string, profileId: string, primaryPassword?: string | undefined | null): Promise<Array<LoginImportResult>>
+  export function importLogins(browser: string, profileId: string, masBuild: boolean): Promise<LoginImport>
   export interface Login {
     url: string
     username: string
     password: string
     note: string
   }
+  export interface LoginImport {
+    logins: Array<LoginImportResult>
+    /** The logins left out of `logins` because they duplicate one of them. */
+    duplicates: Array<DuplicateLogin>
+  }
   export interface LoginImportFailure {
     url: string
     username: string
@@ -304,6 +350,22 @@ export declare namespace chromium_importer {
     loaders: Array<string>
     instructions: string
   }
+  export interface PaymentCard {
+    nameOnCard: string
+    number: string
+    expirationMonth: number
+    expirationYear: number
+    code: string
+    nickname: string
+  }
+  export interface PaymentCardImportFailure {
+    nameOnCard: string
+    error: string
+  }
+  export interface PaymentCardImportResult {
+    card?: PaymentCard
+    failure?: PaymentCardImportFailure
+  }
   /** Pre-translated picker dialog strings supplied by the renderer. */
   export interface PickerStrings {
     message: string
@@ -446,31 +508,175 @@ export declare namespace sshagent_v2 {
      *
      * * `sign_callback` - Allows agent to get approval for sign requests
      * * `list_callback` - Allows agent to get approval for list key requests
+     * * `extra_sockets` - Sockets to listen on in addition to the default socket
+     * * `upstream_socket` - Socket of an agent whose keys are offered alongside the vault's
+     * * `audit_log_path` - JSON-lines file list and sign requests are recorded to
      */
-    static serve(signCallback: ((err: Error | null, arg: SignRequestData) => Promise<boolean>), listCallback: ((err: Error | null, ) => Promise<boolean>)): Promise<SshAgentState>
+    static serve(signCallback: ((err: Error | null, arg: SignRequestData) => Promise<boolean>), listCallback: ((err: Error | null, ) => Promise<boolean>), extraSockets?: Array<AgentSocket> | undefined | null, upstreamSocket?: string | undefined | null, auditLogPath?: string | undefined | null): Promise<SshAgentState>
     stop(): void
     isRunning(): boolean
-    replace(newKeys: Array<SshKeyData>): void
+    /**
+     * Replaces the vault keys held by the agent. The keys are parsed off the main thread, as
+     * deriving the keys of encrypted ones is slow.
+     *
+     * Returns the keys that could not be parsed, so that the user can fix them.
+     */
+    replace(newKeys: Array<SshKeyData>): Promise<Array<KeyParseFailure>>
+    /**
+     * Locks the agent, e.g. when the vault locks. While locked, no keys are listed and
+     * all sign requests are refused.
+     */
+    lock(): void
+    /**
+     * Unlocks the agent. A lock placed by a client with `ssh-add -x` remains until the
+     * client unlocks it.
+     */
+    unlock(): void
+    isLocked(): boolean
+    /**
+     * Sets how many seconds sign approvals are remembered for the same key, requesting
+     * process tree, SSHSIG namespace and host. Zero disables remembering approvals.
+     */
+    setApprovalCacheTtl(seconds: number): void
+    /**
+     * Replaces the policy rules sign requests are evaluated against before asking for
+     * approval. See the `ssh_agent` README for the rule format.
+     */
+    setPolicyRules(rulesJson: string): void
+    /**
+     * Returns up to `limit` of the most recently recorded list and sign requests, newest
+     * first. Empty if no audit log was set when the agent was started.
+     */
+    recentAuditEntries(limit: number): Array<AuditEntry>
+    /**
+     * Sets the limits on the sign requests of each client, the sign requests awaiting approval
+     * and the open connections, resetting the counters of [`Self::rate_limit_stats`].
+     */
+    setRateLimits(limits: RateLimits): void
+    /**
+     * Returns the counters of requests and connections refused because of the rate limits,
+     * for diagnostics.
+     */
+    rateLimitStats(): RateLimitStats
   }
   export type SSHAgentState = SshAgentState
+  /** An additional socket for the agent to listen on. */
+  export interface AgentSocket {
+    /** Name of the socket, used to identify it in logs. */
+    name: string
+    /** Path of the Unix domain socket, or name of the named pipe on Windows. */
+    path: string
+    /** The keys visible to clients of the socket. All keys are visible if not set. */
+    keyFilter?: KeyFilter
+  }
+  /** Limits on the requests and connections of clients. Requests beyond them fail immediately. */
+  export interface RateLimits {
+    /**
+     * Maximum number of sign requests that ask for approval a client may make within
+     * `window_ms`.
+     */
+    maxSignRequests: number
+    /** The sliding window over which sign requests are counted, in milliseconds. */
+    windowMs: number
+    /** Maximum number of sign requests awaiting approval at once, across all clients. */
+    maxPendingApprovals: number
+    /** Maximum number of connections open at once, across all clients. */
+    maxConnections: number
+  }
+  /**
+   * Counters of requests and connections the agent refused because of its rate limits, and of
+   * those in progress.
+   */
+  export interface RateLimitStats {
+    /** Sign requests refused because their client exceeded its rate limit. */
+    rateLimitedSignRequests: number
+    /** Sign requests refused because too many were awaiting approval. */
+    rejectedApprovals: number
+    /** Connections closed because too many were open. */
+    rejectedConnections: number
+    pendingApprovals: number
+    openConnections: number
+  }
+  /** A recorded list or sign request, and whether it was authorized. */
+  export interface AuditEntry {
+    /** Milliseconds since the Unix epoch. */
+    timestampMs: number
+    /** `list` or `sign`. */
+    operation: string
+    pid?: number
+    processName?: string
+    keyFingerprint?: string
+    cipherId?: string
+    namespace?: string
+    hostFingerprint?: string
+    isForwarding: boolean
+    /** `allowed`, `denied` or `error`. */
+    decision: string
+    /**
+     * `keyFilter`, `keyConstraints`, `rateLimit` or `noExternalSigner` if the request was
+     * denied without asking for approval.
+     */
+    deniedBy?: string
+  }
+  /** OpenSSH certificate fields of a sign request. */
+  export interface CertificateInfo {
+    keyId: string
+    principals: Array<string>
+    /** Seconds since the Unix epoch. */
+    validAfter: number
+    /** Seconds since the Unix epoch. `None` means the certificate never expires. */
+    validBefore?: number
+  }
+  /**
+   * Selects the keys visible to clients of a socket. A key is visible if it matches any of
+   * the criteria.
+   */
+  export interface KeyFilter {
+    /** Vault cipher IDs of visible keys. */
+    cipherIds?: Array<string>
+    /** Glob patterns matched against key names, supporting `*` and `?`. */
+    namePatterns?: Array<string>
+    /** Names of vault folders whose keys are visible. */
+    folders?: Array<string>
+  }
   /** SSH public key data */
   export interface PublicKey {
     alg: string
     blob: Array<number>
   }
   /** A sign request's SIG namespace */
-  export const enum SIGNamespace {
-    Git = 'git',
-    File = 'file',
-    Unsupported = 'unsupported'
+  export type SIGNamespace =
+    | { type: 'Git' }
+    | { type: 'File' }
+    | { type: 'Other', namespace: string }
+    | { type: 'Unsupported' }
+  /** A host the connection was bound to, nearest to the agent first. */
+  export interface SessionBindHop {
+    hostFingerprint: string
+    isForwarding: boolean
   }
   /** SSH sign request fields. */
   export interface SignRequest {
     publicKey: PublicKey
     processName?: string
+    /** Absolute path of the requesting process' executable. */
+    processPath?: string
+    /** Command line arguments of the requesting process, including the program name. */
+    processArgs: Array<string>
+    /** User ID of the requesting process. */
+    processUid?: number
+    /** Names of the requesting process' ancestors up to its session leader, nearest first. */
+    processAncestry: Array<string>
+    /** `flatpak`, `snap` or `container` if the requesting process runs in a sandbox. */
+    sandbox?: string
     isForwarding: boolean
     namespace?: SIGNamespace
     hostFingerprint?: string
+    /** Hosts the request was forwarded through, ending with the host being authenticated to. */
+    hops: Array<SessionBindHop>
+    certificate?: CertificateInfo
+    /** The comment of the key, if it is held by the upstream agent rather than the vault. */
+    upstreamKeyComment?: string
   }
   /** Data for a sign request, including vault cipher context. */
   export interface SignRequestData {
@@ -482,6 +688,57 @@ export declare namespace sshagent_v2 {
     privateKey: string
     name: string
     cipherId: string
+    /** OpenSSH user certificate issued for the key, if any. */
+    certificate?: string
+    /** Name of the vault folder the key is in, if any. */
+    folder?: string
+    /** Passphrase of the private key, if it is encrypted. */
+    passphrase?: string
+  }
+  /** A vault SSH key the agent could not parse, and why. */
+  export interface KeyParseFailure {
+    cipherId: string
+    name: string
+    error: string
+  }
+}
+
+export declare namespace sshkeys {
+  /** An SSH key pair in OpenSSH formats, with the fingerprints and randomart shown to the user. */
+  export interface ExportedKey {
+    /** Unencrypted private key in OpenSSH format. */
+    privateKey: string
+    /** Public key in OpenSSH format, without comment. */
+    publicKey: string
+    /** Public key followed by its comment, as a line of an `authorized_keys` file. */
+    authorizedKey: string
+    /** SHA-256 fingerprint, e.g. `SHA256:3/9nSFwDYIRdXW04I+FWoPLgrF7sdSk8fHbH/hDUQNc`. */
+    sha256Fingerprint: string
+    /** MD5 fingerprint, e.g. `MD5:7d:09:a0:dc:c7:3b:0a:54:d7:da:7f:5f:e9:99:22:ce`. */
+    md5Fingerprint: string
+    /** Randomart of the SHA-256 fingerprint, as printed by `ssh-keygen -lv`. */
+    randomart: string
+  }
+  /**
+   * Exports an existing key in OpenSSH formats. Besides OpenSSH, PKCS#1, SEC1 and PKCS#8 PEM
+   * and PuTTY keys are accepted, so that they can be converted when imported into the vault.
+   * Parsing runs off the main thread, as deriving the key of an encrypted key is slow.
+   */
+  export function exportKey(privateKey: string, passphrase?: string | undefined | null, comment?: string | undefined | null): Promise<ExportedKey>
+  /**
+   * Generates a new key pair. Generation runs off the main thread, as RSA keys can take
+   * seconds to generate.
+   */
+  export function generateKey(keyType: KeyType, comment: string): Promise<ExportedKey>
+  /** Types of SSH keys that can be generated. */
+  export const enum KeyType {
+    Ed25519 = 0,
+    EcdsaP256 = 1,
+    EcdsaP384 = 2,
+    EcdsaP521 = 3,
+    Rsa2048 = 4,
+    Rsa3072 = 5,
+    Rsa4096 = 6
   }
 }
 
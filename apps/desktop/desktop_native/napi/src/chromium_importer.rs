@@ -4,11 +4,15 @@ pub mod chromium_importer {
 
     use chromium_importer::{
         chromium::{
-            DefaultInstalledBrowserRetriever, LoginImportResult as _LoginImportResult,
-            ProfileInfo as _ProfileInfo,
+            Address as _Address, AutofillImport as _AutofillImport,
+            DefaultInstalledBrowserRetriever, DuplicateLogin as _DuplicateLogin,
+            IbanImportResult as _IbanImportResult, LoginImport as _LoginImport,
+            LoginImportResult as _LoginImportResult,
+            PaymentCardImportResult as _PaymentCardImportResult, ProfileInfo as _ProfileInfo,
         },
         metadata::NativeImporterMetadata as _NativeImporterMetadata,
     };
+    use tokio::task::spawn_blocking;
 
     #[napi(object)]
     pub struct ProfileInfo {
@@ -37,6 +41,79 @@ pub mod chromium_importer {
         pub failure: Option<LoginImportFailure>,
     }
 
+    #[napi(object)]
+    pub struct DuplicateLogin {
+        pub url: String,
+        pub username: String,
+    }
+
+    #[napi(object)]
+    pub struct LoginImport {
+        pub logins: Vec<LoginImportResult>,
+        /// The logins left out of `logins` because they duplicate one of them.
+        pub duplicates: Vec<DuplicateLogin>,
+    }
+
+    #[napi(object)]
+    pub struct PaymentCard {
+        pub name_on_card: String,
+        pub number: String,
+        pub expiration_month: u32,
+        pub expiration_year: u32,
+        pub code: String,
+        pub nickname: String,
+    }
+
+    #[napi(object)]
+    pub struct PaymentCardImportFailure {
+        pub name_on_card: String,
+        pub error: String,
+    }
+
+    #[napi(object)]
+    pub struct PaymentCardImportResult {
+        pub card: Option<PaymentCard>,
+        pub failure: Option<PaymentCardImportFailure>,
+    }
+
+    #[napi(object)]
+    pub struct Address {
+        pub full_name: String,
+        pub company_name: String,
+        pub street_address: String,
+        pub city: String,
+        pub state: String,
+        pub zip_code: String,
+        pub country_code: String,
+        pub email: String,
+        pub phone_number: String,
+    }
+
+    #[napi(object)]
+    pub struct Iban {
+        pub value: String,
+        pub nickname: String,
+    }
+
+    #[napi(object)]
+    pub struct IbanImportFailure {
+        pub nickname: String,
+        pub error: String,
+    }
+
+    #[napi(object)]
+    pub struct IbanImportResult {
+        pub iban: Option<Iban>,
+        pub failure: Option<IbanImportFailure>,
+    }
+
+    #[napi(object)]
+    pub struct AutofillImportResult {
+        pub cards: Vec<PaymentCardImportResult>,
+        pub addresses: Vec<Address>,
+        pub ibans: Vec<IbanImportResult>,
+    }
+
     #[napi(object)]
     pub struct NativeImporterMetadata {
         pub id: String,
@@ -87,6 +164,100 @@ pub mod chromium_importer {
         }
     }
 
+    impl From<_PaymentCardImportResult> for PaymentCardImportResult {
+        fn from(c: _PaymentCardImportResult) -> Self {
+            match c {
+                _PaymentCardImportResult::Success(c) => PaymentCardImportResult {
+                    card: Some(PaymentCard {
+                        name_on_card: c.name_on_card,
+                        number: c.number,
+                        expiration_month: c.expiration_month,
+                        expiration_year: c.expiration_year,
+                        code: c.code,
+                        nickname: c.nickname,
+                    }),
+                    failure: None,
+                },
+                _PaymentCardImportResult::Failure(c) => PaymentCardImportResult {
+                    card: None,
+                    failure: Some(PaymentCardImportFailure {
+                        name_on_card: c.name_on_card,
+                        error: c.error,
+                    }),
+                },
+            }
+        }
+    }
+
+    impl From<_Address> for Address {
+        fn from(a: _Address) -> Self {
+            Address {
+                full_name: a.full_name,
+                company_name: a.company_name,
+                street_address: a.street_address,
+                city: a.city,
+                state: a.state,
+                zip_code: a.zip_code,
+                country_code: a.country_code,
+                email: a.email,
+                phone_number: a.phone_number,
+            }
+        }
+    }
+
+    impl From<_IbanImportResult> for IbanImportResult {
+        fn from(i: _IbanImportResult) -> Self {
+            match i {
+                _IbanImportResult::Success(i) => IbanImportResult {
+                    iban: Some(Iban {
+                        value: i.value,
+                        nickname: i.nickname,
+                    }),
+                    failure: None,
+                },
+                _IbanImportResult::Failure(i) => IbanImportResult {
+                    iban: None,
+                    failure: Some(IbanImportFailure {
+                        nickname: i.nickname,
+                        error: i.error,
+                    }),
+                },
+            }
+        }
+    }
+
+    impl From<_DuplicateLogin> for DuplicateLogin {
+        fn from(d: _DuplicateLogin) -> Self {
+            DuplicateLogin {
+                url: d.url,
+                username: d.username,
+            }
+        }
+    }
+
+    impl From<_LoginImport> for LoginImport {
+        fn from(l: _LoginImport) -> Self {
+            LoginImport {
+                logins: l.logins.into_iter().map(LoginImportResult::from).collect(),
+                duplicates: l.duplicates.into_iter().map(DuplicateLogin::from).collect(),
+            }
+        }
+    }
+
+    impl From<_AutofillImport> for AutofillImportResult {
+        fn from(a: _AutofillImport) -> Self {
+            AutofillImportResult {
+                cards: a
+                    .cards
+                    .into_iter()
+                    .map(PaymentCardImportResult::from)
+                    .collect(),
+                addresses: a.addresses.into_iter().map(Address::from).collect(),
+                ibans: a.ibans.into_iter().map(IbanImportResult::from).collect(),
+            }
+        }
+    }
+
     impl From<_ProfileInfo> for ProfileInfo {
         fn from(p: _ProfileInfo) -> Self {
             ProfileInfo {
@@ -133,13 +304,57 @@ pub mod chromium_importer {
         browser: String,
         profile_id: String,
         mas_build: bool,
-    ) -> napi::Result<Vec<LoginImportResult>> {
+    ) -> napi::Result<LoginImport> {
         chromium_importer::chromium::import_logins(&browser, &profile_id, mas_build)
             .await
-            .map(|logins| logins.into_iter().map(LoginImportResult::from).collect())
+            .map(LoginImport::from)
+            .map_err(|e| napi::Error::from_reason(e.to_string()))
+    }
+
+    #[napi]
+    pub async fn import_autofill(
+        browser: String,
+        profile_id: String,
+        mas_build: bool,
+    ) -> napi::Result<AutofillImportResult> {
+        chromium_importer::chromium::import_autofill(&browser, &profile_id, mas_build)
+            .await
+            .map(AutofillImportResult::from)
+            .map_err(|e| napi::Error::from_reason(e.to_string()))
+    }
+
+    #[napi]
+    /// Returns the profiles of a Firefox based browser.
+    pub async fn get_firefox_profiles(browser: String) -> napi::Result<Vec<ProfileInfo>> {
+        spawn_blocking(move || chromium_importer::firefox::get_available_profiles(&browser))
+            .await
+            .map_err(|e| napi::Error::from_reason(e.to_string()))?
+            .map(|profiles| profiles.into_iter().map(ProfileInfo::from).collect())
             .map_err(|e| napi::Error::from_reason(e.to_string()))
     }
 
+    #[napi]
+    /// Imports the logins of a Firefox based browser profile. Fails with
+    /// "firefoxImporterPrimaryPasswordRequired" if the profile has a primary password that wasn't
+    /// given. Runs off the main thread, as deriving the key from the primary password is slow.
+    pub async fn import_firefox_logins(
+        browser: String,
+        profile_id: String,
+        primary_password: Option<String>,
+    ) -> napi::Result<Vec<LoginImportResult>> {
+        spawn_blocking(move || {
+            chromium_importer::firefox::import_logins(
+                &browser,
+                &profile_id,
+                primary_password.as_deref(),
+            )
+        })
+        .await
+        .map_err(|e| napi::Error::from_reason(e.to_string()))?
+        .map(|logins| logins.into_iter().map(LoginImportResult::from).collect())
+        .map_err(|e| napi::Error::from_reason(e.to_string()))
+    }
+
     #[napi]
     #[allow(clippy::unused_async)]
     pub async fn request_browser_access(
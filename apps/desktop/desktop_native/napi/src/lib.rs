@@ -22,4 +22,5 @@ pub mod powermonitors;
 pub mod processisolations;
 pub mod sshagent; // deprecated
 pub mod sshagent_v2;
+pub mod sshkeys;
 pub mod windows_registry;
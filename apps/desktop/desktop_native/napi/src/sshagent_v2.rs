@@ -5,7 +5,7 @@
 
 #[napi]
 pub mod sshagent_v2 {
-    use std::{sync::Arc, time::Duration};
+    use std::{collections::HashMap, sync::Arc, time::Duration};
 
     use async_trait::async_trait;
     use napi::{
@@ -14,10 +14,10 @@ pub mod sshagent_v2 {
     };
     use ssh_agent::{
         ApprovalError, ApprovalRequester, BitwardenSSHAgent, InMemoryEncryptedKeyStore,
-        SIGNamespace as SSHSIGNamespace, SignApprovalRequest as SSHSignApprovalRequest,
-        UnparsedSSHKeyData,
+        SIGNamespace as SSHSIGNamespace, SessionBindHop as SSHSessionBindHop,
+        SignApprovalRequest as SSHSignApprovalRequest, UnparsedSSHKeyData,
     };
-    use tokio::time::timeout;
+    use tokio::{task::spawn_blocking, time::timeout};
     use tracing::{debug, error};
 
     /// Timeout for Electron approval callbacks
@@ -30,6 +30,73 @@ pub mod sshagent_v2 {
         pub private_key: String,
         pub name: String,
         pub cipher_id: String,
+        /// OpenSSH user certificate issued for the key, if any.
+        pub certificate: Option<String>,
+        /// Name of the vault folder the key is in, if any.
+        pub folder: Option<String>,
+        /// Passphrase of the private key, if it is encrypted.
+        pub passphrase: Option<String>,
+    }
+
+    /// A vault SSH key the agent could not parse, and why.
+    #[napi(object)]
+    pub struct KeyParseFailure {
+        pub cipher_id: String,
+        pub name: String,
+        pub error: String,
+    }
+
+    impl From<ssh_agent::KeyParseFailure> for KeyParseFailure {
+        fn from(failure: ssh_agent::KeyParseFailure) -> Self {
+            Self {
+                cipher_id: failure.cipher_id,
+                name: failure.name,
+                error: failure.error,
+            }
+        }
+    }
+
+    /// An additional socket for the agent to listen on.
+    #[napi(object)]
+    pub struct AgentSocket {
+        /// Name of the socket, used to identify it in logs.
+        pub name: String,
+        /// Path of the Unix domain socket, or name of the named pipe on Windows.
+        pub path: String,
+        /// The keys visible to clients of the socket. All keys are visible if not set.
+        pub key_filter: Option<KeyFilter>,
+    }
+
+    impl From<AgentSocket> for ssh_agent::AgentSocket {
+        fn from(socket: AgentSocket) -> Self {
+            Self {
+                name: socket.name,
+                path: socket.path.into(),
+                key_filter: socket.key_filter.map(Into::into).unwrap_or_default(),
+            }
+        }
+    }
+
+    /// Selects the keys visible to clients of a socket. A key is visible if it matches any of
+    /// the criteria.
+    #[napi(object)]
+    pub struct KeyFilter {
+        /// Vault cipher IDs of visible keys.
+        pub cipher_ids: Option<Vec<String>>,
+        /// Glob patterns matched against key names, supporting `*` and `?`.
+        pub name_patterns: Option<Vec<String>>,
+        /// Names of vault folders whose keys are visible.
+        pub folders: Option<Vec<String>>,
+    }
+
+    impl From<KeyFilter> for ssh_agent::KeyFilter {
+        fn from(filter: KeyFilter) -> Self {
+            Self {
+                cipher_ids: filter.cipher_ids.unwrap_or_default(),
+                name_patterns: filter.name_patterns.unwrap_or_default(),
+                folders: filter.folders.unwrap_or_default(),
+            }
+        }
     }
 
     /// SSH public key data
@@ -41,11 +108,16 @@ pub mod sshagent_v2 {
     }
 
     /// A sign request's SIG namespace
-    #[napi(string_enum = "camelCase")]
+    #[napi(discriminant = "type")]
     #[derive(Debug)]
     pub enum SIGNamespace {
         Git,
         File,
+        /// Any other namespace, e.g. `email@example.com`.
+        Other {
+            namespace: String,
+        },
+        /// A malformed SSHSIG blob.
         Unsupported,
     }
 
@@ -54,20 +126,76 @@ pub mod sshagent_v2 {
             match ns {
                 SSHSIGNamespace::Git => Self::Git,
                 SSHSIGNamespace::File => Self::File,
+                SSHSIGNamespace::Other(namespace) => Self::Other { namespace },
                 SSHSIGNamespace::Unsupported => Self::Unsupported,
             }
         }
     }
 
+    /// OpenSSH certificate fields of a sign request.
+    #[napi(object)]
+    #[derive(Debug)]
+    pub struct CertificateInfo {
+        pub key_id: String,
+        pub principals: Vec<String>,
+        /// Seconds since the Unix epoch.
+        pub valid_after: i64,
+        /// Seconds since the Unix epoch. `None` means the certificate never expires.
+        pub valid_before: Option<i64>,
+    }
+
+    impl From<ssh_agent::CertificateContext> for CertificateInfo {
+        fn from(c: ssh_agent::CertificateContext) -> Self {
+            Self {
+                key_id: c.key_id,
+                principals: c.principals,
+                valid_after: i64::try_from(c.valid_after).unwrap_or(i64::MAX),
+                valid_before: i64::try_from(c.valid_before).ok(),
+            }
+        }
+    }
+
+    /// A host the connection was bound to, nearest to the agent first.
+    #[napi(object)]
+    #[derive(Debug)]
+    pub struct SessionBindHop {
+        pub host_fingerprint: String,
+        pub is_forwarding: bool,
+    }
+
+    impl From<SSHSessionBindHop> for SessionBindHop {
+        fn from(hop: SSHSessionBindHop) -> Self {
+            Self {
+                host_fingerprint: hop.host_fingerprint,
+                is_forwarding: hop.is_forwarding,
+            }
+        }
+    }
+
     /// SSH sign request fields.
     #[napi(object)]
     #[derive(Debug)]
     pub struct SignRequest {
         pub public_key: PublicKey,
         pub process_name: Option<String>,
+        /// Absolute path of the requesting process' executable.
+        pub process_path: Option<String>,
+        /// Command line arguments of the requesting process, including the program name.
+        pub process_args: Vec<String>,
+        /// User ID of the requesting process.
+        pub process_uid: Option<u32>,
+        /// Names of the requesting process' ancestors up to its session leader, nearest first.
+        pub process_ancestry: Vec<String>,
+        /// `flatpak`, `snap` or `container` if the requesting process runs in a sandbox.
+        pub sandbox: Option<String>,
         pub is_forwarding: bool,
         pub namespace: Option<SIGNamespace>,
         pub host_fingerprint: Option<String>,
+        /// Hosts the request was forwarded through, ending with the host being authenticated to.
+        pub hops: Vec<SessionBindHop>,
+        pub certificate: Option<CertificateInfo>,
+        /// The comment of the key, if it is held by the upstream agent rather than the vault.
+        pub upstream_key_comment: Option<String>,
     }
 
     impl From<ssh_agent::SignRequest> for SignRequest {
@@ -78,13 +206,144 @@ pub mod sshagent_v2 {
                     blob: r.public_key.blob,
                 },
                 process_name: r.connection.process_name,
+                process_path: r.connection.process_path,
+                process_args: r.connection.process_args,
+                process_uid: r.connection.process_uid,
+                process_ancestry: r.connection.process_ancestry_names,
+                sandbox: r.connection.sandbox.map(|sandbox| {
+                    match sandbox {
+                        ssh_agent::PeerSandbox::Flatpak => "flatpak",
+                        ssh_agent::PeerSandbox::Snap => "snap",
+                        ssh_agent::PeerSandbox::Container => "container",
+                    }
+                    .to_string()
+                }),
                 is_forwarding: r
                     .connection
                     .session_bind
                     .as_ref()
                     .is_some_and(|s| s.is_forwarding),
                 namespace: r.namespace.map(Into::into),
+                hops: r
+                    .connection
+                    .session_bind
+                    .as_ref()
+                    .map(|s| s.hops.iter().cloned().map(Into::into).collect())
+                    .unwrap_or_default(),
                 host_fingerprint: r.connection.session_bind.map(|s| s.host_fingerprint),
+                certificate: r.certificate.map(Into::into),
+                upstream_key_comment: r.upstream_key_comment,
+            }
+        }
+    }
+
+    /// Limits on the requests and connections of clients. Requests beyond them fail immediately.
+    #[napi(object)]
+    #[derive(Debug)]
+    pub struct RateLimits {
+        /// Maximum number of sign requests that ask for approval a client may make within
+        /// `window_ms`.
+        pub max_sign_requests: u32,
+        /// The sliding window over which sign requests are counted, in milliseconds.
+        pub window_ms: u32,
+        /// Maximum number of sign requests awaiting approval at once, across all clients.
+        pub max_pending_approvals: u32,
+        /// Maximum number of connections open at once, across all clients.
+        pub max_connections: u32,
+    }
+
+    impl From<RateLimits> for ssh_agent::RateLimits {
+        fn from(limits: RateLimits) -> Self {
+            Self {
+                max_sign_requests: limits.max_sign_requests as usize,
+                window: Duration::from_millis(u64::from(limits.window_ms)),
+                max_pending_approvals: limits.max_pending_approvals as usize,
+                max_connections: limits.max_connections as usize,
+            }
+        }
+    }
+
+    /// Counters of requests and connections the agent refused because of its rate limits, and of
+    /// those in progress.
+    #[napi(object)]
+    #[derive(Debug)]
+    pub struct RateLimitStats {
+        /// Sign requests refused because their client exceeded its rate limit.
+        pub rate_limited_sign_requests: i64,
+        /// Sign requests refused because too many were awaiting approval.
+        pub rejected_approvals: i64,
+        /// Connections closed because too many were open.
+        pub rejected_connections: i64,
+        pub pending_approvals: u32,
+        pub open_connections: u32,
+    }
+
+    impl From<ssh_agent::RateLimitStats> for RateLimitStats {
+        fn from(stats: ssh_agent::RateLimitStats) -> Self {
+            Self {
+                rate_limited_sign_requests: i64::try_from(stats.rate_limited_sign_requests)
+                    .unwrap_or(i64::MAX),
+                rejected_approvals: i64::try_from(stats.rejected_approvals).unwrap_or(i64::MAX),
+                rejected_connections: i64::try_from(stats.rejected_connections).unwrap_or(i64::MAX),
+                pending_approvals: u32::try_from(stats.pending_approvals).unwrap_or(u32::MAX),
+                open_connections: u32::try_from(stats.open_connections).unwrap_or(u32::MAX),
+            }
+        }
+    }
+
+    /// A recorded list or sign request, and whether it was authorized.
+    #[napi(object)]
+    #[derive(Debug)]
+    pub struct AuditEntry {
+        /// Milliseconds since the Unix epoch.
+        pub timestamp_ms: i64,
+        /// `list` or `sign`.
+        pub operation: String,
+        pub pid: Option<u32>,
+        pub process_name: Option<String>,
+        pub key_fingerprint: Option<String>,
+        pub cipher_id: Option<String>,
+        pub namespace: Option<String>,
+        pub host_fingerprint: Option<String>,
+        pub is_forwarding: bool,
+        /// `allowed`, `denied` or `error`.
+        pub decision: String,
+        /// `keyFilter`, `keyConstraints`, `rateLimit` or `noExternalSigner` if the request was
+        /// denied without asking for approval.
+        pub denied_by: Option<String>,
+    }
+
+    impl From<ssh_agent::AuditEntry> for AuditEntry {
+        fn from(entry: ssh_agent::AuditEntry) -> Self {
+            Self {
+                timestamp_ms: i64::try_from(entry.timestamp_ms).unwrap_or(i64::MAX),
+                operation: match entry.operation {
+                    ssh_agent::AuditOperation::List => "list",
+                    ssh_agent::AuditOperation::Sign => "sign",
+                }
+                .to_string(),
+                pid: entry.pid,
+                process_name: entry.process_name,
+                key_fingerprint: entry.key_fingerprint,
+                cipher_id: entry.cipher_id,
+                namespace: entry.namespace,
+                host_fingerprint: entry.host_fingerprint,
+                is_forwarding: entry.is_forwarding,
+                decision: match entry.decision {
+                    ssh_agent::AuditDecision::Allowed => "allowed",
+                    ssh_agent::AuditDecision::Denied => "denied",
+                    ssh_agent::AuditDecision::Error => "error",
+                }
+                .to_string(),
+                denied_by: entry.denied_by.map(|reason| {
+                    match reason {
+                        ssh_agent::AuditDenialReason::KeyFilter => "keyFilter",
+                        ssh_agent::AuditDenialReason::KeyConstraints => "keyConstraints",
+                        ssh_agent::AuditDenialReason::RateLimit => "rateLimit",
+                        ssh_agent::AuditDenialReason::NoExternalSigner => "noExternalSigner",
+                    }
+                    .to_string()
+                }),
             }
         }
     }
@@ -171,11 +430,17 @@ pub mod sshagent_v2 {
         ///
         /// * `sign_callback` - Allows agent to get approval for sign requests
         /// * `list_callback` - Allows agent to get approval for list key requests
+        /// * `extra_sockets` - Sockets to listen on in addition to the default socket
+        /// * `upstream_socket` - Socket of an agent whose keys are offered alongside the vault's
+        /// * `audit_log_path` - JSON-lines file list and sign requests are recorded to
         #[napi(factory)]
         #[allow(clippy::unused_async)]
         pub async fn serve(
             sign_callback: ThreadsafeFunction<SignRequestData, Promise<bool>>,
             list_callback: ThreadsafeFunction<(), Promise<bool>>,
+            extra_sockets: Option<Vec<AgentSocket>>,
+            upstream_socket: Option<String>,
+            audit_log_path: Option<String>,
         ) -> napi::Result<Self> {
             debug!("Creating agent and starting server.");
 
@@ -187,9 +452,16 @@ pub mod sshagent_v2 {
             let keystore = InMemoryEncryptedKeyStore::default();
 
             let mut agent = ssh_agent::BitwardenSSHAgent::new(keystore, approval_handler);
+            agent.set_upstream_socket(upstream_socket.map(Into::into));
+            agent.set_audit_log(audit_log_path.map(Into::into));
 
             // TODO after PM-31827 is merged, can use simplified error conversion
-            agent.start().map_err(|error| {
+            let extra_sockets: Vec<ssh_agent::AgentSocket> = extra_sockets
+                .unwrap_or_default()
+                .into_iter()
+                .map(Into::into)
+                .collect();
+            agent.start_with_sockets(&extra_sockets).map_err(|error| {
                 error!(%error, "Failed to start the server.");
                 napi::Error::from_reason(error.to_string())
             })?;
@@ -209,20 +481,104 @@ pub mod sshagent_v2 {
             self.agent.is_running()
         }
 
+        /// Replaces the vault keys held by the agent. The keys are parsed off the main thread, as
+        /// deriving the keys of encrypted ones is slow.
+        ///
+        /// Returns the keys that could not be parsed, so that the user can fix them.
         #[napi]
-        pub fn replace(&mut self, new_keys: Vec<SSHKeyData>) -> napi::Result<()> {
+        pub async fn replace(
+            &self,
+            new_keys: Vec<SSHKeyData>,
+        ) -> napi::Result<Vec<KeyParseFailure>> {
+            let mut passphrases = HashMap::new();
             let keys = new_keys
                 .into_iter()
-                .map(|k| UnparsedSSHKeyData {
-                    private_key_pem: k.private_key,
-                    name: k.name,
-                    cipher_id: k.cipher_id,
+                .map(|k| {
+                    if let Some(passphrase) = k.passphrase {
+                        passphrases.insert(k.cipher_id.clone(), passphrase);
+                    }
+                    UnparsedSSHKeyData {
+                        private_key_pem: k.private_key,
+                        name: k.name,
+                        cipher_id: k.cipher_id,
+                        certificate: k.certificate,
+                        folder: k.folder,
+                    }
                 })
                 .collect();
 
+            let (keys, failures) = spawn_blocking(move || {
+                ssh_agent::SSHKeyData::from_private_key_pems(keys, |key| {
+                    passphrases.get(&key.cipher_id).cloned()
+                })
+            })
+            .await
+            .map_err(|e| napi::Error::from_reason(e.to_string()))?;
             self.agent
-                .replace(ssh_agent::SSHKeyData::from_private_key_pems(keys))
+                .replace(keys)
+                .map_err(|e| napi::Error::from_reason(e.to_string()))?;
+
+            Ok(failures.into_iter().map(KeyParseFailure::from).collect())
+        }
+
+        /// Locks the agent, e.g. when the vault locks. While locked, no keys are listed and
+        /// all sign requests are refused.
+        #[napi]
+        pub fn lock(&mut self) {
+            self.agent.lock();
+        }
+
+        /// Unlocks the agent. A lock placed by a client with `ssh-add -x` remains until the
+        /// client unlocks it.
+        #[napi]
+        pub fn unlock(&mut self) {
+            self.agent.unlock();
+        }
+
+        #[napi]
+        pub fn is_locked(&self) -> bool {
+            self.agent.is_locked()
+        }
+
+        /// Sets how many seconds sign approvals are remembered for the same key, requesting
+        /// process tree, SSHSIG namespace and host. Zero disables remembering approvals.
+        #[napi]
+        pub fn set_approval_cache_ttl(&mut self, seconds: u32) {
+            self.agent
+                .set_approval_cache_ttl(Duration::from_secs(u64::from(seconds)));
+        }
+
+        /// Replaces the policy rules sign requests are evaluated against before asking for
+        /// approval. See the `ssh_agent` README for the rule format.
+        #[napi]
+        pub fn set_policy_rules(&mut self, rules_json: String) -> napi::Result<()> {
+            self.agent
+                .set_policy_rules(&rules_json)
+                .map_err(|e| napi::Error::from_reason(e.to_string()))
+        }
+
+        /// Returns up to `limit` of the most recently recorded list and sign requests, newest
+        /// first. Empty if no audit log was set when the agent was started.
+        #[napi]
+        pub fn recent_audit_entries(&self, limit: u32) -> napi::Result<Vec<AuditEntry>> {
+            self.agent
+                .recent_audit_entries(limit as usize)
+                .map(|entries| entries.into_iter().map(Into::into).collect())
                 .map_err(|e| napi::Error::from_reason(e.to_string()))
         }
+
+        /// Sets the limits on the sign requests of each client, the sign requests awaiting approval
+        /// and the open connections, resetting the counters of [`Self::rate_limit_stats`].
+        #[napi]
+        pub fn set_rate_limits(&mut self, limits: RateLimits) {
+            self.agent.set_rate_limits(limits.into());
+        }
+
+        /// Returns the counters of requests and connections refused because of the rate limits,
+        /// for diagnostics.
+        #[napi]
+        pub fn rate_limit_stats(&self) -> RateLimitStats {
+            self.agent.rate_limit_stats().into()
+        }
     }
 }
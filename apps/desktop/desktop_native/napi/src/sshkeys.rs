@@ -0,0 +1,91 @@
+//! SSH key generation and export napi:
+//! - Generates keys for the vault, and exports existing keys in OpenSSH formats, so that the
+//!   desktop client relies on the agent's implementation rather than its own.
+
+#[napi]
+pub mod sshkeys {
+    use tokio::task::spawn_blocking;
+
+    /// Types of SSH keys that can be generated.
+    #[napi]
+    pub enum KeyType {
+        Ed25519,
+        EcdsaP256,
+        EcdsaP384,
+        EcdsaP521,
+        Rsa2048,
+        Rsa3072,
+        Rsa4096,
+    }
+
+    impl From<KeyType> for ssh_agent::KeyType {
+        fn from(key_type: KeyType) -> Self {
+            match key_type {
+                KeyType::Ed25519 => Self::Ed25519,
+                KeyType::EcdsaP256 => Self::EcdsaP256,
+                KeyType::EcdsaP384 => Self::EcdsaP384,
+                KeyType::EcdsaP521 => Self::EcdsaP521,
+                KeyType::Rsa2048 => Self::Rsa2048,
+                KeyType::Rsa3072 => Self::Rsa3072,
+                KeyType::Rsa4096 => Self::Rsa4096,
+            }
+        }
+    }
+
+    /// An SSH key pair in OpenSSH formats, with the fingerprints and randomart shown to the user.
+    #[napi(object)]
+    pub struct ExportedKey {
+        /// Unencrypted private key in OpenSSH format.
+        pub private_key: String,
+        /// Public key in OpenSSH format, without comment.
+        pub public_key: String,
+        /// Public key followed by its comment, as a line of an `authorized_keys` file.
+        pub authorized_key: String,
+        /// SHA-256 fingerprint, e.g. `SHA256:3/9nSFwDYIRdXW04I+FWoPLgrF7sdSk8fHbH/hDUQNc`.
+        pub sha256_fingerprint: String,
+        /// MD5 fingerprint, e.g. `MD5:7d:09:a0:dc:c7:3b:0a:54:d7:da:7f:5f:e9:99:22:ce`.
+        pub md5_fingerprint: String,
+        /// Randomart of the SHA-256 fingerprint, as printed by `ssh-keygen -lv`.
+        pub randomart: String,
+    }
+
+    impl From<ssh_agent::ExportedKey> for ExportedKey {
+        fn from(key: ssh_agent::ExportedKey) -> Self {
+            Self {
+                private_key: key.private_key,
+                public_key: key.public_key,
+                authorized_key: key.authorized_key,
+                sha256_fingerprint: key.sha256_fingerprint,
+                md5_fingerprint: key.md5_fingerprint,
+                randomart: key.randomart,
+            }
+        }
+    }
+
+    /// Generates a new key pair. Generation runs off the main thread, as RSA keys can take
+    /// seconds to generate.
+    #[napi]
+    pub async fn generate_key(key_type: KeyType, comment: String) -> napi::Result<ExportedKey> {
+        spawn_blocking(move || ssh_agent::generate_key(key_type.into(), &comment))
+            .await
+            .map_err(|e| napi::Error::from_reason(e.to_string()))?
+            .map(ExportedKey::from)
+            .map_err(|e| napi::Error::from_reason(format!("{e:#}")))
+    }
+
+    /// Exports an existing key in OpenSSH formats. Besides OpenSSH, PKCS#1, SEC1 and PKCS#8 PEM
+    /// and PuTTY keys are accepted, so that they can be converted when imported into the vault.
+    /// Parsing runs off the main thread, as deriving the key of an encrypted key is slow.
+    #[napi]
+    pub async fn export_key(
+        private_key: String,
+        passphrase: Option<String>,
+        comment: Option<String>,
+    ) -> napi::Result<ExportedKey> {
+        spawn_blocking(move || ssh_agent::export_key(&private_key, passphrase, comment.as_deref()))
+            .await
+            .map_err(|e| napi::Error::from_reason(e.to_string()))?
+            .map(ExportedKey::from)
+            .map_err(|e| napi::Error::from_reason(format!("{e:#}")))
+    }
+}
@@ -8,7 +8,7 @@ mod secure_key;
 
 pub use encrypted_memory_store::EncryptedMemoryStore;
 
-use crate::secure_key::DecryptionError;
+pub use crate::secure_key::DecryptionError;
 
 /// The secure memory store provides an ephemeral key-value store for sensitive data.
 /// Data stored in this store is prevented from being swapped to disk and zeroed out. Additionally,
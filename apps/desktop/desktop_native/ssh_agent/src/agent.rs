@@ -1,19 +1,35 @@
 //! Provides an orchestration between the underlying ssh agent server, the keystore
 //! and the upstream approver of server requests.
 
-use std::sync::Arc;
+use std::{path::PathBuf, sync::Arc, time::Duration};
 
 use anyhow::Result;
 use tracing::{debug, info};
 
 use crate::{
-    approval::ApprovalRequester, authorization::BitwardenAuthPolicy, server::SSHAgentServer,
-    storage::keystore::KeyStore,
+    approval::{ApprovalCoordinator, ApprovalRequester},
+    audit::{AuditEntry, AuditSink, JsonLinesAuditLog},
+    authorization::{BitwardenAuthPolicy, CachingAuthPolicy},
+    external_signer::{ExternalSigner, UnavailableExternalSigner},
+    policy_rules::RuleAuthPolicy,
+    server::{
+        AgentSocket, RateLimitStats, RateLimits, SSHAgentServer, SocketUpstreamAgent, UpstreamAgent,
+    },
+    storage::{agent_lock::AgentLock, keystore::KeyStore, session_keystore::SessionKeyStore},
 };
 
+/// Policy rules decide first, then remembered approvals, before the user is asked one prompt at a
+/// time.
+type AgentAuthPolicy<K, H> =
+    RuleAuthPolicy<CachingAuthPolicy<BitwardenAuthPolicy<K, ApprovalCoordinator<H>>>>;
+
 /// - contains the [`KeyStore`] of ssh keys
+/// - contains the [`SessionKeyStore`] of keys added by clients for the agent's session
+/// - contains the [`AgentLock`] state, set by clients or the desktop client
 /// - manages the [`SSHAgentServer`]
-/// - provides an Authentication policy for server requests
+/// - provides an Authentication policy for server requests, applying policy rules and
+///   remembering sign approvals for a configurable window
+/// - prompts for one approval at a time, sharing decisions between identical requests
 pub struct BitwardenSSHAgent<K, H>
 where
     K: KeyStore,
@@ -21,8 +37,16 @@ where
 {
     /// store of ssh keys. shared with the authorization policy and server.
     keystore: Arc<K>,
+    /// store of session-scoped ssh keys. shared with the authorization policy and server.
+    session_keystore: Arc<SessionKeyStore>,
+    /// locked state of the agent. shared with the server.
+    agent_lock: Arc<AgentLock>,
+    /// authorization policy. shared with the server.
+    auth_policy: Arc<AgentAuthPolicy<K, H>>,
+    /// audit log of list and sign requests, if enabled. shared with the server.
+    audit_log: Option<Arc<JsonLinesAuditLog>>,
     // the agent's server
-    server: SSHAgentServer<K, BitwardenAuthPolicy<K, H>>,
+    server: SSHAgentServer<K, AgentAuthPolicy<K, H>>,
 }
 
 impl<K, H> BitwardenSSHAgent<K, H>
@@ -30,26 +54,146 @@ where
     K: KeyStore + Send + Sync + 'static,
     H: ApprovalRequester + 'static,
 {
-    /// Creates a new [`BitwardenSSHAgent`]
+    /// Creates a new [`BitwardenSSHAgent`]. Sign approvals are not remembered until enabled with
+    /// [`Self::set_approval_cache_ttl`]. Security keys are hidden from clients until
+    /// an external signer is provided through [`Self::with_external_signer`].
     pub fn new(keystore: K, approval_handler: H) -> Self {
+        Self::with_external_signer(keystore, approval_handler, UnavailableExternalSigner)
+    }
+
+    /// Creates a new [`BitwardenSSHAgent`] that signs with security keys through
+    /// `external_signer`.
+    pub fn with_external_signer<E: ExternalSigner + 'static>(
+        keystore: K,
+        approval_handler: H,
+        external_signer: E,
+    ) -> Self {
         let keystore = Arc::new(keystore);
-        let auth_policy = Arc::new(BitwardenAuthPolicy::new(keystore.clone(), approval_handler));
-        let server = SSHAgentServer::new(keystore.clone(), auth_policy);
+        let session_keystore = Arc::new(SessionKeyStore::new());
+        let auth_policy = Arc::new(RuleAuthPolicy::new(CachingAuthPolicy::new(
+            BitwardenAuthPolicy::new(
+                keystore.clone(),
+                session_keystore.clone(),
+                ApprovalCoordinator::new(approval_handler),
+            ),
+            Duration::ZERO,
+        )));
+        let agent_lock = Arc::new(AgentLock::new());
+        let server = SSHAgentServer::new(
+            keystore.clone(),
+            session_keystore.clone(),
+            agent_lock.clone(),
+            auth_policy.clone(),
+            Arc::new(external_signer),
+        );
 
-        Self { keystore, server }
+        Self {
+            keystore,
+            session_keystore,
+            agent_lock,
+            auth_policy,
+            audit_log: None,
+            server,
+        }
     }
 
     /// Starts the ssh agent server
     pub fn start(&mut self) -> Result<()> {
-        debug!("Starting the server.");
-        self.server.start_with_default_listeners()
+        self.start_with_sockets(&[])
+    }
+
+    /// Starts the ssh agent server, also listening on the additional `sockets`.
+    pub fn start_with_sockets(&mut self, sockets: &[AgentSocket]) -> Result<()> {
+        debug!(count = sockets.len(), "Starting the server.");
+        self.server.start_with_sockets(sockets)
+    }
+
+    /// Sets the socket of an upstream agent, such as the `SSH_AUTH_SOCK` of the desktop session,
+    /// whose keys are offered alongside the vault's. Sign requests for its keys are approved like
+    /// those for vault keys, then forwarded to it. `None` removes the upstream agent. Takes effect
+    /// when the server is next started.
+    pub fn set_upstream_socket(&mut self, path: Option<PathBuf>) {
+        info!(?path, "Setting upstream agent socket.");
+        self.server.set_upstream(
+            path.map(|path| Arc::new(SocketUpstreamAgent::new(path)) as Arc<dyn UpstreamAgent>),
+        );
+    }
+
+    /// Sets the file list and sign requests are recorded to, as JSON lines. The file is rotated
+    /// once it reaches 10 MiB. `None` stops recording. Takes effect when the server is next
+    /// started.
+    pub fn set_audit_log(&mut self, path: Option<PathBuf>) {
+        info!(?path, "Setting audit log.");
+        self.audit_log = path.map(|path| Arc::new(JsonLinesAuditLog::new(path)));
+        self.server.set_audit_sink(
+            self.audit_log
+                .clone()
+                .map(|audit_log| audit_log as Arc<dyn AuditSink>),
+        );
+    }
+
+    /// # Returns
+    ///
+    /// Up to `limit` of the most recently recorded list and sign requests, newest first. Empty if
+    /// no audit log is set.
+    ///
+    /// # Errors
+    ///
+    /// If the audit log can't be read.
+    pub fn recent_audit_entries(&self, limit: usize) -> Result<Vec<AuditEntry>> {
+        match &self.audit_log {
+            Some(audit_log) => audit_log.recent(limit),
+            None => Ok(Vec::new()),
+        }
+    }
+
+    /// Sets the limits on the sign requests of each client, the sign requests awaiting approval and
+    /// the open connections. Requests beyond them fail immediately. Takes effect immediately, also
+    /// while the server is running.
+    pub fn set_rate_limits(&self, limits: RateLimits) {
+        info!(?limits, "Setting rate limits.");
+        self.server.set_rate_limits(limits);
+    }
+
+    /// # Returns
+    ///
+    /// The counters of requests and connections refused because of the rate limits since they
+    /// were last set, and of those in progress.
+    #[must_use]
+    pub fn rate_limit_stats(&self) -> RateLimitStats {
+        self.server.rate_limit_stats()
     }
 
-    /// Stops the server and clears the keystore.
+    /// Stops the server and clears the keystore, session keys, remembered approvals and any lock
+    /// placed by a client.
     pub fn stop(&mut self) {
         debug!("Stopping server and clearing keys.");
         self.server.stop();
         self.keystore.clear();
+        self.session_keystore.clear();
+        self.auth_policy.inner().invalidate();
+        self.agent_lock.clear_passphrase();
+    }
+
+    /// Locks the agent, e.g. when the vault locks. While locked, no keys are listed and all
+    /// sign requests are refused. Remembered approvals are forgotten.
+    pub fn lock(&self) {
+        self.agent_lock.lock();
+        self.auth_policy.inner().invalidate();
+    }
+
+    /// Unlocks a lock placed with [`Self::lock`]. A lock placed by a client (`ssh-add -x`)
+    /// remains until the client unlocks it.
+    pub fn unlock(&self) {
+        self.agent_lock.unlock();
+    }
+
+    /// # Returns
+    ///
+    /// `true` if the agent is locked, either by a client or with [`Self::lock`].
+    #[must_use]
+    pub fn is_locked(&self) -> bool {
+        self.agent_lock.is_locked()
     }
 
     /// # Returns
@@ -60,13 +204,31 @@ where
         self.server.is_running()
     }
 
-    /// Atomically replaces the keystore contents with the provided keys.
+    /// Atomically replaces the keystore contents with the provided keys. Remembered approvals
+    /// are forgotten.
     pub fn replace(&self, keys: Vec<K::KeyData>) -> Result<()> {
         debug!("Replacing key data.");
         self.keystore.replace(keys)?;
+        self.auth_policy.inner().invalidate();
         info!("Key data replaced.");
         Ok(())
     }
+
+    /// Sets how long sign approvals are remembered for a key, requesting process tree, SSHSIG
+    /// namespace and host. A zero `ttl` disables remembering approvals.
+    pub fn set_approval_cache_ttl(&self, ttl: Duration) {
+        info!(?ttl, "Setting approval cache TTL.");
+        self.auth_policy.inner().set_ttl(ttl);
+    }
+
+    /// Replaces the policy rules sign requests are evaluated against before asking for approval.
+    ///
+    /// # Errors
+    ///
+    /// If `rules_json` is not a valid rule set. The current rules are kept.
+    pub fn set_policy_rules(&self, rules_json: &str) -> Result<()> {
+        self.auth_policy.set_rules_json(rules_json)
+    }
 }
 
 #[cfg(test)]
@@ -84,4 +246,143 @@ mod tests {
 
         agent.stop();
     }
+
+    #[test]
+    fn stop_clears_session_keys() {
+        use ssh_key::{private::Ed25519Keypair, rand_core::OsRng};
+
+        use crate::storage::session_keystore::SessionKeyData;
+
+        let mut keystore = MockKeyStore::new();
+        keystore.expect_clear().return_const(());
+        let mut agent = BitwardenSSHAgent::new(keystore, MockApprovalRequester::new());
+        let key = ssh_key::PrivateKey::new(
+            ssh_key::private::KeypairData::Ed25519(Ed25519Keypair::random(&mut OsRng)),
+            "session",
+        )
+        .unwrap();
+        let key_data = SessionKeyData::try_from(key).unwrap();
+        let public_key = key_data.public_key().clone();
+        agent.session_keystore.insert(key_data).unwrap();
+
+        agent.stop();
+
+        assert!(!agent.session_keystore.contains(&public_key));
+    }
+
+    #[test]
+    fn lock_and_unlock() {
+        let agent = BitwardenSSHAgent::new(MockKeyStore::new(), MockApprovalRequester::new());
+
+        agent.lock();
+        assert!(agent.is_locked());
+
+        agent.unlock();
+        assert!(!agent.is_locked());
+    }
+
+    #[test]
+    fn stop_clears_client_lock() {
+        let mut keystore = MockKeyStore::new();
+        keystore.expect_clear().return_const(());
+        let mut agent = BitwardenSSHAgent::new(keystore, MockApprovalRequester::new());
+        agent.agent_lock.lock_with_passphrase(b"secret").unwrap();
+
+        agent.stop();
+
+        assert!(!agent.is_locked());
+    }
+
+    #[test]
+    fn recent_audit_entries_without_audit_log_is_empty() {
+        let agent = BitwardenSSHAgent::new(MockKeyStore::new(), MockApprovalRequester::new());
+
+        assert!(agent.recent_audit_entries(10).unwrap().is_empty());
+    }
+
+    #[tokio::test]
+    async fn replace_forgets_remembered_approvals() {
+        use crate::{
+            server::{AuthPolicy, AuthRequest, ConnectionContext, SignRequest},
+            storage::keydata::MockQueryableKeyData,
+        };
+
+        let mut keystore = MockKeyStore::new();
+        keystore.expect_get().returning(|_| {
+            let mut key_data = MockQueryableKeyData::new();
+            key_data
+                .expect_cipher_id()
+                .return_const("cipher".to_string());
+            Ok(Some(key_data))
+        });
+        keystore.expect_replace().once().returning(|_| Ok(()));
+        let mut approval_handler = MockApprovalRequester::new();
+        approval_handler
+            .expect_request_sign_approval()
+            .times(2)
+            .returning(|_| Ok(true));
+        let agent = BitwardenSSHAgent::new(keystore, approval_handler);
+        agent.set_approval_cache_ttl(Duration::from_secs(60));
+        let request = AuthRequest::Sign(SignRequest {
+            public_key: crate::crypto::PublicKey {
+                alg: "ssh-ed25519".to_string(),
+                blob: vec![1, 2, 3],
+            },
+            namespace: None,
+            sshsig: None,
+            connection: ConnectionContext {
+                process_name: Some("ssh".to_string()),
+                process_ancestry: vec![100, 1],
+                process_ancestry_names: Vec::new(),
+                process_path: None,
+                process_args: Vec::new(),
+                process_uid: None,
+                sandbox: None,
+                session_bind: None,
+            },
+            certificate: None,
+            confirm_required: false,
+            upstream_key_comment: None,
+        });
+
+        assert!(agent.auth_policy.authorize(&request).await.unwrap());
+        assert!(agent.auth_policy.authorize(&request).await.unwrap());
+        agent.replace(Vec::new()).unwrap();
+        assert!(agent.auth_policy.authorize(&request).await.unwrap());
+    }
+
+    #[tokio::test]
+    async fn policy_rules_decide_before_approval() {
+        use crate::server::{AuthPolicy, AuthRequest, ConnectionContext, SignRequest};
+
+        // Neither the keystore nor the approval handler may be consulted.
+        let agent = BitwardenSSHAgent::new(MockKeyStore::new(), MockApprovalRequester::new());
+        agent
+            .set_policy_rules(r#"{ "rules": [{ "processName": "ssh", "action": "deny" }] }"#)
+            .unwrap();
+        let request = AuthRequest::Sign(SignRequest {
+            public_key: crate::crypto::PublicKey {
+                alg: "ssh-ed25519".to_string(),
+                blob: vec![1, 2, 3],
+            },
+            namespace: None,
+            sshsig: None,
+            connection: ConnectionContext {
+                process_name: Some("ssh".to_string()),
+                process_ancestry: Vec::new(),
+                process_ancestry_names: Vec::new(),
+                process_path: None,
+                process_args: Vec::new(),
+                process_uid: None,
+                sandbox: None,
+                session_bind: None,
+            },
+            certificate: None,
+            confirm_required: false,
+            upstream_key_comment: None,
+        });
+
+        assert!(!agent.auth_policy.authorize(&request).await.unwrap());
+        assert!(agent.set_policy_rules("{}").is_err());
+    }
 }
@@ -2,9 +2,14 @@
 //! to be able to externally request approval for ssh
 //! authorization requests.
 
+use std::{collections::BTreeMap, sync::Mutex};
+
+use anyhow::anyhow;
 use thiserror::Error;
+use tokio::sync::oneshot;
+use tracing::debug;
 
-use crate::server::SignRequest;
+use crate::{authorization::ApprovalKey, server::SignRequest};
 
 /// Errors that can occur when requesting approval from an external handler.
 #[derive(Debug, Error)]
@@ -54,3 +59,352 @@ pub trait ApprovalRequester: Send + Sync {
     /// * `Ok(false)` - Request was denied.
     async fn request_list_approval(&self) -> Result<bool, ApprovalError>;
 }
+
+/// Identifies approval prompts whose decision can be shared by identical requests.
+#[derive(Debug, Clone, PartialEq, Eq, PartialOrd, Ord)]
+enum PromptKey {
+    List,
+    Sign(ApprovalKey),
+}
+
+/// An approval to prompt the user for.
+enum Prompt {
+    List,
+    Sign(Box<SignApprovalRequest>),
+}
+
+/// Requests waiting for the decision of an identical prompt that is in flight.
+type Waiters = Vec<oneshot::Sender<Result<bool, ApprovalError>>>;
+
+/// Wraps an [`ApprovalRequester`], so that the user is prompted for one approval at a time, and
+/// identical requests made while a prompt is in flight share its decision. For example, the
+/// parallel connections of a `git fetch` in a monorepo only ask the user once.
+///
+/// - Sign requests are identical if they use the same key, from the same process tree, for the
+///   same SSHSIG namespace and host
+/// - Sign requests with keys requiring confirmation of every use, or from an unknown process, are
+///   never shared
+/// - All list requests are identical
+pub struct ApprovalCoordinator<H>
+where
+    H: ApprovalRequester,
+{
+    inner: H,
+    /// Held while the user is prompted.
+    prompt_lock: tokio::sync::Mutex<()>,
+    in_flight: Mutex<BTreeMap<PromptKey, Waiters>>,
+}
+
+impl<H> ApprovalCoordinator<H>
+where
+    H: ApprovalRequester,
+{
+    /// Creates a new [`ApprovalCoordinator`], prompting the user through `inner`.
+    pub fn new(inner: H) -> Self {
+        Self {
+            inner,
+            prompt_lock: tokio::sync::Mutex::new(()),
+            in_flight: Mutex::new(BTreeMap::new()),
+        }
+    }
+
+    /// Shares the decision of an identical in-flight prompt, or prompts the user once the
+    /// preceding prompts are decided.
+    async fn coordinate(
+        &self,
+        key: Option<PromptKey>,
+        prompt: Prompt,
+    ) -> Result<bool, ApprovalError> {
+        let Some(key) = key else {
+            return self.prompt(prompt).await;
+        };
+
+        while let Some(decision) = self.join_in_flight(&key) {
+            // An error means the request that was prompted for was cancelled, e.g. because its
+            // client disconnected. One of its waiters then prompts in its place.
+            if let Ok(result) = decision.await {
+                debug!(?key, "Sharing decision of identical approval prompt.");
+                return result;
+            }
+        }
+
+        let in_flight = InFlight {
+            coordinator: self,
+            key: Some(key),
+        };
+        let result = self.prompt(prompt).await;
+        for waiter in in_flight.finish() {
+            // The waiter may have been cancelled.
+            let _ = waiter.send(share(&result));
+        }
+        result
+    }
+
+    /// # Returns
+    ///
+    /// A receiver of the decision if an identical prompt is in flight. Otherwise `None`, with the
+    /// prompt for `key` marked as in flight.
+    fn join_in_flight(
+        &self,
+        key: &PromptKey,
+    ) -> Option<oneshot::Receiver<Result<bool, ApprovalError>>> {
+        let Ok(mut in_flight) = self.in_flight.lock() else {
+            return None;
+        };
+        match in_flight.get_mut(key) {
+            Some(waiters) => {
+                let (sender, receiver) = oneshot::channel();
+                waiters.push(sender);
+                Some(receiver)
+            }
+            None => {
+                in_flight.insert(key.clone(), Vec::new());
+                None
+            }
+        }
+    }
+
+    async fn prompt(&self, prompt: Prompt) -> Result<bool, ApprovalError> {
+        let _prompt_guard = self.prompt_lock.lock().await;
+        match prompt {
+            Prompt::List => self.inner.request_list_approval().await,
+            Prompt::Sign(request) => self.inner.request_sign_approval(*request).await,
+        }
+    }
+}
+
+#[async_trait::async_trait]
+impl<H> ApprovalRequester for ApprovalCoordinator<H>
+where
+    H: ApprovalRequester,
+{
+    async fn request_sign_approval(
+        &self,
+        request: SignApprovalRequest,
+    ) -> Result<bool, ApprovalError> {
+        let key = ApprovalKey::from_sign_request(&request.sign_request).map(PromptKey::Sign);
+        self.coordinate(key, Prompt::Sign(Box::new(request))).await
+    }
+
+    async fn request_list_approval(&self) -> Result<bool, ApprovalError> {
+        self.coordinate(Some(PromptKey::List), Prompt::List).await
+    }
+}
+
+/// Marks a prompt as in flight. Unless finished, it is unmarked when dropped, releasing its
+/// waiters without a decision.
+struct InFlight<'a, H>
+where
+    H: ApprovalRequester,
+{
+    coordinator: &'a ApprovalCoordinator<H>,
+    key: Option<PromptKey>,
+}
+
+impl<H> InFlight<'_, H>
+where
+    H: ApprovalRequester,
+{
+    /// Unmarks the prompt.
+    ///
+    /// # Returns
+    ///
+    /// The requests waiting for its decision.
+    fn finish(mut self) -> Waiters {
+        self.remove()
+    }
+
+    fn remove(&mut self) -> Waiters {
+        let Some(key) = self.key.take() else {
+            return Vec::new();
+        };
+        self.coordinator
+            .in_flight
+            .lock()
+            .ok()
+            .and_then(|mut in_flight| in_flight.remove(&key))
+            .unwrap_or_default()
+    }
+}
+
+impl<H> Drop for InFlight<'_, H>
+where
+    H: ApprovalRequester,
+{
+    fn drop(&mut self) {
+        self.remove();
+    }
+}
+
+/// Copies a decision for a waiting request. Handler failures are passed on by their message.
+fn share(result: &Result<bool, ApprovalError>) -> Result<bool, ApprovalError> {
+    match result {
+        Ok(is_approved) => Ok(*is_approved),
+        Err(ApprovalError::Timeout) => Err(ApprovalError::Timeout),
+        Err(ApprovalError::HandlerFailed(error)) => {
+            Err(ApprovalError::HandlerFailed(anyhow!("{error:#}")))
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::{
+        sync::atomic::{AtomicUsize, Ordering},
+        time::Duration,
+    };
+
+    use super::*;
+    use crate::{crypto::PublicKey, server::ConnectionContext};
+
+    const PROMPT_DURATION: Duration = Duration::from_millis(50);
+
+    /// Approves every request after [`PROMPT_DURATION`], counting the prompts.
+    #[derive(Default)]
+    struct CountingRequester {
+        prompts: AtomicUsize,
+        active: AtomicUsize,
+        max_active: AtomicUsize,
+    }
+
+    impl CountingRequester {
+        async fn prompt(&self) -> Result<bool, ApprovalError> {
+            self.prompts.fetch_add(1, Ordering::SeqCst);
+            let active = self.active.fetch_add(1, Ordering::SeqCst) + 1;
+            self.max_active.fetch_max(active, Ordering::SeqCst);
+            tokio::time::sleep(PROMPT_DURATION).await;
+            self.active.fetch_sub(1, Ordering::SeqCst);
+            Ok(true)
+        }
+    }
+
+    #[async_trait::async_trait]
+    impl ApprovalRequester for CountingRequester {
+        async fn request_sign_approval(
+            &self,
+            _: SignApprovalRequest,
+        ) -> Result<bool, ApprovalError> {
+            self.prompt().await
+        }
+
+        async fn request_list_approval(&self) -> Result<bool, ApprovalError> {
+            self.prompt().await
+        }
+    }
+
+    fn make_request(key_byte: u8, confirm_required: bool) -> SignApprovalRequest {
+        SignApprovalRequest {
+            sign_request: SignRequest {
+                public_key: PublicKey {
+                    alg: "ssh-ed25519".to_string(),
+                    blob: vec![key_byte],
+                },
+                namespace: None,
+                sshsig: None,
+                connection: ConnectionContext {
+                    process_name: Some("ssh".to_string()),
+                    process_ancestry: vec![100, 1],
+                    process_ancestry_names: vec!["git".to_string(), "bash".to_string()],
+                    process_path: None,
+                    process_args: Vec::new(),
+                    process_uid: None,
+                    sandbox: None,
+                    session_bind: None,
+                },
+                certificate: None,
+                confirm_required,
+                upstream_key_comment: None,
+            },
+            cipher_id: None,
+        }
+    }
+
+    #[tokio::test]
+    async fn identical_sign_requests_share_one_prompt() {
+        let coordinator = ApprovalCoordinator::new(CountingRequester::default());
+
+        let (first, second) = tokio::join!(
+            coordinator.request_sign_approval(make_request(1, false)),
+            coordinator.request_sign_approval(make_request(1, false)),
+        );
+
+        assert!(first.unwrap());
+        assert!(second.unwrap());
+        assert_eq!(coordinator.inner.prompts.load(Ordering::SeqCst), 1);
+    }
+
+    #[tokio::test]
+    async fn different_sign_requests_are_prompted_one_at_a_time() {
+        let coordinator = ApprovalCoordinator::new(CountingRequester::default());
+
+        let (first, second) = tokio::join!(
+            coordinator.request_sign_approval(make_request(1, false)),
+            coordinator.request_sign_approval(make_request(2, false)),
+        );
+
+        assert!(first.unwrap());
+        assert!(second.unwrap());
+        assert_eq!(coordinator.inner.prompts.load(Ordering::SeqCst), 2);
+        assert_eq!(coordinator.inner.max_active.load(Ordering::SeqCst), 1);
+    }
+
+    #[tokio::test]
+    async fn sign_requests_requiring_confirmation_are_not_shared() {
+        let coordinator = ApprovalCoordinator::new(CountingRequester::default());
+
+        let (first, second) = tokio::join!(
+            coordinator.request_sign_approval(make_request(1, true)),
+            coordinator.request_sign_approval(make_request(1, true)),
+        );
+
+        assert!(first.unwrap());
+        assert!(second.unwrap());
+        assert_eq!(coordinator.inner.prompts.load(Ordering::SeqCst), 2);
+        assert_eq!(coordinator.inner.max_active.load(Ordering::SeqCst), 1);
+    }
+
+    #[tokio::test]
+    async fn list_requests_share_one_prompt() {
+        let coordinator = ApprovalCoordinator::new(CountingRequester::default());
+
+        let (first, second) = tokio::join!(
+            coordinator.request_list_approval(),
+            coordinator.request_list_approval(),
+        );
+
+        assert!(first.unwrap());
+        assert!(second.unwrap());
+        assert_eq!(coordinator.inner.prompts.load(Ordering::SeqCst), 1);
+    }
+
+    #[tokio::test]
+    async fn waiter_prompts_when_shared_request_is_cancelled() {
+        let coordinator = ApprovalCoordinator::new(CountingRequester::default());
+
+        let (cancelled, waiter) = tokio::join!(
+            tokio::time::timeout(
+                PROMPT_DURATION / 2,
+                coordinator.request_sign_approval(make_request(1, false)),
+            ),
+            coordinator.request_sign_approval(make_request(1, false)),
+        );
+
+        assert!(cancelled.is_err());
+        assert!(waiter.unwrap());
+        assert_eq!(coordinator.inner.prompts.load(Ordering::SeqCst), 2);
+    }
+
+    #[test]
+    fn shared_handler_failure_keeps_message() {
+        let result = Err(ApprovalError::HandlerFailed(anyhow!("window closed")));
+
+        let Err(ApprovalError::HandlerFailed(error)) = share(&result) else {
+            panic!("expected handler failure");
+        };
+        assert_eq!(error.to_string(), "window closed");
+        assert!(matches!(
+            share(&Err(ApprovalError::Timeout)),
+            Err(ApprovalError::Timeout)
+        ));
+    }
+}
@@ -0,0 +1,381 @@
+//! Audit records of the list and sign operations requested from the agent, and their decisions.
+
+use std::{
+    fs::{self, OpenOptions},
+    io::{ErrorKind, Write as _},
+    path::PathBuf,
+    sync::Mutex,
+    time::{SystemTime, UNIX_EPOCH},
+};
+
+use anyhow::{anyhow, Result};
+use serde::{Deserialize, Serialize};
+use tracing::warn;
+
+/// Size at which the audit log file is rotated.
+const DEFAULT_MAX_FILE_LEN: u64 = 10 * 1024 * 1024;
+/// Number of rotated audit log files kept besides the current one.
+const DEFAULT_ROTATED_FILES: usize = 4;
+
+/// The operation an [`AuditEntry`] records.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum AuditOperation {
+    List,
+    Sign,
+}
+
+/// The authorization decision an [`AuditEntry`] records.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum AuditDecision {
+    Allowed,
+    Denied,
+    /// The request could not be authorized, e.g. because the approval prompt timed out.
+    Error,
+}
+
+/// Why a request was denied without asking the authorization policy.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub enum AuditDenialReason {
+    /// The key is not visible on the listener the request was made on.
+    KeyFilter,
+    /// The destination constraints of the key don't permit the hosts the connection is bound to.
+    KeyConstraints,
+    /// The client made too many requests.
+    RateLimit,
+    /// The key is a security key, and no external signer is available to sign with it.
+    NoExternalSigner,
+}
+
+/// A list or sign operation requested by a client, and whether it was authorized.
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub struct AuditEntry {
+    /// Milliseconds since the Unix epoch.
+    pub timestamp_ms: u64,
+    pub operation: AuditOperation,
+    /// PID of the requesting process. `None` when unavailable.
+    pub pid: Option<u32>,
+    /// Name of the requesting process. `None` when unavailable.
+    pub process_name: Option<String>,
+    /// SHA-256 fingerprint of the key to sign with. `None` for list operations.
+    pub key_fingerprint: Option<String>,
+    /// Vault cipher ID of the key to sign with. `None` for list operations and keys that aren't in
+    /// the vault.
+    pub cipher_id: Option<String>,
+    /// SSHSIG namespace of the data to sign. `None` for authentications to a server.
+    pub namespace: Option<String>,
+    /// Fingerprint of the host the connection is bound to. `None` if it is not bound.
+    pub host_fingerprint: Option<String>,
+    /// Whether the request was made over a forwarded connection.
+    pub is_forwarding: bool,
+    pub decision: AuditDecision,
+    /// Why the request was denied without asking for approval. `None` if it was authorized by
+    /// the authorization policy.
+    pub denied_by: Option<AuditDenialReason>,
+}
+
+/// # Returns
+///
+/// The current time as milliseconds since the Unix epoch.
+pub(crate) fn unix_time_ms() -> u64 {
+    SystemTime::now()
+        .duration_since(UNIX_EPOCH)
+        .map(|elapsed| u64::try_from(elapsed.as_millis()).unwrap_or(u64::MAX))
+        .unwrap_or_default()
+}
+
+/// Destination of [`AuditEntry`]s recorded by the server.
+#[cfg_attr(test, mockall::automock)]
+pub trait AuditSink: Send + Sync {
+    /// Records an audit entry.
+    ///
+    /// # Errors
+    ///
+    /// If the entry could not be recorded.
+    fn record(&self, entry: &AuditEntry) -> Result<()>;
+}
+
+/// [`AuditSink`] appending entries to a JSON-lines file, one entry per line.
+///
+/// Once the file would exceed its maximum length it is renamed with a `.1` suffix, shifting
+/// older files up to the number of rotated files kept. The oldest file is deleted.
+pub struct JsonLinesAuditLog {
+    path: PathBuf,
+    max_file_len: u64,
+    rotated_files: usize,
+    /// Serializes appending, rotating and reading the files.
+    lock: Mutex<()>,
+}
+
+impl JsonLinesAuditLog {
+    /// Creates a new [`JsonLinesAuditLog`] writing to `path`, rotated at 10 MiB with 4 rotated
+    /// files kept.
+    #[must_use]
+    pub fn new(path: PathBuf) -> Self {
+        Self {
+            path,
+            max_file_len: DEFAULT_MAX_FILE_LEN,
+            rotated_files: DEFAULT_ROTATED_FILES,
+            lock: Mutex::new(()),
+        }
+    }
+
+    /// Rotates the file once it would exceed `max_file_len` bytes, keeping `rotated_files` older
+    /// files.
+    #[must_use]
+    pub fn with_rotation(mut self, max_file_len: u64, rotated_files: usize) -> Self {
+        self.max_file_len = max_file_len;
+        self.rotated_files = rotated_files;
+        self
+    }
+
+    /// # Returns
+    ///
+    /// Up to `limit` of the most recent entries, newest first. Lines that can't be parsed, e.g.
+    /// because writing them was interrupted, are skipped.
+    ///
+    /// # Errors
+    ///
+    /// If a file exists, but can't be read.
+    pub fn recent(&self, limit: usize) -> Result<Vec<AuditEntry>> {
+        let _guard = self
+            .lock
+            .lock()
+            .map_err(|_| anyhow!("Audit log lock poisoned"))?;
+
+        let mut entries = Vec::new();
+        for index in 0..=self.rotated_files {
+            if entries.len() >= limit {
+                break;
+            }
+            let contents = match fs::read_to_string(self.file_path(index)) {
+                Ok(contents) => contents,
+                Err(error) if error.kind() == ErrorKind::NotFound => continue,
+                Err(error) => return Err(error.into()),
+            };
+            for line in contents.lines().rev().filter(|line| !line.is_empty()) {
+                if entries.len() >= limit {
+                    break;
+                }
+                match serde_json::from_str(line) {
+                    Ok(entry) => entries.push(entry),
+                    Err(error) => warn!(%error, "Skipping malformed audit log line"),
+                }
+            }
+        }
+
+        Ok(entries)
+    }
+
+    /// # Returns
+    ///
+    /// The path of the current file for `index` 0, and of the rotated files after it.
+    fn file_path(&self, index: usize) -> PathBuf {
+        if index == 0 {
+            return self.path.clone();
+        }
+        let mut path = self.path.clone().into_os_string();
+        path.push(format!(".{index}"));
+        path.into()
+    }
+
+    fn rotate(&self) -> Result<()> {
+        if self.rotated_files == 0 {
+            fs::remove_file(&self.path)?;
+            return Ok(());
+        }
+
+        for index in (1..self.rotated_files).rev() {
+            let from = self.file_path(index);
+            if from.exists() {
+                fs::rename(from, self.file_path(index + 1))?;
+            }
+        }
+        fs::rename(&self.path, self.file_path(1))?;
+        Ok(())
+    }
+}
+
+impl AuditSink for JsonLinesAuditLog {
+    fn record(&self, entry: &AuditEntry) -> Result<()> {
+        let mut line = serde_json::to_vec(entry)?;
+        line.push(b'\n');
+
+        let _guard = self
+            .lock
+            .lock()
+            .map_err(|_| anyhow!("Audit log lock poisoned"))?;
+
+        let len = fs::metadata(&self.path).map_or(0, |metadata| metadata.len());
+        if len > 0 && len + line.len() as u64 > self.max_file_len {
+            self.rotate()?;
+        }
+
+        let mut options = OpenOptions::new();
+        options.create(true).append(true);
+        // The log reveals which processes used which keys, so it is only readable by the user.
+        #[cfg(unix)]
+        std::os::unix::fs::OpenOptionsExt::mode(&mut options, 0o600);
+
+        options.open(&self.path)?.write_all(&line)?;
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn temp_log_path(name: &str) -> PathBuf {
+        let path = std::env::temp_dir().join(format!(
+            "bitwarden-ssh-audit-{}-{name}.jsonl",
+            std::process::id()
+        ));
+        remove_log_files(&path);
+        path
+    }
+
+    fn remove_log_files(path: &std::path::Path) {
+        let log = JsonLinesAuditLog::new(path.to_path_buf());
+        for index in 0..=DEFAULT_ROTATED_FILES {
+            let _ = fs::remove_file(log.file_path(index));
+        }
+    }
+
+    fn make_entry(timestamp_ms: u64) -> AuditEntry {
+        AuditEntry {
+            timestamp_ms,
+            operation: AuditOperation::Sign,
+            pid: Some(1234),
+            process_name: Some("ssh".to_string()),
+            key_fingerprint: Some("SHA256:key".to_string()),
+            cipher_id: Some("cipher-1".to_string()),
+            namespace: Some("git".to_string()),
+            host_fingerprint: Some("SHA256:host".to_string()),
+            is_forwarding: false,
+            decision: AuditDecision::Allowed,
+            denied_by: None,
+        }
+    }
+
+    #[test]
+    fn entry_serializes_as_camel_case_json() {
+        let json = serde_json::to_value(make_entry(1)).unwrap();
+
+        assert_eq!(
+            json,
+            serde_json::json!({
+                "timestampMs": 1,
+                "operation": "sign",
+                "pid": 1234,
+                "processName": "ssh",
+                "keyFingerprint": "SHA256:key",
+                "cipherId": "cipher-1",
+                "namespace": "git",
+                "hostFingerprint": "SHA256:host",
+                "isForwarding": false,
+                "decision": "allowed",
+                "deniedBy": null,
+            })
+        );
+    }
+
+    #[test]
+    fn entry_without_denial_reason_deserializes() {
+        let mut json = serde_json::to_value(make_entry(1)).unwrap();
+        json.as_object_mut().unwrap().remove("deniedBy");
+
+        let entry: AuditEntry = serde_json::from_value(json).unwrap();
+
+        assert_eq!(entry, make_entry(1));
+    }
+
+    #[test]
+    fn denial_reason_serializes_as_camel_case() {
+        assert_eq!(
+            serde_json::to_value(AuditDenialReason::KeyConstraints).unwrap(),
+            serde_json::json!("keyConstraints")
+        );
+    }
+
+    #[test]
+    fn records_one_line_per_entry_and_reads_newest_first() {
+        let path = temp_log_path("lines");
+        let log = JsonLinesAuditLog::new(path.clone());
+
+        for timestamp_ms in 1..=3 {
+            log.record(&make_entry(timestamp_ms)).unwrap();
+        }
+
+        assert_eq!(fs::read_to_string(&path).unwrap().lines().count(), 3);
+        assert_eq!(log.recent(2).unwrap(), vec![make_entry(3), make_entry(2)]);
+        assert_eq!(log.recent(10).unwrap().len(), 3);
+        remove_log_files(&path);
+    }
+
+    #[test]
+    fn rotates_when_file_would_exceed_maximum() {
+        let path = temp_log_path("rotation");
+        let line_len = serde_json::to_vec(&make_entry(1)).unwrap().len() as u64 + 1;
+        let log = JsonLinesAuditLog::new(path.clone()).with_rotation(2 * line_len, 2);
+
+        for timestamp_ms in 1..=7 {
+            log.record(&make_entry(timestamp_ms)).unwrap();
+        }
+
+        // Two entries per file, the oldest file with entries 1 and 2 was deleted.
+        assert_eq!(fs::read_to_string(&path).unwrap().lines().count(), 1);
+        assert!(log.file_path(1).exists());
+        assert!(log.file_path(2).exists());
+        assert!(!log.file_path(3).exists());
+        let timestamps: Vec<u64> = log
+            .recent(10)
+            .unwrap()
+            .iter()
+            .map(|entry| entry.timestamp_ms)
+            .collect();
+        assert_eq!(timestamps, vec![7, 6, 5, 4, 3]);
+        remove_log_files(&path);
+    }
+
+    #[test]
+    fn recent_skips_malformed_lines() {
+        let path = temp_log_path("malformed");
+        let log = JsonLinesAuditLog::new(path.clone());
+        log.record(&make_entry(1)).unwrap();
+        fs::OpenOptions::new()
+            .append(true)
+            .open(&path)
+            .unwrap()
+            .write_all(b"{\"timestampMs\":")
+            .unwrap();
+
+        assert_eq!(log.recent(10).unwrap(), vec![make_entry(1)]);
+        remove_log_files(&path);
+    }
+
+    #[test]
+    fn recent_without_file_returns_empty() {
+        let log = JsonLinesAuditLog::new(temp_log_path("missing"));
+
+        assert!(log.recent(10).unwrap().is_empty());
+    }
+
+    #[cfg(unix)]
+    #[test]
+    fn file_is_only_accessible_by_user() {
+        use std::os::unix::fs::PermissionsExt as _;
+
+        let path = temp_log_path("permissions");
+        JsonLinesAuditLog::new(path.clone())
+            .record(&make_entry(1))
+            .unwrap();
+
+        let mode = fs::metadata(&path).unwrap().permissions().mode();
+        assert_eq!(mode & 0o777, 0o600);
+        remove_log_files(&path);
+    }
+}
@@ -1,15 +1,19 @@
 //! Bitwarden's auth policy for SSH agent operations.
 
-use std::sync::Arc;
+use std::{
+    collections::BTreeMap,
+    sync::{Arc, Mutex},
+    time::{Duration, Instant},
+};
 
 use thiserror::Error;
-use tracing::{error, info};
+use tracing::{debug, error, info};
 
 use crate::{
     approval::{ApprovalError, ApprovalRequester, SignApprovalRequest},
-    crypto::QueryableKeyData,
-    server::{AuthPolicy, AuthRequest},
-    storage::keystore::KeyStore,
+    crypto::{PublicKey, QueryableKeyData},
+    server::{AuthPolicy, AuthRequest, IdentityRequest, SIGNamespace, SignRequest},
+    storage::{keystore::KeyStore, session_keystore::SessionKeyStore},
 };
 
 /// Errors that can occur during authorization of SSH agent operations.
@@ -31,7 +35,8 @@ pub enum AuthError {
 /// Bitwarden's SSH operation authorization policy:
 ///
 /// - Allows listing keys when the keystore is initialized and otherwise requests approval.
-/// - Always requires approval for signing operations
+/// - Always requires approval for signing operations, including with session keys
+/// - Allows adding and removing session keys, unless requested over a forwarded connection
 /// - Delegates approval decisions to the provided handler
 pub struct BitwardenAuthPolicy<K, H>
 where
@@ -39,6 +44,7 @@ where
     H: ApprovalRequester,
 {
     keystore: Arc<K>,
+    session_keystore: Arc<SessionKeyStore>,
     approval_handler: H,
 }
 
@@ -47,12 +53,34 @@ where
     K: KeyStore,
     H: ApprovalRequester,
 {
-    pub fn new(keystore: Arc<K>, approval_handler: H) -> Self {
+    pub fn new(
+        keystore: Arc<K>,
+        session_keystore: Arc<SessionKeyStore>,
+        approval_handler: H,
+    ) -> Self {
         Self {
             keystore,
+            session_keystore,
             approval_handler,
         }
     }
+
+    fn authorize_identity_request(request: &IdentityRequest) -> bool {
+        // A remote host the key agent was forwarded to must not be able to change the keys
+        // offered to the local user.
+        let is_forwarding = request
+            .connection
+            .session_bind
+            .as_ref()
+            .is_some_and(|s| s.is_forwarding);
+
+        if is_forwarding {
+            info!(operation = ?request.operation, "Denying identity request over forwarded connection.");
+        } else {
+            info!(operation = ?request.operation, "Allowing identity request.");
+        }
+        !is_forwarding
+    }
 }
 
 #[async_trait::async_trait]
@@ -84,6 +112,10 @@ where
             AuthRequest::Sign(sign_request) => {
                 let cipher_id = match self.keystore.get(&sign_request.public_key) {
                     Ok(Some(key_data)) => Some(key_data.cipher_id().clone()),
+                    // Session keys aren't in the vault, so have no cipher.
+                    Ok(None) if self.session_keystore.contains(&sign_request.public_key) => None,
+                    // Nor are keys of the upstream agent.
+                    Ok(None) if sign_request.upstream_key_comment.is_some() => None,
                     Ok(None) => {
                         return Err(AuthError::KeyNotFound);
                     }
@@ -107,7 +139,147 @@ where
                         error!(%error, public_key = %sign_request.public_key, "Sign request authorization error.");
                     })
             }
+            AuthRequest::ModifyIdentities(identity_request) => {
+                Ok(Self::authorize_identity_request(identity_request))
+            }
+        }
+    }
+}
+
+/// Identifies sign requests that share an approval: the same key, used by the same process tree
+/// for the same purpose.
+#[derive(Debug, Clone, PartialEq, Eq, PartialOrd, Ord)]
+pub(crate) struct ApprovalKey {
+    public_key: PublicKey,
+    process_name: Option<String>,
+    // The requesting process itself is left out, as e.g. every signature of a `git rebase` is
+    // made by a new `ssh-keygen` process.
+    process_ancestry: Vec<u32>,
+    namespace: Option<SIGNamespace>,
+    host_fingerprint: Option<String>,
+}
+
+impl ApprovalKey {
+    /// # Returns
+    ///
+    /// `None` if approvals of the request must not be remembered, because the key requires
+    /// confirmation of every use or the requesting process is unknown.
+    pub(crate) fn from_sign_request(request: &SignRequest) -> Option<Self> {
+        if request.confirm_required || request.connection.process_ancestry.is_empty() {
+            return None;
+        }
+        Some(Self {
+            public_key: request.public_key.clone(),
+            process_name: request.connection.process_name.clone(),
+            process_ancestry: request.connection.process_ancestry.clone(),
+            namespace: request.namespace.clone(),
+            host_fingerprint: request
+                .connection
+                .session_bind
+                .as_ref()
+                .map(|s| s.host_fingerprint.clone()),
+        })
+    }
+}
+
+/// Wraps an [`AuthPolicy`] and remembers its sign approvals for a configurable window, so that
+/// e.g. a `git rebase` signing every commit only asks the user once.
+///
+/// - Only approvals are remembered, denials and errors are always passed through
+/// - Keys added with a confirm constraint are never approved from the cache
+/// - A window of zero disables the cache
+pub struct CachingAuthPolicy<P>
+where
+    P: AuthPolicy,
+{
+    inner: P,
+    ttl: Mutex<Duration>,
+    approvals: Mutex<BTreeMap<ApprovalKey, Instant>>,
+}
+
+impl<P> CachingAuthPolicy<P>
+where
+    P: AuthPolicy,
+{
+    /// Creates a new [`CachingAuthPolicy`], remembering approvals of `inner` for `ttl`.
+    pub fn new(inner: P, ttl: Duration) -> Self {
+        Self {
+            inner,
+            ttl: Mutex::new(ttl),
+            approvals: Mutex::new(BTreeMap::new()),
+        }
+    }
+
+    /// Changes how long approvals are remembered for. Remembered approvals are forgotten.
+    pub fn set_ttl(&self, ttl: Duration) {
+        if let Ok(mut current) = self.ttl.lock() {
+            *current = ttl;
+        }
+        self.invalidate();
+    }
+
+    /// Forgets all remembered approvals.
+    pub fn invalidate(&self) {
+        if let Ok(mut approvals) = self.approvals.lock() {
+            approvals.clear();
+        }
+    }
+
+    fn ttl(&self) -> Duration {
+        self.ttl.lock().map(|ttl| *ttl).unwrap_or_default()
+    }
+
+    fn is_approved(&self, key: &ApprovalKey) -> bool {
+        let Ok(mut approvals) = self.approvals.lock() else {
+            return false;
+        };
+        let now = Instant::now();
+        approvals.retain(|_, expires_at| *expires_at > now);
+        approvals.contains_key(key)
+    }
+
+    fn remember_approval(&self, key: ApprovalKey) {
+        let ttl = self.ttl();
+        if ttl.is_zero() {
+            return;
+        }
+        if let Ok(mut approvals) = self.approvals.lock() {
+            approvals.insert(key, Instant::now() + ttl);
+        }
+    }
+}
+
+#[async_trait::async_trait]
+impl<P> AuthPolicy for CachingAuthPolicy<P>
+where
+    P: AuthPolicy,
+{
+    async fn authorize(&self, request: &AuthRequest) -> Result<bool, AuthError> {
+        let AuthRequest::Sign(sign_request) = request else {
+            return self.inner.authorize(request).await;
+        };
+
+        let key = ApprovalKey::from_sign_request(sign_request);
+        if key.as_ref().is_some_and(|key| self.is_approved(key)) {
+            debug!(public_key = %sign_request.public_key, "Allowing sign request from remembered approval.");
+            return Ok(true);
+        }
+
+        let is_approved = self.inner.authorize(request).await?;
+        if let Some(key) = key.filter(|_| is_approved) {
+            self.remember_approval(key);
+        }
+        Ok(is_approved)
+    }
+
+    fn may_prompt(&self, request: &AuthRequest) -> bool {
+        if let AuthRequest::Sign(sign_request) = request {
+            let key = ApprovalKey::from_sign_request(sign_request);
+            if key.as_ref().is_some_and(|key| self.is_approved(key)) {
+                return false;
+            }
         }
+        self.inner.may_prompt(request)
     }
 }
 
@@ -119,8 +291,12 @@ mod tests {
     use super::*;
     use crate::{
         approval::{ApprovalError, MockApprovalRequester},
-        server::{ConnectionContext, SIGNamespace, SessionBindContext},
-        storage::{keydata::MockQueryableKeyData, keystore::MockKeyStore},
+        server::{ConnectionContext, IdentityOperation, SIGNamespace, SessionBindContext},
+        storage::{
+            keydata::MockQueryableKeyData,
+            keystore::MockKeyStore,
+            session_keystore::{SessionKeyData, SessionKeyStore},
+        },
     };
 
     fn create_stub_public_key() -> crate::crypto::PublicKey {
@@ -139,6 +315,10 @@ mod tests {
             public_key,
             connection,
             namespace,
+            sshsig: None,
+            certificate: None,
+            confirm_required: false,
+            upstream_key_comment: None,
         })
     }
 
@@ -147,6 +327,12 @@ mod tests {
             public_key,
             ConnectionContext {
                 process_name: Some(TEST_PROCESS_NAME.to_string()),
+                process_ancestry: Vec::new(),
+                process_ancestry_names: Vec::new(),
+                process_path: None,
+                process_args: Vec::new(),
+                process_uid: None,
+                sandbox: None,
                 session_bind: None,
             },
             None,
@@ -181,7 +367,11 @@ mod tests {
         // Approval handler must NOT be called when keystore is already initialized.
         let approval_handler = MockApprovalRequester::new();
 
-        let policy = BitwardenAuthPolicy::new(Arc::new(keystore), approval_handler);
+        let policy = BitwardenAuthPolicy::new(
+            Arc::new(keystore),
+            Arc::new(SessionKeyStore::new()),
+            approval_handler,
+        );
 
         let result = policy.authorize(&AuthRequest::List).await;
 
@@ -201,7 +391,11 @@ mod tests {
             .once()
             .returning(|| Ok(true));
 
-        let policy = BitwardenAuthPolicy::new(Arc::new(keystore), approval_handler);
+        let policy = BitwardenAuthPolicy::new(
+            Arc::new(keystore),
+            Arc::new(SessionKeyStore::new()),
+            approval_handler,
+        );
 
         let result = policy.authorize(&AuthRequest::List).await;
 
@@ -221,7 +415,11 @@ mod tests {
             .once()
             .returning(|| Ok(false));
 
-        let policy = BitwardenAuthPolicy::new(Arc::new(keystore), approval_handler);
+        let policy = BitwardenAuthPolicy::new(
+            Arc::new(keystore),
+            Arc::new(SessionKeyStore::new()),
+            approval_handler,
+        );
 
         let result = policy.authorize(&AuthRequest::List).await;
 
@@ -244,7 +442,11 @@ mod tests {
             .times(1)
             .returning(|_| Ok(None));
 
-        let policy = BitwardenAuthPolicy::new(Arc::new(keystore), approval_handler);
+        let policy = BitwardenAuthPolicy::new(
+            Arc::new(keystore),
+            Arc::new(SessionKeyStore::new()),
+            approval_handler,
+        );
 
         let request = create_default_test_sign_request(test_pub_key);
         let result = policy.authorize(&request).await;
@@ -268,7 +470,11 @@ mod tests {
             .times(1)
             .returning(|_| Err(anyhow!("Keystore error")));
 
-        let policy = BitwardenAuthPolicy::new(Arc::new(keystore), approval_handler);
+        let policy = BitwardenAuthPolicy::new(
+            Arc::new(keystore),
+            Arc::new(SessionKeyStore::new()),
+            approval_handler,
+        );
 
         let request = create_default_test_sign_request(test_pub_key);
         let result = policy.authorize(&request).await;
@@ -294,7 +500,11 @@ mod tests {
             .times(1)
             .returning(|_| Ok(true));
 
-        let policy = BitwardenAuthPolicy::new(Arc::new(keystore), approval_handler);
+        let policy = BitwardenAuthPolicy::new(
+            Arc::new(keystore),
+            Arc::new(SessionKeyStore::new()),
+            approval_handler,
+        );
 
         let request = create_default_test_sign_request(test_pub_key);
         let result = policy.authorize(&request).await;
@@ -319,7 +529,11 @@ mod tests {
             .times(1)
             .returning(|_| Ok(false));
 
-        let policy = BitwardenAuthPolicy::new(Arc::new(keystore), approval_handler);
+        let policy = BitwardenAuthPolicy::new(
+            Arc::new(keystore),
+            Arc::new(SessionKeyStore::new()),
+            approval_handler,
+        );
 
         let request = create_default_test_sign_request(test_pub_key);
         let result = policy.authorize(&request).await;
@@ -344,7 +558,11 @@ mod tests {
             .times(1)
             .returning(|_| Err(ApprovalError::HandlerFailed(anyhow!("Handler failed"))));
 
-        let policy = BitwardenAuthPolicy::new(Arc::new(keystore), approval_handler);
+        let policy = BitwardenAuthPolicy::new(
+            Arc::new(keystore),
+            Arc::new(SessionKeyStore::new()),
+            approval_handler,
+        );
 
         let request = create_default_test_sign_request(test_pub_key);
         let result = policy.authorize(&request).await;
@@ -390,15 +608,26 @@ mod tests {
             .times(1)
             .returning(|_| Ok(true));
 
-        let policy = BitwardenAuthPolicy::new(Arc::new(keystore), approval_handler);
+        let policy = BitwardenAuthPolicy::new(
+            Arc::new(keystore),
+            Arc::new(SessionKeyStore::new()),
+            approval_handler,
+        );
 
         let request = create_test_sign_request(
             test_pub_key,
             ConnectionContext {
                 process_name: Some("test-process".to_string()),
+                process_ancestry: Vec::new(),
+                process_ancestry_names: Vec::new(),
+                process_path: None,
+                process_args: Vec::new(),
+                process_uid: None,
+                sandbox: None,
                 session_bind: Some(SessionBindContext {
                     is_forwarding: true,
                     host_fingerprint: "test-fingerprint".to_string(),
+                    hops: Vec::new(),
                 }),
             },
             Some(SIGNamespace::Unsupported),
@@ -407,4 +636,339 @@ mod tests {
 
         assert!(matches!(result, Ok(true)), "Should pass context correctly");
     }
+
+    fn create_identity_request(is_forwarding: Option<bool>) -> AuthRequest {
+        AuthRequest::ModifyIdentities(IdentityRequest {
+            operation: IdentityOperation::RemoveAll,
+            connection: ConnectionContext {
+                process_name: Some(TEST_PROCESS_NAME.to_string()),
+                process_ancestry: Vec::new(),
+                process_ancestry_names: Vec::new(),
+                process_path: None,
+                process_args: Vec::new(),
+                process_uid: None,
+                sandbox: None,
+                session_bind: is_forwarding.map(|is_forwarding| SessionBindContext {
+                    is_forwarding,
+                    host_fingerprint: "test-fingerprint".to_string(),
+                    hops: Vec::new(),
+                }),
+            },
+        })
+    }
+
+    #[tokio::test]
+    async fn test_authorize_sign_session_key_requests_approval_without_cipher() {
+        use ssh_key::{private::Ed25519Keypair, rand_core::OsRng};
+
+        let mut keystore = MockKeyStore::new();
+        let mut approval_handler = MockApprovalRequester::new();
+
+        let key = ssh_key::PrivateKey::new(
+            ssh_key::private::KeypairData::Ed25519(Ed25519Keypair::random(&mut OsRng)),
+            "session",
+        )
+        .unwrap();
+        let key_data = SessionKeyData::try_from(key).unwrap();
+        let public_key = key_data.public_key().clone();
+        let session_keystore = SessionKeyStore::new();
+        session_keystore.insert(key_data).unwrap();
+
+        keystore.expect_get().times(1).returning(|_| Ok(None));
+        approval_handler
+            .expect_request_sign_approval()
+            .withf(|req| req.cipher_id.is_none())
+            .times(1)
+            .returning(|_| Ok(true));
+
+        let policy = BitwardenAuthPolicy::new(
+            Arc::new(keystore),
+            Arc::new(session_keystore),
+            approval_handler,
+        );
+
+        let request = create_default_test_sign_request(public_key);
+        let result = policy.authorize(&request).await;
+
+        assert!(matches!(result, Ok(true)));
+    }
+
+    #[tokio::test]
+    async fn test_authorize_sign_upstream_key_requests_approval_without_cipher() {
+        let mut keystore = MockKeyStore::new();
+        let mut approval_handler = MockApprovalRequester::new();
+
+        keystore.expect_get().times(1).returning(|_| Ok(None));
+        approval_handler
+            .expect_request_sign_approval()
+            .withf(|req| {
+                req.cipher_id.is_none()
+                    && req.sign_request.upstream_key_comment.as_deref() == Some("user@laptop")
+            })
+            .times(1)
+            .returning(|_| Ok(true));
+
+        let policy = BitwardenAuthPolicy::new(
+            Arc::new(keystore),
+            Arc::new(SessionKeyStore::new()),
+            approval_handler,
+        );
+
+        let AuthRequest::Sign(mut sign_request) =
+            create_default_test_sign_request(create_stub_public_key())
+        else {
+            unreachable!()
+        };
+        sign_request.upstream_key_comment = Some("user@laptop".to_string());
+        let result = policy.authorize(&AuthRequest::Sign(sign_request)).await;
+
+        assert!(matches!(result, Ok(true)));
+    }
+
+    #[tokio::test]
+    async fn test_authorize_identity_request_local_allows() {
+        let policy = BitwardenAuthPolicy::new(
+            Arc::new(MockKeyStore::new()),
+            Arc::new(SessionKeyStore::new()),
+            MockApprovalRequester::new(),
+        );
+
+        let result = policy.authorize(&create_identity_request(None)).await;
+
+        assert!(matches!(result, Ok(true)));
+    }
+
+    #[tokio::test]
+    async fn test_authorize_identity_request_bound_not_forwarding_allows() {
+        let policy = BitwardenAuthPolicy::new(
+            Arc::new(MockKeyStore::new()),
+            Arc::new(SessionKeyStore::new()),
+            MockApprovalRequester::new(),
+        );
+
+        let result = policy
+            .authorize(&create_identity_request(Some(false)))
+            .await;
+
+        assert!(matches!(result, Ok(true)));
+    }
+
+    #[tokio::test]
+    async fn test_authorize_identity_request_forwarded_denies() {
+        let policy = BitwardenAuthPolicy::new(
+            Arc::new(MockKeyStore::new()),
+            Arc::new(SessionKeyStore::new()),
+            MockApprovalRequester::new(),
+        );
+
+        let result = policy.authorize(&create_identity_request(Some(true))).await;
+
+        assert!(matches!(result, Ok(false)));
+    }
+
+    struct CountingAuthPolicy {
+        is_approved: bool,
+        calls: std::sync::atomic::AtomicUsize,
+    }
+
+    impl CountingAuthPolicy {
+        fn new(is_approved: bool) -> Self {
+            Self {
+                is_approved,
+                calls: std::sync::atomic::AtomicUsize::new(0),
+            }
+        }
+
+        fn calls(&self) -> usize {
+            self.calls.load(std::sync::atomic::Ordering::SeqCst)
+        }
+    }
+
+    #[async_trait::async_trait]
+    impl AuthPolicy for CountingAuthPolicy {
+        async fn authorize(&self, _: &AuthRequest) -> Result<bool, AuthError> {
+            self.calls.fetch_add(1, std::sync::atomic::Ordering::SeqCst);
+            Ok(self.is_approved)
+        }
+    }
+
+    const TEST_CACHE_TTL: Duration = Duration::from_secs(60);
+
+    fn create_cacheable_sign_request(
+        process_ancestry: Vec<u32>,
+        namespace: Option<SIGNamespace>,
+        host_fingerprint: Option<&str>,
+    ) -> AuthRequest {
+        create_test_sign_request(
+            create_stub_public_key(),
+            ConnectionContext {
+                process_name: Some("ssh-keygen".to_string()),
+                process_ancestry,
+                process_ancestry_names: Vec::new(),
+                process_path: None,
+                process_args: Vec::new(),
+                process_uid: None,
+                sandbox: None,
+                session_bind: host_fingerprint.map(|host_fingerprint| SessionBindContext {
+                    is_forwarding: false,
+                    host_fingerprint: host_fingerprint.to_string(),
+                    hops: Vec::new(),
+                }),
+            },
+            namespace,
+        )
+    }
+
+    async fn authorize_twice(
+        policy: &CachingAuthPolicy<CountingAuthPolicy>,
+        first: &AuthRequest,
+        second: &AuthRequest,
+    ) -> usize {
+        policy.authorize(first).await.unwrap();
+        policy.authorize(second).await.unwrap();
+        policy.inner.calls()
+    }
+
+    #[tokio::test]
+    async fn test_caching_policy_remembers_approval_for_same_process_tree() {
+        let policy = CachingAuthPolicy::new(CountingAuthPolicy::new(true), TEST_CACHE_TTL);
+        let request = create_cacheable_sign_request(vec![100, 1], Some(SIGNamespace::Git), None);
+
+        assert!(policy.may_prompt(&request));
+        assert!(policy.authorize(&request).await.unwrap());
+        assert!(!policy.may_prompt(&request));
+        assert!(policy.authorize(&request).await.unwrap());
+
+        assert_eq!(policy.inner.calls(), 1);
+    }
+
+    #[tokio::test]
+    async fn test_caching_policy_different_process_tree_requests_approval() {
+        let policy = CachingAuthPolicy::new(CountingAuthPolicy::new(true), TEST_CACHE_TTL);
+
+        let calls = authorize_twice(
+            &policy,
+            &create_cacheable_sign_request(vec![100, 1], Some(SIGNamespace::Git), None),
+            &create_cacheable_sign_request(vec![200, 1], Some(SIGNamespace::Git), None),
+        )
+        .await;
+
+        assert_eq!(calls, 2);
+    }
+
+    #[tokio::test]
+    async fn test_caching_policy_different_namespace_requests_approval() {
+        let policy = CachingAuthPolicy::new(CountingAuthPolicy::new(true), TEST_CACHE_TTL);
+
+        let calls = authorize_twice(
+            &policy,
+            &create_cacheable_sign_request(vec![100, 1], Some(SIGNamespace::Git), None),
+            &create_cacheable_sign_request(vec![100, 1], Some(SIGNamespace::File), None),
+        )
+        .await;
+
+        assert_eq!(calls, 2);
+    }
+
+    #[tokio::test]
+    async fn test_caching_policy_different_host_requests_approval() {
+        let policy = CachingAuthPolicy::new(CountingAuthPolicy::new(true), TEST_CACHE_TTL);
+
+        let calls = authorize_twice(
+            &policy,
+            &create_cacheable_sign_request(vec![100, 1], None, Some("SHA256:host-a")),
+            &create_cacheable_sign_request(vec![100, 1], None, Some("SHA256:host-b")),
+        )
+        .await;
+
+        assert_eq!(calls, 2);
+    }
+
+    #[tokio::test]
+    async fn test_caching_policy_unknown_process_is_not_remembered() {
+        let policy = CachingAuthPolicy::new(CountingAuthPolicy::new(true), TEST_CACHE_TTL);
+        let request = create_cacheable_sign_request(Vec::new(), None, None);
+
+        assert_eq!(authorize_twice(&policy, &request, &request).await, 2);
+    }
+
+    #[tokio::test]
+    async fn test_caching_policy_confirm_required_is_never_remembered() {
+        let policy = CachingAuthPolicy::new(CountingAuthPolicy::new(true), TEST_CACHE_TTL);
+        let AuthRequest::Sign(mut sign_request) =
+            create_cacheable_sign_request(vec![100, 1], None, None)
+        else {
+            unreachable!()
+        };
+        sign_request.confirm_required = true;
+        let request = AuthRequest::Sign(sign_request);
+
+        assert_eq!(authorize_twice(&policy, &request, &request).await, 2);
+    }
+
+    #[tokio::test]
+    async fn test_caching_policy_denial_is_not_remembered() {
+        let policy = CachingAuthPolicy::new(CountingAuthPolicy::new(false), TEST_CACHE_TTL);
+        let request = create_cacheable_sign_request(vec![100, 1], None, None);
+
+        assert!(!policy.authorize(&request).await.unwrap());
+        assert!(!policy.authorize(&request).await.unwrap());
+
+        assert_eq!(policy.inner.calls(), 2);
+    }
+
+    #[tokio::test]
+    async fn test_caching_policy_zero_ttl_disables_cache() {
+        let policy = CachingAuthPolicy::new(CountingAuthPolicy::new(true), Duration::ZERO);
+        let request = create_cacheable_sign_request(vec![100, 1], None, None);
+
+        assert_eq!(authorize_twice(&policy, &request, &request).await, 2);
+    }
+
+    #[tokio::test]
+    async fn test_caching_policy_approval_expires_after_ttl() {
+        let policy =
+            CachingAuthPolicy::new(CountingAuthPolicy::new(true), Duration::from_millis(1));
+        let request = create_cacheable_sign_request(vec![100, 1], None, None);
+
+        policy.authorize(&request).await.unwrap();
+        std::thread::sleep(Duration::from_millis(10));
+        policy.authorize(&request).await.unwrap();
+
+        assert_eq!(policy.inner.calls(), 2);
+    }
+
+    #[tokio::test]
+    async fn test_caching_policy_invalidate_forgets_approvals() {
+        let policy = CachingAuthPolicy::new(CountingAuthPolicy::new(true), TEST_CACHE_TTL);
+        let request = create_cacheable_sign_request(vec![100, 1], None, None);
+
+        policy.authorize(&request).await.unwrap();
+        policy.invalidate();
+        policy.authorize(&request).await.unwrap();
+
+        assert_eq!(policy.inner.calls(), 2);
+    }
+
+    #[tokio::test]
+    async fn test_caching_policy_set_ttl_forgets_approvals() {
+        let policy = CachingAuthPolicy::new(CountingAuthPolicy::new(true), TEST_CACHE_TTL);
+        let request = create_cacheable_sign_request(vec![100, 1], None, None);
+
+        policy.authorize(&request).await.unwrap();
+        policy.set_ttl(TEST_CACHE_TTL);
+        policy.authorize(&request).await.unwrap();
+
+        assert_eq!(policy.inner.calls(), 2);
+    }
+
+    #[tokio::test]
+    async fn test_caching_policy_passes_through_list_requests() {
+        let policy = CachingAuthPolicy::new(CountingAuthPolicy::new(true), TEST_CACHE_TTL);
+
+        assert_eq!(
+            authorize_twice(&policy, &AuthRequest::List, &AuthRequest::List).await,
+            2
+        );
+    }
 }
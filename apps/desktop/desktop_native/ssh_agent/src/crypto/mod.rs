@@ -8,6 +8,24 @@
 //! - Ed25519
 //! - ECDSA
 //! - RSA
+//!
+//! Any of the above may additionally be presented as an OpenSSH user certificate
+//! (`*-cert-v01@openssh.com`), in which case signing uses the underlying private key.
+//!
+//! # Security keys
+//!
+//! FIDO security keys (`sk-ssh-ed25519@openssh.com`, `sk-ecdsa-sha2-nistp256@openssh.com`) are
+//! held as [`SecurityKey`] handles only. The private key never leaves the device, so signing is
+//! delegated to an [`ExternalSigner`](crate::ExternalSigner).
+//!
+//! # Key formats
+//!
+//! Vault keys are parsed from OpenSSH, PKCS#1, SEC1, PKCS#8 and PuTTY formats. New keys are
+//! generated here too, and keys are exported in OpenSSH formats along with their fingerprints and
+//! randomart, so that the desktop client doesn't need its own implementation.
+
+mod import;
+mod keygen;
 
 use std::fmt;
 
@@ -15,19 +33,83 @@ use anyhow::anyhow;
 use rkyv::{Archive, Deserialize, Serialize};
 use signature::Signer as _;
 use ssh_key::{
-    private::{EcdsaKeypair, Ed25519Keypair, RsaKeypair},
-    Signature,
+    private::{EcdsaKeypair, Ed25519Keypair, RsaKeypair, SkEcdsaSha2NistP256, SkEd25519},
+    public::KeyData,
+    Certificate, HashAlg, Signature,
 };
 
+pub(crate) use import::parse_private_key;
+pub use keygen::{export_key, generate_key, ExportedKey, KeyType};
+
 use crate::server::SignFlags;
 pub use crate::storage::keydata::{QueryableKeyData, SSHKeyData};
 
+/// Algorithm name suffix shared by all OpenSSH certificate key types.
+// <https://github.com/openssh/openssh-portable/blob/master/PROTOCOL.certkeys>
+const CERTIFICATE_ALG_SUFFIX: &str = "-cert-v01@openssh.com";
+const SECURITY_KEY_ALG_PREFIX: &str = "sk-";
+
 /// Represents an SSH private key.
 #[derive(Clone, PartialEq, Debug)]
 pub enum PrivateKey {
     Ed25519(Ed25519Keypair),
     Ecdsa(EcdsaKeypair),
     Rsa(RsaKeypair),
+    SecurityKey(SecurityKey),
+}
+
+/// Represents a FIDO security key handle. The private key is held by the device, the handle only
+/// identifies the credential on it.
+#[derive(Clone, PartialEq, Debug)]
+pub enum SecurityKey {
+    Ed25519(SkEd25519),
+    Ecdsa(SkEcdsaSha2NistP256),
+}
+
+impl SecurityKey {
+    /// # Returns
+    ///
+    /// The public key of the credential.
+    #[must_use]
+    pub fn public_key(&self) -> KeyData {
+        match self {
+            Self::Ed25519(sk) => KeyData::SkEd25519(sk.public().clone()),
+            Self::Ecdsa(sk) => KeyData::SkEcdsaSha2NistP256(sk.public().clone()),
+        }
+    }
+
+    /// # Returns
+    ///
+    /// The FIDO application the credential was created for, usually `ssh:`.
+    #[must_use]
+    pub fn application(&self) -> &str {
+        match self {
+            Self::Ed25519(sk) => sk.public().application(),
+            Self::Ecdsa(sk) => sk.public().application(),
+        }
+    }
+
+    /// # Returns
+    ///
+    /// The key handle identifying the credential on the device.
+    #[must_use]
+    pub fn key_handle(&self) -> &[u8] {
+        match self {
+            Self::Ed25519(sk) => sk.key_handle(),
+            Self::Ecdsa(sk) => sk.key_handle(),
+        }
+    }
+
+    /// # Returns
+    ///
+    /// The flags the credential was created with, e.g. whether user presence is required.
+    #[must_use]
+    pub fn flags(&self) -> u8 {
+        match self {
+            Self::Ed25519(sk) => sk.flags(),
+            Self::Ecdsa(sk) => sk.flags(),
+        }
+    }
 }
 
 /// A private key that contains everything necessary to sign.
@@ -36,9 +118,8 @@ pub enum PrivateKey {
 ///
 /// # External signers
 ///
-/// Hardware-backed keys are not supported. This type signs directly using key
-/// material held in memory and does not delegate to any hardware device. If
-/// hardware-backed key support is ever added, [`SignablePrivateKey::sign`] must be updated.
+/// This type signs directly using key material held in memory. [`SecurityKey`]s can't be
+/// converted into it, they are signed by an [`ExternalSigner`](crate::ExternalSigner) instead.
 ///
 /// <https://docs.rs/signature/2.2.0/signature/trait.Signer.html>
 pub enum SignablePrivateKey {
@@ -57,16 +138,23 @@ impl SignablePrivateKey {
     }
 }
 
-/// Error returned when constructing a [`SignablePrivateKey`] when RSA is requested but no flags
-/// specified.
-// NOTE: technically the spec allows this request, but SHA-1 is insecure and should not be used in
-// any agent.
+/// Error returned when a [`PrivateKey`] can't be converted into a [`SignablePrivateKey`].
 #[derive(Debug, thiserror::Error)]
-#[error("RSA signing requires an explicit hash algorithm in `SignFlags`; SHA-1 is not permitted")]
-pub struct UnsignableErrRsaRequiresFlags;
+pub enum UnsignableError {
+    /// RSA was requested but no flags specified.
+    // NOTE: technically the spec allows this request, but SHA-1 is insecure and should not be
+    // used in any agent.
+    #[error(
+        "RSA signing requires an explicit hash algorithm in `SignFlags`; SHA-1 is not permitted"
+    )]
+    RsaRequiresFlags,
+    /// The key is a security key, whose private key is held by the device.
+    #[error("Security keys can only be signed with by an external signer")]
+    SecurityKey,
+}
 
 impl TryFrom<(PrivateKey, Option<SignFlags>)> for SignablePrivateKey {
-    type Error = UnsignableErrRsaRequiresFlags;
+    type Error = UnsignableError;
 
     fn try_from((key, flags): (PrivateKey, Option<SignFlags>)) -> Result<Self, Self::Error> {
         match key {
@@ -74,7 +162,8 @@ impl TryFrom<(PrivateKey, Option<SignFlags>)> for SignablePrivateKey {
             PrivateKey::Ecdsa(kp) => Ok(Self::Ecdsa(kp)),
             PrivateKey::Rsa(kp) => flags
                 .map(|flag| Self::Rsa(kp, flag))
-                .ok_or(UnsignableErrRsaRequiresFlags),
+                .ok_or(UnsignableError::RsaRequiresFlags),
+            PrivateKey::SecurityKey(_) => Err(UnsignableError::SecurityKey),
         }
     }
 }
@@ -123,6 +212,18 @@ impl TryFrom<ssh_key::private::PrivateKey> for PrivateKey {
                     .ok_or(anyhow!("Failed to parse RSA key"))?
                     .to_owned(),
             )),
+            ssh_key::Algorithm::SkEd25519 => Ok(Self::SecurityKey(SecurityKey::Ed25519(
+                key.key_data()
+                    .sk_ed25519()
+                    .ok_or(anyhow!("Failed to parse sk-ed25519 key"))?
+                    .to_owned(),
+            ))),
+            ssh_key::Algorithm::SkEcdsaSha2NistP256 => Ok(Self::SecurityKey(SecurityKey::Ecdsa(
+                key.key_data()
+                    .sk_ecdsa_p256()
+                    .ok_or(anyhow!("Failed to parse sk-ecdsa key"))?
+                    .to_owned(),
+            ))),
             _ => Err(anyhow!("Unsupported key type: {}", key.algorithm())),
         }
     }
@@ -148,6 +249,56 @@ impl PublicKey {
     pub fn blob(&self) -> &[u8] {
         &self.blob
     }
+
+    /// # Returns
+    ///
+    /// `true` if this public key is an OpenSSH certificate rather than a plain key.
+    #[must_use]
+    pub fn is_certificate(&self) -> bool {
+        self.alg.ends_with(CERTIFICATE_ALG_SUFFIX)
+    }
+
+    /// # Returns
+    ///
+    /// `true` if this public key, or the key a certificate was issued for, is held on a FIDO
+    /// security key.
+    #[must_use]
+    pub fn is_security_key(&self) -> bool {
+        self.alg.starts_with(SECURITY_KEY_ALG_PREFIX)
+    }
+
+    /// # Returns
+    ///
+    /// The SHA-256 fingerprint of the key, as displayed by `ssh-add -l`. For a certificate, this
+    /// is the fingerprint of the key the certificate was issued for. `None` if the blob cannot be
+    /// parsed.
+    #[must_use]
+    pub fn fingerprint(&self) -> Option<String> {
+        let fingerprint = if self.is_certificate() {
+            Certificate::from_bytes(&self.blob)
+                .ok()?
+                .public_key()
+                .fingerprint(HashAlg::Sha256)
+        } else {
+            ssh_key::PublicKey::from_bytes(&self.blob)
+                .ok()?
+                .fingerprint(HashAlg::Sha256)
+        };
+        Some(fingerprint.to_string())
+    }
+}
+
+impl TryFrom<&Certificate> for PublicKey {
+    type Error = anyhow::Error;
+
+    fn try_from(certificate: &Certificate) -> Result<Self, Self::Error> {
+        Ok(Self {
+            alg: certificate.algorithm().to_certificate_type(),
+            blob: certificate
+                .to_bytes()
+                .map_err(|e| anyhow!("Failed to encode certificate: {e}"))?,
+        })
+    }
 }
 
 impl fmt::Debug for PublicKey {
@@ -164,6 +315,70 @@ impl fmt::Display for PublicKey {
     }
 }
 
+// The `test_common` module contains helper functions that tests across the crate utilize.
+#[cfg(test)]
+pub(crate) mod test_common {
+    use sha2::Digest as _;
+    use signature::Signer as _;
+    use ssh_key::{
+        certificate::Builder,
+        private::{Ed25519Keypair, KeypairData, SkEd25519},
+        public::KeyData,
+        rand_core::OsRng,
+        Algorithm, Certificate, Signature,
+    };
+
+    /// Validity window end used for test certificates (2100-01-01T00:00:00Z).
+    pub(crate) const TEST_CERT_VALID_BEFORE: u64 = 4_102_444_800;
+
+    /// Issues a user certificate for `key` with the given principals, signed by a throwaway CA.
+    pub(crate) fn make_user_certificate(key: &KeyData, principals: &[&str]) -> Certificate {
+        let ca = ssh_key::PrivateKey::random(&mut OsRng, Algorithm::Ed25519).unwrap();
+        let mut builder =
+            Builder::new_with_random_nonce(&mut OsRng, key.clone(), 0, TEST_CERT_VALID_BEFORE)
+                .unwrap();
+        builder.key_id("test-key-id").unwrap();
+        for principal in principals {
+            builder.valid_principal(*principal).unwrap();
+        }
+        builder.sign(&ca).unwrap()
+    }
+
+    /// Creates a security key handle for `keypair`, as if the key was created on a device.
+    pub(crate) fn make_sk_ed25519_key(keypair: &Ed25519Keypair) -> ssh_key::PrivateKey {
+        let public = ssh_key::public::SkEd25519::new(keypair.public, "ssh:");
+        let sk = SkEd25519::new(public, 0x01, vec![0xAB; 32]).unwrap();
+        ssh_key::PrivateKey::new(KeypairData::SkEd25519(sk), "security key").unwrap()
+    }
+
+    /// Signs `data` the way a device holding `keypair` for `sk_key` does.
+    pub(crate) fn sign_with_sk_ed25519(
+        keypair: &Ed25519Keypair,
+        sk_key: &ssh_key::PrivateKey,
+        data: &[u8],
+    ) -> Signature {
+        const FLAGS: u8 = 0x01; // user present
+        const COUNTER: u32 = 1;
+        let application = sk_key
+            .key_data()
+            .sk_ed25519()
+            .unwrap()
+            .public()
+            .application();
+
+        let mut signed_data = sha2::Sha256::digest(application).to_vec();
+        signed_data.push(FLAGS);
+        signed_data.extend_from_slice(&COUNTER.to_be_bytes());
+        signed_data.extend_from_slice(&sha2::Sha256::digest(data));
+        let signature: Signature = keypair.sign(&signed_data);
+
+        let mut sig_bytes = signature.as_bytes().to_vec();
+        sig_bytes.push(FLAGS);
+        sig_bytes.extend_from_slice(&COUNTER.to_be_bytes());
+        Signature::new(Algorithm::SkEd25519, sig_bytes).unwrap()
+    }
+}
+
 #[cfg(test)]
 mod tests {
     use signature::Verifier as _;
@@ -218,6 +433,63 @@ mod tests {
         assert!(matches!(private_key, PrivateKey::Rsa(_)));
     }
 
+    #[test]
+    fn test_privatekey_from_sk_ed25519() {
+        let keypair = Ed25519Keypair::random(&mut OsRng);
+        let ssh_key = test_common::make_sk_ed25519_key(&keypair);
+
+        let private_key = PrivateKey::try_from(ssh_key).unwrap();
+
+        let PrivateKey::SecurityKey(security_key) = private_key else {
+            panic!("expected a security key");
+        };
+        assert_eq!(security_key.application(), "ssh:");
+        assert_eq!(security_key.key_handle(), &[0xAB; 32]);
+        assert_eq!(security_key.flags(), 0x01);
+        assert_eq!(
+            security_key.public_key(),
+            KeyData::SkEd25519(ssh_key::public::SkEd25519::new(keypair.public, "ssh:"))
+        );
+    }
+
+    #[test]
+    fn test_privatekey_from_sk_ecdsa() {
+        use ssh_key::public::EcdsaPublicKey;
+
+        let EcdsaPublicKey::NistP256(ec_point) =
+            EcdsaPublicKey::from(&ecdsa_keypair(EcdsaCurve::NistP256))
+        else {
+            panic!("expected a P-256 key");
+        };
+        let sk = SkEcdsaSha2NistP256::new(
+            ssh_key::public::SkEcdsaSha2NistP256::new(ec_point, "ssh:"),
+            0x01,
+            vec![0xCD; 16],
+        )
+        .unwrap();
+        let ssh_key =
+            ssh_key::PrivateKey::new(ssh_key::private::KeypairData::SkEcdsaSha2NistP256(sk), "")
+                .unwrap();
+
+        let private_key = PrivateKey::try_from(ssh_key).unwrap();
+
+        assert!(matches!(
+            private_key,
+            PrivateKey::SecurityKey(SecurityKey::Ecdsa(_))
+        ));
+    }
+
+    #[test]
+    fn test_signing_key_from_security_key_fails() {
+        let keypair = Ed25519Keypair::random(&mut OsRng);
+        let private_key = PrivateKey::try_from(test_common::make_sk_ed25519_key(&keypair)).unwrap();
+
+        assert!(matches!(
+            SignablePrivateKey::try_from((private_key, None)),
+            Err(UnsignableError::SecurityKey)
+        ));
+    }
+
     #[test]
     fn test_signing_key_from_ed25519_always_succeeds() {
         let keypair = Ed25519Keypair::random(&mut OsRng);
@@ -383,6 +655,90 @@ mod tests {
         );
     }
 
+    #[test]
+    fn test_publickey_is_certificate() {
+        let plain = PublicKey {
+            alg: "ssh-ed25519".to_string(),
+            blob: vec![],
+        };
+        let cert = PublicKey {
+            alg: "ssh-ed25519-cert-v01@openssh.com".to_string(),
+            blob: vec![],
+        };
+
+        assert!(!plain.is_certificate());
+        assert!(cert.is_certificate());
+    }
+
+    #[test]
+    fn test_publickey_is_security_key() {
+        let plain = PublicKey {
+            alg: "ssh-ed25519".to_string(),
+            blob: vec![],
+        };
+        let sk = PublicKey {
+            alg: "sk-ecdsa-sha2-nistp256@openssh.com".to_string(),
+            blob: vec![],
+        };
+        let sk_cert = PublicKey {
+            alg: "sk-ssh-ed25519-cert-v01@openssh.com".to_string(),
+            blob: vec![],
+        };
+
+        assert!(!plain.is_security_key());
+        assert!(sk.is_security_key());
+        assert!(sk_cert.is_security_key());
+    }
+
+    #[test]
+    fn test_publickey_from_certificate() {
+        let keypair = Ed25519Keypair::random(&mut OsRng);
+        let certificate = test_common::make_user_certificate(
+            &ssh_key::public::KeyData::Ed25519(keypair.public),
+            &["alice"],
+        );
+
+        let public_key = PublicKey::try_from(&certificate).unwrap();
+
+        assert_eq!(public_key.alg(), "ssh-ed25519-cert-v01@openssh.com");
+        assert!(public_key.is_certificate());
+        assert_eq!(public_key.blob(), certificate.to_bytes().unwrap());
+    }
+
+    #[test]
+    fn test_publickey_fingerprint_of_certificate_is_fingerprint_of_key() {
+        let keypair = Ed25519Keypair::random(&mut OsRng);
+        let key_data = ssh_key::public::KeyData::Ed25519(keypair.public);
+        let plain = PublicKey {
+            alg: "ssh-ed25519".to_string(),
+            blob: ssh_key::PublicKey::from(key_data.clone())
+                .to_bytes()
+                .unwrap(),
+        };
+        let certificate =
+            PublicKey::try_from(&test_common::make_user_certificate(&key_data, &["alice"]))
+                .unwrap();
+
+        let fingerprint = plain.fingerprint().unwrap();
+
+        assert_eq!(
+            fingerprint,
+            key_data.fingerprint(HashAlg::Sha256).to_string()
+        );
+        assert!(fingerprint.starts_with("SHA256:"));
+        assert_eq!(certificate.fingerprint(), Some(fingerprint));
+    }
+
+    #[test]
+    fn test_publickey_fingerprint_of_invalid_blob_returns_none() {
+        let public_key = PublicKey {
+            alg: "ssh-ed25519".to_string(),
+            blob: vec![1, 2, 3],
+        };
+
+        assert!(public_key.fingerprint().is_none());
+    }
+
     #[test]
     fn test_signing_key_sign_ecdsa_p256_produces_valid_signature() {
         let sig = SignablePrivateKey::Ecdsa(ecdsa_keypair(EcdsaCurve::NistP256)).sign(TEST_DATA);
@@ -0,0 +1,52 @@
+//! An abstraction layer that allows the `[BitwardenSSHAgent]`
+//! to delegate signing to the device holding a key's private key,
+//! for FIDO security keys.
+
+use anyhow::{anyhow, Result};
+use ssh_key::Signature;
+
+use crate::crypto::SecurityKey;
+
+/// Signer for keys whose private key is held outside the agent.
+#[cfg_attr(test, mockall::automock)]
+#[async_trait::async_trait]
+pub trait ExternalSigner: Send + Sync {
+    /// Signs data with a security key.
+    ///
+    /// # Arguments
+    ///
+    /// * `key` - The security key handle identifying the credential on the device
+    /// * `data` - The data to sign
+    ///
+    /// # Returns
+    ///
+    /// The signature, including the flags and counter reported by the device.
+    ///
+    /// # Errors
+    ///
+    /// If the device is unavailable, or refused to sign.
+    async fn sign(&self, key: &SecurityKey, data: &[u8]) -> Result<Signature>;
+
+    /// # Returns
+    ///
+    /// `false` if this signer can never sign. Security keys are then neither listed nor offered
+    /// to the user for approval.
+    fn is_available(&self) -> bool {
+        true
+    }
+}
+
+/// [`ExternalSigner`] for when no device integration is available. Security keys are not listed,
+/// and sign requests for them are refused without asking the user.
+pub struct UnavailableExternalSigner;
+
+#[async_trait::async_trait]
+impl ExternalSigner for UnavailableExternalSigner {
+    async fn sign(&self, _key: &SecurityKey, _data: &[u8]) -> Result<Signature> {
+        Err(anyhow!("No external signer is available for security keys"))
+    }
+
+    fn is_available(&self) -> bool {
+        false
+    }
+}
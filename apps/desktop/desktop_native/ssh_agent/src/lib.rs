@@ -12,10 +12,16 @@
 //! - `server` provides the low-level implementation of the Server protocol. It uses an
 //!   implementation of an `AuthPolicy` to get authorization for the various agent operations that
 //!   are received by connections.
+//! - `audit` provides records of the list and sign operations requested from the server, and their
+//!   authorization decisions.
 //! - `authorization` provides Bitwarden's business logic for the requested authorization from the
 //!   server, for the various agent operations.
+//! - `policy_rules` provides declarative rules, evaluated before `authorization` asks for approval.
+//! - `external_signer` provides an interface for the agent to delegate signing with security keys
+//!   to the device holding them.
 //! - `approval` provides an interface for the agent itself to request approval from an external
-//!   entity (currently, Electron via napi) to approve requests.
+//!   entity (currently, Electron via napi) to approve requests, and coordinates the prompts of
+//!   concurrent requests.
 //! - `agent` contains the store of keys, and the server, and uses the authorization and approval
 //!   impelementation to orchestrate operations between the server and the external (Electron)
 //!   entity.
@@ -24,20 +30,32 @@
 
 mod agent;
 mod approval;
+mod audit;
 mod authorization;
 mod crypto;
+mod external_signer;
+mod policy_rules;
 mod server;
 mod storage;
 
 // external exports for napi
 pub use agent::BitwardenSSHAgent;
-pub use approval::{ApprovalError, ApprovalRequester, SignApprovalRequest};
-pub use authorization::BitwardenAuthPolicy;
-pub use crypto::PublicKey;
+pub use approval::{ApprovalCoordinator, ApprovalError, ApprovalRequester, SignApprovalRequest};
+pub use audit::{
+    AuditDecision, AuditDenialReason, AuditEntry, AuditOperation, AuditSink, JsonLinesAuditLog,
+};
+pub use authorization::{BitwardenAuthPolicy, CachingAuthPolicy};
+pub use crypto::{export_key, generate_key, ExportedKey, KeyType, PublicKey, SecurityKey};
+pub use external_signer::{ExternalSigner, UnavailableExternalSigner};
+pub use policy_rules::{PolicyRule, RuleAction, RuleAuthPolicy};
 pub use server::{
-    AuthRequest, ConnectionContext, SIGNamespace, SessionBindContext, SignFlags, SignRequest,
+    AgentSocket, AuthRequest, CertificateContext, ConnectionContext, IdentityOperation,
+    IdentityRequest, PeerSandbox, RateLimitStats, RateLimits, SIGNamespace, SSHSigData,
+    SessionBindContext, SessionBindHop, SignFlags, SignRequest,
 };
 pub use storage::{
-    keydata::{SSHKeyData, UnparsedSSHKeyData},
+    key_filter::KeyFilter,
+    keydata::{KeyParseFailure, SSHKeyData, UnparsedSSHKeyData},
     keystore::{InMemoryEncryptedKeyStore, KeyStore},
+    session_keystore::{DestinationConstraint, KeyConstraints},
 };
@@ -0,0 +1,496 @@
+//! Declarative policy rules for sign requests, evaluated before the user is asked for approval.
+//!
+//! Rules are loaded from JSON, for example:
+//!
+//! ```json
+//! {
+//!   "rules": [
+//!     { "minHops": 3, "action": "deny" },
+//!     { "isForwarding": true, "action": "deny" },
+//!     { "keyFingerprint": "SHA256:...", "namespace": "git", "action": "prompt" },
+//!     { "keyFingerprint": "SHA256:...", "action": "deny" },
+//!     { "keyFingerprint": "SHA256:...", "hostFingerprint": "SHA256:...", "action": "allow" }
+//!   ]
+//! }
+//! ```
+//!
+//! Rules are evaluated in order and the first rule matching a request decides it.
+
+use std::sync::RwLock;
+
+use anyhow::{anyhow, Result};
+use serde::Deserialize;
+use tracing::{debug, info};
+
+use crate::{
+    authorization::AuthError,
+    server::{AuthPolicy, AuthRequest, SIGNamespace, SignRequest},
+};
+
+/// The decision of a rule matching a sign request.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub enum RuleAction {
+    /// Approve the request without asking the user. Keys added with a confirm constraint are
+    /// still prompted for.
+    Allow,
+    /// Deny the request without asking the user.
+    Deny,
+    /// Ask the user, skipping any later rules.
+    Prompt,
+}
+
+/// A rule for sign requests. All conditions of a rule must match a request for the rule to apply,
+/// and absent conditions match any request.
+#[derive(Debug, Clone, PartialEq, Eq, Deserialize)]
+#[serde(rename_all = "camelCase", deny_unknown_fields)]
+pub struct PolicyRule {
+    /// SHA-256 fingerprint of the requested key, e.g. `SHA256:...`. Rules for a key also apply to
+    /// certificates issued for it.
+    pub key_fingerprint: Option<String>,
+    /// SSHSIG namespace of the request, e.g. `git`. Never matches authentication requests.
+    pub namespace: Option<String>,
+    /// Whether the request was made over a forwarded connection.
+    pub is_forwarding: Option<bool>,
+    /// SHA-256 fingerprint of the host key the connection is bound to.
+    pub host_fingerprint: Option<String>,
+    /// Name of the requesting process.
+    pub process_name: Option<String>,
+    /// Minimum number of hosts the connection was bound to, counting every host the agent was
+    /// forwarded through. Requests without a session-bind have no hops.
+    pub min_hops: Option<usize>,
+    /// The decision when the rule matches.
+    pub action: RuleAction,
+}
+
+#[derive(Deserialize)]
+#[serde(deny_unknown_fields)]
+struct PolicyRules {
+    rules: Vec<PolicyRule>,
+}
+
+impl PolicyRule {
+    fn matches(&self, request: &SignRequest, key_fingerprint: Option<&str>) -> bool {
+        let session_bind = request.connection.session_bind.as_ref();
+
+        condition_matches(self.key_fingerprint.as_ref(), key_fingerprint)
+            && condition_matches(
+                self.namespace.as_ref(),
+                request.namespace.as_ref().and_then(SIGNamespace::as_str),
+            )
+            && self.is_forwarding.is_none_or(|is_forwarding| {
+                is_forwarding == session_bind.is_some_and(|s| s.is_forwarding)
+            })
+            && condition_matches(
+                self.host_fingerprint.as_ref(),
+                session_bind.map(|s| s.host_fingerprint.as_str()),
+            )
+            && condition_matches(
+                self.process_name.as_ref(),
+                request.connection.process_name.as_deref(),
+            )
+            && self
+                .min_hops
+                .is_none_or(|min_hops| session_bind.map_or(0, |s| s.hops.len()) >= min_hops)
+    }
+}
+
+fn condition_matches(condition: Option<&String>, value: Option<&str>) -> bool {
+    condition.is_none_or(|condition| value == Some(condition.as_str()))
+}
+
+/// Wraps an [`AuthPolicy`], deciding sign requests with [`PolicyRule`]s where a rule matches and
+/// falling through to the wrapped policy otherwise. Other requests are always passed through.
+pub struct RuleAuthPolicy<P>
+where
+    P: AuthPolicy,
+{
+    inner: P,
+    rules: RwLock<Vec<PolicyRule>>,
+}
+
+impl<P> RuleAuthPolicy<P>
+where
+    P: AuthPolicy,
+{
+    /// Creates a new [`RuleAuthPolicy`] without any rules.
+    pub fn new(inner: P) -> Self {
+        Self {
+            inner,
+            rules: RwLock::new(Vec::new()),
+        }
+    }
+
+    /// # Returns
+    ///
+    /// A reference to the wrapped policy.
+    pub fn inner(&self) -> &P {
+        &self.inner
+    }
+
+    /// Replaces the rules with the rules in `json`.
+    ///
+    /// # Errors
+    ///
+    /// If `json` is not a valid rule set. The current rules are kept.
+    pub fn set_rules_json(&self, json: &str) -> Result<()> {
+        let PolicyRules { rules } =
+            serde_json::from_str(json).map_err(|e| anyhow!("Failed to parse policy rules: {e}"))?;
+
+        info!(count = rules.len(), "Setting policy rules.");
+        *self
+            .rules
+            .write()
+            .map_err(|e| anyhow!("Failed to acquire lock: {e}"))? = rules;
+        Ok(())
+    }
+
+    /// # Returns
+    ///
+    /// The action of the first rule matching `request`, `None` if no rule matches.
+    fn evaluate(&self, request: &SignRequest) -> Option<RuleAction> {
+        self.first_match(request).map(|(index, action)| {
+            info!(index, ?action, public_key = %request.public_key, "Sign request matched policy rule.");
+            action
+        })
+    }
+
+    /// # Returns
+    ///
+    /// The index and action of the first rule matching `request`, `None` if no rule matches.
+    fn first_match(&self, request: &SignRequest) -> Option<(usize, RuleAction)> {
+        let rules = self.rules.read().ok()?;
+        if rules.is_empty() {
+            return None;
+        }
+
+        let key_fingerprint = request.public_key.fingerprint();
+        rules
+            .iter()
+            .enumerate()
+            .find(|(_, rule)| rule.matches(request, key_fingerprint.as_deref()))
+            .map(|(index, rule)| (index, rule.action))
+    }
+}
+
+#[async_trait::async_trait]
+impl<P> AuthPolicy for RuleAuthPolicy<P>
+where
+    P: AuthPolicy,
+{
+    async fn authorize(&self, request: &AuthRequest) -> Result<bool, AuthError> {
+        let AuthRequest::Sign(sign_request) = request else {
+            return self.inner.authorize(request).await;
+        };
+
+        match self.evaluate(sign_request) {
+            Some(RuleAction::Deny) => Ok(false),
+            Some(RuleAction::Allow) if !sign_request.confirm_required => Ok(true),
+            Some(RuleAction::Allow) => {
+                debug!("Key requires confirmation, ignoring allow rule.");
+                self.inner.authorize(request).await
+            }
+            Some(RuleAction::Prompt) | None => self.inner.authorize(request).await,
+        }
+    }
+
+    fn may_prompt(&self, request: &AuthRequest) -> bool {
+        let AuthRequest::Sign(sign_request) = request else {
+            return self.inner.may_prompt(request);
+        };
+
+        match self.first_match(sign_request).map(|(_, action)| action) {
+            Some(RuleAction::Deny) => false,
+            Some(RuleAction::Allow) if !sign_request.confirm_required => false,
+            Some(_) | None => self.inner.may_prompt(request),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::sync::atomic::{AtomicUsize, Ordering};
+
+    use ssh_key::{private::Ed25519Keypair, rand_core::OsRng};
+
+    use super::*;
+    use crate::{
+        crypto::PublicKey,
+        server::{ConnectionContext, SessionBindContext, SessionBindHop},
+    };
+
+    struct CountingAuthPolicy {
+        calls: AtomicUsize,
+    }
+
+    #[async_trait::async_trait]
+    impl AuthPolicy for CountingAuthPolicy {
+        async fn authorize(&self, _: &AuthRequest) -> Result<bool, AuthError> {
+            self.calls.fetch_add(1, Ordering::SeqCst);
+            Ok(true)
+        }
+    }
+
+    fn make_policy(json: &str) -> RuleAuthPolicy<CountingAuthPolicy> {
+        let policy = RuleAuthPolicy::new(CountingAuthPolicy {
+            calls: AtomicUsize::new(0),
+        });
+        policy.set_rules_json(json).unwrap();
+        policy
+    }
+
+    fn make_public_key() -> PublicKey {
+        let keypair = Ed25519Keypair::random(&mut OsRng);
+        PublicKey {
+            alg: "ssh-ed25519".to_string(),
+            blob: ssh_key::PublicKey::from(keypair.public).to_bytes().unwrap(),
+        }
+    }
+
+    fn make_sign_request(
+        public_key: &PublicKey,
+        namespace: Option<SIGNamespace>,
+        session_bind: Option<(&str, bool)>,
+    ) -> SignRequest {
+        SignRequest {
+            public_key: public_key.clone(),
+            namespace,
+            sshsig: None,
+            connection: ConnectionContext {
+                process_name: Some("git".to_string()),
+                process_ancestry: Vec::new(),
+                process_ancestry_names: Vec::new(),
+                process_path: None,
+                process_args: Vec::new(),
+                process_uid: None,
+                sandbox: None,
+                session_bind: session_bind.map(|(host_fingerprint, is_forwarding)| {
+                    SessionBindContext {
+                        is_forwarding,
+                        host_fingerprint: host_fingerprint.to_string(),
+                        hops: vec![SessionBindHop {
+                            host_fingerprint: host_fingerprint.to_string(),
+                            is_forwarding,
+                        }],
+                    }
+                }),
+            },
+            certificate: None,
+            confirm_required: false,
+            upstream_key_comment: None,
+        }
+    }
+
+    /// # Returns
+    ///
+    /// The authorization result, and whether the request fell through to the wrapped policy.
+    async fn authorize(
+        policy: &RuleAuthPolicy<CountingAuthPolicy>,
+        request: SignRequest,
+    ) -> (bool, bool) {
+        let request = AuthRequest::Sign(request);
+        let may_prompt = policy.may_prompt(&request);
+        let calls = policy.inner().calls.load(Ordering::SeqCst);
+        let result = policy.authorize(&request).await.unwrap();
+        let fell_through = policy.inner().calls.load(Ordering::SeqCst) > calls;
+        assert_eq!(may_prompt, fell_through);
+        (result, fell_through)
+    }
+
+    #[tokio::test]
+    async fn no_rules_falls_through() {
+        let policy = make_policy(r#"{ "rules": [] }"#);
+
+        let result = authorize(&policy, make_sign_request(&make_public_key(), None, None)).await;
+
+        assert_eq!(result, (true, true));
+    }
+
+    #[tokio::test]
+    async fn deny_forwarded_requests() {
+        let policy = make_policy(r#"{ "rules": [{ "isForwarding": true, "action": "deny" }] }"#);
+        let public_key = make_public_key();
+
+        let forwarded = authorize(
+            &policy,
+            make_sign_request(&public_key, None, Some(("SHA256:host", true))),
+        )
+        .await;
+        let direct = authorize(
+            &policy,
+            make_sign_request(&public_key, None, Some(("SHA256:host", false))),
+        )
+        .await;
+
+        assert_eq!(forwarded, (false, false));
+        assert_eq!(direct, (true, true));
+    }
+
+    #[tokio::test]
+    async fn key_restricted_to_git_signing() {
+        let public_key = make_public_key();
+        let fingerprint = public_key.fingerprint().unwrap();
+        let policy = make_policy(&format!(
+            r#"{{ "rules": [
+                {{ "keyFingerprint": "{fingerprint}", "namespace": "git", "action": "prompt" }},
+                {{ "keyFingerprint": "{fingerprint}", "action": "deny" }}
+            ] }}"#
+        ));
+
+        let git = authorize(
+            &policy,
+            make_sign_request(&public_key, Some(SIGNamespace::Git), None),
+        )
+        .await;
+        let file = authorize(
+            &policy,
+            make_sign_request(&public_key, Some(SIGNamespace::File), None),
+        )
+        .await;
+        let authentication = authorize(&policy, make_sign_request(&public_key, None, None)).await;
+        let other_key = authorize(
+            &policy,
+            make_sign_request(&make_public_key(), Some(SIGNamespace::File), None),
+        )
+        .await;
+
+        assert_eq!(git, (true, true));
+        assert_eq!(file, (false, false));
+        assert_eq!(authentication, (false, false));
+        assert_eq!(other_key, (true, true));
+    }
+
+    #[tokio::test]
+    async fn allow_host_for_key() {
+        let public_key = make_public_key();
+        let fingerprint = public_key.fingerprint().unwrap();
+        let policy = make_policy(&format!(
+            r#"{{ "rules": [{{
+                "keyFingerprint": "{fingerprint}",
+                "hostFingerprint": "SHA256:trusted",
+                "action": "allow"
+            }}] }}"#
+        ));
+
+        let trusted = authorize(
+            &policy,
+            make_sign_request(&public_key, None, Some(("SHA256:trusted", false))),
+        )
+        .await;
+        let other = authorize(
+            &policy,
+            make_sign_request(&public_key, None, Some(("SHA256:other", false))),
+        )
+        .await;
+        let unbound = authorize(&policy, make_sign_request(&public_key, None, None)).await;
+
+        assert_eq!(trusted, (true, false));
+        assert_eq!(other, (true, true));
+        assert_eq!(unbound, (true, true));
+    }
+
+    #[tokio::test]
+    async fn namespace_rule_matches_other_namespaces() {
+        let policy =
+            make_policy(r#"{ "rules": [{ "namespace": "email@example.com", "action": "deny" }] }"#);
+
+        let email = authorize(
+            &policy,
+            make_sign_request(
+                &make_public_key(),
+                Some(SIGNamespace::Other("email@example.com".to_string())),
+                None,
+            ),
+        )
+        .await;
+        let unsupported = authorize(
+            &policy,
+            make_sign_request(&make_public_key(), Some(SIGNamespace::Unsupported), None),
+        )
+        .await;
+
+        assert_eq!(email, (false, false));
+        assert_eq!(unsupported, (true, true));
+    }
+
+    #[tokio::test]
+    async fn allow_rule_does_not_skip_confirmation() {
+        let policy = make_policy(r#"{ "rules": [{ "action": "allow" }] }"#);
+        let mut request = make_sign_request(&make_public_key(), None, None);
+        request.confirm_required = true;
+
+        assert_eq!(authorize(&policy, request).await, (true, true));
+    }
+
+    #[tokio::test]
+    async fn process_name_rule() {
+        let policy = make_policy(r#"{ "rules": [{ "processName": "curl", "action": "deny" }] }"#);
+
+        let result = authorize(&policy, make_sign_request(&make_public_key(), None, None)).await;
+
+        assert_eq!(result, (true, true));
+    }
+
+    #[tokio::test]
+    async fn first_matching_rule_decides() {
+        let policy = make_policy(
+            r#"{ "rules": [{ "processName": "git", "action": "allow" }, { "action": "deny" }] }"#,
+        );
+
+        let result = authorize(&policy, make_sign_request(&make_public_key(), None, None)).await;
+
+        assert_eq!(result, (true, false));
+    }
+
+    #[tokio::test]
+    async fn deny_beyond_max_hops() {
+        let policy = make_policy(r#"{ "rules": [{ "minHops": 3, "action": "deny" }] }"#);
+        let public_key = make_public_key();
+        let with_hops = |count: usize| {
+            let mut request = make_sign_request(&public_key, None, Some(("SHA256:host", true)));
+            if let Some(session_bind) = request.connection.session_bind.as_mut() {
+                session_bind.hops = (0..count)
+                    .map(|i| SessionBindHop {
+                        host_fingerprint: format!("SHA256:host{i}"),
+                        is_forwarding: i + 1 < count,
+                    })
+                    .collect();
+            }
+            request
+        };
+
+        let local = authorize(&policy, make_sign_request(&public_key, None, None)).await;
+        let two_hops = authorize(&policy, with_hops(2)).await;
+        let three_hops = authorize(&policy, with_hops(3)).await;
+
+        assert_eq!(local, (true, true));
+        assert_eq!(two_hops, (true, true));
+        assert_eq!(three_hops, (false, false));
+    }
+
+    #[tokio::test]
+    async fn non_sign_requests_fall_through() {
+        let policy = make_policy(r#"{ "rules": [{ "action": "deny" }] }"#);
+
+        assert!(policy.authorize(&AuthRequest::List).await.unwrap());
+        assert_eq!(policy.inner().calls.load(Ordering::SeqCst), 1);
+    }
+
+    #[test]
+    fn set_rules_json_rejects_unknown_conditions() {
+        let policy = make_policy(r#"{ "rules": [{ "action": "deny" }] }"#);
+
+        assert!(policy
+            .set_rules_json(r#"{ "rules": [{ "hostname": "example.com", "action": "allow" }] }"#)
+            .is_err());
+        assert!(policy
+            .set_rules_json(r#"{ "rules": [{ "action": "maybe" }] }"#)
+            .is_err());
+        assert!(policy.set_rules_json("not json").is_err());
+        assert_eq!(
+            policy.rules.read().unwrap().len(),
+            1,
+            "current rules must be kept"
+        );
+    }
+}
@@ -1,8 +1,22 @@
 //! `AuthPolicy` defines an interface for entities external to the
 //! ssh agent server to authorizing SSH agent operations.
 
-use super::protocol::SIGNamespace;
-use crate::{authorization::AuthError, crypto::PublicKey};
+use super::{
+    peer_info::PeerSandbox,
+    protocol::{SIGNamespace, SSHSigData},
+};
+use crate::{
+    authorization::AuthError, crypto::PublicKey, storage::session_keystore::KeyConstraints,
+};
+
+/// A host the connection was bound to with a session-bind extension.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct SessionBindHop {
+    /// SHA-256 fingerprint of the host's public key.
+    pub host_fingerprint: String,
+    /// Whether the agent is forwarded to the host, rather than used to authenticate to it.
+    pub is_forwarding: bool,
+}
 
 /// Session-bind context for an SSH sign request.
 #[derive(Debug, Clone)]
@@ -11,6 +25,9 @@ pub struct SessionBindContext {
     pub is_forwarding: bool,
     /// SHA-256 fingerprint of the remote host's public key.
     pub host_fingerprint: String,
+    /// Hosts the connection was bound to, nearest to the agent first. With agent forwarding
+    /// OpenSSH binds once per hop, so the last entry is the host being authenticated to.
+    pub hops: Vec<SessionBindHop>,
 }
 
 /// Connection-level context for an SSH sign request.
@@ -18,10 +35,39 @@ pub struct SessionBindContext {
 pub struct ConnectionContext {
     /// Name of the process making the request. Often unavailable in sandboxed environments.
     pub process_name: Option<String>,
+    /// PIDs of the requesting process' ancestors up to its session leader, nearest first. Empty
+    /// when unavailable.
+    pub process_ancestry: Vec<u32>,
+    /// Names of the requesting process' ancestors, matching `process_ancestry`, e.g. `git` and
+    /// `bash` for `ssh` run by `git push` in a terminal.
+    pub process_ancestry_names: Vec<String>,
+    /// Absolute path of the requesting process' executable. Only available on Linux and macOS.
+    pub process_path: Option<String>,
+    /// Command line arguments of the requesting process, including the program name. Only
+    /// available on Linux.
+    pub process_args: Vec<String>,
+    /// User ID of the requesting process. Only available on Linux.
+    pub process_uid: Option<u32>,
+    /// The sandbox the requesting process runs in. `None` if it doesn't run in one, or if that
+    /// is unknown. Only detected on Linux.
+    pub sandbox: Option<PeerSandbox>,
     /// Session-bind context. `None` when no session-bind extension was received.
     pub session_bind: Option<SessionBindContext>,
 }
 
+/// OpenSSH certificate context for an SSH sign request.
+#[derive(Debug, Clone)]
+pub struct CertificateContext {
+    /// Identifier the certificate authority assigned to the certificate.
+    pub key_id: String,
+    /// Principals the certificate is valid for. Empty means valid for any principal.
+    pub principals: Vec<String>,
+    /// Start of the validity period, as seconds since the Unix epoch.
+    pub valid_after: u64,
+    /// End of the validity period, as seconds since the Unix epoch. `u64::MAX` means forever.
+    pub valid_before: u64,
+}
+
 /// Request to sign data using an SSH key.
 #[derive(Debug, Clone)]
 pub struct SignRequest {
@@ -30,6 +76,47 @@ pub struct SignRequest {
     /// The parsed representation of the sign request's SIG namespace. For authentications to a
     /// server, this is `None`.
     pub namespace: Option<SIGNamespace>,
+    /// The parsed SSHSIG data to sign. `None` for authentications to a server, and for malformed
+    /// SSHSIG blobs.
+    pub sshsig: Option<SSHSigData>,
+    /// Connection-level context: peer process info and session-bind state.
+    pub connection: ConnectionContext,
+    /// Certificate context. `None` when the request identifies a plain public key.
+    pub certificate: Option<CertificateContext>,
+    /// Whether the key was added with a confirm constraint. Such requests must never be approved
+    /// without asking the user.
+    pub confirm_required: bool,
+    /// The comment of the key as listed by the upstream agent. `Some` when the key is held by the
+    /// upstream agent rather than Bitwarden, and the request will be forwarded to it once approved.
+    pub upstream_key_comment: Option<String>,
+}
+
+/// Change to the agent's session-scoped keys requested by a client such as `ssh-add`.
+#[derive(Debug, Clone)]
+pub enum IdentityOperation {
+    /// Add a key for the lifetime of the agent session.
+    Add {
+        /// The public key of the key being added.
+        public_key: PublicKey,
+        /// The comment the client provided for the key.
+        comment: String,
+        /// The constraints the client placed on the key.
+        constraints: KeyConstraints,
+    },
+    /// Remove a previously added session key.
+    Remove {
+        /// The public key of the key being removed.
+        public_key: PublicKey,
+    },
+    /// Remove all session keys.
+    RemoveAll,
+}
+
+/// Request to change the agent's session-scoped keys.
+#[derive(Debug, Clone)]
+pub struct IdentityRequest {
+    /// The requested change.
+    pub operation: IdentityOperation,
     /// Connection-level context: peer process info and session-bind state.
     pub connection: ConnectionContext,
 }
@@ -41,6 +128,8 @@ pub enum AuthRequest {
     List,
     /// Request to sign data with a specific key
     Sign(SignRequest),
+    /// Request to add or remove session-scoped keys
+    ModifyIdentities(IdentityRequest),
 }
 
 /// Implementers of this policy use the context provided to authorize
@@ -62,4 +151,15 @@ pub trait AuthPolicy: Send + Sync {
     ///
     /// * `AuthError` if an error occurred during authorization
     async fn authorize(&self, request: &AuthRequest) -> Result<bool, AuthError>;
+
+    /// Whether authorizing `request` may ask the user for approval. Only such requests count
+    /// towards the rate limits, so that requests decided by a policy rule or a remembered approval
+    /// aren't refused.
+    ///
+    /// # Returns
+    ///
+    /// `false` if the policy would decide `request` without asking the user.
+    fn may_prompt(&self, _request: &AuthRequest) -> bool {
+        true
+    }
 }
@@ -1,26 +1,47 @@
 //! SSH agent client connection and connection handler
 
-use std::sync::Arc;
+use std::{sync::Arc, time::Duration};
 
+use signature::Verifier as _;
+use ssh_key::Signature;
 use tokio::io::{AsyncRead, AsyncReadExt, AsyncWrite, AsyncWriteExt};
 use tokio_util::sync::CancellationToken;
 use tracing::{debug, error, info, trace, warn};
 
 use super::{
-    auth_policy::{AuthPolicy, AuthRequest, ConnectionContext, SessionBindContext, SignRequest},
+    auth_policy::{
+        AuthPolicy, AuthRequest, CertificateContext, ConnectionContext, IdentityOperation,
+        IdentityRequest, SessionBindContext, SignRequest,
+    },
     peer_info::PeerInfo,
     protocol::{
-        build_identities_answer, build_sign_response, detect_namespace, failure, frame,
-        parse_message, read_ssh_string, success, AgentMessage, SignFlags, EXTENSION,
+        build_identities_answer, build_raw_sign_response, build_sign_response, detect_namespace,
+        failure, frame, parse_message, parse_sshsig, read_ssh_string, success, AgentMessage,
+        SignFlags, EXTENSION, SESSION_BIND_EXTENSION,
     },
+    rate_limit::RateLimiter,
     session_bind::SessionBindState,
+    upstream::{CachedUpstreamAgent, UpstreamAgent},
     KeyStore,
 };
-use crate::crypto::{PublicKey, SignablePrivateKey};
+use crate::{
+    audit::{
+        unix_time_ms, AuditDecision, AuditDenialReason, AuditEntry, AuditOperation, AuditSink,
+    },
+    authorization::AuthError,
+    crypto::{PrivateKey, PublicKey, QueryableKeyData as _, SecurityKey, SignablePrivateKey},
+    external_signer::ExternalSigner,
+    storage::{
+        agent_lock::AgentLock,
+        key_filter::KeyFilter,
+        session_keystore::{KeyConstraints, SessionKeyData, SessionKeyStore},
+    },
+};
 
 // Guards against oversized allocations from untrusted length prefixes on the socket.
 const MAX_MESSAGE_LEN: usize = 256 * 1024;
-const SESSION_BIND_EXTENSION: &[u8] = b"session-bind@openssh.com";
+// Slows down guessing of the lock passphrase by clients.
+const UNLOCK_FAILURE_DELAY: Duration = Duration::from_millis(100);
 
 /// An accepted connection from an SSH agent client, bundling the I/O stream
 /// with information about the connecting peer.
@@ -29,12 +50,21 @@ pub(crate) struct Connection<S> {
     pub(crate) stream: S,
     /// Information about the connected peer process, if available
     pub(crate) peer_info: Option<PeerInfo>,
+    /// The keys visible on the listener that accepted this connection
+    pub(crate) key_filter: Arc<KeyFilter>,
 }
 
 /// Handles an individual SSH agent client connection
 pub(crate) struct ConnectionHandler<K, A, S> {
     keystore: Arc<K>,
+    session_keystore: Arc<SessionKeyStore>,
+    agent_lock: Arc<AgentLock>,
     auth_policy: Arc<A>,
+    external_signer: Arc<dyn ExternalSigner>,
+    /// The upstream agent, whose identities are listed once per connection.
+    upstream: Option<Arc<dyn UpstreamAgent>>,
+    audit_sink: Option<Arc<dyn AuditSink>>,
+    rate_limiter: Arc<RateLimiter>,
     connection: Connection<S>,
     token: CancellationToken,
 }
@@ -46,15 +76,30 @@ where
     S: AsyncRead + AsyncWrite + Unpin,
 {
     /// Create a new connection handler
+    #[allow(clippy::too_many_arguments)]
     pub fn new(
         keystore: Arc<K>,
+        session_keystore: Arc<SessionKeyStore>,
+        agent_lock: Arc<AgentLock>,
         auth_policy: Arc<A>,
+        external_signer: Arc<dyn ExternalSigner>,
+        upstream: Option<Arc<dyn UpstreamAgent>>,
+        audit_sink: Option<Arc<dyn AuditSink>>,
+        rate_limiter: Arc<RateLimiter>,
         connection: Connection<S>,
         token: CancellationToken,
     ) -> Self {
         Self {
             keystore,
+            session_keystore,
+            agent_lock,
             auth_policy,
+            external_signer,
+            upstream: upstream.map(|upstream| {
+                Arc::new(CachedUpstreamAgent::new(upstream)) as Arc<dyn UpstreamAgent>
+            }),
+            audit_sink,
+            rate_limiter,
             connection,
             token,
         }
@@ -102,14 +147,27 @@ where
 
             // Pass Arc clones rather than &self to avoid requiring S: Sync
             let response = if msg.first() == Some(&EXTENSION) {
-                handle_extension_message(&msg[1..], &mut session_bind_state)
+                // Like OpenSSH, a locked agent refuses extensions too, including session-bind.
+                if self.agent_lock.is_locked() {
+                    debug!("Refusing extension while locked");
+                    failure()
+                } else {
+                    handle_extension_message(&msg[1..], &mut session_bind_state)
+                }
             } else {
                 handle_message(
                     &msg,
                     self.connection.peer_info.as_ref(),
                     &session_bind_state,
+                    &self.connection.key_filter,
                     &self.keystore,
+                    &self.session_keystore,
+                    &self.agent_lock,
                     &self.auth_policy,
+                    &self.external_signer,
+                    self.upstream.as_ref(),
+                    self.audit_sink.as_ref(),
+                    &self.rate_limiter,
                 )
                 .await
             };
@@ -175,20 +233,45 @@ fn handle_session_bind(payload: &[u8], session_bind_state: &mut SessionBindState
     }
 }
 
+#[allow(clippy::too_many_arguments)]
 async fn handle_message<K: KeyStore, A: AuthPolicy>(
     msg: &[u8],
     peer_info: Option<&PeerInfo>,
     session_bind_state: &SessionBindState,
+    key_filter: &KeyFilter,
     keystore: &Arc<K>,
+    session_keystore: &Arc<SessionKeyStore>,
+    agent_lock: &Arc<AgentLock>,
     auth_policy: &Arc<A>,
+    external_signer: &Arc<dyn ExternalSigner>,
+    upstream: Option<&Arc<dyn UpstreamAgent>>,
+    audit_sink: Option<&Arc<dyn AuditSink>>,
+    rate_limiter: &RateLimiter,
 ) -> Vec<u8> {
     let Some(message) = parse_message(msg) else {
         error!("Received malformed message");
         return failure();
     };
 
+    if agent_lock.is_locked() {
+        return handle_locked_message(message, agent_lock).await;
+    }
+
     match message {
-        AgentMessage::RequestIdentities => handle_list_request(keystore, auth_policy).await,
+        AgentMessage::RequestIdentities => {
+            handle_list_request(
+                peer_info,
+                session_bind_state,
+                keystore,
+                session_keystore,
+                key_filter,
+                auth_policy,
+                external_signer,
+                upstream,
+                audit_sink,
+            )
+            .await
+        }
         AgentMessage::SignRequest {
             public_key,
             data,
@@ -200,11 +283,44 @@ async fn handle_message<K: KeyStore, A: AuthPolicy>(
                 flags,
                 peer_info,
                 session_bind_state,
+                key_filter,
                 keystore,
+                session_keystore,
+                auth_policy,
+                external_signer,
+                upstream,
+                audit_sink,
+                rate_limiter,
+            )
+            .await
+        }
+        AgentMessage::AddIdentity {
+            private_key,
+            constraints,
+        } => {
+            let connection = connection_context(peer_info, session_bind_state);
+            handle_add_identity(
+                *private_key,
+                constraints,
+                connection,
+                session_keystore,
                 auth_policy,
             )
             .await
         }
+        AgentMessage::RemoveIdentity { public_key } => {
+            let connection = connection_context(peer_info, session_bind_state);
+            handle_remove_identity(public_key, connection, session_keystore, auth_policy).await
+        }
+        AgentMessage::RemoveAllIdentities => {
+            let connection = connection_context(peer_info, session_bind_state);
+            handle_remove_all_identities(connection, session_keystore, auth_policy).await
+        }
+        AgentMessage::Lock { passphrase } => handle_lock(passphrase, agent_lock).await,
+        AgentMessage::Unlock { .. } => {
+            debug!("Received unlock request while not locked");
+            failure()
+        }
         AgentMessage::Unknown(msg_type) => {
             debug!(msg_type, "Received unhandled message type");
             failure()
@@ -212,16 +328,79 @@ async fn handle_message<K: KeyStore, A: AuthPolicy>(
     }
 }
 
+/// Like OpenSSH, a locked agent lists no identities and refuses everything but unlocking.
+async fn handle_locked_message(message: AgentMessage, agent_lock: &Arc<AgentLock>) -> Vec<u8> {
+    match message {
+        AgentMessage::RequestIdentities => build_identities_answer(Vec::new()),
+        AgentMessage::Unlock { passphrase } => handle_unlock(passphrase, agent_lock).await,
+        _ => {
+            debug!("Agent is locked, refusing request");
+            failure()
+        }
+    }
+}
+
+async fn handle_lock(passphrase: Vec<u8>, agent_lock: &Arc<AgentLock>) -> Vec<u8> {
+    debug!("handling lock request");
+
+    let agent_lock = Arc::clone(agent_lock);
+    // Hashing the passphrase is deliberately slow
+    let result =
+        tokio::task::spawn_blocking(move || agent_lock.lock_with_passphrase(&passphrase)).await;
+
+    match result
+        .map_err(anyhow::Error::from)
+        .and_then(|result| result)
+    {
+        Ok(()) => success(),
+        Err(error) => {
+            warn!(%error, "Failed to lock agent");
+            failure()
+        }
+    }
+}
+
+async fn handle_unlock(passphrase: Vec<u8>, agent_lock: &Arc<AgentLock>) -> Vec<u8> {
+    debug!("handling unlock request");
+
+    let unlocking_lock = Arc::clone(agent_lock);
+    // Hashing the passphrase is deliberately slow
+    let result =
+        tokio::task::spawn_blocking(move || unlocking_lock.unlock_with_passphrase(&passphrase))
+            .await;
+
+    match result
+        .map_err(anyhow::Error::from)
+        .and_then(|result| result)
+    {
+        Ok(()) => success(),
+        Err(error) => {
+            warn!(%error, "Failed to unlock agent");
+            tokio::time::sleep(UNLOCK_FAILURE_DELAY).await;
+            failure()
+        }
+    }
+}
+
+#[allow(clippy::too_many_arguments)]
 async fn handle_list_request<K: KeyStore, A: AuthPolicy>(
+    peer_info: Option<&PeerInfo>,
+    session_bind_state: &SessionBindState,
     keystore: &Arc<K>,
+    session_keystore: &Arc<SessionKeyStore>,
+    key_filter: &KeyFilter,
     auth_policy: &Arc<A>,
+    external_signer: &Arc<dyn ExternalSigner>,
+    upstream: Option<&Arc<dyn UpstreamAgent>>,
+    audit_sink: Option<&Arc<dyn AuditSink>>,
 ) -> Vec<u8> {
     debug!("handling list request");
 
-    let Ok(authorized) = auth_policy
-        .authorize(&AuthRequest::List)
-        .await
-        .inspect_err(|error| error!(%error, "Authorization error for list request"))
+    let request = AuthRequest::List;
+    let result = auth_policy.authorize(&request).await;
+    record_audit(audit_sink, &request, &result, None, peer_info, keystore).await;
+    let Ok(authorized) =
+        result.inspect_err(|error| error!(%error, "Authorization error for list request"))
     else {
         return failure();
     };
@@ -231,46 +410,203 @@ async fn handle_list_request<K: KeyStore, A: AuthPolicy>(
         return failure();
     }
 
-    match keystore.get_all_public_keys_and_names() {
-        Ok(keys) => build_identities_answer(keys),
-        Err(error) => {
-            error!(%error, "Failed to retrieve keys from keystore");
-            failure()
+    let Ok(mut keys) = keystore
+        .get_all_public_keys_and_names()
+        .and_then(|keys| filter_keys(keys, keystore, key_filter))
+        .inspect_err(|error| error!(%error, "Failed to retrieve keys from keystore"))
+    else {
+        return failure();
+    };
+
+    let Ok(session_keys) = session_keystore
+        .get_all()
+        .inspect_err(|error| error!(%error, "Failed to retrieve keys from session keystore"))
+    else {
+        return failure();
+    };
+
+    // Like OpenSSH, keys whose destination constraints don't permit the hosts the connection is
+    // bound to aren't listed.
+    keys.extend(
+        session_keys
+            .into_iter()
+            .filter(|key_data| {
+                key_filter.permits_session_key(key_data.comment())
+                    && session_bind_state.permits(key_data.constraints(), false)
+            })
+            .map(|key_data| (key_data.public_key().clone(), key_data.comment().clone())),
+    );
+
+    // Security keys that can't be signed with aren't offered, so clients fall back to other keys.
+    if !external_signer.is_available() {
+        keys.retain(|(public_key, _)| !public_key.is_security_key());
+    }
+
+    // Keys of the upstream agent are offered after Bitwarden's. An unreachable upstream agent
+    // doesn't fail the request.
+    if let Some(upstream) = upstream {
+        match upstream.list_identities().await {
+            Ok(upstream_keys) => {
+                for (public_key, comment) in upstream_keys {
+                    if key_filter.permits_session_key(&comment)
+                        && !keys.iter().any(|(key, _)| *key == public_key)
+                    {
+                        keys.push((public_key, comment));
+                    }
+                }
+            }
+            Err(error) => warn!(%error, "Failed to list keys of the upstream agent"),
         }
     }
+
+    build_identities_answer(keys)
 }
 
+#[allow(clippy::too_many_arguments)]
 async fn handle_sign_request<K: KeyStore, A: AuthPolicy>(
     public_key: PublicKey,
     data: Vec<u8>,
     flags: Option<SignFlags>,
     peer_info: Option<&PeerInfo>,
     session_bind_state: &SessionBindState,
+    key_filter: &KeyFilter,
     keystore: &Arc<K>,
+    session_keystore: &Arc<SessionKeyStore>,
     auth_policy: &Arc<A>,
+    external_signer: &Arc<dyn ExternalSigner>,
+    upstream: Option<&Arc<dyn UpstreamAgent>>,
+    audit_sink: Option<&Arc<dyn AuditSink>>,
+    rate_limiter: &RateLimiter,
 ) -> Vec<u8> {
     debug!("handling sign request");
 
+    let Ok(session_key) = session_keystore
+        .get(&public_key)
+        .inspect_err(|error| error!(%error, "Failed to retrieve key from session keystore"))
+    else {
+        return failure();
+    };
+
+    let upstream_key_comment = match upstream {
+        Some(upstream) if session_key.is_none() => {
+            let Ok(comment) = find_upstream_key(&public_key, keystore, upstream)
+                .await
+                .inspect_err(|error| error!(%error, "Failed to look up key"))
+            else {
+                return failure();
+            };
+            comment
+        }
+        _ => None,
+    };
+
+    // Keys hidden from the listener are refused, so they never reach the user as a prompt.
+    let Ok(permitted) = (match &upstream_key_comment {
+        Some(comment) => Ok(key_filter.permits_session_key(comment)),
+        None => is_key_permitted(&public_key, session_key.as_ref(), keystore, key_filter),
+    })
+    .inspect_err(|error| error!(%error, "Failed to retrieve key from keystore")) else {
+        return failure();
+    };
+    if !permitted {
+        warn!("Key is not visible on this listener");
+        record_refused_sign(
+            audit_sink,
+            &public_key,
+            &data,
+            peer_info,
+            session_bind_state,
+            keystore,
+            AuditDenialReason::KeyFilter,
+        )
+        .await;
+        return failure();
+    }
+
+    // Refused before authorization, so the user is never asked to approve a signature that can't
+    // be made.
+    if upstream_key_comment.is_none()
+        && public_key.is_security_key()
+        && !external_signer.is_available()
+    {
+        warn!("No external signer is available for the security key");
+        record_refused_sign(
+            audit_sink,
+            &public_key,
+            &data,
+            peer_info,
+            session_bind_state,
+            keystore,
+            AuditDenialReason::NoExternalSigner,
+        )
+        .await;
+        return failure();
+    }
+
+    let constraints = session_key
+        .map(|key_data| key_data.constraints().clone())
+        .unwrap_or_default();
+
+    // Destination constraints are enforced here, so that requests for other hosts never reach
+    // the user as an approval prompt.
+    if !session_bind_state.permits(&constraints, true) {
+        warn!(
+            hops = ?session_bind_state.hops,
+            "Session key is not permitted for the bound hosts"
+        );
+        record_refused_sign(
+            audit_sink,
+            &public_key,
+            &data,
+            peer_info,
+            session_bind_state,
+            keystore,
+            AuditDenialReason::KeyConstraints,
+        )
+        .await;
+        return failure();
+    }
+
+    let connection = connection_context(peer_info, session_bind_state);
     let sign_request = SignRequest {
         public_key: public_key.clone(),
         namespace: detect_namespace(&data),
-        connection: ConnectionContext {
-            process_name: peer_info.map(|p| p.process_name().to_string()),
-            session_bind: if session_bind_state.host_fingerprint.is_empty() {
-                None
-            } else {
-                Some(SessionBindContext {
-                    is_forwarding: session_bind_state.is_forwarding,
-                    host_fingerprint: session_bind_state.host_fingerprint.clone(),
-                })
-            },
-        },
+        sshsig: parse_sshsig(&data),
+        connection,
+        certificate: certificate_context(&public_key),
+        confirm_required: constraints.confirm,
+        upstream_key_comment: upstream_key_comment.clone(),
     };
 
-    let Ok(authorized) = auth_policy
-        .authorize(&AuthRequest::Sign(sign_request))
-        .await
-        .inspect_err(|error| error!(%error, "Sign request authorization error"))
+    let request = AuthRequest::Sign(sign_request);
+    // Only requests that may prompt are limited, so that e.g. rebasing many signed commits with a
+    // remembered approval isn't cut short.
+    let approval_slot = if auth_policy.may_prompt(&request) {
+        let slot = rate_limiter
+            .check_sign_request(peer_info)
+            .then(|| rate_limiter.begin_approval())
+            .flatten();
+        if slot.is_none() {
+            record_audit(
+                audit_sink,
+                &request,
+                &Ok(false),
+                Some(AuditDenialReason::RateLimit),
+                peer_info,
+                keystore,
+            )
+            .await;
+            return failure();
+        }
+        slot
+    } else {
+        None
+    };
+    let result = auth_policy.authorize(&request).await;
+    drop(approval_slot);
+    record_audit(audit_sink, &request, &result, None, peer_info, keystore).await;
+    let Ok(authorized) =
+        result.inspect_err(|error| error!(%error, "Sign request authorization error"))
     else {
         return failure();
     };
@@ -280,8 +616,25 @@ async fn handle_sign_request<K: KeyStore, A: AuthPolicy>(
         return failure();
     }
 
+    if let (Some(upstream), Some(_)) = (upstream, upstream_key_comment) {
+        return match upstream
+            .sign(&public_key, &data, flags, &session_bind_state.payloads)
+            .await
+        {
+            Ok(sig_blob) => build_raw_sign_response(&sig_blob),
+            Err(error) => {
+                warn!(%error, "Upstream agent failed to sign");
+                failure()
+            }
+        };
+    }
+
     let Ok(maybe_key) = keystore
         .get_private_key(&public_key)
+        .and_then(|key| match key {
+            Some(key) => Ok(Some(key)),
+            None => session_keystore.get_private_key(&public_key),
+        })
         .inspect_err(|error| error!(%error, "Failed to retrieve key from keystore"))
     else {
         return failure();
@@ -292,6 +645,13 @@ async fn handle_sign_request<K: KeyStore, A: AuthPolicy>(
         return failure();
     };
 
+    if let PrivateKey::SecurityKey(key) = private_key {
+        return match sign_with_security_key(&key, &data, external_signer).await {
+            Some(signature) => build_sign_response(&signature),
+            None => failure(),
+        };
+    }
+
     let Ok(signing_key) = SignablePrivateKey::try_from((private_key, flags)).inspect_err(
         |error| warn!(%error, ?flags, "Unable to create signable key with provided request input"),
     ) else {
@@ -301,6 +661,365 @@ async fn handle_sign_request<K: KeyStore, A: AuthPolicy>(
     build_sign_response(&signing_key.sign(&data))
 }
 
+/// # Returns
+///
+/// The vault keys in `keys` that `key_filter` permits.
+fn filter_keys<K: KeyStore>(
+    keys: Vec<(PublicKey, String)>,
+    keystore: &Arc<K>,
+    key_filter: &KeyFilter,
+) -> anyhow::Result<Vec<(PublicKey, String)>> {
+    if key_filter.is_unrestricted() {
+        return Ok(keys);
+    }
+
+    let mut permitted = Vec::with_capacity(keys.len());
+    for (public_key, name) in keys {
+        if keystore
+            .get(&public_key)?
+            .is_some_and(|key_data| key_filter.permits(&key_data))
+        {
+            permitted.push((public_key, name));
+        }
+    }
+    Ok(permitted)
+}
+
+/// Records the resolution of a list or sign `request` with `audit_sink`, if there is one. Failing
+/// to record is logged, and doesn't fail the request.
+async fn record_audit<K: KeyStore>(
+    audit_sink: Option<&Arc<dyn AuditSink>>,
+    request: &AuthRequest,
+    result: &Result<bool, AuthError>,
+    denied_by: Option<AuditDenialReason>,
+    peer_info: Option<&PeerInfo>,
+    keystore: &Arc<K>,
+) {
+    let Some(audit_sink) = audit_sink else {
+        return;
+    };
+
+    let mut entry = AuditEntry {
+        timestamp_ms: unix_time_ms(),
+        operation: AuditOperation::List,
+        pid: peer_info.map(PeerInfo::pid),
+        process_name: peer_info.map(|peer_info| peer_info.process_name().to_string()),
+        key_fingerprint: None,
+        cipher_id: None,
+        namespace: None,
+        host_fingerprint: None,
+        is_forwarding: false,
+        decision: match result {
+            Ok(true) => AuditDecision::Allowed,
+            Ok(false) => AuditDecision::Denied,
+            Err(_) => AuditDecision::Error,
+        },
+        denied_by,
+    };
+    match request {
+        AuthRequest::List => {}
+        AuthRequest::Sign(sign_request) => {
+            let session_bind = sign_request.connection.session_bind.as_ref();
+            entry.operation = AuditOperation::Sign;
+            entry.key_fingerprint = sign_request.public_key.fingerprint();
+            entry.cipher_id = keystore
+                .get(&sign_request.public_key)
+                .ok()
+                .flatten()
+                .map(|key_data| key_data.cipher_id().clone());
+            entry.namespace = sign_request
+                .namespace
+                .as_ref()
+                .and_then(|namespace| namespace.as_str())
+                .map(str::to_string);
+            entry.host_fingerprint = session_bind.map(|s| s.host_fingerprint.clone());
+            entry.is_forwarding = session_bind.is_some_and(|s| s.is_forwarding);
+        }
+        AuthRequest::ModifyIdentities(_) => return,
+    }
+
+    // Sinks may write to disk, so they are called on a blocking thread. Awaiting the write keeps
+    // the entries of a connection in order.
+    let audit_sink = audit_sink.clone();
+    let result = tokio::task::spawn_blocking(move || audit_sink.record(&entry))
+        .await
+        .map_err(anyhow::Error::from)
+        .flatten();
+    if let Err(error) = result {
+        warn!(%error, "Failed to record audit entry");
+    }
+}
+
+/// Records a sign request for `public_key` that was refused before reaching the authorization
+/// policy.
+async fn record_refused_sign<K: KeyStore>(
+    audit_sink: Option<&Arc<dyn AuditSink>>,
+    public_key: &PublicKey,
+    data: &[u8],
+    peer_info: Option<&PeerInfo>,
+    session_bind_state: &SessionBindState,
+    keystore: &Arc<K>,
+    denied_by: AuditDenialReason,
+) {
+    if audit_sink.is_none() {
+        return;
+    }
+
+    let request = AuthRequest::Sign(SignRequest {
+        public_key: public_key.clone(),
+        namespace: detect_namespace(data),
+        sshsig: parse_sshsig(data),
+        connection: connection_context(peer_info, session_bind_state),
+        certificate: certificate_context(public_key),
+        confirm_required: false,
+        upstream_key_comment: None,
+    });
+    record_audit(
+        audit_sink,
+        &request,
+        &Ok(false),
+        Some(denied_by),
+        peer_info,
+        keystore,
+    )
+    .await;
+}
+
+/// # Returns
+///
+/// The comment of `public_key` if it is held by the upstream agent rather than the vault, `None`
+/// otherwise.
+async fn find_upstream_key<K: KeyStore>(
+    public_key: &PublicKey,
+    keystore: &Arc<K>,
+    upstream: &Arc<dyn UpstreamAgent>,
+) -> anyhow::Result<Option<String>> {
+    if keystore.get(public_key)?.is_some() {
+        return Ok(None);
+    }
+
+    Ok(upstream
+        .list_identities()
+        .await?
+        .into_iter()
+        .find(|(key, _)| key == public_key)
+        .map(|(_, comment)| comment))
+}
+
+/// # Returns
+///
+/// `true` if `key_filter` permits signing with `public_key`, either as a vault key or as the
+/// client-added `session_key`.
+fn is_key_permitted<K: KeyStore>(
+    public_key: &PublicKey,
+    session_key: Option<&SessionKeyData>,
+    keystore: &Arc<K>,
+    key_filter: &KeyFilter,
+) -> anyhow::Result<bool> {
+    if key_filter.is_unrestricted()
+        || session_key.is_some_and(|key_data| key_filter.permits_session_key(key_data.comment()))
+    {
+        return Ok(true);
+    }
+
+    Ok(keystore
+        .get(public_key)?
+        .is_some_and(|key_data| key_filter.permits(&key_data)))
+}
+
+/// Delegates signing to the device holding the security key, and checks the signature it returns
+/// before passing it on to the client.
+async fn sign_with_security_key(
+    key: &SecurityKey,
+    data: &[u8],
+    external_signer: &Arc<dyn ExternalSigner>,
+) -> Option<Signature> {
+    let signature = external_signer
+        .sign(key, data)
+        .await
+        .inspect_err(|error| warn!(%error, "External signer failed to sign"))
+        .ok()?;
+
+    let public_key = key.public_key();
+    if signature.algorithm() != public_key.algorithm()
+        || public_key.verify(data, &signature).is_err()
+    {
+        warn!("External signer returned an invalid signature");
+        return None;
+    }
+
+    Some(signature)
+}
+
+async fn handle_add_identity<A: AuthPolicy>(
+    private_key: ssh_key::PrivateKey,
+    constraints: KeyConstraints,
+    connection: ConnectionContext,
+    session_keystore: &Arc<SessionKeyStore>,
+    auth_policy: &Arc<A>,
+) -> Vec<u8> {
+    debug!("handling add identity request");
+
+    let Ok(key_data) = SessionKeyData::try_from(private_key)
+        .inspect_err(|error| warn!(%error, "Unable to add identity with provided key"))
+    else {
+        return failure();
+    };
+    let key_data = key_data.with_constraints(constraints);
+
+    let request = IdentityRequest {
+        operation: IdentityOperation::Add {
+            public_key: key_data.public_key().clone(),
+            comment: key_data.comment().clone(),
+            constraints: key_data.constraints().clone(),
+        },
+        connection,
+    };
+    if !authorize_identity_request(request, auth_policy).await {
+        return failure();
+    }
+
+    let lifetime = key_data.constraints().lifetime;
+    match session_keystore.insert(key_data) {
+        Ok(()) => {
+            info!(?lifetime, "Session identity added.");
+            if let Some(lifetime) = lifetime {
+                let session_keystore = session_keystore.clone();
+                tokio::spawn(async move {
+                    tokio::time::sleep(lifetime).await;
+                    session_keystore.evict_expired();
+                });
+            }
+            success()
+        }
+        Err(error) => {
+            error!(%error, "Failed to store session identity");
+            failure()
+        }
+    }
+}
+
+async fn handle_remove_identity<A: AuthPolicy>(
+    public_key: PublicKey,
+    connection: ConnectionContext,
+    session_keystore: &Arc<SessionKeyStore>,
+    auth_policy: &Arc<A>,
+) -> Vec<u8> {
+    debug!("handling remove identity request");
+
+    let request = IdentityRequest {
+        operation: IdentityOperation::Remove {
+            public_key: public_key.clone(),
+        },
+        connection,
+    };
+    if !authorize_identity_request(request, auth_policy).await {
+        return failure();
+    }
+
+    // Vault keys are managed by the vault and can't be removed by clients.
+    if session_keystore.remove(&public_key) {
+        info!("Session identity removed.");
+        success()
+    } else {
+        debug!("Identity to remove is not a session identity");
+        failure()
+    }
+}
+
+async fn handle_remove_all_identities<A: AuthPolicy>(
+    connection: ConnectionContext,
+    session_keystore: &Arc<SessionKeyStore>,
+    auth_policy: &Arc<A>,
+) -> Vec<u8> {
+    debug!("handling remove all identities request");
+
+    let request = IdentityRequest {
+        operation: IdentityOperation::RemoveAll,
+        connection,
+    };
+    if !authorize_identity_request(request, auth_policy).await {
+        return failure();
+    }
+
+    session_keystore.clear();
+    info!("All session identities removed.");
+    success()
+}
+
+/// # Returns
+///
+/// `true` if the auth policy approved the identity request, `false` if it was denied or errored.
+async fn authorize_identity_request<A: AuthPolicy>(
+    request: IdentityRequest,
+    auth_policy: &Arc<A>,
+) -> bool {
+    let Ok(authorized) = auth_policy
+        .authorize(&AuthRequest::ModifyIdentities(request))
+        .await
+        .inspect_err(|error| error!(%error, "Identity request authorization error"))
+    else {
+        return false;
+    };
+
+    if !authorized {
+        info!("Identity request denied.");
+    }
+    authorized
+}
+
+/// Builds the [`ConnectionContext`] for an authorization request from the peer and session-bind
+/// state of the connection.
+fn connection_context(
+    peer_info: Option<&PeerInfo>,
+    session_bind_state: &SessionBindState,
+) -> ConnectionContext {
+    ConnectionContext {
+        process_name: peer_info.map(|p| p.process_name().to_string()),
+        process_ancestry: peer_info
+            .map(|p| p.ancestor_pids().to_vec())
+            .unwrap_or_default(),
+        process_ancestry_names: peer_info
+            .map(|p| p.ancestor_names().to_vec())
+            .unwrap_or_default(),
+        process_path: peer_info.and_then(|p| p.exe_path().map(str::to_string)),
+        process_args: peer_info.map(|p| p.args().to_vec()).unwrap_or_default(),
+        process_uid: peer_info.and_then(PeerInfo::uid),
+        sandbox: peer_info.and_then(PeerInfo::sandbox),
+        session_bind: if session_bind_state.host_fingerprint.is_empty() {
+            None
+        } else {
+            Some(SessionBindContext {
+                is_forwarding: session_bind_state.is_forwarding,
+                host_fingerprint: session_bind_state.host_fingerprint.clone(),
+                hops: session_bind_state.hops.clone(),
+            })
+        },
+    }
+}
+
+/// Extracts the [`CertificateContext`] from a certificate public key.
+///
+/// # Returns
+///
+/// `None` if the public key is not a certificate or the certificate cannot be parsed.
+fn certificate_context(public_key: &PublicKey) -> Option<CertificateContext> {
+    if !public_key.is_certificate() {
+        return None;
+    }
+
+    let certificate = ssh_key::Certificate::from_bytes(&public_key.blob)
+        .inspect_err(|error| warn!(%error, "Unable to parse certificate in sign request"))
+        .ok()?;
+
+    Some(CertificateContext {
+        key_id: certificate.key_id().to_string(),
+        principals: certificate.valid_principals().to_vec(),
+        valid_after: certificate.valid_after(),
+        valid_before: certificate.valid_before(),
+    })
+}
+
 #[cfg(test)]
 mod tests {
     use std::sync::Arc;
@@ -308,10 +1027,29 @@ mod tests {
     use mockall::predicate::eq;
 
     use crate::{
+        audit::{
+            AuditDecision, AuditDenialReason, AuditEntry, AuditOperation, AuditSink, MockAuditSink,
+        },
         authorization::AuthError,
         crypto::{PrivateKey, PublicKey},
-        server::{session_bind::SessionBindState, AuthPolicy, AuthRequest},
-        storage::keystore::MockKeyStore,
+        external_signer::{ExternalSigner, MockExternalSigner},
+        server::{
+            peer_info::PeerInfo,
+            protocol::{build_raw_sign_response, parse_identities_answer, SignFlags, EXTENSION},
+            rate_limit::{RateLimiter, RateLimits},
+            session_bind::SessionBindState,
+            upstream::{MockUpstreamAgent, UpstreamAgent},
+            AuthPolicy, AuthRequest,
+        },
+        storage::{
+            agent_lock::AgentLock,
+            key_filter::KeyFilter,
+            keydata::{QueryableKeyData as _, SSHKeyData},
+            keystore::{InMemoryEncryptedKeyStore, KeyStore as _, MockKeyStore},
+            session_keystore::{
+                DestinationConstraint, KeyConstraints, SessionKeyData, SessionKeyStore,
+            },
+        },
     };
     const FAILURE: u8 = 5;
     const SUCCESS: u8 = 6;
@@ -319,9 +1057,14 @@ mod tests {
     const IDENTITIES_ANSWER: u8 = 12;
     const SIGN_REQUEST: u8 = 13;
     const SIGN_RESPONSE: u8 = 14;
+    const REMOVE_ALL_IDENTITIES: u8 = 19;
+    const LOCK: u8 = 22;
+    const UNLOCK: u8 = 23;
 
     use crate::server::test_common::{
-        make_minimal_ed25519_blob, make_session_bind_payload_ed25519, make_sign_request_msg,
+        make_add_id_constrained_msg, make_add_identity_msg, make_minimal_ed25519_blob,
+        make_remove_identity_msg, make_restrict_destination_constraint,
+        make_session_bind_payload_ed25519, make_sign_request_msg, unavailable_external_signer,
         write_ssh_string, AlwaysAllowPolicy,
     };
 
@@ -371,6 +1114,23 @@ mod tests {
         }
     }
 
+    /// Allows every request without asking the user, as if from a remembered approval.
+    struct RememberedApprovalPolicy {
+        calls: std::sync::atomic::AtomicUsize,
+    }
+
+    #[async_trait::async_trait]
+    impl AuthPolicy for RememberedApprovalPolicy {
+        async fn authorize(&self, _: &AuthRequest) -> Result<bool, AuthError> {
+            self.calls.fetch_add(1, std::sync::atomic::Ordering::SeqCst);
+            Ok(true)
+        }
+
+        fn may_prompt(&self, _: &AuthRequest) -> bool {
+            false
+        }
+    }
+
     #[tokio::test]
     async fn unknown_message_type_returns_failure() {
         let keystore = Arc::new(MockKeyStore::new());
@@ -380,8 +1140,15 @@ mod tests {
             &[99u8],
             None,
             &SessionBindState::default(),
+            &KeyFilter::default(),
             &keystore,
+            &Arc::new(SessionKeyStore::new()),
+            &Arc::new(AgentLock::new()),
             &auth_policy,
+            &unavailable_external_signer(),
+            None,
+            None,
+            &RateLimiter::default(),
         )
         .await;
 
@@ -409,8 +1176,15 @@ mod tests {
             &[REQUEST_IDENTITIES],
             None,
             &SessionBindState::default(),
+            &KeyFilter::default(),
             &Arc::new(keystore),
+            &Arc::new(SessionKeyStore::new()),
+            &Arc::new(AgentLock::new()),
             &auth_policy,
+            &unavailable_external_signer(),
+            None,
+            None,
+            &RateLimiter::default(),
         )
         .await;
 
@@ -427,8 +1201,15 @@ mod tests {
             &[REQUEST_IDENTITIES],
             None,
             &SessionBindState::default(),
+            &KeyFilter::default(),
             &Arc::new(keystore),
+            &Arc::new(SessionKeyStore::new()),
+            &Arc::new(AgentLock::new()),
             &auth_policy,
+            &unavailable_external_signer(),
+            None,
+            None,
+            &RateLimiter::default(),
         )
         .await;
 
@@ -448,8 +1229,15 @@ mod tests {
             &[REQUEST_IDENTITIES],
             None,
             &SessionBindState::default(),
+            &KeyFilter::default(),
             &Arc::new(keystore),
+            &Arc::new(SessionKeyStore::new()),
+            &Arc::new(AgentLock::new()),
             &auth_policy,
+            &unavailable_external_signer(),
+            None,
+            None,
+            &RateLimiter::default(),
         )
         .await;
 
@@ -482,27 +1270,347 @@ mod tests {
             &msg,
             None,
             &SessionBindState::default(),
+            &KeyFilter::default(),
             &Arc::new(keystore),
+            &Arc::new(SessionKeyStore::new()),
+            &Arc::new(AgentLock::new()),
             &auth_policy,
+            &unavailable_external_signer(),
+            None,
+            None,
+            &RateLimiter::default(),
         )
         .await;
 
         assert_eq!(response[0], SIGN_RESPONSE);
     }
 
-    #[tokio::test]
-    async fn sign_request_when_auth_denied_returns_failure() {
-        let keystore = Arc::new(MockKeyStore::new());
-        let auth_policy = Arc::new(AlwaysDenyPolicy);
-        let blob = make_minimal_ed25519_blob();
-        let msg = make_sign_request_msg(&blob, b"test data", 0);
-
-        let response = super::handle_message(
+    /// Sends a sign request for a key missing from the keystore, returning whether it reached the
+    /// auth policy.
+    async fn sign_request_reaches_auth_policy(rate_limiter: &RateLimiter) -> bool {
+        let mut keystore = MockKeyStore::new();
+        keystore.expect_get_private_key().returning(|_| Ok(None));
+        let auth_policy = Arc::new(CapturingAuthPolicy {
+            captured: std::sync::Mutex::new(None),
+        });
+        let msg = make_sign_request_msg(&make_minimal_ed25519_blob(), b"test data", 0);
+
+        let response = super::handle_message(
+            &msg,
+            None,
+            &SessionBindState::default(),
+            &KeyFilter::default(),
+            &Arc::new(keystore),
+            &Arc::new(SessionKeyStore::new()),
+            &Arc::new(AgentLock::new()),
+            &auth_policy,
+            &unavailable_external_signer(),
+            None,
+            None,
+            rate_limiter,
+        )
+        .await;
+
+        assert_eq!(response, vec![FAILURE]);
+        let reached = auth_policy.captured.lock().unwrap().is_some();
+        reached
+    }
+
+    #[tokio::test]
+    async fn sign_request_beyond_rate_limit_returns_failure_without_authorization() {
+        let rate_limiter = RateLimiter::new(RateLimits {
+            max_sign_requests: 1,
+            ..RateLimits::default()
+        });
+
+        assert!(sign_request_reaches_auth_policy(&rate_limiter).await);
+        assert!(!sign_request_reaches_auth_policy(&rate_limiter).await);
+        assert_eq!(rate_limiter.stats().rate_limited_sign_requests, 1);
+    }
+
+    #[tokio::test]
+    async fn sign_request_when_approvals_exhausted_returns_failure_without_authorization() {
+        let rate_limiter = RateLimiter::new(RateLimits {
+            max_pending_approvals: 1,
+            ..RateLimits::default()
+        });
+
+        let pending = rate_limiter.begin_approval();
+        assert!(!sign_request_reaches_auth_policy(&rate_limiter).await);
+        drop(pending);
+        assert!(sign_request_reaches_auth_policy(&rate_limiter).await);
+        assert_eq!(rate_limiter.stats().pending_approvals, 0);
+    }
+
+    #[tokio::test]
+    async fn sign_request_without_prompt_is_not_rate_limited() {
+        let rate_limiter = RateLimiter::new(RateLimits {
+            max_sign_requests: 0,
+            max_pending_approvals: 0,
+            ..RateLimits::default()
+        });
+        let mut keystore = MockKeyStore::new();
+        keystore.expect_get_private_key().returning(|_| Ok(None));
+        let keystore = Arc::new(keystore);
+        let auth_policy = Arc::new(RememberedApprovalPolicy {
+            calls: std::sync::atomic::AtomicUsize::new(0),
+        });
+        let msg = make_sign_request_msg(&make_minimal_ed25519_blob(), b"test data", 0);
+
+        for _ in 0..3 {
+            super::handle_message(
+                &msg,
+                None,
+                &SessionBindState::default(),
+                &KeyFilter::default(),
+                &keystore,
+                &Arc::new(SessionKeyStore::new()),
+                &Arc::new(AgentLock::new()),
+                &auth_policy,
+                &unavailable_external_signer(),
+                None,
+                None,
+                &rate_limiter,
+            )
+            .await;
+        }
+
+        assert_eq!(
+            auth_policy.calls.load(std::sync::atomic::Ordering::SeqCst),
+            3
+        );
+        assert_eq!(rate_limiter.stats().rate_limited_sign_requests, 0);
+    }
+
+    async fn sign_with_security_key_using(external_signer: MockExternalSigner) -> Vec<u8> {
+        use ssh_key::{private::Ed25519Keypair, rand_core::OsRng};
+
+        use crate::crypto::test_common::make_sk_ed25519_key;
+
+        let sk_key = make_sk_ed25519_key(&Ed25519Keypair::random(&mut OsRng));
+        let blob = sk_key.public_key().to_bytes().unwrap();
+        let private_key = PrivateKey::try_from(sk_key).unwrap();
+        let mut keystore = MockKeyStore::new();
+        keystore
+            .expect_get_private_key()
+            .once()
+            .return_once(move |_| Ok(Some(private_key)));
+        let external_signer: Arc<dyn ExternalSigner> = Arc::new(external_signer);
+
+        super::handle_message(
+            &make_sign_request_msg(&blob, b"test data", 0),
+            None,
+            &SessionBindState::default(),
+            &KeyFilter::default(),
+            &Arc::new(keystore),
+            &Arc::new(SessionKeyStore::new()),
+            &Arc::new(AgentLock::new()),
+            &Arc::new(AlwaysAllowPolicy),
+            &external_signer,
+            None,
+            None,
+            &RateLimiter::default(),
+        )
+        .await
+    }
+
+    #[tokio::test]
+    async fn sign_request_with_security_key_is_signed_by_external_signer() {
+        use ssh_key::{private::Ed25519Keypair, rand_core::OsRng};
+
+        use crate::crypto::test_common::{make_sk_ed25519_key, sign_with_sk_ed25519};
+
+        let keypair = Ed25519Keypair::random(&mut OsRng);
+        let sk_key = make_sk_ed25519_key(&keypair);
+        let blob = sk_key.public_key().to_bytes().unwrap();
+        let private_key = PrivateKey::try_from(sk_key.clone()).unwrap();
+        let mut keystore = MockKeyStore::new();
+        keystore
+            .expect_get_private_key()
+            .once()
+            .return_once(move |_| Ok(Some(private_key)));
+        let mut external_signer = MockExternalSigner::new();
+        external_signer.expect_is_available().return_const(true);
+        external_signer
+            .expect_sign()
+            .withf(|key, data| key.key_handle() == [0xAB; 32] && data == b"test data")
+            .once()
+            .returning(move |_, data| Ok(sign_with_sk_ed25519(&keypair, &sk_key, data)));
+        let external_signer: Arc<dyn ExternalSigner> = Arc::new(external_signer);
+
+        let response = super::handle_message(
+            &make_sign_request_msg(&blob, b"test data", 0),
+            None,
+            &SessionBindState::default(),
+            &KeyFilter::default(),
+            &Arc::new(keystore),
+            &Arc::new(SessionKeyStore::new()),
+            &Arc::new(AgentLock::new()),
+            &Arc::new(AlwaysAllowPolicy),
+            &external_signer,
+            None,
+            None,
+            &RateLimiter::default(),
+        )
+        .await;
+
+        assert_eq!(response[0], SIGN_RESPONSE);
+        let sig = ssh_key::Signature::try_from(&response[5..]).unwrap();
+        assert_eq!(sig.algorithm(), ssh_key::Algorithm::SkEd25519);
+    }
+
+    #[tokio::test]
+    async fn sign_request_with_security_key_when_external_signer_fails_returns_failure() {
+        let mut external_signer = MockExternalSigner::new();
+        external_signer.expect_is_available().return_const(true);
+        external_signer
+            .expect_sign()
+            .once()
+            .returning(|_, _| Err(anyhow::anyhow!("device unavailable")));
+
+        let response = sign_with_security_key_using(external_signer).await;
+
+        assert_eq!(response, vec![FAILURE]);
+    }
+
+    #[tokio::test]
+    async fn sign_request_with_security_key_when_signature_is_invalid_returns_failure() {
+        use ssh_key::{private::Ed25519Keypair, rand_core::OsRng};
+
+        use crate::crypto::test_common::{make_sk_ed25519_key, sign_with_sk_ed25519};
+
+        // Signed by a different device than the one holding the requested key.
+        let other_keypair = Ed25519Keypair::random(&mut OsRng);
+        let other_key = make_sk_ed25519_key(&other_keypair);
+        let mut external_signer = MockExternalSigner::new();
+        external_signer.expect_is_available().return_const(true);
+        external_signer
+            .expect_sign()
+            .once()
+            .returning(move |_, data| Ok(sign_with_sk_ed25519(&other_keypair, &other_key, data)));
+
+        let response = sign_with_security_key_using(external_signer).await;
+
+        assert_eq!(response, vec![FAILURE]);
+    }
+
+    #[tokio::test]
+    async fn sign_request_with_security_key_without_external_signer_is_refused_before_authorization(
+    ) {
+        use ssh_key::{private::Ed25519Keypair, rand_core::OsRng};
+
+        use crate::crypto::test_common::make_sk_ed25519_key;
+
+        let sk_key = make_sk_ed25519_key(&Ed25519Keypair::random(&mut OsRng));
+        let blob = sk_key.public_key().to_bytes().unwrap();
+        let mut keystore = MockKeyStore::new();
+        keystore.expect_get_private_key().never();
+        let auth_policy = Arc::new(CapturingAuthPolicy {
+            captured: std::sync::Mutex::new(None),
+        });
+
+        let response = super::handle_message(
+            &make_sign_request_msg(&blob, b"test data", 0),
+            None,
+            &SessionBindState::default(),
+            &KeyFilter::default(),
+            &Arc::new(keystore),
+            &Arc::new(SessionKeyStore::new()),
+            &Arc::new(AgentLock::new()),
+            &auth_policy,
+            &unavailable_external_signer(),
+            None,
+            None,
+            &RateLimiter::default(),
+        )
+        .await;
+
+        assert_eq!(response, vec![FAILURE]);
+        assert!(auth_policy.captured.lock().unwrap().is_none());
+    }
+
+    async fn list_with_external_signer(external_signer: &Arc<dyn ExternalSigner>) -> Vec<String> {
+        use ssh_key::{private::Ed25519Keypair, rand_core::OsRng};
+
+        use crate::crypto::test_common::make_sk_ed25519_key;
+
+        let software_key = session_public_key(&make_session_private_key("Software Key"));
+        let security_key =
+            session_public_key(&make_sk_ed25519_key(&Ed25519Keypair::random(&mut OsRng)));
+        let mut keystore = MockKeyStore::new();
+        keystore
+            .expect_get_all_public_keys_and_names()
+            .once()
+            .return_once(move || {
+                Ok(vec![
+                    (software_key, "Software Key".to_string()),
+                    (security_key, "Security Key".to_string()),
+                ])
+            });
+
+        let response = super::handle_message(
+            &[REQUEST_IDENTITIES],
+            None,
+            &SessionBindState::default(),
+            &KeyFilter::default(),
+            &Arc::new(keystore),
+            &Arc::new(SessionKeyStore::new()),
+            &Arc::new(AgentLock::new()),
+            &Arc::new(AlwaysAllowPolicy),
+            external_signer,
+            None,
+            None,
+            &RateLimiter::default(),
+        )
+        .await;
+        parse_identities_answer(&response)
+            .unwrap()
+            .into_iter()
+            .map(|(_, comment)| comment)
+            .collect()
+    }
+
+    #[tokio::test]
+    async fn list_request_without_external_signer_hides_security_keys() {
+        let comments = list_with_external_signer(&unavailable_external_signer()).await;
+
+        assert_eq!(comments, vec!["Software Key".to_string()]);
+    }
+
+    #[tokio::test]
+    async fn list_request_with_external_signer_includes_security_keys() {
+        let mut external_signer = MockExternalSigner::new();
+        external_signer.expect_is_available().return_const(true);
+        let external_signer: Arc<dyn ExternalSigner> = Arc::new(external_signer);
+
+        let comments = list_with_external_signer(&external_signer).await;
+
+        assert_eq!(
+            comments,
+            vec!["Software Key".to_string(), "Security Key".to_string()]
+        );
+    }
+
+    #[tokio::test]
+    async fn sign_request_when_auth_denied_returns_failure() {
+        let keystore = Arc::new(MockKeyStore::new());
+        let auth_policy = Arc::new(AlwaysDenyPolicy);
+        let blob = make_minimal_ed25519_blob();
+        let msg = make_sign_request_msg(&blob, b"test data", 0);
+
+        let response = super::handle_message(
             &msg,
             None,
             &SessionBindState::default(),
+            &KeyFilter::default(),
             &keystore,
+            &Arc::new(SessionKeyStore::new()),
+            &Arc::new(AgentLock::new()),
             &auth_policy,
+            &unavailable_external_signer(),
+            None,
+            None,
+            &RateLimiter::default(),
         )
         .await;
 
@@ -520,8 +1628,15 @@ mod tests {
             &msg,
             None,
             &SessionBindState::default(),
+            &KeyFilter::default(),
             &keystore,
+            &Arc::new(SessionKeyStore::new()),
+            &Arc::new(AgentLock::new()),
             &auth_policy,
+            &unavailable_external_signer(),
+            None,
+            None,
+            &RateLimiter::default(),
         )
         .await;
 
@@ -544,8 +1659,15 @@ mod tests {
             &msg,
             None,
             &SessionBindState::default(),
+            &KeyFilter::default(),
             &Arc::new(keystore),
+            &Arc::new(SessionKeyStore::new()),
+            &Arc::new(AgentLock::new()),
             &auth_policy,
+            &unavailable_external_signer(),
+            None,
+            None,
+            &RateLimiter::default(),
         )
         .await;
 
@@ -568,8 +1690,15 @@ mod tests {
             &msg,
             None,
             &SessionBindState::default(),
+            &KeyFilter::default(),
             &Arc::new(keystore),
+            &Arc::new(SessionKeyStore::new()),
+            &Arc::new(AgentLock::new()),
             &auth_policy,
+            &unavailable_external_signer(),
+            None,
+            None,
+            &RateLimiter::default(),
         )
         .await;
 
@@ -603,8 +1732,15 @@ mod tests {
             &msg,
             None,
             &SessionBindState::default(),
+            &KeyFilter::default(),
             &Arc::new(keystore),
+            &Arc::new(SessionKeyStore::new()),
+            &Arc::new(AgentLock::new()),
             &auth_policy,
+            &unavailable_external_signer(),
+            None,
+            None,
+            &RateLimiter::default(),
         )
         .await;
 
@@ -642,8 +1778,15 @@ mod tests {
             &msg,
             None,
             &SessionBindState::default(),
+            &KeyFilter::default(),
             &Arc::new(keystore),
+            &Arc::new(SessionKeyStore::new()),
+            &Arc::new(AgentLock::new()),
             &auth_policy,
+            &unavailable_external_signer(),
+            None,
+            None,
+            &RateLimiter::default(),
         )
         .await;
 
@@ -759,8 +1902,21 @@ mod tests {
             captured: std::sync::Mutex::new(None),
         });
 
-        let _ =
-            super::handle_message(&msg, None, &state, &Arc::new(keystore), &capturing_policy).await;
+        let _ = super::handle_message(
+            &msg,
+            None,
+            &state,
+            &KeyFilter::default(),
+            &Arc::new(keystore),
+            &Arc::new(SessionKeyStore::new()),
+            &Arc::new(AgentLock::new()),
+            &capturing_policy,
+            &unavailable_external_signer(),
+            None,
+            None,
+            &RateLimiter::default(),
+        )
+        .await;
 
         let captured = capturing_policy.captured.lock().unwrap();
         if let Some(AuthRequest::Sign(sign_req)) = captured.as_ref() {
@@ -803,8 +1959,21 @@ mod tests {
             captured: std::sync::Mutex::new(None),
         });
 
-        let _ =
-            super::handle_message(&msg, None, &state, &Arc::new(keystore), &capturing_policy).await;
+        let _ = super::handle_message(
+            &msg,
+            None,
+            &state,
+            &KeyFilter::default(),
+            &Arc::new(keystore),
+            &Arc::new(SessionKeyStore::new()),
+            &Arc::new(AgentLock::new()),
+            &capturing_policy,
+            &unavailable_external_signer(),
+            None,
+            None,
+            &RateLimiter::default(),
+        )
+        .await;
 
         let captured = capturing_policy.captured.lock().unwrap();
         if let Some(AuthRequest::Sign(sign_req)) = captured.as_ref() {
@@ -817,6 +1986,15 @@ mod tests {
                 Some(expected_fingerprint.as_str()),
                 "host_fingerprint must propagate to auth request"
             );
+            assert_eq!(
+                sign_req
+                    .connection
+                    .session_bind
+                    .as_ref()
+                    .map(|s| s.hops.clone()),
+                Some(state.hops.clone()),
+                "hops must propagate to auth request"
+            );
         } else {
             panic!("expected Sign auth request to be captured");
         }
@@ -841,8 +2019,15 @@ mod tests {
             &msg,
             None,
             &SessionBindState::default(),
+            &KeyFilter::default(),
             &Arc::new(keystore),
+            &Arc::new(SessionKeyStore::new()),
+            &Arc::new(AgentLock::new()),
             &capturing_policy,
+            &unavailable_external_signer(),
+            None,
+            None,
+            &RateLimiter::default(),
         )
         .await;
 
@@ -858,34 +2043,1744 @@ mod tests {
     }
 
     #[tokio::test]
-    async fn oversized_message_length_closes_connection_without_panic() {
-        use tokio::io::{duplex, AsyncWriteExt};
-        use tokio_util::sync::CancellationToken;
+    async fn sshsig_sign_request_propagates_parsed_sshsig_data() {
+        use crate::server::{test_common::write_ssh_string, SIGNamespace};
+
+        let mut data = b"SSHSIG".to_vec();
+        write_ssh_string(&mut data, b"email@example.com");
+        write_ssh_string(&mut data, b"");
+        write_ssh_string(&mut data, b"sha512");
+        write_ssh_string(&mut data, &[7u8; 64]);
+        let blob = make_minimal_ed25519_blob();
+        let msg = make_sign_request_msg(&blob, &data, 0);
 
-        let (mut client, server) = duplex(1024);
-        let keystore = Arc::new(MockKeyStore::new());
-        let auth_policy = Arc::new(AlwaysAllowPolicy);
-        let token = CancellationToken::new();
+        let mut keystore = MockKeyStore::new();
+        keystore
+            .expect_get_private_key()
+            .once()
+            .returning(|_| Ok(None));
 
-        let handler = super::ConnectionHandler::new(
-            keystore,
-            auth_policy,
-            super::Connection {
-                stream: server,
-                peer_info: None,
-            },
-            token,
-        );
+        let capturing_policy = Arc::new(CapturingAuthPolicy {
+            captured: std::sync::Mutex::new(None),
+        });
 
-        // Send a length one byte over the 256 KiB cap
-        let oversized_len = (256 * 1024 + 1) as u32;
-        client
-            .write_all(&oversized_len.to_be_bytes())
-            .await
-            .unwrap();
+        let _ = super::handle_message(
+            &msg,
+            None,
+            &SessionBindState::default(),
+            &KeyFilter::default(),
+            &Arc::new(keystore),
+            &Arc::new(SessionKeyStore::new()),
+            &Arc::new(AgentLock::new()),
+            &capturing_policy,
+            &unavailable_external_signer(),
+            None,
+            None,
+            &RateLimiter::default(),
+        )
+        .await;
 
-        tokio::time::timeout(std::time::Duration::from_secs(1), handler.handle())
-            .await
-            .expect("handler should exit, denying oversized message length");
+        let captured = capturing_policy.captured.lock().unwrap();
+        let Some(AuthRequest::Sign(sign_req)) = captured.as_ref() else {
+            panic!("expected Sign auth request to be captured");
+        };
+        let expected_namespace = SIGNamespace::Other("email@example.com".to_string());
+        assert_eq!(sign_req.namespace, Some(expected_namespace.clone()));
+        let sshsig = sign_req.sshsig.as_ref().expect("SSHSIG data to be parsed");
+        assert_eq!(sshsig.namespace, expected_namespace);
+        assert_eq!(sshsig.hash_algorithm, ssh_key::HashAlg::Sha512);
+        assert_eq!(sshsig.message_hash, vec![7u8; 64]);
+    }
+
+    #[tokio::test]
+    async fn sign_request_with_certificate_propagates_certificate_context() {
+        use ssh_key::{private::Ed25519Keypair, public::KeyData, rand_core::OsRng};
+
+        use crate::crypto::test_common::{make_user_certificate, TEST_CERT_VALID_BEFORE};
+
+        let keypair = Ed25519Keypair::random(&mut OsRng);
+        let certificate = make_user_certificate(&KeyData::Ed25519(keypair.public), &["alice"]);
+        let cert_pub_key = PublicKey::try_from(&certificate).unwrap();
+        let msg = make_sign_request_msg(&cert_pub_key.blob, b"test data", 0);
+
+        let private_key = PrivateKey::Ed25519(keypair);
+        let mut keystore = MockKeyStore::new();
+        keystore
+            .expect_get_private_key()
+            .with(eq(cert_pub_key))
+            .once()
+            .return_once(move |_| Ok(Some(private_key)));
+
+        let capturing_policy = Arc::new(CapturingAuthPolicy {
+            captured: std::sync::Mutex::new(None),
+        });
+
+        let response = super::handle_message(
+            &msg,
+            None,
+            &SessionBindState::default(),
+            &KeyFilter::default(),
+            &Arc::new(keystore),
+            &Arc::new(SessionKeyStore::new()),
+            &Arc::new(AgentLock::new()),
+            &capturing_policy,
+            &unavailable_external_signer(),
+            None,
+            None,
+            &RateLimiter::default(),
+        )
+        .await;
+
+        assert_eq!(response[0], SIGN_RESPONSE);
+        let captured = capturing_policy.captured.lock().unwrap();
+        let Some(AuthRequest::Sign(sign_req)) = captured.as_ref() else {
+            panic!("expected Sign auth request to be captured");
+        };
+        let certificate = sign_req
+            .certificate
+            .as_ref()
+            .expect("certificate context must be set for certificate keys");
+        assert_eq!(certificate.key_id, "test-key-id");
+        assert_eq!(certificate.principals, vec!["alice".to_string()]);
+        assert_eq!(certificate.valid_before, TEST_CERT_VALID_BEFORE);
+    }
+
+    #[tokio::test]
+    async fn sign_request_without_certificate_has_no_certificate_context() {
+        let blob = make_minimal_ed25519_blob();
+        let msg = make_sign_request_msg(&blob, b"test data", 0);
+
+        let mut keystore = MockKeyStore::new();
+        keystore
+            .expect_get_private_key()
+            .once()
+            .returning(|_| Ok(None));
+
+        let capturing_policy = Arc::new(CapturingAuthPolicy {
+            captured: std::sync::Mutex::new(None),
+        });
+
+        let _ = super::handle_message(
+            &msg,
+            None,
+            &SessionBindState::default(),
+            &KeyFilter::default(),
+            &Arc::new(keystore),
+            &Arc::new(SessionKeyStore::new()),
+            &Arc::new(AgentLock::new()),
+            &capturing_policy,
+            &unavailable_external_signer(),
+            None,
+            None,
+            &RateLimiter::default(),
+        )
+        .await;
+
+        let captured = capturing_policy.captured.lock().unwrap();
+        let Some(AuthRequest::Sign(sign_req)) = captured.as_ref() else {
+            panic!("expected Sign auth request to be captured");
+        };
+        assert!(sign_req.certificate.is_none());
+    }
+
+    fn make_session_private_key(comment: &str) -> ssh_key::PrivateKey {
+        use ssh_key::{
+            private::{Ed25519Keypair, KeypairData},
+            rand_core::OsRng,
+        };
+
+        ssh_key::PrivateKey::new(
+            KeypairData::Ed25519(Ed25519Keypair::random(&mut OsRng)),
+            comment,
+        )
+        .unwrap()
+    }
+
+    fn session_public_key(private_key: &ssh_key::PrivateKey) -> PublicKey {
+        PublicKey {
+            alg: private_key.algorithm().to_string(),
+            blob: private_key.public_key().to_bytes().unwrap(),
+        }
+    }
+
+    #[tokio::test]
+    async fn add_identity_when_authorized_stores_session_key() {
+        let private_key = make_session_private_key("session key");
+        let session_keystore = Arc::new(SessionKeyStore::new());
+
+        let response = super::handle_message(
+            &make_add_identity_msg(&private_key),
+            None,
+            &SessionBindState::default(),
+            &KeyFilter::default(),
+            &Arc::new(MockKeyStore::new()),
+            &session_keystore,
+            &Arc::new(AgentLock::new()),
+            &Arc::new(AlwaysAllowPolicy),
+            &unavailable_external_signer(),
+            None,
+            None,
+            &RateLimiter::default(),
+        )
+        .await;
+
+        assert_eq!(response, vec![SUCCESS]);
+        let stored = session_keystore
+            .get(&session_public_key(&private_key))
+            .unwrap()
+            .unwrap();
+        assert_eq!(stored.comment(), "session key");
+    }
+
+    #[tokio::test]
+    async fn add_identity_when_denied_returns_failure() {
+        let private_key = make_session_private_key("session key");
+        let session_keystore = Arc::new(SessionKeyStore::new());
+
+        let response = super::handle_message(
+            &make_add_identity_msg(&private_key),
+            None,
+            &SessionBindState::default(),
+            &KeyFilter::default(),
+            &Arc::new(MockKeyStore::new()),
+            &session_keystore,
+            &Arc::new(AgentLock::new()),
+            &Arc::new(AlwaysDenyPolicy),
+            &unavailable_external_signer(),
+            None,
+            None,
+            &RateLimiter::default(),
+        )
+        .await;
+
+        assert_eq!(response, vec![FAILURE]);
+        assert!(!session_keystore.contains(&session_public_key(&private_key)));
+    }
+
+    #[tokio::test]
+    async fn list_request_includes_session_keys_after_vault_keys() {
+        let private_key = make_session_private_key("session key");
+        let session_keystore = Arc::new(SessionKeyStore::new());
+        session_keystore
+            .insert(private_key.clone().try_into().unwrap())
+            .unwrap();
+        let mut keystore = MockKeyStore::new();
+        keystore
+            .expect_get_all_public_keys_and_names()
+            .once()
+            .returning(|| {
+                Ok(vec![(
+                    PublicKey {
+                        alg: "ssh-ed25519".to_string(),
+                        blob: vec![1, 2, 3],
+                    },
+                    "Vault Key".to_string(),
+                )])
+            });
+
+        let response = super::handle_message(
+            &[REQUEST_IDENTITIES],
+            None,
+            &SessionBindState::default(),
+            &KeyFilter::default(),
+            &Arc::new(keystore),
+            &session_keystore,
+            &Arc::new(AgentLock::new()),
+            &Arc::new(AlwaysAllowPolicy),
+            &unavailable_external_signer(),
+            None,
+            None,
+            &RateLimiter::default(),
+        )
+        .await;
+
+        assert_eq!(response[0], IDENTITIES_ANSWER);
+        assert_eq!(u32::from_be_bytes(response[1..5].try_into().unwrap()), 2);
+    }
+
+    #[tokio::test]
+    async fn sign_request_with_session_key_returns_sign_response() {
+        let private_key = make_session_private_key("session key");
+        let public_key = session_public_key(&private_key);
+        let session_keystore = Arc::new(SessionKeyStore::new());
+        session_keystore
+            .insert(private_key.try_into().unwrap())
+            .unwrap();
+        let mut keystore = MockKeyStore::new();
+        keystore
+            .expect_get_private_key()
+            .once()
+            .returning(|_| Ok(None));
+
+        let response = super::handle_message(
+            &make_sign_request_msg(&public_key.blob, b"test data", 0),
+            None,
+            &SessionBindState::default(),
+            &KeyFilter::default(),
+            &Arc::new(keystore),
+            &session_keystore,
+            &Arc::new(AgentLock::new()),
+            &Arc::new(AlwaysAllowPolicy),
+            &unavailable_external_signer(),
+            None,
+            None,
+            &RateLimiter::default(),
+        )
+        .await;
+
+        assert_eq!(response[0], SIGN_RESPONSE);
+    }
+
+    #[tokio::test]
+    async fn add_id_constrained_stores_constraints() {
+        let private_key = make_session_private_key("session key");
+        let session_keystore = Arc::new(SessionKeyStore::new());
+        let mut constraints = vec![1u8]; // SSH_AGENT_CONSTRAIN_LIFETIME
+        constraints.extend_from_slice(&300u32.to_be_bytes());
+        constraints.push(2); // SSH_AGENT_CONSTRAIN_CONFIRM
+
+        let response = super::handle_message(
+            &make_add_id_constrained_msg(&private_key, &constraints),
+            None,
+            &SessionBindState::default(),
+            &KeyFilter::default(),
+            &Arc::new(MockKeyStore::new()),
+            &session_keystore,
+            &Arc::new(AgentLock::new()),
+            &Arc::new(AlwaysAllowPolicy),
+            &unavailable_external_signer(),
+            None,
+            None,
+            &RateLimiter::default(),
+        )
+        .await;
+
+        assert_eq!(response, vec![SUCCESS]);
+        let stored = session_keystore
+            .get(&session_public_key(&private_key))
+            .unwrap()
+            .unwrap();
+        assert_eq!(
+            stored.constraints().lifetime,
+            Some(std::time::Duration::from_secs(300))
+        );
+        assert!(stored.constraints().confirm);
+    }
+
+    #[tokio::test]
+    async fn add_id_constrained_with_unknown_constraint_returns_failure() {
+        let private_key = make_session_private_key("session key");
+        let session_keystore = Arc::new(SessionKeyStore::new());
+
+        let response = super::handle_message(
+            &make_add_id_constrained_msg(&private_key, &[42]),
+            None,
+            &SessionBindState::default(),
+            &KeyFilter::default(),
+            &Arc::new(MockKeyStore::new()),
+            &session_keystore,
+            &Arc::new(AgentLock::new()),
+            &Arc::new(AlwaysAllowPolicy),
+            &unavailable_external_signer(),
+            None,
+            None,
+            &RateLimiter::default(),
+        )
+        .await;
+
+        assert_eq!(response, vec![FAILURE]);
+        assert!(!session_keystore.contains(&session_public_key(&private_key)));
+    }
+
+    fn bound_session_state(host_keypair: &ssh_key::private::Ed25519Keypair) -> SessionBindState {
+        let bind_payload = make_session_bind_payload_ed25519(host_keypair, &[0x42u8; 32], false);
+        let ext_payload = make_extension_payload(b"session-bind@openssh.com", &bind_payload);
+        let mut state = SessionBindState::default();
+        super::handle_extension_message(&ext_payload, &mut state);
+        state
+    }
+
+    async fn sign_with_destination_constrained_key(
+        permitted_host: &ssh_key::private::Ed25519Keypair,
+        bound_host: &ssh_key::private::Ed25519Keypair,
+        auth_policy: &Arc<CapturingAuthPolicy>,
+    ) -> Vec<u8> {
+        let private_key = make_session_private_key("session key");
+        let public_key = session_public_key(&private_key);
+        let session_keystore = Arc::new(SessionKeyStore::new());
+        let permitted_host_blob = ssh_key::PublicKey::from(permitted_host.public)
+            .to_bytes()
+            .unwrap();
+        let constraint =
+            make_restrict_destination_constraint("example.com", &[(&permitted_host_blob, false)]);
+        super::handle_message(
+            &make_add_id_constrained_msg(&private_key, &constraint),
+            None,
+            &SessionBindState::default(),
+            &KeyFilter::default(),
+            &Arc::new(MockKeyStore::new()),
+            &session_keystore,
+            &Arc::new(AgentLock::new()),
+            &Arc::new(AlwaysAllowPolicy),
+            &unavailable_external_signer(),
+            None,
+            None,
+            &RateLimiter::default(),
+        )
+        .await;
+
+        let mut keystore = MockKeyStore::new();
+        keystore.expect_get_private_key().returning(|_| Ok(None));
+
+        super::handle_message(
+            &make_sign_request_msg(&public_key.blob, b"test data", 0),
+            None,
+            &bound_session_state(bound_host),
+            &KeyFilter::default(),
+            &Arc::new(keystore),
+            &session_keystore,
+            &Arc::new(AgentLock::new()),
+            auth_policy,
+            &unavailable_external_signer(),
+            None,
+            None,
+            &RateLimiter::default(),
+        )
+        .await
+    }
+
+    #[tokio::test]
+    async fn sign_request_with_destination_constrained_key_for_permitted_host_succeeds() {
+        use ssh_key::{private::Ed25519Keypair, rand_core::OsRng};
+
+        let host = Ed25519Keypair::random(&mut OsRng);
+        let auth_policy = Arc::new(CapturingAuthPolicy {
+            captured: std::sync::Mutex::new(None),
+        });
+
+        let response = sign_with_destination_constrained_key(&host, &host, &auth_policy).await;
+
+        assert_eq!(response[0], SIGN_RESPONSE);
+        assert!(auth_policy.captured.lock().unwrap().is_some());
+    }
+
+    #[tokio::test]
+    async fn sign_request_with_destination_constrained_key_for_other_host_fails_without_prompt() {
+        use ssh_key::{private::Ed25519Keypair, rand_core::OsRng};
+
+        let permitted_host = Ed25519Keypair::random(&mut OsRng);
+        let other_host = Ed25519Keypair::random(&mut OsRng);
+        let auth_policy = Arc::new(CapturingAuthPolicy {
+            captured: std::sync::Mutex::new(None),
+        });
+
+        let response =
+            sign_with_destination_constrained_key(&permitted_host, &other_host, &auth_policy).await;
+
+        assert_eq!(response, vec![FAILURE]);
+        assert!(auth_policy.captured.lock().unwrap().is_none());
+    }
+
+    /// A session key store holding a key that may only be used to authenticate to `host`.
+    fn destination_constrained_session_keystore(
+        host: &ssh_key::private::Ed25519Keypair,
+    ) -> (Arc<SessionKeyStore>, PublicKey) {
+        let private_key = make_session_private_key("session key");
+        let public_key = session_public_key(&private_key);
+        let session_keystore = Arc::new(SessionKeyStore::new());
+        session_keystore
+            .insert(
+                SessionKeyData::try_from(private_key)
+                    .unwrap()
+                    .with_constraints(KeyConstraints {
+                        destinations: vec![DestinationConstraint {
+                            from_hostname: String::new(),
+                            from_host_key_fingerprints: Vec::new(),
+                            to_hostname: "example.com".to_string(),
+                            to_host_key_fingerprints: vec![ssh_key::PublicKey::from(host.public)
+                                .fingerprint(ssh_key::HashAlg::Sha256)
+                                .to_string()],
+                        }],
+                        ..KeyConstraints::default()
+                    }),
+            )
+            .unwrap();
+        (session_keystore, public_key)
+    }
+
+    async fn list_with_session_bind_state(
+        session_keystore: &Arc<SessionKeyStore>,
+        session_bind_state: &SessionBindState,
+    ) -> Vec<(PublicKey, String)> {
+        let response = super::handle_message(
+            &[REQUEST_IDENTITIES],
+            None,
+            session_bind_state,
+            &KeyFilter::default(),
+            &Arc::new(InMemoryEncryptedKeyStore::new()),
+            session_keystore,
+            &Arc::new(AgentLock::new()),
+            &Arc::new(AlwaysAllowPolicy),
+            &unavailable_external_signer(),
+            None,
+            None,
+            &RateLimiter::default(),
+        )
+        .await;
+        parse_identities_answer(&response).unwrap()
+    }
+
+    #[tokio::test]
+    async fn list_request_includes_destination_constrained_key_for_permitted_host() {
+        use ssh_key::{private::Ed25519Keypair, rand_core::OsRng};
+
+        let host = Ed25519Keypair::random(&mut OsRng);
+        let (session_keystore, public_key) = destination_constrained_session_keystore(&host);
+
+        let local =
+            list_with_session_bind_state(&session_keystore, &SessionBindState::default()).await;
+        let bound =
+            list_with_session_bind_state(&session_keystore, &bound_session_state(&host)).await;
+
+        assert_eq!(local, vec![(public_key.clone(), "session key".to_string())]);
+        assert_eq!(bound, vec![(public_key, "session key".to_string())]);
+    }
+
+    #[tokio::test]
+    async fn list_request_hides_destination_constrained_key_for_other_host() {
+        use ssh_key::{private::Ed25519Keypair, rand_core::OsRng};
+
+        let permitted_host = Ed25519Keypair::random(&mut OsRng);
+        let other_host = Ed25519Keypair::random(&mut OsRng);
+        let (session_keystore, _) = destination_constrained_session_keystore(&permitted_host);
+
+        let keys =
+            list_with_session_bind_state(&session_keystore, &bound_session_state(&other_host))
+                .await;
+
+        assert!(keys.is_empty());
+    }
+
+    #[tokio::test]
+    async fn sign_request_with_destination_constrained_key_after_failed_bind_fails() {
+        use ssh_key::{private::Ed25519Keypair, rand_core::OsRng};
+
+        let host = Ed25519Keypair::random(&mut OsRng);
+        let (session_keystore, public_key) = destination_constrained_session_keystore(&host);
+        let mut session_bind_state = SessionBindState::default();
+        let ext_payload = make_extension_payload(b"session-bind@openssh.com", b"invalid");
+        super::handle_extension_message(&ext_payload, &mut session_bind_state);
+        let mut keystore = MockKeyStore::new();
+        keystore.expect_get_private_key().returning(|_| Ok(None));
+        keystore.expect_get().returning(|_| Ok(None));
+        let auth_policy = Arc::new(CapturingAuthPolicy {
+            captured: std::sync::Mutex::new(None),
+        });
+
+        let response = super::handle_message(
+            &make_sign_request_msg(&public_key.blob, b"test data", 0),
+            None,
+            &session_bind_state,
+            &KeyFilter::default(),
+            &Arc::new(keystore),
+            &session_keystore,
+            &Arc::new(AgentLock::new()),
+            &auth_policy,
+            &unavailable_external_signer(),
+            None,
+            None,
+            &RateLimiter::default(),
+        )
+        .await;
+
+        assert_eq!(response, vec![FAILURE]);
+        assert!(auth_policy.captured.lock().unwrap().is_none());
+        assert!(
+            list_with_session_bind_state(&session_keystore, &session_bind_state)
+                .await
+                .is_empty()
+        );
+    }
+
+    #[tokio::test]
+    async fn sign_request_with_confirm_constrained_key_sets_confirm_required() {
+        let private_key = make_session_private_key("session key");
+        let public_key = session_public_key(&private_key);
+        let session_keystore = Arc::new(SessionKeyStore::new());
+        session_keystore
+            .insert(
+                SessionKeyData::try_from(private_key)
+                    .unwrap()
+                    .with_constraints(KeyConstraints {
+                        confirm: true,
+                        ..KeyConstraints::default()
+                    }),
+            )
+            .unwrap();
+        let mut keystore = MockKeyStore::new();
+        keystore.expect_get_private_key().returning(|_| Ok(None));
+        let auth_policy = Arc::new(CapturingAuthPolicy {
+            captured: std::sync::Mutex::new(None),
+        });
+
+        super::handle_message(
+            &make_sign_request_msg(&public_key.blob, b"test data", 0),
+            None,
+            &SessionBindState::default(),
+            &KeyFilter::default(),
+            &Arc::new(keystore),
+            &session_keystore,
+            &Arc::new(AgentLock::new()),
+            &auth_policy,
+            &unavailable_external_signer(),
+            None,
+            None,
+            &RateLimiter::default(),
+        )
+        .await;
+
+        let captured = auth_policy.captured.lock().unwrap();
+        let Some(AuthRequest::Sign(sign_req)) = captured.as_ref() else {
+            panic!("expected sign request");
+        };
+        assert!(sign_req.confirm_required);
+    }
+
+    #[tokio::test]
+    async fn remove_identity_of_session_key_returns_success() {
+        let private_key = make_session_private_key("session key");
+        let public_key = session_public_key(&private_key);
+        let session_keystore = Arc::new(SessionKeyStore::new());
+        session_keystore
+            .insert(private_key.try_into().unwrap())
+            .unwrap();
+
+        let response = super::handle_message(
+            &make_remove_identity_msg(&public_key.blob),
+            None,
+            &SessionBindState::default(),
+            &KeyFilter::default(),
+            &Arc::new(MockKeyStore::new()),
+            &session_keystore,
+            &Arc::new(AgentLock::new()),
+            &Arc::new(AlwaysAllowPolicy),
+            &unavailable_external_signer(),
+            None,
+            None,
+            &RateLimiter::default(),
+        )
+        .await;
+
+        assert_eq!(response, vec![SUCCESS]);
+        assert!(!session_keystore.contains(&public_key));
+    }
+
+    #[tokio::test]
+    async fn remove_identity_of_unknown_key_returns_failure() {
+        let response = super::handle_message(
+            &make_remove_identity_msg(&make_minimal_ed25519_blob()),
+            None,
+            &SessionBindState::default(),
+            &KeyFilter::default(),
+            &Arc::new(MockKeyStore::new()),
+            &Arc::new(SessionKeyStore::new()),
+            &Arc::new(AgentLock::new()),
+            &Arc::new(AlwaysAllowPolicy),
+            &unavailable_external_signer(),
+            None,
+            None,
+            &RateLimiter::default(),
+        )
+        .await;
+
+        assert_eq!(response, vec![FAILURE]);
+    }
+
+    #[tokio::test]
+    async fn remove_all_identities_when_denied_keeps_session_keys() {
+        let private_key = make_session_private_key("session key");
+        let public_key = session_public_key(&private_key);
+        let session_keystore = Arc::new(SessionKeyStore::new());
+        session_keystore
+            .insert(private_key.try_into().unwrap())
+            .unwrap();
+
+        let response = super::handle_message(
+            &[REMOVE_ALL_IDENTITIES],
+            None,
+            &SessionBindState::default(),
+            &KeyFilter::default(),
+            &Arc::new(MockKeyStore::new()),
+            &session_keystore,
+            &Arc::new(AgentLock::new()),
+            &Arc::new(AlwaysDenyPolicy),
+            &unavailable_external_signer(),
+            None,
+            None,
+            &RateLimiter::default(),
+        )
+        .await;
+
+        assert_eq!(response, vec![FAILURE]);
+        assert!(session_keystore.contains(&public_key));
+    }
+
+    #[tokio::test]
+    async fn remove_all_identities_clears_session_keys() {
+        let private_key = make_session_private_key("session key");
+        let public_key = session_public_key(&private_key);
+        let session_keystore = Arc::new(SessionKeyStore::new());
+        session_keystore
+            .insert(private_key.try_into().unwrap())
+            .unwrap();
+
+        let response = super::handle_message(
+            &[REMOVE_ALL_IDENTITIES],
+            None,
+            &SessionBindState::default(),
+            &KeyFilter::default(),
+            &Arc::new(MockKeyStore::new()),
+            &session_keystore,
+            &Arc::new(AgentLock::new()),
+            &Arc::new(AlwaysAllowPolicy),
+            &unavailable_external_signer(),
+            None,
+            None,
+            &RateLimiter::default(),
+        )
+        .await;
+
+        assert_eq!(response, vec![SUCCESS]);
+        assert!(!session_keystore.contains(&public_key));
+    }
+
+    fn make_passphrase_msg(msg_type: u8, passphrase: &[u8]) -> Vec<u8> {
+        let mut msg = vec![msg_type];
+        write_ssh_string(&mut msg, passphrase);
+        msg
+    }
+
+    async fn handle_with_lock(
+        msg: &[u8],
+        session_keystore: &Arc<SessionKeyStore>,
+        agent_lock: &Arc<AgentLock>,
+    ) -> Vec<u8> {
+        // The keystore mock has no expectations, so it must not be consulted.
+        super::handle_message(
+            msg,
+            None,
+            &SessionBindState::default(),
+            &KeyFilter::default(),
+            &Arc::new(MockKeyStore::new()),
+            session_keystore,
+            agent_lock,
+            &Arc::new(AlwaysAllowPolicy),
+            &unavailable_external_signer(),
+            None,
+            None,
+            &RateLimiter::default(),
+        )
+        .await
+    }
+
+    #[tokio::test]
+    async fn lock_request_locks_agent() {
+        let agent_lock = Arc::new(AgentLock::new());
+
+        let response = handle_with_lock(
+            &make_passphrase_msg(LOCK, b"secret"),
+            &Arc::new(SessionKeyStore::new()),
+            &agent_lock,
+        )
+        .await;
+
+        assert_eq!(response, vec![SUCCESS]);
+        assert!(agent_lock.is_locked());
+    }
+
+    #[tokio::test]
+    async fn lock_request_when_locked_returns_failure() {
+        let agent_lock = Arc::new(AgentLock::new());
+        agent_lock.lock_with_passphrase(b"secret").unwrap();
+
+        let response = handle_with_lock(
+            &make_passphrase_msg(LOCK, b"other"),
+            &Arc::new(SessionKeyStore::new()),
+            &agent_lock,
+        )
+        .await;
+
+        assert_eq!(response, vec![FAILURE]);
+    }
+
+    #[tokio::test]
+    async fn unlock_request_when_not_locked_returns_failure() {
+        let response = handle_with_lock(
+            &make_passphrase_msg(UNLOCK, b"secret"),
+            &Arc::new(SessionKeyStore::new()),
+            &Arc::new(AgentLock::new()),
+        )
+        .await;
+
+        assert_eq!(response, vec![FAILURE]);
+    }
+
+    #[tokio::test]
+    async fn unlock_request_with_correct_passphrase_unlocks_agent() {
+        let agent_lock = Arc::new(AgentLock::new());
+        agent_lock.lock_with_passphrase(b"secret").unwrap();
+
+        let response = handle_with_lock(
+            &make_passphrase_msg(UNLOCK, b"secret"),
+            &Arc::new(SessionKeyStore::new()),
+            &agent_lock,
+        )
+        .await;
+
+        assert_eq!(response, vec![SUCCESS]);
+        assert!(!agent_lock.is_locked());
+    }
+
+    #[tokio::test]
+    async fn unlock_request_with_wrong_passphrase_stays_locked() {
+        let agent_lock = Arc::new(AgentLock::new());
+        agent_lock.lock_with_passphrase(b"secret").unwrap();
+
+        let response = handle_with_lock(
+            &make_passphrase_msg(UNLOCK, b"wrong"),
+            &Arc::new(SessionKeyStore::new()),
+            &agent_lock,
+        )
+        .await;
+
+        assert_eq!(response, vec![FAILURE]);
+        assert!(agent_lock.is_locked());
+    }
+
+    #[tokio::test]
+    async fn list_request_when_locked_returns_no_identities() {
+        let agent_lock = Arc::new(AgentLock::new());
+        agent_lock.lock();
+        let session_keystore = Arc::new(SessionKeyStore::new());
+        session_keystore
+            .insert(make_session_private_key("session key").try_into().unwrap())
+            .unwrap();
+
+        let response =
+            handle_with_lock(&[REQUEST_IDENTITIES], &session_keystore, &agent_lock).await;
+
+        assert_eq!(response, vec![IDENTITIES_ANSWER, 0, 0, 0, 0]);
+    }
+
+    #[tokio::test]
+    async fn sign_request_when_locked_returns_failure() {
+        let agent_lock = Arc::new(AgentLock::new());
+        agent_lock.lock_with_passphrase(b"secret").unwrap();
+        let private_key = make_session_private_key("session key");
+        let public_key = session_public_key(&private_key);
+        let session_keystore = Arc::new(SessionKeyStore::new());
+        session_keystore
+            .insert(private_key.try_into().unwrap())
+            .unwrap();
+
+        let response = handle_with_lock(
+            &make_sign_request_msg(&public_key.blob, b"test data", 0),
+            &session_keystore,
+            &agent_lock,
+        )
+        .await;
+
+        assert_eq!(response, vec![FAILURE]);
+    }
+
+    #[tokio::test]
+    async fn add_identity_when_locked_returns_failure() {
+        let agent_lock = Arc::new(AgentLock::new());
+        agent_lock.lock();
+        let private_key = make_session_private_key("session key");
+        let session_keystore = Arc::new(SessionKeyStore::new());
+
+        let response = handle_with_lock(
+            &make_add_identity_msg(&private_key),
+            &session_keystore,
+            &agent_lock,
+        )
+        .await;
+
+        assert_eq!(response, vec![FAILURE]);
+        assert!(!session_keystore.contains(&session_public_key(&private_key)));
+    }
+
+    #[tokio::test]
+    async fn oversized_message_length_closes_connection_without_panic() {
+        use tokio::io::{duplex, AsyncWriteExt};
+        use tokio_util::sync::CancellationToken;
+
+        let (mut client, server) = duplex(1024);
+        let keystore = Arc::new(MockKeyStore::new());
+        let auth_policy = Arc::new(AlwaysAllowPolicy);
+        let token = CancellationToken::new();
+
+        let handler = super::ConnectionHandler::new(
+            keystore,
+            Arc::new(SessionKeyStore::new()),
+            Arc::new(AgentLock::new()),
+            auth_policy,
+            unavailable_external_signer(),
+            None,
+            None,
+            Arc::new(RateLimiter::default()),
+            super::Connection {
+                stream: server,
+                peer_info: None,
+                key_filter: Arc::new(KeyFilter::default()),
+            },
+            token,
+        );
+
+        // Send a length one byte over the 256 KiB cap
+        let oversized_len = (256 * 1024 + 1) as u32;
+        client
+            .write_all(&oversized_len.to_be_bytes())
+            .await
+            .unwrap();
+
+        tokio::time::timeout(std::time::Duration::from_secs(1), handler.handle())
+            .await
+            .expect("handler should exit, denying oversized message length");
+    }
+
+    fn make_vault_key(name: &str, folder: Option<&str>) -> SSHKeyData {
+        let private_key = make_session_private_key(name);
+        let public_key = session_public_key(&private_key);
+        SSHKeyData::new(
+            PrivateKey::try_from(private_key).unwrap(),
+            public_key,
+            name.to_string(),
+            format!("cipher-{name}"),
+        )
+        .with_folder(folder.map(str::to_string))
+    }
+
+    #[tokio::test]
+    async fn list_request_with_key_filter_lists_only_permitted_keys() {
+        let keystore = Arc::new(InMemoryEncryptedKeyStore::new());
+        keystore
+            .replace(vec![
+                make_vault_key("work", Some("Work")),
+                make_vault_key("personal", Some("Personal")),
+                make_vault_key("unfiled", None),
+            ])
+            .unwrap();
+        let session_keystore = Arc::new(SessionKeyStore::new());
+        for comment in ["work-temporary", "temporary"] {
+            session_keystore
+                .insert(make_session_private_key(comment).try_into().unwrap())
+                .unwrap();
+        }
+        let key_filter = KeyFilter {
+            folders: vec!["Work".to_string()],
+            name_patterns: vec!["work-*".to_string()],
+            ..Default::default()
+        };
+
+        let response = super::handle_message(
+            &[REQUEST_IDENTITIES],
+            None,
+            &SessionBindState::default(),
+            &key_filter,
+            &keystore,
+            &session_keystore,
+            &Arc::new(AgentLock::new()),
+            &Arc::new(AlwaysAllowPolicy),
+            &unavailable_external_signer(),
+            None,
+            None,
+            &RateLimiter::default(),
+        )
+        .await;
+
+        assert_eq!(response[0], IDENTITIES_ANSWER);
+        assert_eq!(u32::from_be_bytes(response[1..5].try_into().unwrap()), 2);
+    }
+
+    #[tokio::test]
+    async fn sign_request_with_key_hidden_by_filter_returns_failure_without_authorization() {
+        let key_data = make_vault_key("personal", None);
+        let public_key = key_data.public_key().clone();
+        let keystore = Arc::new(InMemoryEncryptedKeyStore::new());
+        keystore.replace(vec![key_data]).unwrap();
+        let key_filter = KeyFilter {
+            cipher_ids: vec!["cipher-work".to_string()],
+            ..Default::default()
+        };
+        let capturing_policy = Arc::new(CapturingAuthPolicy {
+            captured: std::sync::Mutex::new(None),
+        });
+
+        let response = super::handle_message(
+            &make_sign_request_msg(&public_key.blob, b"test data", 0),
+            None,
+            &SessionBindState::default(),
+            &key_filter,
+            &keystore,
+            &Arc::new(SessionKeyStore::new()),
+            &Arc::new(AgentLock::new()),
+            &capturing_policy,
+            &unavailable_external_signer(),
+            None,
+            None,
+            &RateLimiter::default(),
+        )
+        .await;
+
+        assert_eq!(response, vec![FAILURE]);
+        assert!(capturing_policy.captured.lock().unwrap().is_none());
+    }
+
+    #[tokio::test]
+    async fn sign_request_with_key_permitted_by_filter_returns_sign_response() {
+        let key_data = make_vault_key("work", None);
+        let public_key = key_data.public_key().clone();
+        let keystore = Arc::new(InMemoryEncryptedKeyStore::new());
+        keystore.replace(vec![key_data]).unwrap();
+        let key_filter = KeyFilter {
+            cipher_ids: vec!["cipher-work".to_string()],
+            ..Default::default()
+        };
+
+        let response = super::handle_message(
+            &make_sign_request_msg(&public_key.blob, b"test data", 0),
+            None,
+            &SessionBindState::default(),
+            &key_filter,
+            &keystore,
+            &Arc::new(SessionKeyStore::new()),
+            &Arc::new(AgentLock::new()),
+            &Arc::new(AlwaysAllowPolicy),
+            &unavailable_external_signer(),
+            None,
+            None,
+            &RateLimiter::default(),
+        )
+        .await;
+
+        assert_eq!(response[0], SIGN_RESPONSE);
+    }
+
+    fn make_upstream(keys: Vec<(PublicKey, String)>) -> MockUpstreamAgent {
+        let mut upstream = MockUpstreamAgent::new();
+        upstream
+            .expect_list_identities()
+            .returning(move || Ok(keys.clone()));
+        upstream
+    }
+
+    fn upstream_public_key() -> PublicKey {
+        session_public_key(&make_session_private_key("user@laptop"))
+    }
+
+    #[tokio::test]
+    async fn list_request_merges_upstream_keys_after_own_keys() {
+        let key_data = make_vault_key("work", None);
+        let vault_public_key = key_data.public_key().clone();
+        let keystore = Arc::new(InMemoryEncryptedKeyStore::new());
+        keystore.replace(vec![key_data]).unwrap();
+        let upstream_public_key = upstream_public_key();
+        let upstream: Arc<dyn UpstreamAgent> = Arc::new(make_upstream(vec![
+            (vault_public_key.clone(), "duplicate".to_string()),
+            (upstream_public_key.clone(), "user@laptop".to_string()),
+        ]));
+
+        let response = super::handle_message(
+            &[REQUEST_IDENTITIES],
+            None,
+            &SessionBindState::default(),
+            &KeyFilter::default(),
+            &keystore,
+            &Arc::new(SessionKeyStore::new()),
+            &Arc::new(AgentLock::new()),
+            &Arc::new(AlwaysAllowPolicy),
+            &unavailable_external_signer(),
+            Some(&upstream),
+            None,
+            &RateLimiter::default(),
+        )
+        .await;
+
+        assert_eq!(
+            parse_identities_answer(&response),
+            Some(vec![
+                (vault_public_key, "work".to_string()),
+                (upstream_public_key, "user@laptop".to_string()),
+            ])
+        );
+    }
+
+    #[tokio::test]
+    async fn list_request_applies_key_filter_to_upstream_keys() {
+        let upstream: Arc<dyn UpstreamAgent> = Arc::new(make_upstream(vec![
+            (upstream_public_key(), "user@laptop".to_string()),
+            (
+                session_public_key(&make_session_private_key("work-laptop")),
+                "work-laptop".to_string(),
+            ),
+        ]));
+        let key_filter = KeyFilter {
+            name_patterns: vec!["work-*".to_string()],
+            ..Default::default()
+        };
+
+        let response = super::handle_message(
+            &[REQUEST_IDENTITIES],
+            None,
+            &SessionBindState::default(),
+            &key_filter,
+            &Arc::new(InMemoryEncryptedKeyStore::new()),
+            &Arc::new(SessionKeyStore::new()),
+            &Arc::new(AgentLock::new()),
+            &Arc::new(AlwaysAllowPolicy),
+            &unavailable_external_signer(),
+            Some(&upstream),
+            None,
+            &RateLimiter::default(),
+        )
+        .await;
+
+        let keys = parse_identities_answer(&response).unwrap();
+        assert_eq!(keys.len(), 1);
+        assert_eq!(keys[0].1, "work-laptop");
+    }
+
+    #[tokio::test]
+    async fn list_request_when_upstream_unreachable_lists_own_keys() {
+        let keystore = Arc::new(InMemoryEncryptedKeyStore::new());
+        keystore
+            .replace(vec![make_vault_key("work", None)])
+            .unwrap();
+        let mut upstream = MockUpstreamAgent::new();
+        upstream
+            .expect_list_identities()
+            .once()
+            .returning(|| Err(anyhow::anyhow!("connection refused")));
+        let upstream: Arc<dyn UpstreamAgent> = Arc::new(upstream);
+
+        let response = super::handle_message(
+            &[REQUEST_IDENTITIES],
+            None,
+            &SessionBindState::default(),
+            &KeyFilter::default(),
+            &keystore,
+            &Arc::new(SessionKeyStore::new()),
+            &Arc::new(AgentLock::new()),
+            &Arc::new(AlwaysAllowPolicy),
+            &unavailable_external_signer(),
+            Some(&upstream),
+            None,
+            &RateLimiter::default(),
+        )
+        .await;
+
+        assert_eq!(parse_identities_answer(&response).unwrap().len(), 1);
+    }
+
+    #[tokio::test]
+    async fn sign_request_for_upstream_key_is_authorized_then_forwarded() {
+        let public_key = upstream_public_key();
+        let mut upstream = make_upstream(vec![(public_key.clone(), "user@laptop".to_string())]);
+        upstream
+            .expect_sign()
+            .withf(|_, data, flags, session_binds| {
+                data == b"test data"
+                    && *flags == Some(SignFlags::RsaSha512)
+                    && session_binds.is_empty()
+            })
+            .once()
+            .returning(|_, _, _, _| Ok(b"signature".to_vec()));
+        let upstream: Arc<dyn UpstreamAgent> = Arc::new(upstream);
+        let capturing_policy = Arc::new(CapturingAuthPolicy {
+            captured: std::sync::Mutex::new(None),
+        });
+
+        let response = super::handle_message(
+            &make_sign_request_msg(&public_key.blob, b"test data", 4),
+            None,
+            &SessionBindState::default(),
+            &KeyFilter::default(),
+            &Arc::new(InMemoryEncryptedKeyStore::new()),
+            &Arc::new(SessionKeyStore::new()),
+            &Arc::new(AgentLock::new()),
+            &capturing_policy,
+            &unavailable_external_signer(),
+            Some(&upstream),
+            None,
+            &RateLimiter::default(),
+        )
+        .await;
+
+        assert_eq!(response, build_raw_sign_response(b"signature"));
+        let captured = capturing_policy.captured.lock().unwrap().clone();
+        let Some(AuthRequest::Sign(sign_request)) = captured else {
+            panic!("expected a sign request to be authorized");
+        };
+        assert_eq!(
+            sign_request.upstream_key_comment.as_deref(),
+            Some("user@laptop")
+        );
+    }
+
+    #[tokio::test]
+    async fn sign_request_for_upstream_key_replays_session_binds() {
+        use ssh_key::{private::Ed25519Keypair, rand_core::OsRng};
+
+        let keypair = Ed25519Keypair::random(&mut OsRng);
+        let bastion = make_session_bind_payload_ed25519(&keypair, &[0x01u8; 32], true);
+        let target = make_session_bind_payload_ed25519(&keypair, &[0x02u8; 32], false);
+        let mut session_bind_state = SessionBindState::default();
+        for payload in [&bastion, &target] {
+            let extension = make_extension_payload(b"session-bind@openssh.com", payload);
+            super::handle_extension_message(&extension, &mut session_bind_state);
+        }
+
+        let public_key = upstream_public_key();
+        let mut upstream = make_upstream(vec![(public_key.clone(), "user@laptop".to_string())]);
+        let expected_binds = vec![bastion, target];
+        upstream
+            .expect_sign()
+            .withf(move |_, _, _, session_binds| session_binds == expected_binds.as_slice())
+            .once()
+            .returning(|_, _, _, _| Ok(b"signature".to_vec()));
+        let upstream: Arc<dyn UpstreamAgent> = Arc::new(upstream);
+
+        let response = super::handle_message(
+            &make_sign_request_msg(&public_key.blob, b"test data", 0),
+            None,
+            &session_bind_state,
+            &KeyFilter::default(),
+            &Arc::new(InMemoryEncryptedKeyStore::new()),
+            &Arc::new(SessionKeyStore::new()),
+            &Arc::new(AgentLock::new()),
+            &Arc::new(AlwaysAllowPolicy),
+            &unavailable_external_signer(),
+            Some(&upstream),
+            None,
+            &RateLimiter::default(),
+        )
+        .await;
+
+        assert_eq!(response, build_raw_sign_response(b"signature"));
+    }
+
+    #[tokio::test]
+    async fn sign_request_for_upstream_key_when_denied_is_not_forwarded() {
+        let public_key = upstream_public_key();
+        let mut upstream = make_upstream(vec![(public_key.clone(), "user@laptop".to_string())]);
+        upstream.expect_sign().never();
+        let upstream: Arc<dyn UpstreamAgent> = Arc::new(upstream);
+
+        let response = super::handle_message(
+            &make_sign_request_msg(&public_key.blob, b"test data", 0),
+            None,
+            &SessionBindState::default(),
+            &KeyFilter::default(),
+            &Arc::new(InMemoryEncryptedKeyStore::new()),
+            &Arc::new(SessionKeyStore::new()),
+            &Arc::new(AgentLock::new()),
+            &Arc::new(AlwaysDenyPolicy),
+            &unavailable_external_signer(),
+            Some(&upstream),
+            None,
+            &RateLimiter::default(),
+        )
+        .await;
+
+        assert_eq!(response, vec![FAILURE]);
+    }
+
+    #[tokio::test]
+    async fn sign_request_for_vault_key_is_not_forwarded() {
+        let key_data = make_vault_key("work", None);
+        let public_key = key_data.public_key().clone();
+        let keystore = Arc::new(InMemoryEncryptedKeyStore::new());
+        keystore.replace(vec![key_data]).unwrap();
+        let mut upstream = MockUpstreamAgent::new();
+        upstream.expect_list_identities().never();
+        upstream.expect_sign().never();
+        let upstream: Arc<dyn UpstreamAgent> = Arc::new(upstream);
+
+        let response = super::handle_message(
+            &make_sign_request_msg(&public_key.blob, b"test data", 0),
+            None,
+            &SessionBindState::default(),
+            &KeyFilter::default(),
+            &keystore,
+            &Arc::new(SessionKeyStore::new()),
+            &Arc::new(AgentLock::new()),
+            &Arc::new(AlwaysAllowPolicy),
+            &unavailable_external_signer(),
+            Some(&upstream),
+            None,
+            &RateLimiter::default(),
+        )
+        .await;
+
+        assert_eq!(response[0], SIGN_RESPONSE);
+    }
+
+    #[tokio::test]
+    async fn connection_lists_upstream_identities_once() {
+        use tokio::io::{duplex, AsyncReadExt, AsyncWriteExt};
+        use tokio_util::sync::CancellationToken;
+
+        async fn request<S: AsyncReadExt + AsyncWriteExt + Unpin>(
+            client: &mut S,
+            msg: Vec<u8>,
+        ) -> Vec<u8> {
+            client.write_all(&super::frame(msg)).await.unwrap();
+            let mut len_buf = [0u8; 4];
+            client.read_exact(&mut len_buf).await.unwrap();
+            let mut response = vec![0u8; u32::from_be_bytes(len_buf) as usize];
+            client.read_exact(&mut response).await.unwrap();
+            response
+        }
+
+        let public_key = upstream_public_key();
+        let mut upstream = MockUpstreamAgent::new();
+        let keys = vec![(public_key.clone(), "user@laptop".to_string())];
+        upstream
+            .expect_list_identities()
+            .once()
+            .returning(move || Ok(keys.clone()));
+        upstream
+            .expect_sign()
+            .once()
+            .returning(|_, _, _, _| Ok(b"signature".to_vec()));
+        let (mut client, server) = duplex(4096);
+        let handler = super::ConnectionHandler::new(
+            Arc::new(InMemoryEncryptedKeyStore::new()),
+            Arc::new(SessionKeyStore::new()),
+            Arc::new(AgentLock::new()),
+            Arc::new(AlwaysAllowPolicy),
+            unavailable_external_signer(),
+            Some(Arc::new(upstream) as Arc<dyn UpstreamAgent>),
+            None,
+            Arc::new(RateLimiter::default()),
+            super::Connection {
+                stream: server,
+                peer_info: None,
+                key_filter: Arc::new(KeyFilter::default()),
+            },
+            CancellationToken::new(),
+        );
+        let handler_task = tokio::spawn(handler.handle());
+
+        let list_response = request(&mut client, vec![REQUEST_IDENTITIES]).await;
+        let sign_response = request(
+            &mut client,
+            make_sign_request_msg(&public_key.blob, b"test data", 0),
+        )
+        .await;
+        drop(client);
+        handler_task.await.unwrap();
+
+        assert_eq!(parse_identities_answer(&list_response).unwrap().len(), 1);
+        assert_eq!(sign_response[0], SIGN_RESPONSE);
+    }
+
+    #[tokio::test]
+    async fn locked_agent_refuses_session_bind() {
+        use ssh_key::{private::Ed25519Keypair, rand_core::OsRng};
+        use tokio::io::{duplex, AsyncReadExt, AsyncWriteExt};
+        use tokio_util::sync::CancellationToken;
+
+        let keypair = Ed25519Keypair::random(&mut OsRng);
+        let bind_payload = make_session_bind_payload_ed25519(&keypair, &[0x42u8; 32], false);
+        let mut msg = vec![EXTENSION];
+        msg.extend(make_extension_payload(
+            b"session-bind@openssh.com",
+            &bind_payload,
+        ));
+        let agent_lock = Arc::new(AgentLock::new());
+        agent_lock.lock();
+        let (mut client, server) = duplex(4096);
+        let handler = super::ConnectionHandler::new(
+            Arc::new(MockKeyStore::new()),
+            Arc::new(SessionKeyStore::new()),
+            agent_lock.clone(),
+            Arc::new(AlwaysAllowPolicy),
+            unavailable_external_signer(),
+            None,
+            None,
+            Arc::new(RateLimiter::default()),
+            super::Connection {
+                stream: server,
+                peer_info: None,
+                key_filter: Arc::new(KeyFilter::default()),
+            },
+            CancellationToken::new(),
+        );
+        let handler_task = tokio::spawn(handler.handle());
+
+        let mut responses = Vec::new();
+        for unlock in [false, true] {
+            if unlock {
+                agent_lock.unlock();
+            }
+            client.write_all(&super::frame(msg.clone())).await.unwrap();
+            let mut response = [0u8; 5];
+            client.read_exact(&mut response).await.unwrap();
+            responses.push(response[4]);
+        }
+        drop(client);
+        handler_task.await.unwrap();
+
+        assert_eq!(responses, vec![FAILURE, SUCCESS]);
+    }
+
+    /// # Returns
+    ///
+    /// An audit sink expecting a single entry, and the entry once recorded.
+    fn make_capturing_audit_sink() -> (
+        Arc<dyn AuditSink>,
+        Arc<std::sync::Mutex<Option<AuditEntry>>>,
+    ) {
+        let captured = Arc::new(std::sync::Mutex::new(None));
+        let mut audit_sink = MockAuditSink::new();
+        let captured_clone = captured.clone();
+        audit_sink.expect_record().once().returning(move |entry| {
+            *captured_clone.lock().unwrap() = Some(entry.clone());
+            Ok(())
+        });
+        (Arc::new(audit_sink), captured)
+    }
+
+    #[tokio::test]
+    async fn list_request_is_audited_with_requesting_process() {
+        let (audit_sink, captured) = make_capturing_audit_sink();
+        let peer_info = PeerInfo::from_pid(std::process::id()).unwrap();
+
+        super::handle_message(
+            &[REQUEST_IDENTITIES],
+            Some(&peer_info),
+            &SessionBindState::default(),
+            &KeyFilter::default(),
+            &Arc::new(MockKeyStore::new()),
+            &Arc::new(SessionKeyStore::new()),
+            &Arc::new(AgentLock::new()),
+            &Arc::new(AlwaysDenyPolicy),
+            &unavailable_external_signer(),
+            None,
+            Some(&audit_sink),
+            &RateLimiter::default(),
+        )
+        .await;
+
+        let entry = captured.lock().unwrap().clone().unwrap();
+        assert_eq!(entry.operation, AuditOperation::List);
+        assert_eq!(entry.decision, AuditDecision::Denied);
+        assert_eq!(entry.pid, Some(std::process::id()));
+        assert_eq!(
+            entry.process_name.as_deref(),
+            Some(peer_info.process_name())
+        );
+        assert_eq!(entry.key_fingerprint, None);
+    }
+
+    #[tokio::test]
+    async fn sign_request_is_audited_with_key_and_host() {
+        let key_data = make_vault_key("work", None);
+        let public_key = key_data.public_key().clone();
+        let keystore = Arc::new(InMemoryEncryptedKeyStore::new());
+        keystore.replace(vec![key_data]).unwrap();
+        let (audit_sink, captured) = make_capturing_audit_sink();
+        let host_keypair = ssh_key::private::Ed25519Keypair::random(&mut ssh_key::rand_core::OsRng);
+        let bind_payload = make_session_bind_payload_ed25519(&host_keypair, &[0x42u8; 32], true);
+        let mut session_bind_state = SessionBindState::default();
+        super::handle_extension_message(
+            &make_extension_payload(b"session-bind@openssh.com", &bind_payload),
+            &mut session_bind_state,
+        );
+        let mut sshsig = b"SSHSIG".to_vec();
+        write_ssh_string(&mut sshsig, b"git");
+        write_ssh_string(&mut sshsig, b"");
+        write_ssh_string(&mut sshsig, b"sha512");
+        write_ssh_string(&mut sshsig, &[7u8; 64]);
+
+        let response = super::handle_message(
+            &make_sign_request_msg(&public_key.blob, &sshsig, 0),
+            None,
+            &session_bind_state,
+            &KeyFilter::default(),
+            &keystore,
+            &Arc::new(SessionKeyStore::new()),
+            &Arc::new(AgentLock::new()),
+            &Arc::new(AlwaysAllowPolicy),
+            &unavailable_external_signer(),
+            None,
+            Some(&audit_sink),
+            &RateLimiter::default(),
+        )
+        .await;
+
+        assert_eq!(response[0], SIGN_RESPONSE);
+        let entry = captured.lock().unwrap().clone().unwrap();
+        assert_eq!(entry.operation, AuditOperation::Sign);
+        assert_eq!(entry.decision, AuditDecision::Allowed);
+        assert_eq!(entry.key_fingerprint, public_key.fingerprint());
+        assert_eq!(entry.cipher_id.as_deref(), Some("cipher-work"));
+        assert_eq!(entry.namespace.as_deref(), Some("git"));
+        assert!(entry.host_fingerprint.is_some());
+        assert!(entry.is_forwarding);
+        assert_eq!(entry.pid, None);
+    }
+
+    #[tokio::test]
+    async fn sign_request_authorization_error_is_audited() {
+        let public_key = session_public_key(&make_session_private_key("unknown"));
+        let (audit_sink, captured) = make_capturing_audit_sink();
+
+        let response = super::handle_message(
+            &make_sign_request_msg(&public_key.blob, b"test data", 0),
+            None,
+            &SessionBindState::default(),
+            &KeyFilter::default(),
+            &Arc::new(InMemoryEncryptedKeyStore::new()),
+            &Arc::new(SessionKeyStore::new()),
+            &Arc::new(AgentLock::new()),
+            &Arc::new(ErrorAuthPolicy),
+            &unavailable_external_signer(),
+            None,
+            Some(&audit_sink),
+            &RateLimiter::default(),
+        )
+        .await;
+
+        assert_eq!(response, vec![FAILURE]);
+        let entry = captured.lock().unwrap().clone().unwrap();
+        assert_eq!(entry.decision, AuditDecision::Error);
+        assert_eq!(entry.cipher_id, None);
+    }
+
+    /// # Returns
+    ///
+    /// The audit entry recorded for a sign request with `public_key` that is refused before
+    /// reaching the authorization policy.
+    async fn audit_refused_sign_request(
+        public_key: &PublicKey,
+        keystore: &Arc<InMemoryEncryptedKeyStore>,
+        session_keystore: &Arc<SessionKeyStore>,
+        key_filter: &KeyFilter,
+        rate_limiter: &RateLimiter,
+    ) -> AuditEntry {
+        let (audit_sink, captured) = make_capturing_audit_sink();
+        let auth_policy = Arc::new(CapturingAuthPolicy {
+            captured: std::sync::Mutex::new(None),
+        });
+
+        let response = super::handle_message(
+            &make_sign_request_msg(&public_key.blob, b"test data", 0),
+            None,
+            &SessionBindState::default(),
+            key_filter,
+            keystore,
+            session_keystore,
+            &Arc::new(AgentLock::new()),
+            &auth_policy,
+            &unavailable_external_signer(),
+            None,
+            Some(&audit_sink),
+            rate_limiter,
+        )
+        .await;
+
+        assert_eq!(response, vec![FAILURE]);
+        assert!(auth_policy.captured.lock().unwrap().is_none());
+        let entry = captured.lock().unwrap().clone();
+        entry.unwrap()
+    }
+
+    #[tokio::test]
+    async fn sign_request_refused_by_key_filter_is_audited() {
+        let key_data = make_vault_key("personal", None);
+        let public_key = key_data.public_key().clone();
+        let keystore = Arc::new(InMemoryEncryptedKeyStore::new());
+        keystore.replace(vec![key_data]).unwrap();
+        let key_filter = KeyFilter {
+            name_patterns: vec!["work-*".to_string()],
+            ..Default::default()
+        };
+
+        let entry = audit_refused_sign_request(
+            &public_key,
+            &keystore,
+            &Arc::new(SessionKeyStore::new()),
+            &key_filter,
+            &RateLimiter::default(),
+        )
+        .await;
+
+        assert_eq!(entry.operation, AuditOperation::Sign);
+        assert_eq!(entry.decision, AuditDecision::Denied);
+        assert_eq!(entry.denied_by, Some(AuditDenialReason::KeyFilter));
+        assert_eq!(entry.key_fingerprint, public_key.fingerprint());
+        assert_eq!(entry.cipher_id.as_deref(), Some("cipher-personal"));
+    }
+
+    #[tokio::test]
+    async fn sign_request_refused_by_key_constraints_is_audited() {
+        use ssh_key::{private::Ed25519Keypair, rand_core::OsRng};
+
+        let host = Ed25519Keypair::random(&mut OsRng);
+        let (session_keystore, public_key) = destination_constrained_session_keystore(&host);
+        let (audit_sink, captured) = make_capturing_audit_sink();
+        let other_host = Ed25519Keypair::random(&mut OsRng);
+
+        let response = super::handle_message(
+            &make_sign_request_msg(&public_key.blob, b"test data", 0),
+            None,
+            &bound_session_state(&other_host),
+            &KeyFilter::default(),
+            &Arc::new(InMemoryEncryptedKeyStore::new()),
+            &session_keystore,
+            &Arc::new(AgentLock::new()),
+            &Arc::new(AlwaysAllowPolicy),
+            &unavailable_external_signer(),
+            None,
+            Some(&audit_sink),
+            &RateLimiter::default(),
+        )
+        .await;
+
+        assert_eq!(response, vec![FAILURE]);
+        let entry = captured.lock().unwrap().clone().unwrap();
+        assert_eq!(entry.decision, AuditDecision::Denied);
+        assert_eq!(entry.denied_by, Some(AuditDenialReason::KeyConstraints));
+        assert!(entry.host_fingerprint.is_some());
+    }
+
+    #[tokio::test]
+    async fn sign_request_with_security_key_without_external_signer_is_audited() {
+        use ssh_key::{private::Ed25519Keypair, rand_core::OsRng};
+
+        use crate::crypto::test_common::make_sk_ed25519_key;
+
+        let public_key =
+            session_public_key(&make_sk_ed25519_key(&Ed25519Keypair::random(&mut OsRng)));
+
+        let entry = audit_refused_sign_request(
+            &public_key,
+            &Arc::new(InMemoryEncryptedKeyStore::new()),
+            &Arc::new(SessionKeyStore::new()),
+            &KeyFilter::default(),
+            &RateLimiter::default(),
+        )
+        .await;
+
+        assert_eq!(entry.decision, AuditDecision::Denied);
+        assert_eq!(entry.denied_by, Some(AuditDenialReason::NoExternalSigner));
+    }
+
+    #[tokio::test]
+    async fn sign_request_refused_by_rate_limiter_is_audited() {
+        let key_data = make_vault_key("work", None);
+        let public_key = key_data.public_key().clone();
+        let keystore = Arc::new(InMemoryEncryptedKeyStore::new());
+        keystore.replace(vec![key_data]).unwrap();
+        let rate_limiter = RateLimiter::new(RateLimits {
+            max_sign_requests: 0,
+            ..RateLimits::default()
+        });
+
+        let entry = audit_refused_sign_request(
+            &public_key,
+            &keystore,
+            &Arc::new(SessionKeyStore::new()),
+            &KeyFilter::default(),
+            &rate_limiter,
+        )
+        .await;
+
+        assert_eq!(entry.decision, AuditDecision::Denied);
+        assert_eq!(entry.denied_by, Some(AuditDenialReason::RateLimit));
+    }
+
+    #[tokio::test]
+    async fn sign_request_without_approval_slot_is_audited() {
+        let key_data = make_vault_key("work", None);
+        let public_key = key_data.public_key().clone();
+        let keystore = Arc::new(InMemoryEncryptedKeyStore::new());
+        keystore.replace(vec![key_data]).unwrap();
+        let rate_limiter = RateLimiter::new(RateLimits {
+            max_pending_approvals: 0,
+            ..RateLimits::default()
+        });
+
+        let entry = audit_refused_sign_request(
+            &public_key,
+            &keystore,
+            &Arc::new(SessionKeyStore::new()),
+            &KeyFilter::default(),
+            &rate_limiter,
+        )
+        .await;
+
+        assert_eq!(entry.denied_by, Some(AuditDenialReason::RateLimit));
+    }
+
+    #[tokio::test]
+    async fn authorized_sign_request_has_no_denial_reason() {
+        let key_data = make_vault_key("work", None);
+        let public_key = key_data.public_key().clone();
+        let keystore = Arc::new(InMemoryEncryptedKeyStore::new());
+        keystore.replace(vec![key_data]).unwrap();
+        let (audit_sink, captured) = make_capturing_audit_sink();
+
+        super::handle_message(
+            &make_sign_request_msg(&public_key.blob, b"test data", 0),
+            None,
+            &SessionBindState::default(),
+            &KeyFilter::default(),
+            &keystore,
+            &Arc::new(SessionKeyStore::new()),
+            &Arc::new(AgentLock::new()),
+            &Arc::new(AlwaysAllowPolicy),
+            &unavailable_external_signer(),
+            None,
+            Some(&audit_sink),
+            &RateLimiter::default(),
+        )
+        .await;
+
+        let entry = captured.lock().unwrap().clone().unwrap();
+        assert_eq!(entry.decision, AuditDecision::Allowed);
+        assert_eq!(entry.denied_by, None);
+    }
+
+    #[tokio::test]
+    async fn audit_sink_failure_does_not_fail_request() {
+        let mut audit_sink = MockAuditSink::new();
+        audit_sink
+            .expect_record()
+            .once()
+            .returning(|_| Err(anyhow::anyhow!("disk full")));
+        let audit_sink: Arc<dyn AuditSink> = Arc::new(audit_sink);
+
+        let response = super::handle_message(
+            &[REQUEST_IDENTITIES],
+            None,
+            &SessionBindState::default(),
+            &KeyFilter::default(),
+            &Arc::new(InMemoryEncryptedKeyStore::new()),
+            &Arc::new(SessionKeyStore::new()),
+            &Arc::new(AgentLock::new()),
+            &Arc::new(AlwaysAllowPolicy),
+            &unavailable_external_signer(),
+            None,
+            Some(&audit_sink),
+            &RateLimiter::default(),
+        )
+        .await;
+
+        assert_eq!(response[0], IDENTITIES_ANSWER);
     }
 }
@@ -6,15 +6,18 @@ pub(crate) mod unix;
 #[cfg(windows)]
 pub(crate) mod windows;
 
+use std::path::PathBuf;
+
 use anyhow::Result;
 use tokio::{
     io::{AsyncRead, AsyncWrite},
     sync::mpsc::Sender,
 };
 use tokio_util::sync::CancellationToken;
-use tracing::{debug, error};
+use tracing::{debug, error, warn};
 
 use super::connection::Connection;
+use crate::storage::key_filter::KeyFilter;
 
 /// Implementors handle platform-specific socket/pipe creation and connection acceptance.
 #[async_trait::async_trait]
@@ -22,20 +25,77 @@ pub(crate) trait Listener: Send + Sync {
     /// The stream type returned by `accept()`
     type Stream: AsyncRead + AsyncWrite + Send + Unpin + 'static;
 
+    /// Name of the listener, used to identify it in logs.
+    fn name(&self) -> &str;
+
+    /// # Returns
+    ///
+    /// The path of the socket or named pipe the listener accepts connections on, if known.
+    fn path(&self) -> Option<PathBuf>;
+
     /// Accept a new connection
     async fn accept(&mut self) -> Result<Connection<Self::Stream>>;
 }
 
+/// An additional socket for the agent to listen on, alongside the default one.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct AgentSocket {
+    /// Name of the socket, used to identify it in logs.
+    pub name: String,
+    /// Path of the Unix domain socket, or name of the named pipe on Windows.
+    pub path: PathBuf,
+    /// The keys visible to clients of the socket.
+    pub key_filter: KeyFilter,
+}
+
 /// Creates the listeners for the Unix platform.
+///
+/// Sockets inherited through systemd socket activation replace the default socket. Additional
+/// sockets that can't be bound are skipped.
+///
+/// # Errors
+///
+/// If the default socket can't be bound, or an inherited socket can't be adopted.
 #[cfg(unix)]
-pub(crate) fn create_listeners() -> Result<Vec<impl Listener>> {
-    Ok(vec![unix::UnixListener::new()?])
+pub(crate) fn create_listeners(sockets: &[AgentSocket]) -> Result<Vec<impl Listener>> {
+    let inherited = unix::inherited_fds();
+    let mut listeners = if inherited.is_empty() {
+        vec![unix::UnixListener::new()?]
+    } else {
+        inherited
+            .into_iter()
+            .map(|(fd, name)| unix::UnixListener::from_inherited_fd(fd, name))
+            .collect::<Result<_>>()?
+    };
+
+    listeners.extend(sockets.iter().filter_map(|socket| {
+        unix::UnixListener::bind(socket.name.clone(), &socket.path)
+            .map(|listener| listener.with_key_filter(socket.key_filter.clone()))
+            .inspect_err(|error| warn!(%error, name = socket.name, "Skipping socket"))
+            .ok()
+    }));
+
+    Ok(listeners)
 }
 
-/// Creates the listeners for the Windows platform.
+/// Creates the listeners for the Windows platform. Additional pipes that can't be created are
+/// skipped.
+///
+/// # Errors
+///
+/// If the default named pipe can't be created.
 #[cfg(windows)]
-pub(crate) fn create_listeners() -> Result<Vec<impl Listener>> {
-    Ok(vec![windows::WindowsListener::new()?])
+pub(crate) fn create_listeners(sockets: &[AgentSocket]) -> Result<Vec<impl Listener>> {
+    let mut listeners = vec![windows::WindowsListener::new()?];
+
+    listeners.extend(sockets.iter().filter_map(|socket| {
+        windows::WindowsListener::bind(socket.name.clone(), &socket.path.to_string_lossy())
+            .map(|listener| listener.with_key_filter(socket.key_filter.clone()))
+            .inspect_err(|error| warn!(%error, name = socket.name, "Skipping named pipe"))
+            .ok()
+    }));
+
+    Ok(listeners)
 }
 
 /// Spawns an independent tokio task for each listener in `listeners`.
@@ -56,7 +116,7 @@ pub(crate) fn spawn_listener_tasks<L>(
             loop {
                 tokio::select! {
                     () = token.cancelled() => {
-                        debug!("Listener task received cancellation signal");
+                        debug!(name = listener.name(), "Listener task received cancellation signal");
                         break;
                     }
                     result = listener.accept() => match result {
@@ -68,7 +128,7 @@ pub(crate) fn spawn_listener_tasks<L>(
                         }
                         // Continue to retry on transient errors
                         Err(error) => {
-                            error!(%error, "Listener accept failed");
+                            error!(%error, name = listener.name(), "Listener accept failed");
                         }
                     }
                 }
@@ -1,13 +1,24 @@
 //! Unix domain socket listener for the SSH agent server
 
-use std::{fs, os::unix::fs::PermissionsExt, path::PathBuf};
+use std::{
+    fs,
+    os::{
+        fd::{BorrowedFd, RawFd},
+        unix::fs::{FileTypeExt, MetadataExt, PermissionsExt},
+    },
+    path::{Path, PathBuf},
+    sync::{Arc, OnceLock},
+};
 
 use anyhow::{anyhow, Result};
 use tokio::net::UnixStream;
-use tracing::{debug, error, info};
+use tracing::{debug, error, info, warn};
 
 use super::Listener;
-use crate::server::{connection::Connection, peer_info::PeerInfo};
+use crate::{
+    server::{connection::Connection, peer_info::PeerInfo},
+    storage::key_filter::KeyFilter,
+};
 
 /// Environment variable that overrides the default socket path
 const ENV_BITWARDEN_SSH_AUTH_SOCK: &str = "BITWARDEN_SSH_AUTH_SOCK";
@@ -16,9 +27,49 @@ const FLATPAK_DATA_DIR: &str = ".var/app/com.bitwarden.desktop/data";
 
 const SOCKFILE_NAME: &str = ".bitwarden-ssh-agent.sock";
 
+/// Environment variables set by systemd for socket activated services, see `sd_listen_fds(3)`.
+const ENV_LISTEN_PID: &str = "LISTEN_PID";
+const ENV_LISTEN_FDS: &str = "LISTEN_FDS";
+const ENV_LISTEN_FDNAMES: &str = "LISTEN_FDNAMES";
+
+/// The first file descriptor passed by systemd.
+const SD_LISTEN_FDS_START: RawFd = 3;
+
+/// The sockets passed by systemd, read once when first needed.
+static INHERITED_FDS: OnceLock<Vec<(RawFd, String)>> = OnceLock::new();
+
 /// Unix domain socket listener for the SSH agent server
 pub(crate) struct UnixListener {
     inner: tokio::net::UnixListener,
+    /// Name of the listener, used to identify it in logs.
+    name: String,
+    /// The keys visible to connections accepted by this listener.
+    key_filter: Arc<KeyFilter>,
+    /// The socket file created by [`Self::bind`], removed when the listener is dropped.
+    socket_file: Option<SocketFile>,
+}
+
+/// A socket file bound by the agent.
+struct SocketFile {
+    path: PathBuf,
+    /// Device and inode of the socket, so that a socket bound to the same path later isn't
+    /// removed.
+    dev: u64,
+    ino: u64,
+}
+
+impl Drop for SocketFile {
+    fn drop(&mut self) {
+        let is_same_socket = fs::symlink_metadata(&self.path)
+            .is_ok_and(|metadata| metadata.dev() == self.dev && metadata.ino() == self.ino);
+        if !is_same_socket {
+            return;
+        }
+
+        if let Err(error) = fs::remove_file(&self.path) {
+            warn!(%error, path = ?self.path, "Failed to remove socket");
+        }
+    }
 }
 
 impl UnixListener {
@@ -34,33 +85,148 @@ impl UnixListener {
     /// Returns an error if the socket path cannot be resolved, the stale socket cannot be removed,
     /// binding fails, or permissions cannot be set.
     pub(crate) fn new() -> Result<Self> {
-        let socket_path = get_socket_path()?;
+        Self::bind("default".to_string(), &get_socket_path()?)
+    }
 
-        remove_stale_socket(&socket_path)?;
+    /// Creates a new [`UnixListener`] named `name`, binding to `socket_path`. As with
+    /// [`Self::new`], a stale socket is removed and permissions are set to `0o600`. The socket is
+    /// removed again when the listener is dropped.
+    ///
+    /// # Errors
+    ///
+    /// Returns an error if a file other than a socket exists at `socket_path`, the stale socket
+    /// cannot be removed, binding fails, or permissions cannot be set.
+    pub(crate) fn bind(name: String, socket_path: &Path) -> Result<Self> {
+        remove_stale_socket(socket_path)?;
 
-        debug!(?socket_path, "Binding socket");
+        debug!(name, ?socket_path, "Binding socket");
 
-        let listener = tokio::net::UnixListener::bind(&socket_path)
+        let listener = tokio::net::UnixListener::bind(socket_path)
             .map_err(|e| anyhow!("Unable to bind to socket {}: {e}", socket_path.display()))?;
 
-        set_user_permissions(&socket_path)?;
+        let metadata = fs::symlink_metadata(socket_path)
+            .map_err(|e| anyhow!("Unable to stat socket {}: {e}", socket_path.display()))?;
+        let socket_file = SocketFile {
+            path: socket_path.to_path_buf(),
+            dev: metadata.dev(),
+            ino: metadata.ino(),
+        };
+
+        set_user_permissions(socket_path)?;
+
+        info!(name, ?socket_path, "socket listener ready");
+
+        Ok(Self {
+            inner: listener,
+            name,
+            key_filter: Arc::default(),
+            socket_file: Some(socket_file),
+        })
+    }
+
+    /// Creates a new [`UnixListener`] named `name` from a listening socket inherited from the
+    /// service manager. The inherited descriptor is duplicated rather than taken over, so the
+    /// server can be restarted with it.
+    ///
+    /// # Errors
+    ///
+    /// Returns an error if `fd` is not a Unix domain socket.
+    pub(crate) fn from_inherited_fd(fd: RawFd, name: String) -> Result<Self> {
+        // SAFETY: `fd` was passed by the service manager and is kept open for the lifetime of the
+        // process. It is only borrowed to be duplicated.
+        let fd = unsafe { BorrowedFd::borrow_raw(fd) }
+            .try_clone_to_owned()
+            .map_err(|e| anyhow!("Unable to duplicate inherited socket {fd}: {e}"))?;
+
+        let listener = std::os::unix::net::UnixListener::from(fd);
+        let socket_path = listener
+            .local_addr()
+            .map_err(|e| anyhow!("Inherited socket {name} is not a Unix domain socket: {e}"))?;
+        listener.set_nonblocking(true)?;
+        let listener = tokio::net::UnixListener::from_std(listener)?;
+
+        info!(name, ?socket_path, "inherited socket listener ready");
+
+        Ok(Self {
+            inner: listener,
+            name,
+            key_filter: Arc::default(),
+            socket_file: None,
+        })
+    }
+
+    /// Restricts the keys visible to connections accepted by this listener to `key_filter`.
+    #[must_use]
+    pub(crate) fn with_key_filter(mut self, key_filter: KeyFilter) -> Self {
+        self.key_filter = Arc::new(key_filter);
+        self
+    }
+}
 
-        info!(?socket_path, "socket listener ready");
+/// # Returns
+///
+/// The file descriptors and names of the sockets passed by systemd socket activation, empty if
+/// the process was not socket activated.
+pub(crate) fn inherited_fds() -> Vec<(RawFd, String)> {
+    INHERITED_FDS.get_or_init(read_inherited_fds).clone()
+}
 
-        Ok(Self { inner: listener })
+/// Reads the sockets passed by systemd socket activation. The environment variables describing
+/// them are left in place, since child processes don't adopt the sockets as `LISTEN_PID` doesn't
+/// match their PID.
+fn read_inherited_fds() -> Vec<(RawFd, String)> {
+    let is_listen_pid = std::env::var(ENV_LISTEN_PID)
+        .ok()
+        .and_then(|pid| pid.parse::<u32>().ok())
+        .is_some_and(|pid| pid == std::process::id());
+    if !is_listen_pid {
+        return Vec::new();
     }
+
+    let count = std::env::var(ENV_LISTEN_FDS)
+        .ok()
+        .and_then(|count| count.parse::<RawFd>().ok())
+        .unwrap_or(0);
+    let names = std::env::var(ENV_LISTEN_FDNAMES).unwrap_or_default();
+    let mut names = names.split(':');
+
+    (0..count)
+        .map(|i| {
+            let name = names
+                .next()
+                .filter(|name| !name.is_empty())
+                .unwrap_or("systemd");
+            (SD_LISTEN_FDS_START + i, name.to_string())
+        })
+        .collect()
 }
 
 #[async_trait::async_trait]
 impl Listener for UnixListener {
     type Stream = UnixStream;
 
+    fn name(&self) -> &str {
+        &self.name
+    }
+
+    fn path(&self) -> Option<PathBuf> {
+        self.inner
+            .local_addr()
+            .ok()
+            .and_then(|addr| addr.as_pathname().map(Path::to_path_buf))
+    }
+
     async fn accept(&mut self) -> Result<Connection<Self::Stream>> {
         let (stream, _addr) = self.inner.accept().await?;
 
         let peer_info = get_peer_info(&stream);
+        debug!(name = self.name, ?peer_info, "Accepted connection");
 
-        Ok(Connection { stream, peer_info })
+        Ok(Connection {
+            stream,
+            peer_info,
+            key_filter: self.key_filter.clone(),
+        })
     }
 }
 
@@ -107,7 +273,7 @@ fn get_default_socket_path() -> Result<PathBuf> {
     Ok(home.join(SOCKFILE_NAME))
 }
 
-fn set_user_permissions(path: &PathBuf) -> Result<()> {
+fn set_user_permissions(path: &Path) -> Result<()> {
     fs::set_permissions(path, fs::Permissions::from_mode(0o600)).map_err(|e| {
         anyhow!(
             "Could not set socket permissions for {}: {e}",
@@ -116,12 +282,24 @@ fn set_user_permissions(path: &PathBuf) -> Result<()> {
     })
 }
 
-fn remove_stale_socket(path: &PathBuf) -> Result<()> {
-    if let Ok(true) = std::fs::exists(path) {
-        std::fs::remove_file(path)
-            .map_err(|e| anyhow!("Error removing stale socket {}: {e}", path.display()))?;
+/// Removes a socket left behind at `path`. Any other kind of file is left in place, so a
+/// misconfigured path can't delete the user's files.
+fn remove_stale_socket(path: &Path) -> Result<()> {
+    let metadata = match fs::symlink_metadata(path) {
+        Ok(metadata) => metadata,
+        Err(e) if e.kind() == std::io::ErrorKind::NotFound => return Ok(()),
+        Err(e) => return Err(anyhow!("Unable to stat socket {}: {e}", path.display())),
+    };
+
+    if !metadata.file_type().is_socket() {
+        return Err(anyhow!(
+            "Refusing to replace {}, which is not a socket",
+            path.display()
+        ));
     }
-    Ok(())
+
+    fs::remove_file(path)
+        .map_err(|e| anyhow!("Error removing stale socket {}: {e}", path.display()))
 }
 
 #[cfg(test)]
@@ -191,14 +369,146 @@ mod tests {
         assert!(peer_info.is_some());
     }
 
+    #[test]
+    #[serial_test::serial]
+    fn test_inherited_fds_requires_matching_pid() {
+        // SAFETY: tests touching env vars are serialized via #[serial]
+        unsafe {
+            std::env::set_var(ENV_LISTEN_PID, "1");
+            std::env::set_var(ENV_LISTEN_FDS, "1");
+        }
+
+        let fds = read_inherited_fds();
+
+        unsafe {
+            std::env::remove_var(ENV_LISTEN_PID);
+            std::env::remove_var(ENV_LISTEN_FDS);
+        }
+        assert!(fds.is_empty());
+    }
+
+    #[test]
+    #[serial_test::serial]
+    fn test_inherited_fds_with_names() {
+        // SAFETY: tests touching env vars are serialized via #[serial]
+        unsafe {
+            std::env::set_var(ENV_LISTEN_PID, std::process::id().to_string());
+            std::env::set_var(ENV_LISTEN_FDS, "3");
+            std::env::set_var(ENV_LISTEN_FDNAMES, "work::personal");
+        }
+
+        let fds = read_inherited_fds();
+
+        unsafe {
+            std::env::remove_var(ENV_LISTEN_PID);
+            std::env::remove_var(ENV_LISTEN_FDS);
+            std::env::remove_var(ENV_LISTEN_FDNAMES);
+        }
+        assert_eq!(
+            fds,
+            vec![
+                (3, "work".to_string()),
+                (4, "systemd".to_string()),
+                (5, "personal".to_string()),
+            ]
+        );
+    }
+
+    #[tokio::test]
+    async fn test_bind_accepts_connections() {
+        let path = rand_path_in_temp();
+        let mut listener = UnixListener::bind("work".to_string(), &path).unwrap();
+
+        let _client = tokio::net::UnixStream::connect(&path).await.unwrap();
+
+        assert!(listener.accept().await.is_ok());
+        assert_eq!(listener.path(), Some(path));
+    }
+
+    #[tokio::test]
+    async fn test_bind_removes_socket_when_dropped() {
+        let path = rand_path_in_temp();
+        let listener = UnixListener::bind("work".to_string(), &path).unwrap();
+        assert!(fs::exists(&path).unwrap());
+
+        drop(listener);
+
+        assert!(!fs::exists(&path).unwrap());
+    }
+
+    #[tokio::test]
+    async fn test_bind_keeps_socket_of_newer_listener_when_dropped() {
+        let path = rand_path_in_temp();
+        let old_listener = UnixListener::bind("work".to_string(), &path).unwrap();
+        let mut new_listener = UnixListener::bind("work".to_string(), &path).unwrap();
+
+        drop(old_listener);
+
+        let _client = tokio::net::UnixStream::connect(&path).await.unwrap();
+        assert!(new_listener.accept().await.is_ok());
+    }
+
+    #[tokio::test]
+    async fn test_bind_refuses_to_replace_regular_file() {
+        let path = rand_path_in_temp();
+        fs::write(&path, "not a socket").unwrap();
+
+        assert!(UnixListener::bind("work".to_string(), &path).is_err());
+
+        assert_eq!(fs::read_to_string(&path).unwrap(), "not a socket");
+        fs::remove_file(&path).unwrap();
+    }
+
+    #[tokio::test]
+    async fn test_from_inherited_fd_accepts_connections() {
+        use std::os::fd::AsRawFd;
+
+        let path = rand_path_in_temp();
+        let inherited = std::os::unix::net::UnixListener::bind(&path).unwrap();
+        let mut listener =
+            UnixListener::from_inherited_fd(inherited.as_raw_fd(), "systemd".to_string()).unwrap();
+
+        let _client = tokio::net::UnixStream::connect(&path).await.unwrap();
+
+        assert!(listener.accept().await.is_ok());
+        drop(listener);
+        assert!(
+            inherited.local_addr().is_ok(),
+            "inherited socket must stay open"
+        );
+        remove_stale_socket(&path).unwrap();
+    }
+
+    #[test]
+    fn test_from_inherited_fd_rejects_non_socket() {
+        use std::os::fd::AsRawFd;
+
+        let path = rand_path_in_temp();
+        let file = fs::File::create(&path).unwrap();
+
+        assert!(UnixListener::from_inherited_fd(file.as_raw_fd(), "systemd".to_string()).is_err());
+        fs::remove_file(&path).unwrap();
+    }
+
     #[test]
     fn test_remove_stale_socket_exists() {
         let path = rand_path_in_temp();
-        fs::write(&path, "").unwrap();
+        drop(std::os::unix::net::UnixListener::bind(&path).unwrap());
         remove_stale_socket(&path).unwrap();
         assert!(!fs::exists(&path).unwrap());
     }
 
+    #[test]
+    fn test_remove_stale_socket_regular_file_returns_error() {
+        let path = rand_path_in_temp();
+        fs::write(&path, "").unwrap();
+
+        assert!(remove_stale_socket(&path).is_err());
+
+        assert!(fs::exists(&path).unwrap());
+        fs::remove_file(&path).unwrap();
+    }
+
     #[test]
     fn test_remove_stale_socket_not_found() {
         let path = rand_path_in_temp();
@@ -216,6 +526,6 @@ mod tests {
         let permissions = fs::metadata(&path).unwrap().permissions().mode();
         assert_eq!(permissions, 0o100_600);
 
-        remove_stale_socket(&path).unwrap();
+        fs::remove_file(&path).unwrap();
     }
 }
@@ -1,21 +1,101 @@
 //! Windows named pipe listener for the SSH agent server
 
-use std::{mem, os::windows::io::AsRawHandle};
+use std::{io, mem, os::windows::io::AsRawHandle, path::PathBuf, sync::Arc};
 
 use anyhow::{anyhow, Result};
 use tokio::net::windows::named_pipe::{NamedPipeServer, ServerOptions};
-use tracing::{info, warn};
-use windows::Win32::{Foundation::HANDLE, System::Pipes::GetNamedPipeClientProcessId};
+use tracing::{debug, info, warn};
+use windows::{
+    core::{w, PCWSTR},
+    Win32::{
+        Foundation::{LocalFree, HANDLE, HLOCAL},
+        Security::{
+            Authorization::{
+                ConvertStringSecurityDescriptorToSecurityDescriptorW, SDDL_REVISION_1,
+            },
+            PSECURITY_DESCRIPTOR, SECURITY_ATTRIBUTES,
+        },
+        System::Pipes::GetNamedPipeClientProcessId,
+    },
+};
 
 use super::Listener;
-use crate::server::{connection::Connection, peer_info::PeerInfo};
+use crate::{
+    server::{connection::Connection, peer_info::PeerInfo},
+    storage::key_filter::KeyFilter,
+};
 
 /// The fixed named pipe path that OpenSSH clients expect on Windows
 const PIPE_NAME: &str = r"\\.\pipe\openssh-ssh-agent";
 
+/// Grants access to the pipe to its owner and `SYSTEM` only, without inheriting other entries.
+const PIPE_SECURITY_DESCRIPTOR: PCWSTR = w!("D:P(A;;GA;;;SY)(A;;GA;;;OW)");
+
 /// Windows named pipe listener for the SSH agent server
 pub(crate) struct WindowsListener {
     inner: NamedPipeServer,
+    /// Name of the listener, used to identify it in logs.
+    name: String,
+    /// The keys visible to connections accepted by this listener.
+    key_filter: Arc<KeyFilter>,
+    pipe_name: String,
+    /// The security descriptor of the pipe's instances. `None` for the default pipe, which keeps
+    /// the default security of named pipes.
+    security_descriptor: Option<PipeSecurityDescriptor>,
+}
+
+/// The security descriptor every instance of the agent's additional pipes is created with.
+struct PipeSecurityDescriptor(PSECURITY_DESCRIPTOR);
+
+// SAFETY: the descriptor is never modified after it is created, and is freed once on drop.
+unsafe impl Send for PipeSecurityDescriptor {}
+// SAFETY: see above.
+unsafe impl Sync for PipeSecurityDescriptor {}
+
+impl PipeSecurityDescriptor {
+    fn new() -> Result<Self> {
+        let mut descriptor = PSECURITY_DESCRIPTOR::default();
+
+        // SAFETY: `PIPE_SECURITY_DESCRIPTOR` is a valid, nul-terminated string, and `descriptor`
+        // is a local that receives a buffer freed on drop.
+        unsafe {
+            ConvertStringSecurityDescriptorToSecurityDescriptorW(
+                PIPE_SECURITY_DESCRIPTOR,
+                SDDL_REVISION_1,
+                &raw mut descriptor,
+                None,
+            )
+        }
+        .map_err(|e| anyhow!("Unable to create named pipe security descriptor: {e}"))?;
+
+        Ok(Self(descriptor))
+    }
+
+    /// Creates an instance of the pipe `pipe_name` restricted to the current user. With
+    /// `first_instance`, creation fails if another process already serves the pipe.
+    fn create_pipe(&self, pipe_name: &str, first_instance: bool) -> io::Result<NamedPipeServer> {
+        let mut attributes = SECURITY_ATTRIBUTES {
+            #[allow(clippy::cast_possible_truncation)]
+            nLength: mem::size_of::<SECURITY_ATTRIBUTES>() as u32,
+            lpSecurityDescriptor: self.0 .0,
+            bInheritHandle: false.into(),
+        };
+
+        // SAFETY: `attributes` and the descriptor it points to outlive the call.
+        unsafe {
+            ServerOptions::new()
+                .first_pipe_instance(first_instance)
+                .create_with_security_attributes_raw(pipe_name, (&raw mut attributes).cast())
+        }
+    }
+}
+
+impl Drop for PipeSecurityDescriptor {
+    fn drop(&mut self) {
+        // SAFETY: the descriptor was allocated by
+        // `ConvertStringSecurityDescriptorToSecurityDescriptorW` and isn't used after this.
+        unsafe { LocalFree(Some(HLOCAL(self.0 .0))) };
+    }
 }
 
 impl WindowsListener {
@@ -34,7 +114,44 @@ impl WindowsListener {
 
         info!(pipe_name = PIPE_NAME, "Named pipe listener ready");
 
-        Ok(Self { inner: server })
+        Ok(Self {
+            inner: server,
+            name: "default".to_string(),
+            pipe_name: PIPE_NAME.to_string(),
+            key_filter: Arc::default(),
+            security_descriptor: None,
+        })
+    }
+
+    /// Creates a new [`WindowsListener`] named `name`, binding to `pipe_name`. Only the current
+    /// user can connect to the pipe.
+    ///
+    /// # Errors
+    ///
+    /// Returns an error if the named pipe cannot be created, or is already served by another
+    /// process.
+    pub(crate) fn bind(name: String, pipe_name: &str) -> Result<Self> {
+        let security_descriptor = PipeSecurityDescriptor::new()?;
+        let server = security_descriptor
+            .create_pipe(pipe_name, true)
+            .map_err(|e| anyhow!("Unable to create named pipe {pipe_name}: {e}"))?;
+
+        info!(name, pipe_name, "Named pipe listener ready");
+
+        Ok(Self {
+            inner: server,
+            name,
+            pipe_name: pipe_name.to_string(),
+            key_filter: Arc::default(),
+            security_descriptor: Some(security_descriptor),
+        })
+    }
+
+    /// Restricts the keys visible to connections accepted by this listener to `key_filter`.
+    #[must_use]
+    pub(crate) fn with_key_filter(mut self, key_filter: KeyFilter) -> Self {
+        self.key_filter = Arc::new(key_filter);
+        self
     }
 }
 
@@ -42,19 +159,34 @@ impl WindowsListener {
 impl Listener for WindowsListener {
     type Stream = NamedPipeServer;
 
+    fn name(&self) -> &str {
+        &self.name
+    }
+
+    fn path(&self) -> Option<PathBuf> {
+        Some(PathBuf::from(&self.pipe_name))
+    }
+
     async fn accept(&mut self) -> Result<Connection<Self::Stream>> {
         self.inner.connect().await?;
 
         // Create the next server instance before handing off the current one, so the
         // pipe name remains available for subsequent clients without a gap.
-        let next = ServerOptions::new()
-            .create(PIPE_NAME)
-            .map_err(|e| anyhow!("Failed to create next pipe instance after accept: {e}"))?;
+        let next = match &self.security_descriptor {
+            Some(security_descriptor) => security_descriptor.create_pipe(&self.pipe_name, false),
+            None => ServerOptions::new().create(&self.pipe_name),
+        }
+        .map_err(|e| anyhow!("Failed to create next pipe instance after accept: {e}"))?;
 
         let stream = mem::replace(&mut self.inner, next);
         let peer_info = get_peer_info(&stream);
+        debug!(name = self.name, ?peer_info, "Accepted connection");
 
-        Ok(Connection { stream, peer_info })
+        Ok(Connection {
+            stream,
+            peer_info,
+            key_filter: self.key_filter.clone(),
+        })
     }
 }
 
@@ -88,6 +220,26 @@ mod tests {
         WindowsListener::new().unwrap();
     }
 
+    #[serial_test::serial]
+    #[tokio::test]
+    async fn test_bind_accepts_on_additional_pipe() {
+        let pipe_name = r"\\.\pipe\bitwarden-ssh-agent-test-additional";
+        let mut listener = WindowsListener::bind("work".to_string(), pipe_name).unwrap();
+
+        let _client = ClientOptions::new().open(pipe_name).unwrap();
+
+        assert!(listener.accept().await.is_ok());
+    }
+
+    #[serial_test::serial]
+    #[tokio::test]
+    async fn test_bind_refuses_pipe_served_by_another_listener() {
+        let pipe_name = r"\\.\pipe\bitwarden-ssh-agent-test-squatted";
+        let _listener = WindowsListener::bind("work".to_string(), pipe_name).unwrap();
+
+        assert!(WindowsListener::bind("personal".to_string(), pipe_name).is_err());
+    }
+
     #[serial_test::serial]
     #[tokio::test]
     async fn test_get_peer_info_connected_client_returns_some() {
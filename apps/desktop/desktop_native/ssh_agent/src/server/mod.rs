@@ -8,22 +8,37 @@ mod connection;
 mod listener;
 mod peer_info;
 mod protocol;
+mod rate_limit;
 mod session_bind;
+mod upstream;
 
-use std::sync::Arc;
+use std::{path::Path, sync::Arc};
 
 use anyhow::Result;
 pub(crate) use auth_policy::AuthPolicy;
 // external exports for napi
-pub use auth_policy::{AuthRequest, ConnectionContext, SessionBindContext, SignRequest};
+pub use auth_policy::{
+    AuthRequest, CertificateContext, ConnectionContext, IdentityOperation, IdentityRequest,
+    SessionBindContext, SessionBindHop, SignRequest,
+};
 use connection::{Connection, ConnectionHandler};
+pub use listener::AgentSocket;
 pub(crate) use listener::Listener;
-pub use protocol::{SIGNamespace, SignFlags};
+pub use peer_info::PeerSandbox;
+pub use protocol::{SIGNamespace, SSHSigData, SignFlags};
+use rate_limit::RateLimiter;
+pub use rate_limit::{RateLimitStats, RateLimits};
 use tokio::{sync::mpsc, task::JoinHandle};
 use tokio_util::sync::CancellationToken;
 use tracing::{debug, info, warn};
+pub(crate) use upstream::{SocketUpstreamAgent, UpstreamAgent};
 
-use crate::KeyStore;
+use crate::{
+    audit::AuditSink,
+    external_signer::ExternalSigner,
+    storage::{agent_lock::AgentLock, session_keystore::SessionKeyStore},
+    KeyStore,
+};
 
 /// Buffer accepted connections pending dispatch to handler tasks.
 const CONNECTION_CHANNEL_CAPACITY: usize = 32;
@@ -38,8 +53,20 @@ const CONNECTION_CHANNEL_CAPACITY: usize = 32;
 pub struct SSHAgentServer<K, A> {
     /// The storage of SSH key data
     keystore: Arc<K>,
+    /// The storage of session-scoped SSH keys added by clients
+    session_keystore: Arc<SessionKeyStore>,
+    /// The locked state of the agent, shared with connections
+    agent_lock: Arc<AgentLock>,
     /// The authenticator policy to invoke for operations that require authorization
     auth_policy: Arc<A>,
+    /// The signer for security keys, whose private keys are held by a device
+    external_signer: Arc<dyn ExternalSigner>,
+    /// The agent whose keys are offered alongside the keystore's. `None` if there is none.
+    upstream: Option<Arc<dyn UpstreamAgent>>,
+    /// The destination of audit entries for list and sign requests. `None` if they aren't recorded.
+    audit_sink: Option<Arc<dyn AuditSink>>,
+    /// The limits on requests and connections of clients, with counters of refused ones
+    rate_limiter: Arc<RateLimiter>,
     /// Async task coordination to use when asked to stop. Is `None` when not running.
     cancellation_token: Option<CancellationToken>,
     /// Task handle for the accept loop. Is `None` when not running.
@@ -52,17 +79,56 @@ where
     A: AuthPolicy + 'static,
 {
     /// Creates a new [`SSHAgentServer`]
-    pub(crate) fn new(keystore: Arc<K>, auth_policy: Arc<A>) -> Self {
+    pub(crate) fn new(
+        keystore: Arc<K>,
+        session_keystore: Arc<SessionKeyStore>,
+        agent_lock: Arc<AgentLock>,
+        auth_policy: Arc<A>,
+        external_signer: Arc<dyn ExternalSigner>,
+    ) -> Self {
         Self {
             keystore,
+            session_keystore,
+            agent_lock,
             auth_policy,
+            external_signer,
+            upstream: None,
+            audit_sink: None,
+            rate_limiter: Arc::new(RateLimiter::default()),
             cancellation_token: None,
             accept_handle: None,
         }
     }
 
-    pub(crate) fn start_with_default_listeners(&mut self) -> Result<()> {
-        let listeners = listener::create_listeners()?;
+    /// Sets the agent whose keys are offered alongside the keystore's. Takes effect when the
+    /// server is next started.
+    pub(crate) fn set_upstream(&mut self, upstream: Option<Arc<dyn UpstreamAgent>>) {
+        self.upstream = upstream;
+    }
+
+    /// Sets the destination of audit entries for list and sign requests. Takes effect when the
+    /// server is next started.
+    pub(crate) fn set_audit_sink(&mut self, audit_sink: Option<Arc<dyn AuditSink>>) {
+        self.audit_sink = audit_sink;
+    }
+
+    /// Sets the limits on requests and connections of clients, resetting the counters of
+    /// [`Self::rate_limit_stats`]. Takes effect immediately, also while the server is running.
+    pub(crate) fn set_rate_limits(&self, limits: RateLimits) {
+        self.rate_limiter.set_limits(limits);
+    }
+
+    /// # Returns
+    ///
+    /// The counters of requests and connections refused because of the rate limits, and of those
+    /// in progress.
+    pub(crate) fn rate_limit_stats(&self) -> RateLimitStats {
+        self.rate_limiter.stats()
+    }
+
+    /// Starts the server, listening on the default socket and the additional `sockets`.
+    pub(crate) fn start_with_sockets(&mut self, sockets: &[AgentSocket]) -> Result<()> {
+        let listeners = listener::create_listeners(sockets)?;
         self.start(listeners)
     }
 
@@ -82,10 +148,26 @@ where
 
         info!("Starting server");
 
+        // Forwarding to the agent's own socket would loop, e.g. when it was started with
+        // `SSH_AUTH_SOCK` already pointing at it.
+        let upstream = self.upstream.clone().filter(|upstream| {
+            let is_own = is_own_socket(upstream.as_ref(), &listeners);
+            if is_own {
+                warn!(path = ?upstream.path(), "Ignoring upstream agent listening on our own socket");
+            }
+            !is_own
+        });
+
         let accept_handle = tokio::spawn(Self::accept(
             listeners,
             self.keystore.clone(),
+            self.session_keystore.clone(),
+            self.agent_lock.clone(),
             self.auth_policy.clone(),
+            self.external_signer.clone(),
+            upstream,
+            self.audit_sink.clone(),
+            self.rate_limiter.clone(),
             cancel_token.clone(),
         ));
 
@@ -120,10 +202,17 @@ where
     /// Spawns listener tasks for each listener.
     /// Incoming connections from listener tasks are dispatched to handler tasks.
     /// Loops until cancelled or all listener tasks have exited.
+    #[allow(clippy::too_many_arguments)]
     async fn accept<L>(
         listeners: Vec<L>,
         keystore: Arc<K>,
+        session_keystore: Arc<SessionKeyStore>,
+        agent_lock: Arc<AgentLock>,
         auth_policy: Arc<A>,
+        external_signer: Arc<dyn ExternalSigner>,
+        upstream: Option<Arc<dyn UpstreamAgent>>,
+        audit_sink: Option<Arc<dyn AuditSink>>,
+        rate_limiter: Arc<RateLimiter>,
         cancel_token: CancellationToken,
     ) where
         L: Listener + 'static,
@@ -148,13 +237,26 @@ where
                 conn = rx.recv() => if let Some(connection) = conn {
                     debug!(peer_info = ?connection.peer_info, "Connection accepted");
 
+                    // Dropping the connection closes it.
+                    let Some(connection_slot) = rate_limiter.open_connection() else {
+                        continue;
+                    };
                     let handler = ConnectionHandler::new(
                         keystore.clone(),
+                        session_keystore.clone(),
+                        agent_lock.clone(),
                         auth_policy.clone(),
+                        external_signer.clone(),
+                        upstream.clone(),
+                        audit_sink.clone(),
+                        rate_limiter.clone(),
                         connection,
                         cancel_token.clone(),
                     );
-                    tokio::spawn(async move { handler.handle().await });
+                    tokio::spawn(async move {
+                        handler.handle().await;
+                        drop(connection_slot);
+                    });
                 } else {
                     debug!("All listener tasks exited");
                     break;
@@ -170,17 +272,26 @@ where
 // within `server` utilize.
 #[cfg(test)]
 pub(crate) mod test_common {
+    use std::sync::Arc;
+
     use signature::{SignatureEncoding as _, Signer as _};
-    use ssh_key::private::{Ed25519Keypair, KeypairData, RsaKeypair};
+    use ssh_key::private::{EcdsaKeypair, Ed25519Keypair, KeypairData, RsaKeypair};
 
     use super::{
         protocol::{RSA_SHA2_256, RSA_SHA2_512},
         AuthPolicy, AuthRequest,
     };
-    use crate::authorization::AuthError;
+    use crate::{
+        authorization::AuthError,
+        external_signer::{ExternalSigner, UnavailableExternalSigner},
+    };
 
     pub(crate) struct AlwaysAllowPolicy;
 
+    pub(crate) fn unavailable_external_signer() -> Arc<dyn ExternalSigner> {
+        Arc::new(UnavailableExternalSigner)
+    }
+
     #[async_trait::async_trait]
     impl AuthPolicy for AlwaysAllowPolicy {
         async fn authorize(&self, _: &AuthRequest) -> Result<bool, AuthError> {
@@ -209,6 +320,65 @@ pub(crate) mod test_common {
         blob
     }
 
+    pub(crate) fn make_add_identity_msg(private_key: &ssh_key::PrivateKey) -> Vec<u8> {
+        use ssh_encoding::Encode as _;
+
+        let mut msg = vec![17u8]; // SSH2_AGENTC_ADD_IDENTITY
+        private_key.key_data().encode(&mut msg).unwrap();
+        write_ssh_string(&mut msg, private_key.comment().as_bytes());
+        msg
+    }
+
+    pub(crate) fn make_add_id_constrained_msg(
+        private_key: &ssh_key::PrivateKey,
+        constraints: &[u8],
+    ) -> Vec<u8> {
+        let mut msg = make_add_identity_msg(private_key);
+        msg[0] = 25; // SSH2_AGENTC_ADD_ID_CONSTRAINED
+        msg.extend_from_slice(constraints);
+        msg
+    }
+
+    /// Builds a `restrict-destination-v00@openssh.com` constraint permitting use from the local
+    /// host to `to_hostname`, identified by the given host keys.
+    pub(crate) fn make_restrict_destination_constraint(
+        to_hostname: &str,
+        to_host_keys: &[(&[u8], bool)],
+    ) -> Vec<u8> {
+        let mut from_hop = Vec::new();
+        write_ssh_string(&mut from_hop, b"");
+        write_ssh_string(&mut from_hop, b"");
+        write_ssh_string(&mut from_hop, b"");
+
+        let mut to_hop = Vec::new();
+        write_ssh_string(&mut to_hop, b"");
+        write_ssh_string(&mut to_hop, to_hostname.as_bytes());
+        write_ssh_string(&mut to_hop, b"");
+        for (host_key, is_ca) in to_host_keys {
+            write_ssh_string(&mut to_hop, host_key);
+            to_hop.push(u8::from(*is_ca));
+        }
+
+        let mut destination = Vec::new();
+        write_ssh_string(&mut destination, &from_hop);
+        write_ssh_string(&mut destination, &to_hop);
+        write_ssh_string(&mut destination, b"");
+
+        let mut destinations = Vec::new();
+        write_ssh_string(&mut destinations, &destination);
+
+        let mut constraint = vec![255u8]; // SSH_AGENT_CONSTRAIN_EXTENSION
+        write_ssh_string(&mut constraint, b"restrict-destination-v00@openssh.com");
+        write_ssh_string(&mut constraint, &destinations);
+        constraint
+    }
+
+    pub(crate) fn make_remove_identity_msg(blob: &[u8]) -> Vec<u8> {
+        let mut msg = vec![18u8]; // SSH2_AGENTC_REMOVE_IDENTITY
+        write_ssh_string(&mut msg, blob);
+        msg
+    }
+
     pub(crate) fn write_ssh_string(buf: &mut Vec<u8>, data: &[u8]) {
         buf.extend_from_slice(&(data.len() as u32).to_be_bytes());
         buf.extend_from_slice(data);
@@ -270,26 +440,90 @@ pub(crate) mod test_common {
         payload.push(u8::from(is_forwarding));
         payload
     }
+
+    pub(crate) fn make_session_bind_payload_ecdsa(
+        keypair: &EcdsaKeypair,
+        session_id: &[u8],
+        is_forwarding: bool,
+    ) -> Vec<u8> {
+        let private_key =
+            ssh_key::PrivateKey::new(KeypairData::Ecdsa(keypair.clone()), "").unwrap();
+        let hostkey_bytes = private_key.public_key().to_bytes().unwrap();
+        let sig: ssh_key::Signature = keypair.sign(session_id);
+
+        let mut sig_outer = Vec::new();
+        write_ssh_string(&mut sig_outer, sig.algorithm().to_string().as_bytes());
+        write_ssh_string(&mut sig_outer, sig.as_bytes());
+
+        let mut payload = Vec::new();
+        write_ssh_string(&mut payload, &hostkey_bytes);
+        write_ssh_string(&mut payload, session_id);
+        write_ssh_string(&mut payload, &sig_outer);
+        payload.push(u8::from(is_forwarding));
+        payload
+    }
+}
+
+/// # Returns
+///
+/// `true` if `upstream` listens on the socket of one of `listeners`.
+fn is_own_socket<L: Listener>(upstream: &dyn UpstreamAgent, listeners: &[L]) -> bool {
+    let Some(upstream_path) = upstream.path() else {
+        return false;
+    };
+
+    listeners
+        .iter()
+        .filter_map(Listener::path)
+        .any(|path| is_same_path(&path, &upstream_path))
+}
+
+/// # Returns
+///
+/// `true` if `a` and `b` are the same path, or resolve to the same file through symlinks.
+fn is_same_path(a: &Path, b: &Path) -> bool {
+    a == b
+        || matches!(
+            (std::fs::canonicalize(a), std::fs::canonicalize(b)),
+            (Ok(a), Ok(b)) if a == b
+        )
 }
 
 #[cfg(test)]
 mod tests {
-    use std::sync::Arc;
+    use std::{path::PathBuf, sync::Arc};
 
     use anyhow::anyhow;
     use tokio::io::DuplexStream;
 
-    use super::{connection::Connection, test_common::AlwaysAllowPolicy, Listener, SSHAgentServer};
-    use crate::storage::keystore::MockKeyStore;
+    use super::{
+        connection::Connection,
+        is_own_socket,
+        test_common::{unavailable_external_signer, AlwaysAllowPolicy},
+        upstream::MockUpstreamAgent,
+        Listener, SSHAgentServer,
+    };
+    use crate::storage::{
+        agent_lock::AgentLock, keystore::MockKeyStore, session_keystore::SessionKeyStore,
+    };
 
     struct StubListener {
         rx: tokio::sync::mpsc::Receiver<Connection<DuplexStream>>,
+        path: Option<PathBuf>,
     }
 
     #[async_trait::async_trait]
     impl Listener for StubListener {
         type Stream = DuplexStream;
 
+        fn name(&self) -> &str {
+            "stub"
+        }
+
+        fn path(&self) -> Option<PathBuf> {
+            self.path.clone()
+        }
+
         async fn accept(&mut self) -> anyhow::Result<Connection<Self::Stream>> {
             self.rx
                 .recv()
@@ -303,11 +537,17 @@ mod tests {
         tokio::sync::mpsc::Sender<Connection<DuplexStream>>,
     ) {
         let (tx, rx) = tokio::sync::mpsc::channel(8);
-        (StubListener { rx }, tx)
+        (StubListener { rx, path: None }, tx)
     }
 
     fn make_server() -> SSHAgentServer<MockKeyStore, AlwaysAllowPolicy> {
-        SSHAgentServer::new(Arc::new(MockKeyStore::new()), Arc::new(AlwaysAllowPolicy))
+        SSHAgentServer::new(
+            Arc::new(MockKeyStore::new()),
+            Arc::new(SessionKeyStore::new()),
+            Arc::new(AgentLock::new()),
+            Arc::new(AlwaysAllowPolicy),
+            unavailable_external_signer(),
+        )
     }
 
     #[tokio::test]
@@ -366,4 +606,51 @@ mod tests {
         assert!(server.is_running());
         server.stop();
     }
+
+    fn make_upstream_at(path: Option<PathBuf>) -> MockUpstreamAgent {
+        let mut upstream = MockUpstreamAgent::new();
+        upstream.expect_path().return_const(path);
+        upstream
+    }
+
+    fn make_stub_listener_at(path: PathBuf) -> StubListener {
+        let (mut listener, _tx) = make_stub_listener();
+        listener.path = Some(path);
+        listener
+    }
+
+    #[test]
+    fn test_is_own_socket_matches_listener_path() {
+        let listeners = vec![make_stub_listener_at(PathBuf::from("/tmp/agent.sock"))];
+
+        assert!(is_own_socket(
+            &make_upstream_at(Some(PathBuf::from("/tmp/agent.sock"))),
+            &listeners
+        ));
+        assert!(!is_own_socket(
+            &make_upstream_at(Some(PathBuf::from("/tmp/other.sock"))),
+            &listeners
+        ));
+        assert!(!is_own_socket(&make_upstream_at(None), &listeners));
+    }
+
+    #[cfg(unix)]
+    #[test]
+    fn test_is_own_socket_follows_symlinks() {
+        let dir = std::env::temp_dir().join(format!("bw-own-socket-{}", std::process::id()));
+        std::fs::create_dir_all(&dir).unwrap();
+        let socket_path = dir.join("agent.sock");
+        let link_path = dir.join("link.sock");
+        std::fs::write(&socket_path, "").unwrap();
+        let _ = std::fs::remove_file(&link_path);
+        std::os::unix::fs::symlink(&socket_path, &link_path).unwrap();
+
+        let is_own = is_own_socket(
+            &make_upstream_at(Some(link_path)),
+            &[make_stub_listener_at(socket_path)],
+        );
+
+        std::fs::remove_dir_all(&dir).unwrap();
+        assert!(is_own);
+    }
 }
@@ -3,28 +3,59 @@
 use sysinfo::{Pid, System};
 use tracing::warn;
 
+/// Upper bound on the number of ancestors resolved for a peer process.
+const MAX_ANCESTRY_DEPTH: usize = 16;
+
+/// A sandbox the peer process runs in, which limits what can be said about it. Its executable
+/// path, for example, is a path within the sandbox.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum PeerSandbox {
+    Flatpak,
+    Snap,
+    /// A process in a different PID namespace than the agent, e.g. a Docker or Podman container.
+    Container,
+}
+
 /// Information about the connecting peer process
 #[derive(Debug, Clone)]
 pub(crate) struct PeerInfo {
     pid: u32,
     process_name: String,
+    /// Absolute path of the peer's executable, if it could be resolved.
+    exe_path: Option<String>,
+    /// The peer's command line arguments, including the program name. Empty when unavailable.
+    args: Vec<String>,
+    /// Real user ID of the peer, if it could be resolved.
+    uid: Option<u32>,
+    /// PIDs of the peer's ancestor processes, nearest first.
+    ancestor_pids: Vec<u32>,
+    /// Names of the peer's ancestor processes, matching `ancestor_pids`.
+    ancestor_names: Vec<String>,
+    sandbox: Option<PeerSandbox>,
 }
 
 impl PeerInfo {
     /// Looks up the process name for `pid`, trying the macOS fast path
     /// first and falling back to the cross-platform `sysinfo` path.
     /// Intermediate failures are logged; returns `None` only when both
-    /// paths fail.
+    /// paths fail. On Linux, the executable path, arguments, user and
+    /// sandbox are read from `/proc`.
     pub(crate) fn from_pid(pid: u32) -> Option<Self> {
         #[cfg(target_os = "macos")]
         match peer_info_from_libproc(pid) {
-            Ok(info) => return Some(info),
+            Ok(mut info) => {
+                (info.ancestor_pids, info.ancestor_names) = ancestors(pid).into_iter().unzip();
+                return Some(info);
+            }
             Err(e) => tracing::debug!(
                 "libproc peer-info resolution failed for pid {pid}: {e}; falling back to sysinfo"
             ),
         }
 
         match peer_info_from_sysinfo(pid) {
+            #[cfg(target_os = "linux")]
+            Ok(info) => Some(info.with_proc_details()),
+            #[cfg(not(target_os = "linux"))]
             Ok(info) => Some(info),
             Err(e) => {
                 warn!("failed to resolve peer process for pid {pid}: {e}");
@@ -40,6 +71,148 @@ impl PeerInfo {
     pub(crate) fn process_name(&self) -> &str {
         &self.process_name
     }
+
+    pub(crate) fn exe_path(&self) -> Option<&str> {
+        self.exe_path.as_deref()
+    }
+
+    pub(crate) fn args(&self) -> &[String] {
+        &self.args
+    }
+
+    pub(crate) fn uid(&self) -> Option<u32> {
+        self.uid
+    }
+
+    pub(crate) fn ancestor_pids(&self) -> &[u32] {
+        &self.ancestor_pids
+    }
+
+    pub(crate) fn ancestor_names(&self) -> &[String] {
+        &self.ancestor_names
+    }
+
+    pub(crate) fn sandbox(&self) -> Option<PeerSandbox> {
+        self.sandbox
+    }
+
+    /// Fills in the details only available from `/proc/<pid>`.
+    #[cfg(target_os = "linux")]
+    fn with_proc_details(mut self) -> Self {
+        let proc_dir = std::path::PathBuf::from(format!("/proc/{}", self.pid));
+
+        self.exe_path = std::fs::read_link(proc_dir.join("exe"))
+            .ok()
+            .and_then(|path| path.to_str().map(str::to_string));
+        self.args = std::fs::read(proc_dir.join("cmdline"))
+            .map(|cmdline| parse_cmdline(&cmdline))
+            .unwrap_or_default();
+        self.uid = std::fs::read_to_string(proc_dir.join("status"))
+            .ok()
+            .and_then(|status| parse_status_uid(&status));
+        self.sandbox = detect_sandbox(&proc_dir);
+        self
+    }
+}
+
+/// Walks up the process tree from `pid`, stopping at the session leader (typically the shell of
+/// a terminal), the root, a process that can't be resolved or after [`MAX_ANCESTRY_DEPTH`]
+/// ancestors.
+///
+/// # Returns
+///
+/// The PIDs and names of the ancestors, nearest first.
+fn ancestors(pid: u32) -> Vec<(u32, String)> {
+    let mut system = System::new();
+    let mut ancestors: Vec<(u32, String)> = Vec::new();
+    let mut current = Pid::from_u32(pid);
+    system.refresh_processes(sysinfo::ProcessesToUpdate::Some(&[current]), true);
+
+    while ancestors.len() < MAX_ANCESTRY_DEPTH {
+        let Some(parent) = system.process(current).and_then(sysinfo::Process::parent) else {
+            break;
+        };
+        // Guards against pid reuse producing a cycle.
+        if parent.as_u32() == 0
+            || parent.as_u32() == pid
+            || ancestors
+                .iter()
+                .any(|(ancestor, _)| *ancestor == parent.as_u32())
+        {
+            break;
+        }
+        system.refresh_processes(sysinfo::ProcessesToUpdate::Some(&[parent]), true);
+        let Some(process) = system.process(parent) else {
+            break;
+        };
+        ancestors.push((
+            parent.as_u32(),
+            process.name().to_string_lossy().into_owned(),
+        ));
+        if process.session_id() == Some(parent) {
+            break;
+        }
+        current = parent;
+    }
+
+    ancestors
+}
+
+/// Splits the NUL-separated contents of `/proc/<pid>/cmdline` into arguments.
+#[cfg(target_os = "linux")]
+fn parse_cmdline(cmdline: &[u8]) -> Vec<String> {
+    let cmdline = cmdline.strip_suffix(b"\0").unwrap_or(cmdline);
+    if cmdline.is_empty() {
+        return Vec::new();
+    }
+    cmdline
+        .split(|&b| b == 0)
+        .map(|arg| String::from_utf8_lossy(arg).into_owned())
+        .collect()
+}
+
+/// # Returns
+///
+/// The real user ID from the `Uid:` line of `/proc/<pid>/status`.
+#[cfg(target_os = "linux")]
+fn parse_status_uid(status: &str) -> Option<u32> {
+    status
+        .lines()
+        .find_map(|line| line.strip_prefix("Uid:"))?
+        .split_whitespace()
+        .next()?
+        .parse()
+        .ok()
+}
+
+/// Detects whether the process of `proc_dir` runs in a sandbox. Flatpak apps have a
+/// `.flatpak-info` file at their root, and snaps run in a `snap.*` cgroup. Processes of other
+/// containers are recognized by their PID namespace differing from the agent's.
+#[cfg(target_os = "linux")]
+fn detect_sandbox(proc_dir: &std::path::Path) -> Option<PeerSandbox> {
+    if proc_dir.join("root/.flatpak-info").exists() {
+        return Some(PeerSandbox::Flatpak);
+    }
+    if std::fs::read_to_string(proc_dir.join("cgroup")).is_ok_and(|cgroup| is_snap_cgroup(&cgroup))
+    {
+        return Some(PeerSandbox::Snap);
+    }
+
+    let peer_namespace = std::fs::read_link(proc_dir.join("ns/pid")).ok()?;
+    let own_namespace = std::fs::read_link("/proc/self/ns/pid").ok()?;
+    (peer_namespace != own_namespace).then_some(PeerSandbox::Container)
+}
+
+/// # Returns
+///
+/// `true` if the contents of `/proc/<pid>/cgroup` place the process in a snap's cgroup, e.g.
+/// `0::/user.slice/user-1000.slice/user@1000.service/app.slice/snap.firefox.firefox-1234.scope`.
+#[cfg(target_os = "linux")]
+fn is_snap_cgroup(cgroup: &str) -> bool {
+    cgroup
+        .lines()
+        .filter_map(|line| line.splitn(3, ':').nth(2))
+        .any(|path| path.split('/').any(|segment| segment.starts_with("snap.")))
 }
 
 /// Alternative to the `sysinfo`-based lookup that is permissive within
@@ -52,7 +225,16 @@ fn peer_info_from_libproc(pid: u32) -> Result<PeerInfo, String> {
     if process_name.is_empty() {
         return Err("Process name is empty".to_string());
     }
-    Ok(PeerInfo { pid, process_name })
+    Ok(PeerInfo {
+        pid,
+        process_name,
+        exe_path: Some(path),
+        args: Vec::new(),
+        uid: None,
+        ancestor_pids: Vec::new(),
+        ancestor_names: Vec::new(),
+        sandbox: None,
+    })
 }
 
 /// Safe wrapper over `proc_pidpath(2)`, declared in macOS's
@@ -110,7 +292,17 @@ fn peer_info_from_sysinfo(pid: u32) -> Result<PeerInfo, String> {
         .to_str()
         .ok_or_else(|| format!("sysinfo: process {pid} name is not valid UTF-8"))?
         .to_string();
-    Ok(PeerInfo { pid, process_name })
+    let (ancestor_pids, ancestor_names) = ancestors(pid).into_iter().unzip();
+    Ok(PeerInfo {
+        pid,
+        process_name,
+        exe_path: None,
+        args: Vec::new(),
+        uid: None,
+        ancestor_pids,
+        ancestor_names,
+        sandbox: None,
+    })
 }
 
 /// Extract the basename from a path string, falling back to the original
@@ -140,6 +332,69 @@ mod tests {
         assert!(!peer_info.process_name().is_empty());
     }
 
+    #[cfg(unix)]
+    #[test]
+    fn test_from_pid_current_process_includes_parent() {
+        let peer_info = PeerInfo::from_pid(std::process::id()).unwrap();
+        assert_eq!(
+            peer_info.ancestor_pids().first().copied(),
+            Some(std::os::unix::process::parent_id())
+        );
+    }
+
+    #[cfg(target_os = "linux")]
+    #[test]
+    fn test_from_pid_current_process_includes_proc_details() {
+        let peer_info = PeerInfo::from_pid(std::process::id()).unwrap();
+
+        assert_eq!(
+            peer_info.exe_path().map(std::path::PathBuf::from),
+            std::env::current_exe().ok()
+        );
+        assert_eq!(
+            peer_info.args(),
+            std::env::args().collect::<Vec<_>>().as_slice()
+        );
+        // SAFETY: `getuid` has no preconditions and cannot fail.
+        assert_eq!(peer_info.uid(), Some(unsafe { libc::getuid() }));
+        assert_eq!(
+            peer_info.ancestor_names().len(),
+            peer_info.ancestor_pids().len()
+        );
+        assert_eq!(peer_info.sandbox(), None);
+    }
+
+    #[cfg(target_os = "linux")]
+    #[test]
+    fn test_parse_cmdline() {
+        assert_eq!(
+            parse_cmdline(b"ssh\0-T\0git@github.com\0"),
+            vec!["ssh", "-T", "git@github.com"]
+        );
+        assert_eq!(parse_cmdline(b"ssh\0\0"), vec!["ssh", ""]);
+        assert!(parse_cmdline(b"").is_empty());
+    }
+
+    #[cfg(target_os = "linux")]
+    #[test]
+    fn test_parse_status_uid_returns_real_uid() {
+        let status = "Name:\tssh\nPid:\t42\nUid:\t1000\t1001\t1002\t1003\nGid:\t100\n";
+        assert_eq!(parse_status_uid(status), Some(1000));
+        assert_eq!(parse_status_uid("Name:\tssh\n"), None);
+    }
+
+    #[cfg(target_os = "linux")]
+    #[test]
+    fn test_is_snap_cgroup() {
+        assert!(is_snap_cgroup(
+            "0::/user.slice/user-1000.slice/user@1000.service/app.slice/snap.firefox.firefox-1234.scope\n"
+        ));
+        assert!(!is_snap_cgroup(
+            "0::/user.slice/user-1000.slice/user@1000.service/app.slice/app-ssh.scope\n"
+        ));
+        assert!(!is_snap_cgroup("0::/snapshots.slice\n"));
+    }
+
     #[test]
     fn test_from_pid_nonexistent_returns_none() {
         // u32::MAX = 4294967295 far exceeds the maximum PID on any supported platform
@@ -3,10 +3,16 @@
 //! Adheres to the protocol defined in:
 //! <https://datatracker.ietf.org/doc/draft-ietf-sshm-ssh-agent/>
 
-use ssh_key::Signature;
+use std::time::Duration;
+
+use ssh_encoding::Decode as _;
+use ssh_key::{private::KeypairData, HashAlg, Signature};
 use tracing::warn;
 
-use crate::crypto::PublicKey;
+use crate::{
+    crypto::PublicKey,
+    storage::session_keystore::{DestinationConstraint, KeyConstraints},
+};
 
 /// `SSH_AGENT_FAILURE`
 pub(super) const FAILURE: u8 = 5;
@@ -20,9 +26,33 @@ pub(super) const IDENTITIES_ANSWER: u8 = 12;
 pub(super) const SIGN_REQUEST: u8 = 13;
 /// `SSH2_AGENT_SIGN_RESPONSE`
 pub(super) const SIGN_RESPONSE: u8 = 14;
+/// `SSH2_AGENTC_ADD_IDENTITY`
+pub(super) const ADD_IDENTITY: u8 = 17;
+/// `SSH2_AGENTC_REMOVE_IDENTITY`
+pub(super) const REMOVE_IDENTITY: u8 = 18;
+/// `SSH2_AGENTC_REMOVE_ALL_IDENTITIES`
+pub(super) const REMOVE_ALL_IDENTITIES: u8 = 19;
+/// `SSH_AGENTC_LOCK`
+pub(super) const LOCK: u8 = 22;
+/// `SSH_AGENTC_UNLOCK`
+pub(super) const UNLOCK: u8 = 23;
+/// `SSH2_AGENTC_ADD_ID_CONSTRAINED`
+pub(super) const ADD_ID_CONSTRAINED: u8 = 25;
 /// `SSH2_AGENTC_EXTENSION`
 pub(super) const EXTENSION: u8 = 27;
 
+/// Extension binding a connection to the SSH session it is used in
+pub(super) const SESSION_BIND_EXTENSION: &[u8] = b"session-bind@openssh.com";
+
+/// `SSH_AGENT_CONSTRAIN_LIFETIME`
+const CONSTRAIN_LIFETIME: u8 = 1;
+/// `SSH_AGENT_CONSTRAIN_CONFIRM`
+const CONSTRAIN_CONFIRM: u8 = 2;
+/// `SSH_AGENT_CONSTRAIN_EXTENSION`
+const CONSTRAIN_EXTENSION: u8 = 255;
+/// Key constraint extension restricting the hosts a key may be used for
+const RESTRICT_DESTINATION_EXTENSION: &[u8] = b"restrict-destination-v00@openssh.com";
+
 /// RSA with SHA-256 signing algorithm identifier
 pub(super) const RSA_SHA2_256: &str = "rsa-sha2-256";
 /// RSA with SHA-512 signing algorithm identifier
@@ -32,6 +62,10 @@ pub(super) const RSA_SHA2_512: &str = "rsa-sha2-512";
 pub(super) const SSHSIG_NAMESPACE_GIT: &str = "git";
 /// SSHSIG `file` namespace identifier
 pub(super) const SSHSIG_NAMESPACE_FILE: &str = "file";
+/// Length of the flags byte and counter ending security key signatures
+const SK_SIGNATURE_TRAILER_LEN: usize = 5;
+/// Magic preamble of the SSHSIG data to sign
+const SSHSIG_MAGIC: &[u8] = b"SSHSIG";
 
 /// Returns an SSH `SSH_AGENT_FAILURE` response message.
 pub(super) fn failure() -> Vec<u8> {
@@ -45,13 +79,47 @@ pub(super) fn success() -> Vec<u8> {
 
 /// Represents the parsed SSHSIG namespace.
 // <https://github.com/openssh/openssh-portable/blob/master/PROTOCOL.sshsig>
-#[derive(Debug, Clone, PartialEq)]
+#[derive(Debug, Clone, PartialEq, Eq, PartialOrd, Ord)]
 pub enum SIGNamespace {
     Git,
     File,
+    /// Any other namespace, e.g. `email@example.com` for `ssh-keygen -Y sign -n email@example.com`.
+    Other(String),
+    /// The data to sign starts with the SSHSIG preamble, but is not a valid SSHSIG blob.
     Unsupported,
 }
 
+impl SIGNamespace {
+    /// # Returns
+    ///
+    /// The namespace as passed to `ssh-keygen -Y sign -n`, or `None` for
+    /// [`SIGNamespace::Unsupported`].
+    #[must_use]
+    pub fn as_str(&self) -> Option<&str> {
+        match self {
+            Self::Git => Some(SSHSIG_NAMESPACE_GIT),
+            Self::File => Some(SSHSIG_NAMESPACE_FILE),
+            Self::Other(namespace) => Some(namespace),
+            Self::Unsupported => None,
+        }
+    }
+}
+
+/// The parsed and validated data to sign of an SSHSIG request, as produced by
+/// `ssh-keygen -Y sign`.
+// <https://github.com/openssh/openssh-portable/blob/master/PROTOCOL.sshsig>
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct SSHSigData {
+    /// The namespace the signature is bound to.
+    pub namespace: SIGNamespace,
+    /// The reserved field. Empty for all current clients.
+    pub reserved: Vec<u8>,
+    /// The algorithm the signed message was hashed with.
+    pub hash_algorithm: ssh_key::HashAlg,
+    /// The hash of the signed message. Its length matches [`Self::hash_algorithm`].
+    pub message_hash: Vec<u8>,
+}
+
 /// SSH agent signing flags from the `SIGN_REQUEST` `flags` field.
 ///
 /// Only the two RSA hash-selection flags are represented; all other bits are
@@ -94,6 +162,21 @@ pub(super) enum AgentMessage {
         data: Vec<u8>,
         flags: Option<SignFlags>,
     },
+    AddIdentity {
+        /// The private key, with the client-provided comment.
+        private_key: Box<ssh_key::PrivateKey>,
+        constraints: KeyConstraints,
+    },
+    RemoveIdentity {
+        public_key: PublicKey,
+    },
+    RemoveAllIdentities,
+    Lock {
+        passphrase: Vec<u8>,
+    },
+    Unlock {
+        passphrase: Vec<u8>,
+    },
     Unknown(u8),
 }
 
@@ -111,6 +194,39 @@ pub(super) fn parse_message(msg: &[u8]) -> Option<AgentMessage> {
                 flags,
             })
         }
+        ADD_IDENTITY => {
+            let (private_key, _) = parse_add_identity_payload(&msg[1..])?;
+            Some(AgentMessage::AddIdentity {
+                private_key: Box::new(private_key),
+                constraints: KeyConstraints::default(),
+            })
+        }
+        ADD_ID_CONSTRAINED => {
+            let (private_key, rest) = parse_add_identity_payload(&msg[1..])?;
+            let constraints = parse_key_constraints(rest)?;
+            Some(AgentMessage::AddIdentity {
+                private_key: Box::new(private_key),
+                constraints,
+            })
+        }
+        REMOVE_IDENTITY => {
+            let (blob, _) = read_ssh_string(&msg[1..])?;
+            let public_key = parse_public_key_blob(blob)?;
+            Some(AgentMessage::RemoveIdentity { public_key })
+        }
+        REMOVE_ALL_IDENTITIES => Some(AgentMessage::RemoveAllIdentities),
+        LOCK => {
+            let (passphrase, _) = read_ssh_string(&msg[1..])?;
+            Some(AgentMessage::Lock {
+                passphrase: passphrase.to_vec(),
+            })
+        }
+        UNLOCK => {
+            let (passphrase, _) = read_ssh_string(&msg[1..])?;
+            Some(AgentMessage::Unlock {
+                passphrase: passphrase.to_vec(),
+            })
+        }
         unknown => Some(AgentMessage::Unknown(unknown)),
     }
 }
@@ -166,22 +282,10 @@ pub(super) fn build_identities_answer(keys: Vec<(PublicKey, String)>) -> Vec<u8>
     msg
 }
 
-/// Parses the payload of a `SIGN_REQUEST` message (the bytes after the message type byte).
+/// Parses an SSH wire-format public key blob into a [`PublicKey`].
 ///
-/// Returns `(public_key, data_to_sign, flags)` or `None` if the payload is malformed.
-fn parse_sign_request_payload(payload: &[u8]) -> Option<(PublicKey, Vec<u8>, Option<SignFlags>)> {
-    // Parse key blob: [u32 len][blob bytes]
-    if payload.len() < 4 {
-        warn!("Sign request payload too short to read key blob length");
-        return None;
-    }
-    let blob_len = u32::from_be_bytes(payload[0..4].try_into().ok()?) as usize;
-    if payload.len() < 4 + blob_len {
-        warn!("Sign request payload truncated: key blob extends past end");
-        return None;
-    }
-    let blob = &payload[4..4 + blob_len];
-
+/// Returns `None` if the algorithm name at the start of the blob is truncated or not UTF-8.
+fn parse_public_key_blob(blob: &[u8]) -> Option<PublicKey> {
     // Algorithm name is the first SSH string inside the blob
     if blob.len() < 4 {
         warn!("Key blob too short to read algorithm name length");
@@ -199,10 +303,143 @@ fn parse_sign_request_payload(payload: &[u8]) -> Option<(PublicKey, Vec<u8>, Opt
         return None;
     };
 
-    let public_key = PublicKey {
+    Some(PublicKey {
         alg,
         blob: blob.to_vec(),
-    };
+    })
+}
+
+/// Parses the payload of an `ADD_IDENTITY` message (the bytes after the message type byte).
+///
+/// The payload is the key type, the key-type specific private key fields, and a comment.
+///
+/// Returns the private key, with the comment attached, and the remaining bytes holding any
+/// constraints, or `None` if the payload is malformed.
+fn parse_add_identity_payload(mut payload: &[u8]) -> Option<(ssh_key::PrivateKey, &[u8])> {
+    let keypair_data = KeypairData::decode(&mut payload)
+        .inspect_err(|error| warn!(%error, "Unable to decode add identity private key"))
+        .ok()?;
+    let comment = String::decode(&mut payload)
+        .inspect_err(|error| warn!(%error, "Unable to decode add identity comment"))
+        .ok()?;
+
+    let private_key = ssh_key::PrivateKey::new(keypair_data, comment)
+        .inspect_err(|error| warn!(%error, "Invalid add identity private key"))
+        .ok()?;
+    Some((private_key, payload))
+}
+
+/// Parses the constraints trailing an `ADD_ID_CONSTRAINED` message.
+///
+/// Returns `None` if the constraints are malformed or contain a constraint we don't support. Keys
+/// must never be added without a constraint the client asked for.
+fn parse_key_constraints(mut data: &[u8]) -> Option<KeyConstraints> {
+    let mut constraints = KeyConstraints::default();
+
+    while let Some((&constraint, rest)) = data.split_first() {
+        data = rest;
+        match constraint {
+            CONSTRAIN_LIFETIME => {
+                let seconds = u32::from_be_bytes(data.get(..4)?.try_into().ok()?);
+                data = &data[4..];
+                constraints.lifetime = Some(Duration::from_secs(u64::from(seconds)));
+            }
+            CONSTRAIN_CONFIRM => constraints.confirm = true,
+            CONSTRAIN_EXTENSION => {
+                let (name, rest) = read_ssh_string(data)?;
+                if name != RESTRICT_DESTINATION_EXTENSION {
+                    warn!(
+                        name = std::str::from_utf8(name).unwrap_or("<non-utf8>"),
+                        "Received unsupported key constraint extension"
+                    );
+                    return None;
+                }
+                let (mut destinations, rest) = read_ssh_string(rest)?;
+                data = rest;
+                while !destinations.is_empty() {
+                    let (destination, rest) = read_ssh_string(destinations)?;
+                    destinations = rest;
+                    constraints
+                        .destinations
+                        .push(parse_destination_constraint(destination)?);
+                }
+            }
+            unknown => {
+                warn!(constraint = unknown, "Received unsupported key constraint");
+                return None;
+            }
+        }
+    }
+
+    Some(constraints)
+}
+
+/// Parses a single `restrict-destination-v00@openssh.com` destination constraint:
+/// `[string from_hop][string to_hop][string reserved]`.
+fn parse_destination_constraint(data: &[u8]) -> Option<DestinationConstraint> {
+    let (from_hop, rest) = read_ssh_string(data)?;
+    let (to_hop, rest) = read_ssh_string(rest)?;
+    let _ = read_ssh_string(rest)?;
+
+    let (from_hostname, from_host_key_fingerprints) = parse_destination_hop(from_hop)?;
+    let (to_hostname, to_host_key_fingerprints) = parse_destination_hop(to_hop)?;
+
+    Some(DestinationConstraint {
+        from_hostname,
+        from_host_key_fingerprints,
+        to_hostname,
+        to_host_key_fingerprints,
+    })
+}
+
+/// Parses a destination constraint hop:
+/// `[string username][string hostname][string reserved]` followed by
+/// `[string host_key][bool is_ca]` key specs.
+///
+/// Returns the hostname and the SHA-256 fingerprints of its host keys.
+fn parse_destination_hop(data: &[u8]) -> Option<(String, Vec<String>)> {
+    let (_username, rest) = read_ssh_string(data)?;
+    let (hostname, rest) = read_ssh_string(rest)?;
+    let (_reserved, mut rest) = read_ssh_string(rest)?;
+    let hostname = std::str::from_utf8(hostname).ok()?.to_string();
+
+    let mut fingerprints = Vec::new();
+    while !rest.is_empty() {
+        let (host_key, after_key) = read_ssh_string(rest)?;
+        let (&is_ca, after_is_ca) = after_key.split_first()?;
+        rest = after_is_ca;
+
+        // Host certificates aren't verified during session-bind, so a CA key can never match.
+        if is_ca != 0 {
+            warn!(%hostname, "Ignoring host CA key in destination constraint");
+            continue;
+        }
+
+        let host_key = ssh_key::PublicKey::from_bytes(host_key)
+            .inspect_err(|error| warn!(%error, "Invalid host key in destination constraint"))
+            .ok()?;
+        fingerprints.push(host_key.fingerprint(HashAlg::Sha256).to_string());
+    }
+
+    Some((hostname, fingerprints))
+}
+
+/// Parses the payload of a `SIGN_REQUEST` message (the bytes after the message type byte).
+///
+/// Returns `(public_key, data_to_sign, flags)` or `None` if the payload is malformed.
+fn parse_sign_request_payload(payload: &[u8]) -> Option<(PublicKey, Vec<u8>, Option<SignFlags>)> {
+    // Parse key blob: [u32 len][blob bytes]
+    if payload.len() < 4 {
+        warn!("Sign request payload too short to read key blob length");
+        return None;
+    }
+    let blob_len = u32::from_be_bytes(payload[0..4].try_into().ok()?) as usize;
+    if payload.len() < 4 + blob_len {
+        warn!("Sign request payload truncated: key blob extends past end");
+        return None;
+    }
+    let blob = &payload[4..4 + blob_len];
+    let public_key = parse_public_key_blob(blob)?;
 
     // Parse data to sign: [u32 len][data bytes]
     let rest = &payload[4 + blob_len..];
@@ -228,11 +465,23 @@ fn parse_sign_request_payload(payload: &[u8]) -> Option<(PublicKey, Vec<u8>, Opt
 }
 
 /// Builds an SSH `AGENT_SIGN_RESPONSE` message from a [`Signature`].
+///
+/// Security key signatures end with the flags byte and counter reported by the device, which
+/// follow the signature string rather than being part of it.
 pub(super) fn build_sign_response(sig: &Signature) -> Vec<u8> {
     let alg = sig.algorithm().to_string();
-    let sig_bytes = sig.as_bytes();
+    let (sig_bytes, sk_trailer) = match sig.algorithm() {
+        ssh_key::Algorithm::SkEd25519 | ssh_key::Algorithm::SkEcdsaSha2NistP256 => {
+            sig.as_bytes().split_at(
+                sig.as_bytes()
+                    .len()
+                    .saturating_sub(SK_SIGNATURE_TRAILER_LEN),
+            )
+        }
+        _ => (sig.as_bytes(), &[][..]),
+    };
 
-    let blob_len = 4 + alg.len() + 4 + sig_bytes.len();
+    let blob_len = 4 + alg.len() + 4 + sig_bytes.len() + sk_trailer.len();
     let blob_len_bytes = u32::try_from(blob_len)
         .expect("signature blob length to fit in u32::MAX")
         .to_be_bytes();
@@ -251,40 +500,157 @@ pub(super) fn build_sign_response(sig: &Signature) -> Vec<u8> {
     msg.extend_from_slice(alg.as_bytes());
     msg.extend_from_slice(&sig_len_bytes);
     msg.extend_from_slice(sig_bytes);
+    msg.extend_from_slice(sk_trailer);
+
+    msg
+}
+
+/// Builds an SSH `AGENT_SIGN_RESPONSE` message from a signature blob as returned by another agent.
+pub(super) fn build_raw_sign_response(sig_blob: &[u8]) -> Vec<u8> {
+    let blob_len_bytes = u32::try_from(sig_blob.len())
+        .expect("signature blob length to fit in u32::MAX")
+        .to_be_bytes();
+
+    let mut msg = Vec::with_capacity(1 + 4 + sig_blob.len());
+    msg.push(SIGN_RESPONSE);
+    msg.extend_from_slice(&blob_len_bytes);
+    msg.extend_from_slice(sig_blob);
 
     msg
 }
 
+/// Builds an SSH `AGENT_SIGN_REQUEST` message, for forwarding a request to another agent.
+pub(super) fn build_sign_request(
+    public_key: &PublicKey,
+    data: &[u8],
+    flags: Option<SignFlags>,
+) -> Vec<u8> {
+    let blob = public_key.blob();
+    let blob_len_bytes = u32::try_from(blob.len())
+        .expect("key blob length to fit in u32::MAX")
+        .to_be_bytes();
+    let data_len_bytes = u32::try_from(data.len())
+        .expect("data length to fit in u32::MAX")
+        .to_be_bytes();
+    let raw_flags = flags.map_or(0, |flags| flags as u32);
+
+    let mut msg = Vec::with_capacity(1 + 4 + blob.len() + 4 + data.len() + 4);
+    msg.push(SIGN_REQUEST);
+    msg.extend_from_slice(&blob_len_bytes);
+    msg.extend_from_slice(blob);
+    msg.extend_from_slice(&data_len_bytes);
+    msg.extend_from_slice(data);
+    msg.extend_from_slice(&raw_flags.to_be_bytes());
+
+    msg
+}
+
+/// Builds an SSH `session-bind@openssh.com` extension message with the payload received from a
+/// client, for replaying the binding to another agent.
+pub(super) fn build_session_bind(payload: &[u8]) -> Vec<u8> {
+    let name_len_bytes = u32::try_from(SESSION_BIND_EXTENSION.len())
+        .expect("extension name length to fit in u32::MAX")
+        .to_be_bytes();
+
+    let mut msg = Vec::with_capacity(1 + 4 + SESSION_BIND_EXTENSION.len() + payload.len());
+    msg.push(EXTENSION);
+    msg.extend_from_slice(&name_len_bytes);
+    msg.extend_from_slice(SESSION_BIND_EXTENSION);
+    msg.extend_from_slice(payload);
+
+    msg
+}
+
+/// Parses an SSH `AGENT_IDENTITIES_ANSWER` message received from another agent.
+///
+/// Returns the public keys and comments, or `None` if the message is not a well-formed identities
+/// answer.
+pub(super) fn parse_identities_answer(msg: &[u8]) -> Option<Vec<(PublicKey, String)>> {
+    let (&msg_type, payload) = msg.split_first()?;
+    if msg_type != IDENTITIES_ANSWER || payload.len() < 4 {
+        return None;
+    }
+    let count = u32::from_be_bytes(payload[0..4].try_into().ok()?);
+
+    let mut keys = Vec::new();
+    let mut rest = &payload[4..];
+    for _ in 0..count {
+        let (blob, after_blob) = read_ssh_string(rest)?;
+        let (comment, after_comment) = read_ssh_string(after_blob)?;
+        rest = after_comment;
+
+        keys.push((
+            parse_public_key_blob(blob)?,
+            String::from_utf8_lossy(comment).into_owned(),
+        ));
+    }
+
+    Some(keys)
+}
+
+/// Parses an SSH `AGENT_SIGN_RESPONSE` message received from another agent.
+///
+/// Returns the signature blob, or `None` if the agent refused to sign or the message is malformed.
+pub(super) fn parse_sign_response(msg: &[u8]) -> Option<&[u8]> {
+    let (&msg_type, payload) = msg.split_first()?;
+    if msg_type != SIGN_RESPONSE {
+        return None;
+    }
+    read_ssh_string(payload).map(|(sig_blob, _)| sig_blob)
+}
+
 /// Detects the SIG namespace from the data being signed.
 ///
 /// Returns `Some(namespace)` if the data is an SSHSIG blob, or `None` for
-/// regular SSH authentication data.
+/// regular SSH authentication data. A malformed SSHSIG blob is
+/// [`SIGNamespace::Unsupported`].
 pub(super) fn detect_namespace(data: &[u8]) -> Option<SIGNamespace> {
-    // SSHSIG format: [6-byte magic "SSHSIG"][u32 version][string namespace]...
-    // https://github.com/openssh/openssh-portable/blob/master/PROTOCOL.sshsig
-    const SSHSIG_MAGIC: &[u8] = b"SSHSIG";
-
     if !data.starts_with(SSHSIG_MAGIC) {
         return None;
     }
 
-    // Skip magic (6 bytes) + version uint32 (4 bytes) = 10 bytes
-    let offset = 10;
-    if data.len() < offset + 4 {
-        return Some(SIGNamespace::Unsupported);
-    }
+    Some(parse_sshsig(data).map_or(SIGNamespace::Unsupported, |sshsig| sshsig.namespace))
+}
 
-    let ns_len = u32::from_be_bytes(data[offset..offset + 4].try_into().ok()?) as usize;
-    if data.len() < offset + 4 + ns_len {
-        return Some(SIGNamespace::Unsupported);
+/// Parses and validates an SSHSIG blob from the data being signed.
+///
+/// Returns `None` for regular SSH authentication data, or if the blob is malformed: an empty or
+/// non-UTF-8 namespace, a hash algorithm other than `sha256` or `sha512`, a message hash of the
+/// wrong length, or trailing data.
+pub(super) fn parse_sshsig(data: &[u8]) -> Option<SSHSigData> {
+    // SSHSIG format: [6-byte magic "SSHSIG"][string namespace][string reserved]
+    // [string hash_algorithm][string H(message)]
+    // https://github.com/openssh/openssh-portable/blob/master/PROTOCOL.sshsig
+    let data = data.strip_prefix(SSHSIG_MAGIC)?;
+    let (namespace, data) = read_ssh_string(data)?;
+    let (reserved, data) = read_ssh_string(data)?;
+    let (hash_algorithm, data) = read_ssh_string(data)?;
+    let (message_hash, data) = read_ssh_string(data)?;
+    if !data.is_empty() {
+        return None;
     }
 
-    let ns_str = std::str::from_utf8(&data[offset + 4..offset + 4 + ns_len]).ok()?;
-    match ns_str {
-        SSHSIG_NAMESPACE_GIT => Some(SIGNamespace::Git),
-        SSHSIG_NAMESPACE_FILE => Some(SIGNamespace::File),
-        _ => Some(SIGNamespace::Unsupported),
+    let namespace = match std::str::from_utf8(namespace).ok()? {
+        "" => return None,
+        SSHSIG_NAMESPACE_GIT => SIGNamespace::Git,
+        SSHSIG_NAMESPACE_FILE => SIGNamespace::File,
+        other => SIGNamespace::Other(other.to_string()),
+    };
+    let hash_algorithm = match hash_algorithm {
+        b"sha256" => ssh_key::HashAlg::Sha256,
+        b"sha512" => ssh_key::HashAlg::Sha512,
+        _ => return None,
+    };
+    if message_hash.len() != hash_algorithm.digest_size() {
+        return None;
     }
+
+    Some(SSHSigData {
+        namespace,
+        reserved: reserved.to_vec(),
+        hash_algorithm,
+        message_hash: message_hash.to_vec(),
+    })
 }
 
 #[cfg(test)]
@@ -292,20 +658,33 @@ mod tests {
     use super::*;
     use crate::{
         crypto::PublicKey,
-        server::test_common::{make_minimal_ed25519_blob, make_sign_request_msg},
+        server::test_common::{
+            make_add_id_constrained_msg, make_add_identity_msg, make_minimal_ed25519_blob,
+            make_remove_identity_msg, make_restrict_destination_constraint, make_sign_request_msg,
+            write_ssh_string,
+        },
     };
 
     const TEST_DATA: &[u8] = b"test data";
 
-    fn make_sshsig_blob(namespace: &str) -> Vec<u8> {
+    fn make_sshsig_blob_with(
+        namespace: &str,
+        hash_algorithm: &str,
+        message_hash: &[u8],
+    ) -> Vec<u8> {
         let mut v = Vec::new();
         v.extend_from_slice(b"SSHSIG");
-        v.extend_from_slice(&1u32.to_be_bytes()); // version
-        v.extend_from_slice(&(namespace.len() as u32).to_be_bytes());
-        v.extend_from_slice(namespace.as_bytes());
+        write_ssh_string(&mut v, namespace.as_bytes());
+        write_ssh_string(&mut v, b""); // reserved
+        write_ssh_string(&mut v, hash_algorithm.as_bytes());
+        write_ssh_string(&mut v, message_hash);
         v
     }
 
+    fn make_sshsig_blob(namespace: &str) -> Vec<u8> {
+        make_sshsig_blob_with(namespace, "sha512", &[7u8; 64])
+    }
+
     #[test]
     fn frame_prepends_four_byte_be_length() {
         let msg = vec![1u8, 2, 3];
@@ -400,6 +779,156 @@ mod tests {
         ));
     }
 
+    #[test]
+    fn parse_message_add_identity_returns_private_key_with_comment() {
+        use ssh_key::{private::Ed25519Keypair, rand_core::OsRng};
+
+        let key = ssh_key::PrivateKey::new(
+            KeypairData::Ed25519(Ed25519Keypair::random(&mut OsRng)),
+            "user@host",
+        )
+        .unwrap();
+        let msg = make_add_identity_msg(&key);
+
+        let Some(AgentMessage::AddIdentity {
+            private_key,
+            constraints,
+        }) = parse_message(&msg)
+        else {
+            panic!("expected AddIdentity");
+        };
+        assert_eq!(private_key.comment(), "user@host");
+        assert_eq!(private_key.key_data(), key.key_data());
+        assert_eq!(constraints, KeyConstraints::default());
+    }
+
+    fn make_test_private_key() -> ssh_key::PrivateKey {
+        use ssh_key::{private::Ed25519Keypair, rand_core::OsRng};
+
+        ssh_key::PrivateKey::new(
+            KeypairData::Ed25519(Ed25519Keypair::random(&mut OsRng)),
+            "user@host",
+        )
+        .unwrap()
+    }
+
+    fn parse_constraints(constraints: &[u8]) -> Option<KeyConstraints> {
+        let msg = make_add_id_constrained_msg(&make_test_private_key(), constraints);
+        match parse_message(&msg)? {
+            AgentMessage::AddIdentity { constraints, .. } => Some(constraints),
+            _ => panic!("expected AddIdentity"),
+        }
+    }
+
+    #[test]
+    fn parse_message_add_id_constrained_lifetime_and_confirm() {
+        let mut constraints = vec![CONSTRAIN_LIFETIME];
+        constraints.extend_from_slice(&300u32.to_be_bytes());
+        constraints.push(CONSTRAIN_CONFIRM);
+
+        let constraints = parse_constraints(&constraints).unwrap();
+
+        assert_eq!(constraints.lifetime, Some(Duration::from_secs(300)));
+        assert!(constraints.confirm);
+        assert!(constraints.destinations.is_empty());
+    }
+
+    #[test]
+    fn parse_message_add_id_constrained_truncated_lifetime_returns_none() {
+        assert!(parse_constraints(&[CONSTRAIN_LIFETIME, 0, 1]).is_none());
+    }
+
+    #[test]
+    fn parse_message_add_id_constrained_unknown_constraint_returns_none() {
+        assert!(parse_constraints(&[42]).is_none());
+    }
+
+    #[test]
+    fn parse_message_add_id_constrained_unknown_extension_returns_none() {
+        let mut constraints = vec![CONSTRAIN_EXTENSION];
+        write_ssh_string(&mut constraints, b"unknown@example.com");
+
+        assert!(parse_constraints(&constraints).is_none());
+    }
+
+    #[test]
+    fn parse_message_add_id_constrained_restrict_destination() {
+        let host_key = make_test_private_key().public_key().clone();
+        let host_key_blob = host_key.to_bytes().unwrap();
+        let ca_key_blob = make_test_private_key().public_key().to_bytes().unwrap();
+        let constraint = make_restrict_destination_constraint(
+            "example.com",
+            &[(&host_key_blob, false), (&ca_key_blob, true)],
+        );
+
+        let constraints = parse_constraints(&constraint).unwrap();
+
+        assert_eq!(
+            constraints.destinations,
+            vec![DestinationConstraint {
+                from_hostname: String::new(),
+                from_host_key_fingerprints: Vec::new(),
+                to_hostname: "example.com".to_string(),
+                to_host_key_fingerprints: vec![host_key.fingerprint(HashAlg::Sha256).to_string()],
+            }]
+        );
+    }
+
+    #[test]
+    fn parse_message_add_identity_truncated_returns_none() {
+        let mut msg = vec![ADD_IDENTITY];
+        write_ssh_string(&mut msg, b"ssh-ed25519");
+
+        assert!(parse_message(&msg).is_none());
+    }
+
+    #[test]
+    fn parse_message_remove_identity_returns_public_key() {
+        let blob = make_minimal_ed25519_blob();
+        let msg = make_remove_identity_msg(&blob);
+
+        let Some(AgentMessage::RemoveIdentity { public_key }) = parse_message(&msg) else {
+            panic!("expected RemoveIdentity");
+        };
+        assert_eq!(public_key.alg(), "ssh-ed25519");
+        assert_eq!(public_key.blob(), blob.as_slice());
+    }
+
+    #[test]
+    fn parse_message_remove_all_identities_returns_variant() {
+        assert!(matches!(
+            parse_message(&[REMOVE_ALL_IDENTITIES]),
+            Some(AgentMessage::RemoveAllIdentities)
+        ));
+    }
+
+    #[test]
+    fn parse_message_lock_returns_passphrase() {
+        let mut msg = vec![LOCK];
+        write_ssh_string(&mut msg, b"secret");
+
+        let Some(AgentMessage::Lock { passphrase }) = parse_message(&msg) else {
+            panic!("expected Lock");
+        };
+        assert_eq!(passphrase, b"secret");
+    }
+
+    #[test]
+    fn parse_message_unlock_returns_passphrase() {
+        let mut msg = vec![UNLOCK];
+        write_ssh_string(&mut msg, b"secret");
+
+        let Some(AgentMessage::Unlock { passphrase }) = parse_message(&msg) else {
+            panic!("expected Unlock");
+        };
+        assert_eq!(passphrase, b"secret");
+    }
+
+    #[test]
+    fn parse_message_lock_without_passphrase_returns_none() {
+        assert!(parse_message(&[LOCK]).is_none());
+    }
+
     #[test]
     fn parse_message_sign_request_valid_payload_returns_parsed_fields() {
         let blob = make_minimal_ed25519_blob();
@@ -553,19 +1082,70 @@ mod tests {
     }
 
     #[test]
-    fn detect_namespace_sshsig_unknown_namespace_returns_unsupported() {
+    fn detect_namespace_sshsig_other_namespace_returns_other() {
         assert_eq!(
-            detect_namespace(&make_sshsig_blob("email")),
-            Some(SIGNamespace::Unsupported)
+            detect_namespace(&make_sshsig_blob("email@example.com")),
+            Some(SIGNamespace::Other("email@example.com".to_string()))
         );
     }
 
     #[test]
-    fn detect_namespace_truncated_after_version_returns_unsupported() {
+    fn detect_namespace_truncated_after_magic_returns_unsupported() {
+        assert_eq!(detect_namespace(b"SSHSIG"), Some(SIGNamespace::Unsupported));
+    }
+
+    #[test]
+    fn detect_namespace_malformed_sshsig_returns_unsupported() {
+        let mut data = make_sshsig_blob("git");
+        data.push(0); // trailing byte
+        assert_eq!(detect_namespace(&data), Some(SIGNamespace::Unsupported));
+    }
+
+    #[test]
+    fn parse_sshsig_returns_all_fields() {
+        let sshsig = parse_sshsig(&make_sshsig_blob_with("file", "sha256", &[9u8; 32])).unwrap();
+
+        assert_eq!(sshsig.namespace, SIGNamespace::File);
+        assert!(sshsig.reserved.is_empty());
+        assert_eq!(sshsig.hash_algorithm, ssh_key::HashAlg::Sha256);
+        assert_eq!(sshsig.message_hash, vec![9u8; 32]);
+    }
+
+    #[test]
+    fn parse_sshsig_non_sshsig_returns_none() {
+        assert!(parse_sshsig(b"hello world").is_none());
+    }
+
+    #[test]
+    fn parse_sshsig_empty_namespace_returns_none() {
+        assert!(parse_sshsig(&make_sshsig_blob("")).is_none());
+    }
+
+    #[test]
+    fn parse_sshsig_non_utf8_namespace_returns_none() {
         let mut data = Vec::new();
         data.extend_from_slice(b"SSHSIG");
-        data.extend_from_slice(&1u32.to_be_bytes()); // version only, no namespace length
-        assert_eq!(detect_namespace(&data), Some(SIGNamespace::Unsupported));
+        write_ssh_string(&mut data, &[0xff, 0xfe]);
+        write_ssh_string(&mut data, b"");
+        write_ssh_string(&mut data, b"sha512");
+        write_ssh_string(&mut data, &[7u8; 64]);
+        assert!(parse_sshsig(&data).is_none());
+    }
+
+    #[test]
+    fn parse_sshsig_unknown_hash_algorithm_returns_none() {
+        assert!(parse_sshsig(&make_sshsig_blob_with("git", "sha1", &[7u8; 20])).is_none());
+    }
+
+    #[test]
+    fn parse_sshsig_hash_length_mismatch_returns_none() {
+        assert!(parse_sshsig(&make_sshsig_blob_with("git", "sha512", &[7u8; 32])).is_none());
+    }
+
+    #[test]
+    fn parse_sshsig_truncated_returns_none() {
+        let data = make_sshsig_blob("git");
+        assert!(parse_sshsig(&data[..data.len() - 1]).is_none());
     }
 
     #[test]
@@ -615,6 +1195,109 @@ mod tests {
         assert_eq!(sig_bytes, sig.as_bytes());
     }
 
+    #[test]
+    fn build_sign_response_places_security_key_flags_and_counter_after_signature() {
+        use ssh_key::{private::Ed25519Keypair, rand_core::OsRng};
+
+        use crate::crypto::test_common::{make_sk_ed25519_key, sign_with_sk_ed25519};
+
+        let keypair = Ed25519Keypair::random(&mut OsRng);
+        let sk_key = make_sk_ed25519_key(&keypair);
+        let sig = sign_with_sk_ed25519(&keypair, &sk_key, TEST_DATA);
+
+        let msg = build_sign_response(&sig);
+
+        let blob_len = u32::from_be_bytes(msg[1..5].try_into().unwrap()) as usize;
+        assert_eq!(blob_len, msg.len() - 5);
+        let blob = &msg[5..];
+        assert_eq!(ssh_key::Signature::try_from(blob).unwrap(), sig);
+    }
+
+    #[test]
+    fn build_raw_sign_response_wraps_signature_blob() {
+        let msg = build_raw_sign_response(&[1, 2, 3]);
+
+        assert_eq!(msg, vec![SIGN_RESPONSE, 0, 0, 0, 3, 1, 2, 3]);
+        assert_eq!(parse_sign_response(&msg), Some(&[1u8, 2, 3][..]));
+    }
+
+    #[test]
+    fn build_sign_request_round_trips_through_parse_message() {
+        let public_key = PublicKey {
+            alg: "ssh-ed25519".to_string(),
+            blob: make_minimal_ed25519_blob(),
+        };
+
+        let msg = build_sign_request(&public_key, TEST_DATA, Some(SignFlags::RsaSha512));
+
+        assert_eq!(
+            msg,
+            make_sign_request_msg(&public_key.blob, TEST_DATA, 4),
+            "encoded like a client's request"
+        );
+        let Some(AgentMessage::SignRequest {
+            public_key: parsed_key,
+            data,
+            flags,
+        }) = parse_message(&msg)
+        else {
+            panic!("expected SignRequest variant");
+        };
+        assert_eq!(parsed_key, public_key);
+        assert_eq!(data, TEST_DATA);
+        assert_eq!(flags, Some(SignFlags::RsaSha512));
+    }
+
+    #[test]
+    fn parse_identities_answer_round_trips_build_identities_answer() {
+        let keys = vec![
+            (
+                PublicKey {
+                    alg: "ssh-ed25519".to_string(),
+                    blob: make_minimal_ed25519_blob(),
+                },
+                "Key One".to_string(),
+            ),
+            (
+                PublicKey {
+                    alg: "ssh-ed25519".to_string(),
+                    blob: make_minimal_ed25519_blob(),
+                },
+                String::new(),
+            ),
+        ];
+
+        let msg = build_identities_answer(keys.clone());
+
+        assert_eq!(parse_identities_answer(&msg), Some(keys));
+        assert_eq!(
+            parse_identities_answer(&build_identities_answer(Vec::new())),
+            Some(Vec::new())
+        );
+    }
+
+    #[test]
+    fn parse_identities_answer_malformed_returns_none() {
+        let mut truncated = build_identities_answer(vec![(
+            PublicKey {
+                alg: "ssh-ed25519".to_string(),
+                blob: make_minimal_ed25519_blob(),
+            },
+            "Key".to_string(),
+        )]);
+        truncated.pop();
+
+        assert!(parse_identities_answer(&[]).is_none());
+        assert!(parse_identities_answer(&failure()).is_none());
+        assert!(parse_identities_answer(&truncated).is_none());
+    }
+
+    #[test]
+    fn parse_sign_response_failure_returns_none() {
+        assert!(parse_sign_response(&failure()).is_none());
+        assert!(parse_sign_response(&[SIGN_RESPONSE, 0, 0, 0, 3, 1]).is_none());
+    }
+
     #[test]
     fn read_ssh_string_inputs_shorter_than_four_bytes_return_none() {
         for len in 0..4 {
@@ -0,0 +1,346 @@
+//! Limits on the requests and connections of clients, protecting the user from being flooded with
+//! approval prompts by a misbehaving client.
+
+use std::{
+    collections::{HashMap, VecDeque},
+    sync::{
+        atomic::{AtomicU64, AtomicUsize, Ordering},
+        Arc, Mutex,
+    },
+    time::{Duration, Instant},
+};
+
+use tracing::warn;
+
+use super::peer_info::PeerInfo;
+
+/// Limits on the requests and connections of clients.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct RateLimits {
+    /// Maximum number of sign requests asking for approval a peer may make within `window`.
+    /// Requests decided by a policy rule or a remembered approval aren't counted.
+    pub max_sign_requests: usize,
+    /// The sliding window over which sign requests are counted.
+    pub window: Duration,
+    /// Maximum number of sign requests awaiting authorization at once, across all peers.
+    pub max_pending_approvals: usize,
+    /// Maximum number of connections open at once, across all peers.
+    pub max_connections: usize,
+}
+
+impl Default for RateLimits {
+    fn default() -> Self {
+        Self {
+            max_sign_requests: 60,
+            window: Duration::from_secs(10),
+            max_pending_approvals: 16,
+            max_connections: 256,
+        }
+    }
+}
+
+/// Counters of the requests and connections refused by a [`RateLimiter`], and of those currently
+/// in progress.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub struct RateLimitStats {
+    /// Sign requests refused because their peer exceeded its rate limit.
+    pub rate_limited_sign_requests: u64,
+    /// Sign requests refused because too many were awaiting authorization.
+    pub rejected_approvals: u64,
+    /// Connections closed on accept because too many were open.
+    pub rejected_connections: u64,
+    /// Sign requests currently awaiting authorization.
+    pub pending_approvals: usize,
+    /// Connections currently open.
+    pub open_connections: usize,
+}
+
+/// Identifies a peer for rate limiting. The PID is not part of it, since scripts typically start
+/// a new `ssh` process for every request.
+#[derive(Debug, Clone, PartialEq, Eq, Hash)]
+struct PeerKey {
+    uid: Option<u32>,
+    /// The executable path of the peer, falling back to its process name.
+    process: Option<String>,
+}
+
+impl PeerKey {
+    fn new(peer_info: Option<&PeerInfo>) -> Self {
+        Self {
+            uid: peer_info.and_then(PeerInfo::uid),
+            process: peer_info.map(|peer_info| {
+                peer_info
+                    .exe_path()
+                    .unwrap_or(peer_info.process_name())
+                    .to_string()
+            }),
+        }
+    }
+}
+
+/// Enforces [`RateLimits`] for a server, shared by all of its connections.
+#[derive(Debug)]
+pub(crate) struct RateLimiter {
+    limits: Mutex<RateLimits>,
+    /// Times of the sign requests within the window, per peer.
+    sign_requests: Mutex<HashMap<PeerKey, VecDeque<Instant>>>,
+    pending_approvals: AtomicUsize,
+    open_connections: AtomicUsize,
+    rate_limited_sign_requests: AtomicU64,
+    rejected_approvals: AtomicU64,
+    rejected_connections: AtomicU64,
+}
+
+impl Default for RateLimiter {
+    fn default() -> Self {
+        Self::new(RateLimits::default())
+    }
+}
+
+impl RateLimiter {
+    pub(crate) fn new(limits: RateLimits) -> Self {
+        Self {
+            limits: Mutex::new(limits),
+            sign_requests: Mutex::new(HashMap::new()),
+            pending_approvals: AtomicUsize::new(0),
+            open_connections: AtomicUsize::new(0),
+            rate_limited_sign_requests: AtomicU64::new(0),
+            rejected_approvals: AtomicU64::new(0),
+            rejected_connections: AtomicU64::new(0),
+        }
+    }
+
+    /// Replaces the limits, resetting the counters of refused requests and connections. Requests
+    /// and connections in progress keep their slots.
+    pub(crate) fn set_limits(&self, limits: RateLimits) {
+        if let Ok(mut current) = self.limits.lock() {
+            *current = limits;
+        }
+        self.rate_limited_sign_requests.store(0, Ordering::Relaxed);
+        self.rejected_approvals.store(0, Ordering::Relaxed);
+        self.rejected_connections.store(0, Ordering::Relaxed);
+    }
+
+    fn limits(&self) -> RateLimits {
+        self.limits.lock().map(|limits| *limits).unwrap_or_default()
+    }
+
+    /// Counts a sign request of the peer against its rate limit.
+    ///
+    /// # Returns
+    ///
+    /// `true` if the request is within the limit, `false` if it must be refused.
+    pub(crate) fn check_sign_request(&self, peer_info: Option<&PeerInfo>) -> bool {
+        let limits = self.limits();
+        let now = Instant::now();
+        let Ok(mut sign_requests) = self.sign_requests.lock() else {
+            return false;
+        };
+        // Expired requests of all peers are dropped, so that peers that stopped making requests
+        // don't accumulate.
+        sign_requests.retain(|_, times| {
+            while times
+                .front()
+                .is_some_and(|time| now.duration_since(*time) >= limits.window)
+            {
+                times.pop_front();
+            }
+            !times.is_empty()
+        });
+
+        let times = sign_requests.entry(PeerKey::new(peer_info)).or_default();
+        if times.len() >= limits.max_sign_requests {
+            self.rate_limited_sign_requests
+                .fetch_add(1, Ordering::Relaxed);
+            warn!(
+                pid = peer_info.map(PeerInfo::pid),
+                "Peer exceeded the sign request rate limit"
+            );
+            return false;
+        }
+        times.push_back(now);
+        true
+    }
+
+    /// Reserves one of the slots for sign requests awaiting authorization.
+    ///
+    /// # Returns
+    ///
+    /// A guard releasing the slot when dropped, or `None` if all slots are taken.
+    pub(crate) fn begin_approval(&self) -> Option<ApprovalSlot<'_>> {
+        if !try_increment(&self.pending_approvals, self.limits().max_pending_approvals) {
+            self.rejected_approvals.fetch_add(1, Ordering::Relaxed);
+            warn!("Too many sign requests awaiting authorization");
+            return None;
+        }
+        Some(ApprovalSlot { limiter: self })
+    }
+
+    /// Reserves one of the slots for open connections.
+    ///
+    /// # Returns
+    ///
+    /// A guard releasing the slot when dropped, or `None` if all slots are taken.
+    pub(crate) fn open_connection(self: &Arc<Self>) -> Option<ConnectionSlot> {
+        if !try_increment(&self.open_connections, self.limits().max_connections) {
+            self.rejected_connections.fetch_add(1, Ordering::Relaxed);
+            warn!("Too many open connections");
+            return None;
+        }
+        Some(ConnectionSlot {
+            limiter: Arc::clone(self),
+        })
+    }
+
+    pub(crate) fn stats(&self) -> RateLimitStats {
+        RateLimitStats {
+            rate_limited_sign_requests: self.rate_limited_sign_requests.load(Ordering::Relaxed),
+            rejected_approvals: self.rejected_approvals.load(Ordering::Relaxed),
+            rejected_connections: self.rejected_connections.load(Ordering::Relaxed),
+            pending_approvals: self.pending_approvals.load(Ordering::Relaxed),
+            open_connections: self.open_connections.load(Ordering::Relaxed),
+        }
+    }
+}
+
+/// Increments `counter` unless it reached `max`.
+///
+/// # Returns
+///
+/// `true` if the counter was incremented.
+fn try_increment(counter: &AtomicUsize, max: usize) -> bool {
+    counter
+        .fetch_update(Ordering::AcqRel, Ordering::Acquire, |count| {
+            (count < max).then_some(count + 1)
+        })
+        .is_ok()
+}
+
+/// A slot for a sign request awaiting authorization, released when dropped.
+pub(crate) struct ApprovalSlot<'a> {
+    limiter: &'a RateLimiter,
+}
+
+impl Drop for ApprovalSlot<'_> {
+    fn drop(&mut self) {
+        self.limiter
+            .pending_approvals
+            .fetch_sub(1, Ordering::AcqRel);
+    }
+}
+
+/// A slot for an open connection, released when dropped.
+pub(crate) struct ConnectionSlot {
+    limiter: Arc<RateLimiter>,
+}
+
+impl Drop for ConnectionSlot {
+    fn drop(&mut self) {
+        self.limiter.open_connections.fetch_sub(1, Ordering::AcqRel);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn make_limiter(max_sign_requests: usize, window: Duration) -> RateLimiter {
+        RateLimiter::new(RateLimits {
+            max_sign_requests,
+            window,
+            max_pending_approvals: 2,
+            max_connections: 2,
+        })
+    }
+
+    #[test]
+    fn sign_requests_beyond_limit_are_refused() {
+        let limiter = make_limiter(2, Duration::from_secs(60));
+
+        assert!(limiter.check_sign_request(None));
+        assert!(limiter.check_sign_request(None));
+        assert!(!limiter.check_sign_request(None));
+        assert_eq!(limiter.stats().rate_limited_sign_requests, 1);
+    }
+
+    #[test]
+    fn sign_requests_are_limited_per_peer() {
+        let limiter = make_limiter(1, Duration::from_secs(60));
+        let peer_info = PeerInfo::from_pid(std::process::id()).unwrap();
+
+        assert!(limiter.check_sign_request(Some(&peer_info)));
+        assert!(!limiter.check_sign_request(Some(&peer_info)));
+        assert!(limiter.check_sign_request(None));
+    }
+
+    #[test]
+    fn sign_requests_are_allowed_again_after_window() {
+        let limiter = make_limiter(1, Duration::from_millis(20));
+
+        assert!(limiter.check_sign_request(None));
+        assert!(!limiter.check_sign_request(None));
+        std::thread::sleep(Duration::from_millis(30));
+        assert!(limiter.check_sign_request(None));
+    }
+
+    #[test]
+    fn pending_approvals_are_capped_until_released() {
+        let limiter = make_limiter(10, Duration::from_secs(60));
+
+        let first = limiter.begin_approval();
+        let second = limiter.begin_approval();
+        assert!(first.is_some() && second.is_some());
+        assert!(limiter.begin_approval().is_none());
+        assert_eq!(limiter.stats().pending_approvals, 2);
+
+        drop(first);
+        assert!(limiter.begin_approval().is_some());
+        assert_eq!(limiter.stats().rejected_approvals, 1);
+    }
+
+    #[test]
+    fn set_limits_applies_to_later_requests_and_resets_counters() {
+        let limiter = make_limiter(1, Duration::from_secs(60));
+        let pending = limiter.begin_approval();
+        assert!(limiter.check_sign_request(None));
+        assert!(!limiter.check_sign_request(None));
+
+        limiter.set_limits(RateLimits {
+            max_sign_requests: 2,
+            window: Duration::from_secs(60),
+            max_pending_approvals: 2,
+            max_connections: 2,
+        });
+
+        assert!(limiter.check_sign_request(None));
+        assert_eq!(
+            limiter.stats(),
+            RateLimitStats {
+                pending_approvals: 1,
+                ..Default::default()
+            }
+        );
+        drop(pending);
+    }
+
+    #[test]
+    fn open_connections_are_capped_until_closed() {
+        let limiter = Arc::new(make_limiter(10, Duration::from_secs(60)));
+
+        let first = limiter.open_connection();
+        let _second = limiter.open_connection();
+        assert!(limiter.open_connection().is_none());
+        assert_eq!(limiter.stats().open_connections, 2);
+
+        drop(first);
+        assert!(limiter.open_connection().is_some());
+        assert_eq!(
+            limiter.stats(),
+            RateLimitStats {
+                rejected_connections: 1,
+                open_connections: 1,
+                ..Default::default()
+            }
+        );
+    }
+}
@@ -6,13 +6,28 @@ use signature::Verifier as _;
 use ssh_key::{public::KeyData, Algorithm, HashAlg, Signature};
 use tracing::{info, warn};
 
-use super::protocol::{read_ssh_string, RSA_SHA2_256, RSA_SHA2_512};
+use crate::storage::session_keystore::KeyConstraints;
+
+use super::{
+    auth_policy::SessionBindHop,
+    protocol::{read_ssh_string, RSA_SHA2_256, RSA_SHA2_512},
+};
+
+/// Maximum number of session-bind records accepted on one connection, matching OpenSSH's agent.
+const MAX_SESSION_BIND_HOPS: usize = 16;
 
 /// Session-Bind state for one connection.
 #[derive(Debug, Default)]
 pub(super) struct SessionBindState {
+    /// Whether a session-bind was received, even if it failed verification.
+    pub bind_attempted: bool,
     pub is_forwarding: bool,
     pub host_fingerprint: String,
+    /// Every verified binding, in the order received.
+    pub hops: Vec<SessionBindHop>,
+    /// The payload of every session-bind received, including those that failed verification,
+    /// to be replayed to the upstream agent.
+    pub payloads: Vec<Vec<u8>>,
 }
 
 impl SessionBindState {
@@ -21,10 +36,31 @@ impl SessionBindState {
     /// Returns `true` on success, `false` on any parse or verification failure — on failure
     /// the state is left unchanged.
     pub(super) fn parse_and_verify(&mut self, payload: &[u8]) -> bool {
+        self.bind_attempted = true;
+        if self.payloads.len() < MAX_SESSION_BIND_HOPS {
+            self.payloads.push(payload.to_vec());
+        }
         self.try_update(payload).is_some()
     }
 
+    /// # Returns
+    ///
+    /// `true` if the destination `constraints` of a key permit it to be used on this connection,
+    /// to sign if `signing`, or to be listed otherwise.
+    pub(super) fn permits(&self, constraints: &KeyConstraints, signing: bool) -> bool {
+        let bind_failed = self.bind_attempted && self.hops.is_empty();
+        constraints.permits_hops(&self.hops, bind_failed, signing)
+    }
+
     fn try_update(&mut self, payload: &[u8]) -> Option<()> {
+        if self.hops.len() >= MAX_SESSION_BIND_HOPS {
+            warn!(
+                max = MAX_SESSION_BIND_HOPS,
+                "session-bind exceeded the maximum number of hops"
+            );
+            return None;
+        }
+
         let (hostkey_bytes, rest) = read_ssh_string(payload)?;
         let (session_id, rest) = read_ssh_string(rest)?;
         let (sig_outer, rest) = read_ssh_string(rest)?;
@@ -42,9 +78,8 @@ impl SessionBindState {
                 key.verify(session_id, &sig).is_ok()
             }
             KeyData::Rsa(key) => verify_rsa(key, alg, session_id, raw_sig),
-            KeyData::Ecdsa(_) => {
-                warn!("session-bind ECDSA host key not yet supported");
-                false
+            KeyData::Ecdsa(_) | KeyData::SkEcdsaSha2NistP256(_) | KeyData::SkEd25519(_) => {
+                verify_signature_blob(&pub_key, session_id, sig_outer)
             }
             _ => {
                 warn!("session-bind received unknown host key type");
@@ -68,10 +103,32 @@ impl SessionBindState {
             self.is_forwarding = true;
         }
         self.host_fingerprint = pub_key.fingerprint(HashAlg::Sha256).to_string();
+        self.hops.push(SessionBindHop {
+            host_fingerprint: self.host_fingerprint.clone(),
+            is_forwarding,
+        });
         Some(())
     }
 }
 
+/// Verifies host keys whose signature encoding is decoded by [`Signature`]: NIST P-256, P-384 and
+/// P-521 ECDSA, and security keys, whose signatures are followed by a flags byte and a counter.
+fn verify_signature_blob(key: &ssh_key::PublicKey, session_id: &[u8], sig_blob: &[u8]) -> bool {
+    let Ok(sig) = Signature::try_from(sig_blob) else {
+        warn!("session-bind received malformed signature");
+        return false;
+    };
+    if sig.algorithm() != key.algorithm() {
+        warn!(
+            alg = %sig.algorithm(),
+            "session-bind signature algorithm does not match host key"
+        );
+        return false;
+    }
+
+    key.key_data().verify(session_id, &sig).is_ok()
+}
+
 fn verify_rsa(
     key: &ssh_key::public::RsaPublicKey,
     alg: &str,
@@ -110,15 +167,19 @@ fn verify_rsa(
 
 #[cfg(test)]
 mod tests {
+    use sha2::Digest as _;
     use signature::Signer as _;
     use ssh_key::{
-        private::{Ed25519Keypair, KeypairData, RsaKeypair},
+        private::{EcdsaKeypair, Ed25519Keypair, KeypairData, RsaKeypair},
+        public::{EcdsaPublicKey, KeyData, SkEcdsaSha2NistP256, SkEd25519},
         rand_core::OsRng,
+        EcdsaCurve,
     };
 
     use super::SessionBindState;
     use crate::server::test_common::{
-        make_session_bind_payload_ed25519, make_session_bind_payload_rsa, write_ssh_string,
+        make_session_bind_payload_ecdsa, make_session_bind_payload_ed25519,
+        make_session_bind_payload_rsa, write_ssh_string,
     };
 
     fn apply(payload: &[u8]) -> Option<SessionBindState> {
@@ -162,6 +223,135 @@ mod tests {
         assert!(!state.is_forwarding);
     }
 
+    /// Signs `session_id` the way a security key does, over the hashed application, flags, counter
+    /// and hashed message, and encodes the signature with its trailing flags and counter.
+    fn make_session_bind_payload_sk(
+        hostkey: KeyData,
+        sign: impl Fn(&[u8]) -> Vec<u8>,
+        session_id: &[u8],
+    ) -> Vec<u8> {
+        const FLAGS: u8 = 0x01; // user present
+        const COUNTER: u32 = 7;
+        let application = match &hostkey {
+            KeyData::SkEd25519(key) => key.application().to_string(),
+            KeyData::SkEcdsaSha2NistP256(key) => key.application().to_string(),
+            _ => unreachable!("security key host key expected"),
+        };
+
+        let mut signed_data = sha2::Sha256::digest(application.as_bytes()).to_vec();
+        signed_data.push(FLAGS);
+        signed_data.extend_from_slice(&COUNTER.to_be_bytes());
+        signed_data.extend_from_slice(&sha2::Sha256::digest(session_id));
+
+        let mut sig_outer = Vec::new();
+        write_ssh_string(&mut sig_outer, hostkey.algorithm().as_str().as_bytes());
+        write_ssh_string(&mut sig_outer, &sign(&signed_data));
+        sig_outer.push(FLAGS);
+        sig_outer.extend_from_slice(&COUNTER.to_be_bytes());
+
+        let mut payload = Vec::new();
+        write_ssh_string(
+            &mut payload,
+            &ssh_key::PublicKey::from(hostkey).to_bytes().unwrap(),
+        );
+        write_ssh_string(&mut payload, session_id);
+        write_ssh_string(&mut payload, &sig_outer);
+        payload.push(1u8);
+        payload
+    }
+
+    #[test]
+    fn valid_ecdsa_returns_true_for_every_curve() {
+        for curve in [
+            EcdsaCurve::NistP256,
+            EcdsaCurve::NistP384,
+            EcdsaCurve::NistP521,
+        ] {
+            let keypair = EcdsaKeypair::random(&mut OsRng, curve).unwrap();
+            let payload = make_session_bind_payload_ecdsa(&keypair, &[0x42u8; 32], true);
+            let state = apply(&payload).unwrap();
+            assert!(state.is_forwarding, "{curve:?}");
+            assert!(state.host_fingerprint.starts_with("SHA256:"), "{curve:?}");
+        }
+    }
+
+    #[test]
+    fn ecdsa_signature_for_other_session_returns_false() {
+        let keypair = EcdsaKeypair::random(&mut OsRng, EcdsaCurve::NistP384).unwrap();
+        let mut payload = make_session_bind_payload_ecdsa(&keypair, &[0x42u8; 32], false);
+        let other = make_session_bind_payload_ecdsa(&keypair, &[0x43u8; 32], false);
+        // Swap the session identifier, keeping the signature over the original one.
+        let hostkey_len = 4 + u32::from_be_bytes(payload[..4].try_into().unwrap()) as usize;
+        payload[hostkey_len..hostkey_len + 36]
+            .copy_from_slice(&other[hostkey_len..hostkey_len + 36]);
+        assert!(apply(&payload).is_none());
+    }
+
+    #[test]
+    fn ecdsa_signature_with_mismatched_curve_returns_false() {
+        let host = EcdsaKeypair::random(&mut OsRng, EcdsaCurve::NistP256).unwrap();
+        let signer = EcdsaKeypair::random(&mut OsRng, EcdsaCurve::NistP384).unwrap();
+        let host_payload = make_session_bind_payload_ecdsa(&host, &[0x42u8; 32], false);
+        let signer_payload = make_session_bind_payload_ecdsa(&signer, &[0x42u8; 32], false);
+        // Keep the P-256 host key, but use the P-384 signature.
+        let host_len = 4 + u32::from_be_bytes(host_payload[..4].try_into().unwrap()) as usize;
+        let signer_len = 4 + u32::from_be_bytes(signer_payload[..4].try_into().unwrap()) as usize;
+        let mut payload = host_payload[..host_len].to_vec();
+        payload.extend_from_slice(&signer_payload[signer_len..]);
+        assert!(apply(&payload).is_none());
+    }
+
+    #[test]
+    fn valid_sk_ed25519_returns_true() {
+        let keypair = Ed25519Keypair::random(&mut OsRng);
+        let hostkey = KeyData::SkEd25519(SkEd25519::new(keypair.public, "ssh:"));
+        let payload = make_session_bind_payload_sk(
+            hostkey,
+            |data| {
+                let sig: ssh_key::Signature = keypair.sign(data);
+                sig.as_bytes().to_vec()
+            },
+            &[0x42u8; 32],
+        );
+        let state = apply(&payload).unwrap();
+        assert!(state.is_forwarding);
+    }
+
+    #[test]
+    fn valid_sk_ecdsa_returns_true() {
+        let keypair = EcdsaKeypair::random(&mut OsRng, EcdsaCurve::NistP256).unwrap();
+        let EcdsaPublicKey::NistP256(ec_point) = EcdsaPublicKey::from(&keypair) else {
+            unreachable!("P-256 keypair expected");
+        };
+        let hostkey = KeyData::SkEcdsaSha2NistP256(SkEcdsaSha2NistP256::new(ec_point, "ssh:"));
+        let payload = make_session_bind_payload_sk(
+            hostkey,
+            |data| {
+                let sig: ssh_key::Signature = keypair.sign(data);
+                sig.as_bytes().to_vec()
+            },
+            &[0x42u8; 32],
+        );
+        let state = apply(&payload).unwrap();
+        assert!(state.is_forwarding);
+    }
+
+    #[test]
+    fn sk_ed25519_signature_without_application_hash_returns_false() {
+        let keypair = Ed25519Keypair::random(&mut OsRng);
+        let hostkey = KeyData::SkEd25519(SkEd25519::new(keypair.public, "ssh:"));
+        // A plain signature over the session identifier, as a non-security key would make.
+        let payload = make_session_bind_payload_sk(
+            hostkey,
+            |_| {
+                let sig: ssh_key::Signature = keypair.sign(&[0x42u8; 32]);
+                sig.as_bytes().to_vec()
+            },
+            &[0x42u8; 32],
+        );
+        assert!(apply(&payload).is_none());
+    }
+
     #[test]
     fn tampered_signature_bytes_returns_false() {
         let keypair = Ed25519Keypair::random(&mut OsRng);
@@ -310,6 +500,7 @@ mod tests {
             state.host_fingerprint.is_empty(),
             "failed verification must not modify host_fingerprint"
         );
+        assert!(state.hops.is_empty());
     }
 
     #[test]
@@ -348,4 +539,60 @@ mod tests {
             "latch: is_forwarding must not be cleared by a non-forwarding rebind"
         );
     }
+
+    #[test]
+    fn hops_recorded_in_order() {
+        let bastion = Ed25519Keypair::random(&mut OsRng);
+        let target = Ed25519Keypair::random(&mut OsRng);
+        let mut state = SessionBindState::default();
+
+        assert!(state.parse_and_verify(&make_session_bind_payload_ed25519(
+            &bastion,
+            &[0x01u8; 32],
+            true
+        )));
+        assert!(state.parse_and_verify(&make_session_bind_payload_ed25519(
+            &target,
+            &[0x02u8; 32],
+            false
+        )));
+
+        assert_eq!(state.hops.len(), 2);
+        assert_eq!(
+            state.hops[0].host_fingerprint,
+            ssh_key::PublicKey::from(bastion.public)
+                .fingerprint(ssh_key::HashAlg::Sha256)
+                .to_string()
+        );
+        assert!(state.hops[0].is_forwarding);
+        assert_eq!(state.hops[1].host_fingerprint, state.host_fingerprint);
+        assert!(!state.hops[1].is_forwarding);
+    }
+
+    #[test]
+    fn hops_beyond_maximum_rejected() {
+        let keypair = Ed25519Keypair::random(&mut OsRng);
+        let mut state = SessionBindState::default();
+
+        for i in 0..super::MAX_SESSION_BIND_HOPS {
+            let payload = make_session_bind_payload_ed25519(&keypair, &[i as u8; 32], true);
+            assert!(state.parse_and_verify(&payload));
+        }
+        let payload = make_session_bind_payload_ed25519(&keypair, &[0xFFu8; 32], true);
+
+        assert!(!state.parse_and_verify(&payload));
+        assert_eq!(state.hops.len(), super::MAX_SESSION_BIND_HOPS);
+    }
+
+    #[test]
+    fn payloads_recorded_including_failed_binds() {
+        let keypair = Ed25519Keypair::random(&mut OsRng);
+        let valid = make_session_bind_payload_ed25519(&keypair, &[0x01u8; 32], false);
+        let mut state = SessionBindState::default();
+
+        assert!(!state.parse_and_verify(b"garbage"));
+        assert!(state.parse_and_verify(&valid));
+
+        assert_eq!(state.payloads, vec![b"garbage".to_vec(), valid]);
+    }
 }
@@ -0,0 +1,473 @@
+//! Client for an upstream SSH agent, such as the agent of the user's desktop session, whose keys
+//! are offered to clients alongside Bitwarden's.
+
+use std::{
+    path::{Path, PathBuf},
+    sync::{Arc, Mutex},
+    time::Duration,
+};
+
+use anyhow::{anyhow, Result};
+use tokio::io::{AsyncRead, AsyncReadExt, AsyncWrite, AsyncWriteExt};
+
+use super::protocol::{
+    build_session_bind, build_sign_request, frame, parse_identities_answer, parse_sign_response,
+    SignFlags, REQUEST_IDENTITIES,
+};
+use crate::crypto::PublicKey;
+
+// Keys are listed for every connection, so an unresponsive upstream agent must not hold up
+// clients for long.
+const LIST_TIMEOUT: Duration = Duration::from_secs(5);
+// The upstream agent may ask the user to confirm, or to touch a security key.
+const SIGN_TIMEOUT: Duration = Duration::from_secs(60);
+// Guards against oversized allocations from a misbehaving upstream agent.
+const MAX_RESPONSE_LEN: usize = 256 * 1024;
+
+/// An SSH agent whose keys are merged into the keys the server offers.
+#[cfg_attr(test, mockall::automock)]
+#[async_trait::async_trait]
+pub(crate) trait UpstreamAgent: Send + Sync {
+    /// # Returns
+    ///
+    /// The socket or named pipe the upstream agent listens on, if known.
+    fn path(&self) -> Option<PathBuf>;
+
+    /// # Returns
+    ///
+    /// The public keys held by the upstream agent, with their comments.
+    ///
+    /// # Errors
+    ///
+    /// If the upstream agent is unreachable, or its response is malformed.
+    async fn list_identities(&self) -> Result<Vec<(PublicKey, String)>>;
+
+    /// Asks the upstream agent to sign data.
+    ///
+    /// # Arguments
+    ///
+    /// * `public_key` - The key to sign with
+    /// * `data` - The data to sign
+    /// * `flags` - The signing flags of the client's request
+    /// * `session_binds` - The `session-bind@openssh.com` payloads received on the client's
+    ///   connection. They are replayed to the upstream agent first, so that it enforces the
+    ///   destination constraints of its keys.
+    ///
+    /// # Returns
+    ///
+    /// The signature blob, as returned by the upstream agent.
+    ///
+    /// # Errors
+    ///
+    /// If the upstream agent is unreachable, or refused to sign.
+    async fn sign(
+        &self,
+        public_key: &PublicKey,
+        data: &[u8],
+        flags: Option<SignFlags>,
+        session_binds: &[Vec<u8>],
+    ) -> Result<Vec<u8>>;
+}
+
+/// [`UpstreamAgent`] listening on a Unix domain socket or Windows named pipe, e.g. the
+/// `SSH_AUTH_SOCK` of the desktop session. A new connection is made for each request, so that
+/// the upstream agent can be restarted while the server is running.
+pub(crate) struct SocketUpstreamAgent {
+    path: PathBuf,
+}
+
+impl SocketUpstreamAgent {
+    pub(crate) fn new(path: PathBuf) -> Self {
+        Self { path }
+    }
+
+    /// Sends `msgs` on a new connection, in order.
+    ///
+    /// # Returns
+    ///
+    /// The response to the last message.
+    async fn request(&self, msgs: Vec<Vec<u8>>, timeout: Duration) -> Result<Vec<u8>> {
+        tokio::time::timeout(timeout, async {
+            let mut stream = connect(&self.path).await?;
+            let mut response = Vec::new();
+            for msg in msgs {
+                response = exchange(&mut stream, msg).await?;
+            }
+            Ok(response)
+        })
+        .await
+        .map_err(|_| anyhow!("Upstream agent did not respond within {timeout:?}"))?
+    }
+}
+
+#[async_trait::async_trait]
+impl UpstreamAgent for SocketUpstreamAgent {
+    fn path(&self) -> Option<PathBuf> {
+        Some(self.path.clone())
+    }
+
+    async fn list_identities(&self) -> Result<Vec<(PublicKey, String)>> {
+        let response = self
+            .request(vec![vec![REQUEST_IDENTITIES]], LIST_TIMEOUT)
+            .await?;
+        parse_identities_answer(&response)
+            .ok_or_else(|| anyhow!("Upstream agent returned an invalid identities answer"))
+    }
+
+    async fn sign(
+        &self,
+        public_key: &PublicKey,
+        data: &[u8],
+        flags: Option<SignFlags>,
+        session_binds: &[Vec<u8>],
+    ) -> Result<Vec<u8>> {
+        // The responses to the bindings aren't checked: like for a client connected to it
+        // directly, the upstream agent refuses constrained keys if a binding failed.
+        let mut msgs: Vec<Vec<u8>> = session_binds
+            .iter()
+            .map(|payload| build_session_bind(payload))
+            .collect();
+        msgs.push(build_sign_request(public_key, data, flags));
+        let response = self.request(msgs, SIGN_TIMEOUT).await?;
+        parse_sign_response(&response)
+            .map(<[u8]>::to_vec)
+            .ok_or_else(|| anyhow!("Upstream agent refused to sign"))
+    }
+}
+
+/// [`UpstreamAgent`] for a single client connection. The identities of the upstream agent are
+/// listed once, then reused for the rest of the connection, so that sign requests don't list them
+/// again to look up their key.
+pub(crate) struct CachedUpstreamAgent {
+    inner: Arc<dyn UpstreamAgent>,
+    identities: Mutex<Option<Vec<(PublicKey, String)>>>,
+}
+
+impl CachedUpstreamAgent {
+    pub(crate) fn new(inner: Arc<dyn UpstreamAgent>) -> Self {
+        Self {
+            inner,
+            identities: Mutex::new(None),
+        }
+    }
+}
+
+#[async_trait::async_trait]
+impl UpstreamAgent for CachedUpstreamAgent {
+    fn path(&self) -> Option<PathBuf> {
+        self.inner.path()
+    }
+
+    async fn list_identities(&self) -> Result<Vec<(PublicKey, String)>> {
+        if let Some(identities) = self
+            .identities
+            .lock()
+            .map_err(|_| anyhow!("Upstream identities lock poisoned"))?
+            .clone()
+        {
+            return Ok(identities);
+        }
+
+        // Failures aren't cached, so the next request tries again.
+        let identities = self.inner.list_identities().await?;
+        *self
+            .identities
+            .lock()
+            .map_err(|_| anyhow!("Upstream identities lock poisoned"))? = Some(identities.clone());
+        Ok(identities)
+    }
+
+    async fn sign(
+        &self,
+        public_key: &PublicKey,
+        data: &[u8],
+        flags: Option<SignFlags>,
+        session_binds: &[Vec<u8>],
+    ) -> Result<Vec<u8>> {
+        self.inner
+            .sign(public_key, data, flags, session_binds)
+            .await
+    }
+}
+
+#[cfg(unix)]
+async fn connect(path: &Path) -> Result<tokio::net::UnixStream> {
+    Ok(tokio::net::UnixStream::connect(path).await?)
+}
+
+// Opening a named pipe client doesn't block, but is kept async to match the Unix implementation.
+#[cfg(windows)]
+#[allow(clippy::unused_async)]
+async fn connect(path: &Path) -> Result<tokio::net::windows::named_pipe::NamedPipeClient> {
+    Ok(tokio::net::windows::named_pipe::ClientOptions::new().open(path)?)
+}
+
+/// Sends a single message to an agent and reads its response.
+async fn exchange<S: AsyncRead + AsyncWrite + Unpin>(
+    stream: &mut S,
+    msg: Vec<u8>,
+) -> Result<Vec<u8>> {
+    stream.write_all(&frame(msg)).await?;
+
+    let mut len_buf = [0u8; 4];
+    stream.read_exact(&mut len_buf).await?;
+    let len = u32::from_be_bytes(len_buf) as usize;
+    if len > MAX_RESPONSE_LEN {
+        return Err(anyhow!(
+            "Upstream agent response of {len} bytes exceeds maximum"
+        ));
+    }
+
+    let mut response = vec![0u8; len];
+    stream.read_exact(&mut response).await?;
+    Ok(response)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::server::{
+        protocol::{build_identities_answer, build_raw_sign_response, failure, success, EXTENSION},
+        test_common::make_minimal_ed25519_blob,
+    };
+
+    fn make_public_key() -> PublicKey {
+        PublicKey {
+            alg: "ssh-ed25519".to_string(),
+            blob: make_minimal_ed25519_blob(),
+        }
+    }
+
+    /// Answers a single request on `stream` with `response`, returning the request.
+    async fn serve_once<S: AsyncRead + AsyncWrite + Unpin>(
+        stream: &mut S,
+        response: Vec<u8>,
+    ) -> Vec<u8> {
+        let mut len_buf = [0u8; 4];
+        stream.read_exact(&mut len_buf).await.unwrap();
+        let mut request = vec![0u8; u32::from_be_bytes(len_buf) as usize];
+        stream.read_exact(&mut request).await.unwrap();
+        stream.write_all(&frame(response)).await.unwrap();
+        request
+    }
+
+    #[tokio::test]
+    async fn exchange_frames_request_and_reads_response() {
+        let (mut client, mut upstream) = tokio::io::duplex(1024);
+        let upstream_task = tokio::spawn(async move { serve_once(&mut upstream, vec![6]).await });
+
+        let response = exchange(&mut client, vec![REQUEST_IDENTITIES])
+            .await
+            .unwrap();
+
+        assert_eq!(response, vec![6]);
+        assert_eq!(upstream_task.await.unwrap(), vec![REQUEST_IDENTITIES]);
+    }
+
+    #[tokio::test]
+    async fn exchange_rejects_oversized_response() {
+        let (mut client, mut upstream) = tokio::io::duplex(1024);
+        tokio::spawn(async move {
+            let mut request = [0u8; 5];
+            upstream.read_exact(&mut request).await.unwrap();
+            let len = u32::try_from(MAX_RESPONSE_LEN + 1).unwrap();
+            upstream.write_all(&len.to_be_bytes()).await.unwrap();
+        });
+
+        assert!(exchange(&mut client, vec![REQUEST_IDENTITIES])
+            .await
+            .is_err());
+    }
+
+    #[tokio::test]
+    async fn exchange_fails_when_upstream_closes() {
+        let (mut client, upstream) = tokio::io::duplex(1024);
+        drop(upstream);
+
+        assert!(exchange(&mut client, vec![REQUEST_IDENTITIES])
+            .await
+            .is_err());
+    }
+
+    #[cfg(unix)]
+    mod socket {
+        use rand::{distr::Alphanumeric, Rng as _};
+
+        use super::*;
+
+        fn rand_path_in_temp() -> PathBuf {
+            let mut path = std::env::temp_dir();
+            let s: String = rand::rng()
+                .sample_iter(&Alphanumeric)
+                .take(16)
+                .map(char::from)
+                .collect();
+            path.push(s);
+            path
+        }
+
+        /// Binds a fake upstream agent answering one request per connection with `responses`.
+        fn spawn_fake_upstream(
+            path: &Path,
+            responses: Vec<Vec<u8>>,
+        ) -> tokio::task::JoinHandle<Vec<Vec<u8>>> {
+            let listener = tokio::net::UnixListener::bind(path).unwrap();
+            tokio::spawn(async move {
+                let mut requests = Vec::new();
+                for response in responses {
+                    let (mut stream, _) = listener.accept().await.unwrap();
+                    requests.push(serve_once(&mut stream, response).await);
+                }
+                requests
+            })
+        }
+
+        #[tokio::test]
+        async fn lists_upstream_identities() {
+            let path = rand_path_in_temp();
+            let keys = vec![(make_public_key(), "user@laptop".to_string())];
+            let upstream_task =
+                spawn_fake_upstream(&path, vec![build_identities_answer(keys.clone())]);
+
+            let identities = SocketUpstreamAgent::new(path.clone())
+                .list_identities()
+                .await
+                .unwrap();
+
+            assert_eq!(identities, keys);
+            assert_eq!(upstream_task.await.unwrap(), vec![vec![REQUEST_IDENTITIES]]);
+            std::fs::remove_file(path).unwrap();
+        }
+
+        #[tokio::test]
+        async fn forwards_sign_requests() {
+            let path = rand_path_in_temp();
+            let public_key = make_public_key();
+            let upstream_task =
+                spawn_fake_upstream(&path, vec![build_raw_sign_response(b"sig"), failure()]);
+            let upstream = SocketUpstreamAgent::new(path.clone());
+
+            let signature = upstream
+                .sign(&public_key, b"data", Some(SignFlags::RsaSha256), &[])
+                .await
+                .unwrap();
+            let refused = upstream.sign(&public_key, b"data", None, &[]).await;
+
+            assert_eq!(signature, b"sig");
+            assert!(refused.is_err());
+            assert_eq!(
+                upstream_task.await.unwrap(),
+                vec![
+                    build_sign_request(&public_key, b"data", Some(SignFlags::RsaSha256)),
+                    build_sign_request(&public_key, b"data", None),
+                ]
+            );
+            std::fs::remove_file(path).unwrap();
+        }
+
+        /// Binds a fake upstream agent holding a key that, like a key added with `ssh-add -h`,
+        /// only signs on connections bound with `permitted_bind`.
+        fn spawn_constrained_upstream(
+            path: &Path,
+            permitted_bind: Vec<u8>,
+            connections: usize,
+        ) -> tokio::task::JoinHandle<()> {
+            let listener = tokio::net::UnixListener::bind(path).unwrap();
+            tokio::spawn(async move {
+                for _ in 0..connections {
+                    let (mut stream, _) = listener.accept().await.unwrap();
+                    let mut bound = false;
+                    loop {
+                        let mut len_buf = [0u8; 4];
+                        stream.read_exact(&mut len_buf).await.unwrap();
+                        let mut request = vec![0u8; u32::from_be_bytes(len_buf) as usize];
+                        stream.read_exact(&mut request).await.unwrap();
+
+                        if request.first() == Some(&EXTENSION) {
+                            bound |= request == build_session_bind(&permitted_bind);
+                            stream.write_all(&frame(success())).await.unwrap();
+                            continue;
+                        }
+                        let response = if bound {
+                            build_raw_sign_response(b"sig")
+                        } else {
+                            failure()
+                        };
+                        stream.write_all(&frame(response)).await.unwrap();
+                        break;
+                    }
+                }
+            })
+        }
+
+        #[tokio::test]
+        async fn destination_constrained_key_signs_only_for_replayed_session_bind() {
+            let path = rand_path_in_temp();
+            let public_key = make_public_key();
+            let upstream_task = spawn_constrained_upstream(&path, b"permitted".to_vec(), 3);
+            let upstream = SocketUpstreamAgent::new(path.clone());
+
+            let unbound = upstream.sign(&public_key, b"data", None, &[]).await;
+            let other_host = upstream
+                .sign(&public_key, b"data", None, &[b"other".to_vec()])
+                .await;
+            let permitted = upstream
+                .sign(
+                    &public_key,
+                    b"data",
+                    None,
+                    &[b"bastion".to_vec(), b"permitted".to_vec()],
+                )
+                .await;
+
+            assert!(unbound.is_err());
+            assert!(other_host.is_err());
+            assert_eq!(permitted.unwrap(), b"sig");
+            upstream_task.await.unwrap();
+            std::fs::remove_file(path).unwrap();
+        }
+
+        #[tokio::test]
+        async fn unreachable_upstream_returns_error() {
+            let upstream = SocketUpstreamAgent::new(rand_path_in_temp());
+
+            assert!(upstream.list_identities().await.is_err());
+        }
+    }
+
+    #[tokio::test]
+    async fn cached_upstream_lists_identities_once() {
+        let mut inner = MockUpstreamAgent::new();
+        inner
+            .expect_list_identities()
+            .once()
+            .returning(|| Ok(vec![(make_public_key(), "user@laptop".to_string())]));
+        let upstream = CachedUpstreamAgent::new(Arc::new(inner));
+
+        let first = upstream.list_identities().await.unwrap();
+        let second = upstream.list_identities().await.unwrap();
+
+        assert_eq!(first, vec![(make_public_key(), "user@laptop".to_string())]);
+        assert_eq!(first, second);
+    }
+
+    #[tokio::test]
+    async fn cached_upstream_does_not_cache_failures() {
+        let mut inner = MockUpstreamAgent::new();
+        let mut seq = mockall::Sequence::new();
+        inner
+            .expect_list_identities()
+            .once()
+            .in_sequence(&mut seq)
+            .returning(|| Err(anyhow!("upstream agent unreachable")));
+        inner
+            .expect_list_identities()
+            .once()
+            .in_sequence(&mut seq)
+            .returning(|| Ok(Vec::new()));
+        let upstream = CachedUpstreamAgent::new(Arc::new(inner));
+
+        assert!(upstream.list_identities().await.is_err());
+        assert_eq!(upstream.list_identities().await.unwrap(), Vec::new());
+    }
+}
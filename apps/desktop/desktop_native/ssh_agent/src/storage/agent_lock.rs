@@ -0,0 +1,334 @@
+//! Provides the locked state of the agent. The agent is locked either by a client with a
+//! passphrase (`ssh-add -x`), or by the desktop client when the vault locks.
+//!
+//! While locked, the agent lists no identities and refuses all signing.
+
+use std::sync::{
+    atomic::{AtomicBool, Ordering},
+    Mutex,
+};
+
+use anyhow::{anyhow, Result};
+use secure_memory::{EncryptedMemoryStore, SecureMemoryStore};
+use sha2::Sha256;
+use ssh_key::rand_core::{OsRng, RngCore};
+use tracing::{error, info};
+
+/// Key under which the salted passphrase hash is kept in the encrypted memory store.
+const PASSPHRASE_HASH_KEY: &str = "passphrase_hash";
+const SALT_LEN: usize = 16;
+const HASH_LEN: usize = 32;
+/// PBKDF2-HMAC-SHA256 iterations for the passphrase hash, as recommended by OWASP. Fewer in tests,
+/// whose dependencies aren't optimized.
+#[cfg(not(test))]
+const PBKDF2_ITERATIONS: u32 = 600_000;
+#[cfg(test)]
+const PBKDF2_ITERATIONS: u32 = 1_000;
+
+/// The locked state of the agent.
+///
+/// Any error while checking or unlocking a lock placed by a client leaves the agent locked. The
+/// desktop client can still remove such a lock with [`AgentLock::clear_passphrase`].
+pub struct AgentLock<S = EncryptedMemoryStore<&'static str>> {
+    /// Salt followed by the PBKDF2 hash of the passphrase a client locked the agent with. Empty
+    /// when not locked by a client.
+    passphrase_hash: Mutex<S>,
+    /// Whether the agent has been locked by a client. Kept apart from `passphrase_hash`, which
+    /// drops its contents when it fails to decrypt them.
+    passphrase_locked: AtomicBool,
+    /// Whether the agent has been locked by the desktop client.
+    vault_locked: AtomicBool,
+}
+
+impl AgentLock {
+    /// Creates a new, unlocked [`AgentLock`].
+    #[must_use]
+    pub fn new() -> Self {
+        Self::with_store(EncryptedMemoryStore::new())
+    }
+}
+
+impl<S: SecureMemoryStore<KeyType = &'static str>> AgentLock<S> {
+    /// Creates a new, unlocked [`AgentLock`] keeping the passphrase hash in `store`.
+    fn with_store(store: S) -> Self {
+        Self {
+            passphrase_hash: Mutex::new(store),
+            passphrase_locked: AtomicBool::new(false),
+            vault_locked: AtomicBool::new(false),
+        }
+    }
+
+    /// # Returns
+    ///
+    /// `true` if the agent is locked by a client or the desktop client.
+    #[must_use]
+    pub fn is_locked(&self) -> bool {
+        self.vault_locked.load(Ordering::SeqCst) || self.passphrase_locked.load(Ordering::SeqCst)
+    }
+
+    /// Locks the agent on behalf of a client, until it is unlocked with the same passphrase.
+    ///
+    /// Hashing the passphrase is deliberately slow, call this from a blocking context.
+    ///
+    /// # Errors
+    ///
+    /// If the agent is already locked by a client.
+    pub fn lock_with_passphrase(&self, passphrase: &[u8]) -> Result<()> {
+        if self.passphrase_locked.load(Ordering::SeqCst) {
+            return Err(anyhow!("Agent is already locked"));
+        }
+
+        let mut salt = [0u8; SALT_LEN];
+        OsRng.fill_bytes(&mut salt);
+        let mut salted_hash = salt.to_vec();
+        salted_hash.extend_from_slice(&hash_passphrase(&salt, passphrase));
+
+        let mut store = self.lock_store()?;
+        if self.passphrase_locked.swap(true, Ordering::SeqCst) {
+            return Err(anyhow!("Agent is already locked"));
+        }
+        store.put(PASSPHRASE_HASH_KEY, &salted_hash);
+
+        info!("Agent locked with passphrase.");
+        Ok(())
+    }
+
+    /// Unlocks a lock placed by a client.
+    ///
+    /// Hashing the passphrase is deliberately slow, call this from a blocking context.
+    ///
+    /// # Errors
+    ///
+    /// If the agent is not locked by a client, the passphrase does not match, or the passphrase
+    /// hash can't be read. The agent stays locked in all these cases.
+    pub fn unlock_with_passphrase(&self, passphrase: &[u8]) -> Result<()> {
+        let mut store = self.lock_store()?;
+        if !self.passphrase_locked.load(Ordering::SeqCst) {
+            return Err(anyhow!("Agent is not locked"));
+        }
+
+        // The store drops its contents when it fails to decrypt them, after which the passphrase
+        // can't be verified anymore.
+        let salted_hash = store
+            .get(&PASSPHRASE_HASH_KEY)
+            .map_err(|error| {
+                error!(%error, "Failed to decrypt passphrase hash, the agent stays locked");
+                anyhow!("Failed to decrypt passphrase hash")
+            })?
+            .ok_or_else(|| anyhow!("Passphrase hash is missing, the agent stays locked"))?;
+
+        let (salt, expected_hash) = salted_hash.split_at(SALT_LEN.min(salted_hash.len()));
+        if !constant_time_eq(&hash_passphrase(salt, passphrase), expected_hash) {
+            return Err(anyhow!("Incorrect passphrase"));
+        }
+
+        store.remove(&PASSPHRASE_HASH_KEY);
+        self.passphrase_locked.store(false, Ordering::SeqCst);
+        info!("Agent unlocked with passphrase.");
+        Ok(())
+    }
+
+    /// Locks the agent on behalf of the desktop client, e.g. when the vault locks.
+    pub fn lock(&self) {
+        self.vault_locked.store(true, Ordering::SeqCst);
+        info!("Agent locked by the desktop client.");
+    }
+
+    /// Unlocks a lock placed by the desktop client. A lock placed by a client is kept.
+    pub fn unlock(&self) {
+        self.vault_locked.store(false, Ordering::SeqCst);
+        info!("Agent unlocked by the desktop client.");
+    }
+
+    /// Removes a lock placed by a client.
+    pub fn clear_passphrase(&self) {
+        match self.lock_store() {
+            Ok(mut store) => {
+                store.clear();
+                self.passphrase_locked.store(false, Ordering::SeqCst);
+            }
+            Err(error) => error!(%error, "Failed to remove the passphrase lock"),
+        }
+    }
+
+    fn lock_store(&self) -> Result<std::sync::MutexGuard<'_, S>> {
+        self.passphrase_hash
+            .lock()
+            .map_err(|e| anyhow!("Failed to acquire lock: {e}"))
+    }
+}
+
+impl Default for AgentLock {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+fn hash_passphrase(salt: &[u8], passphrase: &[u8]) -> [u8; HASH_LEN] {
+    pbkdf2::pbkdf2_hmac_array::<Sha256, HASH_LEN>(passphrase, salt, PBKDF2_ITERATIONS)
+}
+
+fn constant_time_eq(a: &[u8], b: &[u8]) -> bool {
+    a.len() == b.len() && a.iter().zip(b).fold(0u8, |acc, (x, y)| acc | (x ^ y)) == 0
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn new_is_unlocked() {
+        assert!(!AgentLock::new().is_locked());
+    }
+
+    #[test]
+    fn lock_with_passphrase_locks() {
+        let agent_lock = AgentLock::new();
+
+        agent_lock.lock_with_passphrase(b"secret").unwrap();
+
+        assert!(agent_lock.is_locked());
+    }
+
+    #[test]
+    fn lock_with_passphrase_when_locked_returns_error() {
+        let agent_lock = AgentLock::new();
+        agent_lock.lock_with_passphrase(b"secret").unwrap();
+
+        assert!(agent_lock.lock_with_passphrase(b"other").is_err());
+        assert!(agent_lock.unlock_with_passphrase(b"other").is_err());
+        assert!(agent_lock.unlock_with_passphrase(b"secret").is_ok());
+    }
+
+    #[test]
+    fn unlock_with_correct_passphrase_unlocks() {
+        let agent_lock = AgentLock::new();
+        agent_lock.lock_with_passphrase(b"secret").unwrap();
+
+        agent_lock.unlock_with_passphrase(b"secret").unwrap();
+
+        assert!(!agent_lock.is_locked());
+    }
+
+    #[test]
+    fn unlock_with_wrong_passphrase_returns_error_and_stays_locked() {
+        let agent_lock = AgentLock::new();
+        agent_lock.lock_with_passphrase(b"secret").unwrap();
+
+        assert!(agent_lock.unlock_with_passphrase(b"wrong").is_err());
+        assert!(agent_lock.unlock_with_passphrase(b"").is_err());
+        assert!(agent_lock.is_locked());
+    }
+
+    #[test]
+    fn unlock_with_passphrase_when_unlocked_returns_error() {
+        assert!(AgentLock::new().unlock_with_passphrase(b"secret").is_err());
+    }
+
+    #[test]
+    fn lock_and_unlock_by_desktop_client() {
+        let agent_lock = AgentLock::new();
+
+        agent_lock.lock();
+        assert!(agent_lock.is_locked());
+
+        agent_lock.unlock();
+        assert!(!agent_lock.is_locked());
+    }
+
+    #[test]
+    fn unlock_by_desktop_client_keeps_passphrase_lock() {
+        let agent_lock = AgentLock::new();
+        agent_lock.lock_with_passphrase(b"secret").unwrap();
+
+        agent_lock.unlock();
+
+        assert!(agent_lock.is_locked());
+    }
+
+    #[test]
+    fn unlock_with_passphrase_keeps_desktop_client_lock() {
+        let agent_lock = AgentLock::new();
+        agent_lock.lock();
+        agent_lock.lock_with_passphrase(b"secret").unwrap();
+
+        agent_lock.unlock_with_passphrase(b"secret").unwrap();
+
+        assert!(agent_lock.is_locked());
+    }
+
+    #[test]
+    fn clear_passphrase_removes_passphrase_lock() {
+        let agent_lock = AgentLock::new();
+        agent_lock.lock_with_passphrase(b"secret").unwrap();
+
+        agent_lock.clear_passphrase();
+
+        assert!(!agent_lock.is_locked());
+    }
+
+    /// A store whose contents fail to decrypt, like a tampered [`EncryptedMemoryStore`].
+    #[derive(Default)]
+    struct TamperedStore {
+        has_hash: bool,
+    }
+
+    impl SecureMemoryStore for TamperedStore {
+        type KeyType = &'static str;
+
+        fn put(&mut self, _key: Self::KeyType, _value: &[u8]) {
+            self.has_hash = true;
+        }
+
+        fn get(
+            &mut self,
+            _key: &Self::KeyType,
+        ) -> Result<Option<Vec<u8>>, secure_memory::DecryptionError> {
+            if !self.has_hash {
+                return Ok(None);
+            }
+            // Like the encrypted memory store, the contents are dropped on decryption errors
+            self.has_hash = false;
+            Err(secure_memory::DecryptionError::CouldNotDecrypt)
+        }
+
+        fn has(&self, _key: &Self::KeyType) -> bool {
+            self.has_hash
+        }
+
+        fn remove(&mut self, _key: &Self::KeyType) {
+            self.has_hash = false;
+        }
+
+        fn clear(&mut self) {
+            self.has_hash = false;
+        }
+    }
+
+    #[test]
+    fn unlock_with_passphrase_after_decryption_error_stays_locked() {
+        let agent_lock = AgentLock::with_store(TamperedStore::default());
+        agent_lock.lock_with_passphrase(b"secret").unwrap();
+
+        assert!(agent_lock.unlock_with_passphrase(b"secret").is_err());
+        assert!(agent_lock.is_locked());
+        assert!(agent_lock.unlock_with_passphrase(b"secret").is_err());
+        assert!(agent_lock.is_locked());
+        assert!(agent_lock.lock_with_passphrase(b"other").is_err());
+
+        agent_lock.clear_passphrase();
+        assert!(!agent_lock.is_locked());
+    }
+
+    #[test]
+    fn hash_passphrase_is_salted() {
+        assert_ne!(
+            hash_passphrase(&[1; SALT_LEN], b"secret"),
+            hash_passphrase(&[2; SALT_LEN], b"secret")
+        );
+        assert_eq!(
+            hash_passphrase(&[1; SALT_LEN], b"secret"),
+            hash_passphrase(&[1; SALT_LEN], b"secret")
+        );
+    }
+}
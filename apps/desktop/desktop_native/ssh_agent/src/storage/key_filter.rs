@@ -0,0 +1,231 @@
+//! Restricts the keys a listener exposes to its clients.
+
+use super::keydata::QueryableKeyData;
+
+/// Longest key name or comment, in characters, matched against name patterns. Comments are chosen
+/// by clients, so longer ones are never visible through a pattern.
+const MAX_NAME_LEN: usize = 1024;
+
+/// Selects the keys visible to clients of a listener. A key is visible if it matches any of the
+/// filter's criteria. A filter without criteria makes all keys visible.
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct KeyFilter {
+    /// Vault cipher IDs of visible keys.
+    pub cipher_ids: Vec<String>,
+    /// Glob patterns matched against key names, where `*` matches any run of characters and `?`
+    /// matches a single character. Also matched against the comments of keys added by clients and
+    /// of keys held by an upstream agent.
+    pub name_patterns: Vec<String>,
+    /// Names of vault folders whose keys are visible.
+    pub folders: Vec<String>,
+}
+
+impl KeyFilter {
+    /// # Returns
+    ///
+    /// `true` if the filter has no criteria, making all keys visible.
+    #[must_use]
+    pub fn is_unrestricted(&self) -> bool {
+        self.cipher_ids.is_empty() && self.name_patterns.is_empty() && self.folders.is_empty()
+    }
+
+    /// # Returns
+    ///
+    /// `true` if the vault key `key_data` is visible.
+    pub fn permits<K: QueryableKeyData>(&self, key_data: &K) -> bool {
+        self.is_unrestricted()
+            || self.cipher_ids.contains(key_data.cipher_id())
+            || self.permits_name(key_data.name())
+            || key_data
+                .folder()
+                .as_ref()
+                .is_some_and(|folder| self.folders.contains(folder))
+    }
+
+    /// # Returns
+    ///
+    /// `true` if a key added by a client with `comment` is visible.
+    #[must_use]
+    pub fn permits_session_key(&self, comment: &str) -> bool {
+        self.is_unrestricted() || self.permits_name(comment)
+    }
+
+    fn permits_name(&self, name: &str) -> bool {
+        if self.name_patterns.is_empty() || name.chars().nth(MAX_NAME_LEN).is_some() {
+            return false;
+        }
+
+        let name: Vec<char> = name.chars().collect();
+        self.name_patterns.iter().any(|pattern| {
+            let pattern: Vec<char> = pattern.chars().collect();
+            glob_matches(&pattern, &name)
+        })
+    }
+}
+
+/// Matches `value` against a glob `pattern` supporting `*` and `?`.
+///
+/// On a mismatch, only the most recent `*` is retried against one more character, which takes
+/// O(n·m) time without recursion.
+fn glob_matches(pattern: &[char], value: &[char]) -> bool {
+    let (mut p, mut v) = (0, 0);
+    // Position of the last `*` in `pattern`, and of the character in `value` it was matched up to.
+    let mut backtrack: Option<(usize, usize)> = None;
+
+    while v < value.len() {
+        match pattern.get(p) {
+            Some('*') => {
+                backtrack = Some((p, v));
+                p += 1;
+            }
+            Some(&c) if c == '?' || c == value[v] => {
+                p += 1;
+                v += 1;
+            }
+            _ => match backtrack {
+                Some((star, matched)) => {
+                    backtrack = Some((star, matched + 1));
+                    p = star + 1;
+                    v = matched + 1;
+                }
+                None => return false,
+            },
+        }
+    }
+
+    pattern[p..].iter().all(|&c| c == '*')
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::storage::keydata::MockQueryableKeyData;
+
+    fn make_key_data(name: &str, cipher_id: &str, folder: Option<&str>) -> MockQueryableKeyData {
+        let mut key_data = MockQueryableKeyData::new();
+        key_data.expect_name().return_const(name.to_string());
+        key_data
+            .expect_cipher_id()
+            .return_const(cipher_id.to_string());
+        key_data
+            .expect_folder()
+            .return_const(folder.map(str::to_string));
+        key_data
+    }
+
+    #[test]
+    fn unrestricted_filter_permits_all_keys() {
+        let filter = KeyFilter::default();
+
+        assert!(filter.is_unrestricted());
+        assert!(filter.permits(&make_key_data("key", "cipher-1", None)));
+        assert!(filter.permits_session_key("comment"));
+    }
+
+    #[test]
+    fn permits_by_cipher_id() {
+        let filter = KeyFilter {
+            cipher_ids: vec!["cipher-1".to_string()],
+            ..Default::default()
+        };
+
+        assert!(filter.permits(&make_key_data("key", "cipher-1", None)));
+        assert!(!filter.permits(&make_key_data("key", "cipher-2", None)));
+    }
+
+    #[test]
+    fn permits_by_folder() {
+        let filter = KeyFilter {
+            folders: vec!["Work".to_string()],
+            ..Default::default()
+        };
+
+        assert!(filter.permits(&make_key_data("key", "cipher-1", Some("Work"))));
+        assert!(!filter.permits(&make_key_data("key", "cipher-1", Some("Personal"))));
+        assert!(!filter.permits(&make_key_data("key", "cipher-1", None)));
+    }
+
+    #[test]
+    fn permits_by_name_pattern() {
+        let filter = KeyFilter {
+            name_patterns: vec!["work-*".to_string()],
+            ..Default::default()
+        };
+
+        assert!(filter.permits(&make_key_data("work-github", "cipher-1", None)));
+        assert!(!filter.permits(&make_key_data("personal-github", "cipher-1", None)));
+        assert!(filter.permits_session_key("work-temporary"));
+        assert!(!filter.permits_session_key("temporary"));
+    }
+
+    #[test]
+    fn session_keys_not_permitted_by_cipher_id_or_folder() {
+        let filter = KeyFilter {
+            cipher_ids: vec!["cipher-1".to_string()],
+            folders: vec!["Work".to_string()],
+            ..Default::default()
+        };
+
+        assert!(!filter.permits_session_key("cipher-1"));
+        assert!(!filter.permits_session_key("Work"));
+    }
+
+    #[test]
+    fn glob_patterns() {
+        for (pattern, value, expected) in [
+            ("*", "", true),
+            ("*", "anything", true),
+            ("work-*", "work-", true),
+            ("work-*", "work", false),
+            ("*-prod", "db-prod", true),
+            ("*-prod", "db-prod-2", false),
+            ("db-?", "db-1", true),
+            ("db-?", "db-10", false),
+            ("a*b*c", "aXXbYYc", true),
+            ("a*b*c", "aXXcYYb", false),
+            ("exact", "exact", true),
+            ("exact", "Exact", false),
+            ("schlüssel-?", "schlüssel-ä", true),
+            ("**", "", true),
+            ("a**", "a", true),
+            ("?", "", false),
+            ("*a*", "bab", true),
+            ("*a?", "ba", false),
+        ] {
+            let filter = KeyFilter {
+                name_patterns: vec![pattern.to_string()],
+                ..Default::default()
+            };
+            assert_eq!(
+                filter.permits_session_key(value),
+                expected,
+                "{pattern} / {value}"
+            );
+        }
+    }
+
+    #[test]
+    fn long_comment_with_many_stars_is_not_permitted() {
+        let filter = KeyFilter {
+            name_patterns: vec!["*a*a*a*a*a*a*a*a*b".to_string()],
+            ..Default::default()
+        };
+        let comment = "a".repeat(256 * 1024);
+
+        let started = std::time::Instant::now();
+        assert!(!filter.permits_session_key(&comment));
+        assert!(started.elapsed() < std::time::Duration::from_secs(1));
+    }
+
+    #[test]
+    fn comment_at_length_limit_is_matched() {
+        let filter = KeyFilter {
+            name_patterns: vec!["*a*a*a*a*a*a*a*a*b".to_string()],
+            ..Default::default()
+        };
+
+        assert!(filter.permits_session_key(&("a".repeat(MAX_NAME_LEN - 1) + "b")));
+        assert!(!filter.permits_session_key(&"a".repeat(MAX_NAME_LEN)));
+        assert!(!filter.permits_session_key(&("a".repeat(MAX_NAME_LEN) + "b")));
+    }
+}
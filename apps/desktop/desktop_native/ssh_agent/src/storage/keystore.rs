@@ -2,9 +2,12 @@
 //! implementation for storing SSH keys securely. All stored data is ephemeral and
 //! lost when the store is dropped.
 
-use std::sync::{
-    atomic::{AtomicBool, Ordering},
-    Arc, Mutex,
+use std::{
+    collections::BTreeMap,
+    sync::{
+        atomic::{AtomicBool, Ordering},
+        Arc, Mutex,
+    },
 };
 
 use anyhow::Result;
@@ -27,7 +30,8 @@ pub trait KeyStore: Send + Sync {
     /// If a key with the same public key already exists, it will be overwritten.
     fn insert(&self, key_data: Self::KeyData) -> Result<()>;
 
-    /// Retrieves SSH key data by its [`PublicKey`]
+    /// Retrieves SSH key data by its [`PublicKey`], or by the [`PublicKey`] of the OpenSSH
+    /// certificate attached to it.
     ///
     /// # Returns
     ///
@@ -47,7 +51,8 @@ pub trait KeyStore: Send + Sync {
 
     /// # Returns
     ///
-    /// A vector of tuples containing each key's public key and human-readable name.
+    /// A vector of tuples containing each key's public key and human-readable name. Keys that
+    /// carry an OpenSSH certificate are listed a second time with the certificate's public key.
     fn get_all_public_keys_and_names(&self) -> Result<Vec<(PublicKey, String)>>;
 
     /// Atomically replaces all keys in the keystore.
@@ -68,6 +73,8 @@ pub trait KeyStore: Send + Sync {
 /// All data is lost when the instance is dropped.
 pub struct InMemoryEncryptedKeyStore {
     secure_memory: Arc<Mutex<EncryptedMemoryStore<PublicKey>>>,
+    /// Maps certificate public keys to the public key of the key pair they were issued for.
+    certificates: Mutex<BTreeMap<PublicKey, PublicKey>>,
     initialized: AtomicBool,
 }
 
@@ -77,9 +84,24 @@ impl InMemoryEncryptedKeyStore {
     pub fn new() -> Self {
         Self {
             secure_memory: Arc::new(Mutex::new(EncryptedMemoryStore::new())),
+            certificates: Mutex::new(BTreeMap::new()),
             initialized: AtomicBool::new(false),
         }
     }
+
+    /// Resolves a certificate public key to the public key of its key pair. Plain public keys
+    /// are returned unchanged.
+    fn resolve(&self, public_key: &PublicKey) -> PublicKey {
+        if !public_key.is_certificate() {
+            return public_key.clone();
+        }
+        self.certificates
+            .lock()
+            .expect("Mutex is not poisoned")
+            .get(public_key)
+            .cloned()
+            .unwrap_or_else(|| public_key.clone())
+    }
 }
 
 impl Default for InMemoryEncryptedKeyStore {
@@ -93,21 +115,29 @@ impl KeyStore for InMemoryEncryptedKeyStore {
 
     fn insert(&self, key_data: Self::KeyData) -> Result<()> {
         let pub_key = key_data.public_key().clone();
+        let cert_pub_key = key_data.certificate_public_key()?;
         let bytes: Vec<u8> = key_data.try_into()?;
 
         self.secure_memory
             .lock()
             .expect("Mutex is not poisoned")
-            .put(pub_key, bytes.as_slice());
+            .put(pub_key.clone(), bytes.as_slice());
+
+        let mut certificates = self.certificates.lock().expect("Mutex is not poisoned");
+        certificates.retain(|_, key| *key != pub_key);
+        if let Some(cert_pub_key) = cert_pub_key {
+            certificates.insert(cert_pub_key, pub_key);
+        }
 
         Ok(())
     }
 
     fn get(&self, public_key: &PublicKey) -> Result<Option<Self::KeyData>> {
+        let public_key = self.resolve(public_key);
         self.secure_memory
             .lock()
             .expect("Mutex is not poisoned.")
-            .get(public_key)?
+            .get(&public_key)?
             .map(SSHKeyData::try_from)
             .transpose()
     }
@@ -119,10 +149,15 @@ impl KeyStore for InMemoryEncryptedKeyStore {
             .to_vec()?
             .into_iter()
             .map(|bytes| {
-                SSHKeyData::try_from(bytes)
-                    .map(|key_data| (key_data.public_key().clone(), key_data.name().clone()))
+                let key_data = SSHKeyData::try_from(bytes)?;
+                let mut identities = vec![(key_data.public_key().clone(), key_data.name().clone())];
+                if let Some(cert_pub_key) = key_data.certificate_public_key()? {
+                    identities.push((cert_pub_key, key_data.name().clone()));
+                }
+                Ok(identities)
             })
-            .collect::<Result<Vec<_>, _>>()
+            .collect::<Result<Vec<_>>>()
+            .map(|identities| identities.into_iter().flatten().collect())
     }
 
     fn get_private_key(&self, public_key: &PublicKey) -> Result<Option<PrivateKey>> {
@@ -134,16 +169,22 @@ impl KeyStore for InMemoryEncryptedKeyStore {
             .into_iter()
             .map(|k| {
                 let pub_key = k.public_key().clone();
+                let cert_pub_key = k.certificate_public_key()?;
                 let bytes: Vec<u8> = k.try_into()?;
-                Ok((pub_key, bytes))
+                Ok((pub_key, cert_pub_key, bytes))
             })
             .collect::<Result<Vec<_>>>()?;
 
         {
             let mut store = self.secure_memory.lock().expect("Mutex is not poisoned");
+            let mut certificates = self.certificates.lock().expect("Mutex is not poisoned");
 
             store.clear();
-            for (pub_key, bytes) in entries {
+            certificates.clear();
+            for (pub_key, cert_pub_key, bytes) in entries {
+                if let Some(cert_pub_key) = cert_pub_key {
+                    certificates.insert(cert_pub_key, pub_key.clone());
+                }
                 store.put(pub_key, bytes.as_slice());
             }
         }
@@ -156,6 +197,10 @@ impl KeyStore for InMemoryEncryptedKeyStore {
             .lock()
             .expect("Mutex is not poisoned")
             .clear();
+        self.certificates
+            .lock()
+            .expect("Mutex is not poisoned")
+            .clear();
         self.initialized.store(false, Ordering::Relaxed);
     }
 
@@ -172,7 +217,7 @@ mod tests {
     };
 
     use super::*;
-    use crate::crypto::{PrivateKey, QueryableKeyData};
+    use crate::crypto::{test_common::make_user_certificate, PrivateKey, QueryableKeyData};
 
     fn create_test_keydata_ed25519(name: &str, cipher_id: &str) -> SSHKeyData {
         let ed25519_keypair = Ed25519Keypair::random(&mut OsRng);
@@ -212,6 +257,19 @@ mod tests {
         )
     }
 
+    fn create_test_keydata_with_certificate(name: &str, cipher_id: &str) -> SSHKeyData {
+        let mut key_data = create_test_keydata_ed25519(name, cipher_id);
+        let PrivateKey::Ed25519(keypair) = key_data.private_key() else {
+            unreachable!("test key is ed25519");
+        };
+        let certificate = make_user_certificate(
+            &ssh_key::public::KeyData::Ed25519(keypair.public),
+            &["alice"],
+        );
+        key_data.certificate = Some(certificate);
+        key_data
+    }
+
     #[test]
     fn test_new_creates_empty_store() {
         let ks = InMemoryEncryptedKeyStore::new();
@@ -418,4 +476,52 @@ mod tests {
         assert!(public_keys.contains(&pub_key2));
         assert!(public_keys.contains(&pub_key3));
     }
+
+    #[test]
+    fn test_get_all_lists_key_and_certificate() {
+        let ks = InMemoryEncryptedKeyStore::new();
+        let key = create_test_keydata_with_certificate("key", "cipher");
+        let pub_key = key.public_key().clone();
+        let cert_pub_key = key.certificate_public_key().unwrap().unwrap();
+
+        ks.replace(vec![key]).unwrap();
+
+        let result = ks.get_all_public_keys_and_names().unwrap();
+        assert_eq!(
+            result,
+            vec![
+                (pub_key, "key".to_string()),
+                (cert_pub_key, "key".to_string())
+            ]
+        );
+    }
+
+    #[test]
+    fn test_get_by_certificate_returns_key() {
+        let ks = InMemoryEncryptedKeyStore::new();
+        let key = create_test_keydata_with_certificate("key", "cipher");
+        let private_key = key.private_key().clone();
+        let cert_pub_key = key.certificate_public_key().unwrap().unwrap();
+
+        ks.insert(key).unwrap();
+
+        let retrieved = ks.get(&cert_pub_key).unwrap().unwrap();
+        assert_eq!(retrieved.cipher_id(), "cipher");
+        assert_eq!(
+            ks.get_private_key(&cert_pub_key).unwrap(),
+            Some(private_key)
+        );
+    }
+
+    #[test]
+    fn test_get_by_certificate_after_replace_without_certificate_returns_none() {
+        let ks = InMemoryEncryptedKeyStore::new();
+        let key = create_test_keydata_with_certificate("key", "cipher");
+        let cert_pub_key = key.certificate_public_key().unwrap().unwrap();
+        ks.replace(vec![key]).unwrap();
+
+        ks.replace(vec![]).unwrap();
+
+        assert!(ks.get(&cert_pub_key).unwrap().is_none());
+    }
 }
@@ -1,6 +1,9 @@
 //! Contains all data structures and storage implementations for managing SSH keys during the
 //! Agent's runtime.
 
+pub mod agent_lock;
+pub mod key_filter;
 pub mod keydata;
 pub mod keystore;
 mod serialization;
+pub mod session_keystore;
@@ -0,0 +1,658 @@
+//! Provides an encrypted in-memory store for session-scoped SSH keys, added by clients such as
+//! `ssh-add`. These keys are never synced to the vault and are lost when the agent stops, or
+//! earlier if the client constrained their lifetime.
+
+use std::{
+    collections::BTreeMap,
+    sync::Mutex,
+    time::{Duration, Instant},
+};
+
+use anyhow::{anyhow, Result};
+use rkyv::{Archive, Deserialize, Serialize};
+use secure_memory::{EncryptedMemoryStore, SecureMemoryStore};
+use tracing::info;
+
+use crate::{
+    crypto::{PrivateKey, PublicKey},
+    server::SessionBindHop,
+};
+
+/// Constraints a client placed on a session key when adding it (`ssh-add -t`, `-c` and `-h`).
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct KeyConstraints {
+    /// How long the key may be used for, after which it is evicted.
+    pub lifetime: Option<Duration>,
+    /// Whether every use of the key must be confirmed by the user.
+    pub confirm: bool,
+    /// Hosts the key may be used to authenticate to. Empty means unrestricted.
+    pub destinations: Vec<DestinationConstraint>,
+}
+
+/// A single `restrict-destination-v00@openssh.com` hop a key may be used for.
+// <https://github.com/openssh/openssh-portable/blob/master/PROTOCOL.agent>
+#[derive(Debug, Clone, PartialEq, Eq, Archive, Serialize, Deserialize)]
+pub struct DestinationConstraint {
+    /// Hostname the key is used from. Empty for the local host.
+    pub from_hostname: String,
+    /// SHA-256 fingerprints of the host keys accepted for `from_hostname`. Empty for the local
+    /// host.
+    pub from_host_key_fingerprints: Vec<String>,
+    /// Hostname the key may be used to authenticate to.
+    pub to_hostname: String,
+    /// SHA-256 fingerprints of the host keys accepted for `to_hostname`.
+    pub to_host_key_fingerprints: Vec<String>,
+}
+
+impl KeyConstraints {
+    /// Checks the destination constraints against the hosts the connection was bound to, like
+    /// OpenSSH's `identity_permitted`. Every hop must be permitted by a constraint from the
+    /// previous hop, the local host for the first one.
+    ///
+    /// # Arguments
+    ///
+    /// * `hops` - The verified session-bind hops of the connection, nearest to the agent first
+    /// * `bind_failed` - Whether a session-bind was attempted on the connection without success
+    /// * `signing` - Whether the key is used to sign, rather than listed
+    ///
+    /// # Returns
+    ///
+    /// `true` if the key is unrestricted, used locally, or permitted for every hop.
+    #[must_use]
+    pub fn permits_hops(&self, hops: &[SessionBindHop], bind_failed: bool, signing: bool) -> bool {
+        if self.destinations.is_empty() {
+            return true;
+        }
+        // Like OpenSSH, a connection without any session-bind is local use, e.g.
+        // `ssh-keygen -Y sign`. One whose session-bind failed can't be trusted to be.
+        let Some(last_hop) = hops.last() else {
+            return !bind_failed;
+        };
+
+        let mut from = None;
+        for (index, hop) in hops.iter().enumerate() {
+            if index == hops.len() - 1 {
+                // A host the agent is forwarded to must not authenticate with the key itself
+                if signing && hop.is_forwarding {
+                    return false;
+                }
+            } else if !hop.is_forwarding {
+                // Only the last hop may be an authentication rather than a forwarding
+                return false;
+            }
+            if !self.permits_hop(from, Some(&hop.host_fingerprint)) {
+                return false;
+            }
+            from = Some(hop.host_fingerprint.as_str());
+        }
+
+        // Keys that may authenticate to the host the agent is forwarded to, but not be used any
+        // further from it, are hidden from it.
+        signing
+            || !last_hop.is_forwarding
+            || self.permits_hop(Some(&last_hop.host_fingerprint), None)
+    }
+
+    /// # Returns
+    ///
+    /// `true` if a destination permits the hop from the host with the `from` fingerprint, or the
+    /// local host if `None`, to the host with the `to` fingerprint, or any host if `None`.
+    fn permits_hop(&self, from: Option<&str>, to: Option<&str>) -> bool {
+        self.destinations.iter().any(|destination| {
+            let from_permitted = match from {
+                None => {
+                    destination.from_hostname.is_empty()
+                        && destination.from_host_key_fingerprints.is_empty()
+                }
+                Some(from) => destination
+                    .from_host_key_fingerprints
+                    .iter()
+                    .any(|fingerprint| fingerprint == from),
+            };
+            let to_permitted = to.is_none_or(|to| {
+                destination
+                    .to_host_key_fingerprints
+                    .iter()
+                    .any(|fingerprint| fingerprint == to)
+            });
+            from_permitted && to_permitted
+        })
+    }
+}
+
+/// Represents a session-scoped SSH key and its associated metadata.
+#[derive(Clone)]
+pub struct SessionKeyData {
+    /// Private key of the key pair
+    pub(super) private_key: PrivateKey,
+    /// Public key of the key pair
+    pub(super) public_key: PublicKey,
+    /// Comment provided by the client that added the key
+    pub(super) comment: String,
+    /// Constraints provided by the client that added the key
+    pub(super) constraints: KeyConstraints,
+}
+
+impl TryFrom<ssh_key::PrivateKey> for SessionKeyData {
+    type Error = anyhow::Error;
+
+    fn try_from(key: ssh_key::PrivateKey) -> Result<Self, Self::Error> {
+        let blob = key
+            .public_key()
+            .to_bytes()
+            .map_err(|e| anyhow!("Failed to encode public key: {e}"))?;
+
+        let alg = key.algorithm().to_string();
+        let comment = key.comment().to_string();
+        let private_key = PrivateKey::try_from(key)?;
+
+        Ok(Self {
+            private_key,
+            public_key: PublicKey { alg, blob },
+            comment,
+            constraints: KeyConstraints::default(),
+        })
+    }
+}
+
+impl SessionKeyData {
+    /// Applies the constraints the client provided when adding the key.
+    #[must_use]
+    pub fn with_constraints(mut self, constraints: KeyConstraints) -> Self {
+        self.constraints = constraints;
+        self
+    }
+
+    /// # Returns
+    ///
+    /// A reference to the [`PrivateKey`].
+    #[must_use]
+    pub fn private_key(&self) -> &PrivateKey {
+        &self.private_key
+    }
+
+    /// # Returns
+    ///
+    /// A reference to the [`PublicKey`].
+    #[must_use]
+    pub fn public_key(&self) -> &PublicKey {
+        &self.public_key
+    }
+
+    /// # Returns
+    ///
+    /// A reference to the comment provided by the client that added the key.
+    #[must_use]
+    pub fn comment(&self) -> &String {
+        &self.comment
+    }
+
+    /// # Returns
+    ///
+    /// A reference to the [`KeyConstraints`] provided by the client that added the key.
+    #[must_use]
+    pub fn constraints(&self) -> &KeyConstraints {
+        &self.constraints
+    }
+}
+
+/// A thread-safe, in-memory, and encrypted store of session-scoped SSH keys.
+///
+/// Uses [`EncryptedMemoryStore`] to keep key material encrypted at rest in memory, with the
+/// [`PublicKey`] as the lookup key. Keys added with a lifetime are evicted once it elapses.
+pub struct SessionKeyStore {
+    secure_memory: Mutex<EncryptedMemoryStore<PublicKey>>,
+    /// Expiry of keys that were added with a lifetime constraint.
+    expirations: Mutex<BTreeMap<PublicKey, Instant>>,
+}
+
+impl Default for SessionKeyStore {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl SessionKeyStore {
+    /// Creates a new, empty [`SessionKeyStore`].
+    #[must_use]
+    pub fn new() -> Self {
+        Self {
+            secure_memory: Mutex::new(EncryptedMemoryStore::new()),
+            expirations: Mutex::new(BTreeMap::new()),
+        }
+    }
+
+    /// Stores a session key. A key with the same public key is overwritten.
+    pub fn insert(&self, key_data: SessionKeyData) -> Result<()> {
+        let pub_key = key_data.public_key().clone();
+        let lifetime = key_data.constraints().lifetime;
+        let bytes: Vec<u8> = key_data.try_into()?;
+
+        self.secure_memory
+            .lock()
+            .expect("Mutex is not poisoned")
+            .put(pub_key.clone(), bytes.as_slice());
+
+        let mut expirations = self.expirations.lock().expect("Mutex is not poisoned");
+        match lifetime {
+            Some(lifetime) => expirations.insert(pub_key, Instant::now() + lifetime),
+            None => expirations.remove(&pub_key),
+        };
+
+        Ok(())
+    }
+
+    /// Removes all keys whose lifetime has elapsed.
+    pub fn evict_expired(&self) {
+        let now = Instant::now();
+        let expired: Vec<PublicKey> = {
+            let mut expirations = self.expirations.lock().expect("Mutex is not poisoned");
+            let expired = expirations
+                .iter()
+                .filter(|(_, &expires_at)| expires_at <= now)
+                .map(|(public_key, _)| public_key.clone())
+                .collect::<Vec<_>>();
+            for public_key in &expired {
+                expirations.remove(public_key);
+            }
+            expired
+        };
+
+        if expired.is_empty() {
+            return;
+        }
+
+        let mut store = self.secure_memory.lock().expect("Mutex is not poisoned");
+        for public_key in &expired {
+            store.remove(public_key);
+        }
+        info!(count = expired.len(), "Evicted expired session keys.");
+    }
+
+    /// Retrieves a session key by its [`PublicKey`].
+    ///
+    /// # Returns
+    ///
+    /// * `Ok(Some(SessionKeyData))` if the key was found
+    /// * `Ok(None)` if no key with the given public key exists
+    /// * `Err(_)` if an error occurred during retrieval
+    pub fn get(&self, public_key: &PublicKey) -> Result<Option<SessionKeyData>> {
+        self.evict_expired();
+        self.secure_memory
+            .lock()
+            .expect("Mutex is not poisoned")
+            .get(public_key)?
+            .map(SessionKeyData::try_from)
+            .transpose()
+    }
+
+    /// Retrieves the [`PrivateKey`] associated with the given [`PublicKey`].
+    ///
+    /// # Returns
+    ///
+    /// * `Ok(Some(PrivateKey))` if the key was found
+    /// * `Ok(None)` if no key with the given public key exists
+    /// * `Err(_)` if an error occurred during retrieval
+    pub fn get_private_key(&self, public_key: &PublicKey) -> Result<Option<PrivateKey>> {
+        Ok(self.get(public_key)?.map(|kd| kd.private_key().clone()))
+    }
+
+    /// # Returns
+    ///
+    /// `true` if a session key with the given [`PublicKey`] exists.
+    pub fn contains(&self, public_key: &PublicKey) -> bool {
+        self.evict_expired();
+        self.secure_memory
+            .lock()
+            .expect("Mutex is not poisoned")
+            .has(public_key)
+    }
+
+    /// # Returns
+    ///
+    /// All session keys.
+    pub fn get_all(&self) -> Result<Vec<SessionKeyData>> {
+        self.evict_expired();
+        self.secure_memory
+            .lock()
+            .expect("Mutex is not poisoned")
+            .to_vec()?
+            .into_iter()
+            .map(SessionKeyData::try_from)
+            .collect()
+    }
+
+    /// # Returns
+    ///
+    /// A vector of tuples containing each session key's public key and comment.
+    pub fn get_all_public_keys_and_comments(&self) -> Result<Vec<(PublicKey, String)>> {
+        Ok(self
+            .get_all()?
+            .into_iter()
+            .map(|key_data| (key_data.public_key, key_data.comment))
+            .collect())
+    }
+
+    /// Removes the session key with the given [`PublicKey`].
+    ///
+    /// # Returns
+    ///
+    /// `true` if the key existed and was removed, `false` if no such key exists.
+    pub fn remove(&self, public_key: &PublicKey) -> bool {
+        let mut store = self.secure_memory.lock().expect("Mutex is not poisoned");
+        let existed = store.has(public_key);
+        store.remove(public_key);
+        self.expirations
+            .lock()
+            .expect("Mutex is not poisoned")
+            .remove(public_key);
+        existed
+    }
+
+    /// Clears the store of all session keys.
+    pub fn clear(&self) {
+        self.secure_memory
+            .lock()
+            .expect("Mutex is not poisoned")
+            .clear();
+        self.expirations
+            .lock()
+            .expect("Mutex is not poisoned")
+            .clear();
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use ssh_key::{private::Ed25519Keypair, rand_core::OsRng};
+
+    use super::*;
+
+    fn create_session_key(comment: &str) -> SessionKeyData {
+        let keypair = Ed25519Keypair::random(&mut OsRng);
+        let key =
+            ssh_key::PrivateKey::new(ssh_key::private::KeypairData::Ed25519(keypair), comment)
+                .unwrap();
+        SessionKeyData::try_from(key).unwrap()
+    }
+
+    #[test]
+    fn test_try_from_private_key_keeps_comment_and_public_key() {
+        let keypair = Ed25519Keypair::random(&mut OsRng);
+        let key =
+            ssh_key::PrivateKey::new(ssh_key::private::KeypairData::Ed25519(keypair), "user@host")
+                .unwrap();
+        let expected_blob = key.public_key().to_bytes().unwrap();
+
+        let key_data = SessionKeyData::try_from(key).unwrap();
+
+        assert_eq!(key_data.comment(), "user@host");
+        assert_eq!(key_data.public_key().alg(), "ssh-ed25519");
+        assert_eq!(key_data.public_key().blob(), expected_blob.as_slice());
+    }
+
+    #[test]
+    fn test_insert_and_get() {
+        let store = SessionKeyStore::new();
+        let key = create_session_key("added");
+        let pub_key = key.public_key().clone();
+        let private_key = key.private_key().clone();
+
+        store.insert(key).unwrap();
+
+        let retrieved = store.get(&pub_key).unwrap().unwrap();
+        assert_eq!(retrieved.comment(), "added");
+        assert_eq!(store.get_private_key(&pub_key).unwrap(), Some(private_key));
+        assert!(store.contains(&pub_key));
+    }
+
+    #[test]
+    fn test_get_nonexistent_returns_none() {
+        let store = SessionKeyStore::new();
+        let key = create_session_key("missing");
+
+        assert!(store.get(key.public_key()).unwrap().is_none());
+        assert!(!store.contains(key.public_key()));
+    }
+
+    #[test]
+    fn test_get_all_public_keys_and_comments() {
+        let store = SessionKeyStore::new();
+        let key1 = create_session_key("one");
+        let key2 = create_session_key("two");
+        let mut expected = vec![
+            (key1.public_key().clone(), "one".to_string()),
+            (key2.public_key().clone(), "two".to_string()),
+        ];
+        expected.sort();
+
+        store.insert(key1).unwrap();
+        store.insert(key2).unwrap();
+
+        let mut result = store.get_all_public_keys_and_comments().unwrap();
+        result.sort();
+        assert_eq!(result, expected);
+    }
+
+    #[test]
+    fn test_remove_existing_key_returns_true() {
+        let store = SessionKeyStore::new();
+        let key = create_session_key("removed");
+        let pub_key = key.public_key().clone();
+        store.insert(key).unwrap();
+
+        assert!(store.remove(&pub_key));
+        assert!(!store.contains(&pub_key));
+    }
+
+    #[test]
+    fn test_remove_nonexistent_key_returns_false() {
+        let store = SessionKeyStore::new();
+        let key = create_session_key("missing");
+
+        assert!(!store.remove(key.public_key()));
+    }
+
+    #[test]
+    fn test_clear_removes_all_keys() {
+        let store = SessionKeyStore::new();
+        store.insert(create_session_key("one")).unwrap();
+        store.insert(create_session_key("two")).unwrap();
+
+        store.clear();
+
+        assert!(store.get_all_public_keys_and_comments().unwrap().is_empty());
+    }
+
+    fn destination(fingerprint: &str) -> DestinationConstraint {
+        hop_destination(None, fingerprint)
+    }
+
+    /// A destination permitting the hop from the host with the `from` fingerprint, or the local
+    /// host if `None`, to the host with the `to` fingerprint.
+    fn hop_destination(from: Option<&str>, to: &str) -> DestinationConstraint {
+        DestinationConstraint {
+            from_hostname: from
+                .map(|_| "jump.example.com".to_string())
+                .unwrap_or_default(),
+            from_host_key_fingerprints: from.map(str::to_string).into_iter().collect(),
+            to_hostname: "example.com".to_string(),
+            to_host_key_fingerprints: vec![to.to_string()],
+        }
+    }
+
+    fn hop(host_fingerprint: &str, is_forwarding: bool) -> SessionBindHop {
+        SessionBindHop {
+            host_fingerprint: host_fingerprint.to_string(),
+            is_forwarding,
+        }
+    }
+
+    #[test]
+    fn test_insert_with_elapsed_lifetime_evicts_key() {
+        let store = SessionKeyStore::new();
+        let key = create_session_key("expiring").with_constraints(KeyConstraints {
+            lifetime: Some(Duration::ZERO),
+            ..KeyConstraints::default()
+        });
+        let pub_key = key.public_key().clone();
+
+        store.insert(key).unwrap();
+
+        assert!(!store.contains(&pub_key));
+        assert!(store.get(&pub_key).unwrap().is_none());
+        assert!(store.get_all_public_keys_and_comments().unwrap().is_empty());
+    }
+
+    #[test]
+    fn test_insert_with_remaining_lifetime_keeps_key() {
+        let store = SessionKeyStore::new();
+        let key = create_session_key("expiring").with_constraints(KeyConstraints {
+            lifetime: Some(Duration::from_secs(3600)),
+            ..KeyConstraints::default()
+        });
+        let pub_key = key.public_key().clone();
+
+        store.insert(key).unwrap();
+        store.evict_expired();
+
+        assert!(store.contains(&pub_key));
+    }
+
+    #[test]
+    fn test_insert_without_lifetime_overrides_previous_lifetime() {
+        let store = SessionKeyStore::new();
+        let key = create_session_key("expiring");
+        let pub_key = key.public_key().clone();
+        store
+            .insert(key.clone().with_constraints(KeyConstraints {
+                lifetime: Some(Duration::ZERO),
+                ..KeyConstraints::default()
+            }))
+            .unwrap();
+
+        store.insert(key).unwrap();
+
+        assert!(store.contains(&pub_key));
+    }
+
+    #[test]
+    fn test_get_returns_constraints() {
+        let store = SessionKeyStore::new();
+        let constraints = KeyConstraints {
+            lifetime: None,
+            confirm: true,
+            destinations: vec![destination("SHA256:abc")],
+        };
+        let key = create_session_key("constrained").with_constraints(constraints.clone());
+        let pub_key = key.public_key().clone();
+
+        store.insert(key).unwrap();
+
+        assert_eq!(
+            store.get(&pub_key).unwrap().unwrap().constraints(),
+            &constraints
+        );
+    }
+
+    #[test]
+    fn test_permits_hops_unrestricted() {
+        let constraints = KeyConstraints::default();
+
+        assert!(constraints.permits_hops(&[], false, true));
+        assert!(constraints.permits_hops(&[], true, true));
+        assert!(constraints.permits_hops(&[hop("SHA256:abc", true)], false, true));
+    }
+
+    #[test]
+    fn test_permits_hops_restricted_allows_local_use() {
+        let constraints = KeyConstraints {
+            destinations: vec![destination("SHA256:abc")],
+            ..KeyConstraints::default()
+        };
+
+        assert!(constraints.permits_hops(&[], false, true));
+        assert!(constraints.permits_hops(&[], false, false));
+    }
+
+    #[test]
+    fn test_permits_hops_restricted_refuses_failed_bind() {
+        let constraints = KeyConstraints {
+            destinations: vec![destination("SHA256:abc")],
+            ..KeyConstraints::default()
+        };
+
+        assert!(!constraints.permits_hops(&[], true, true));
+        assert!(!constraints.permits_hops(&[], true, false));
+    }
+
+    #[test]
+    fn test_permits_hops_restricted_matches_fingerprint() {
+        let constraints = KeyConstraints {
+            destinations: vec![destination("SHA256:abc"), destination("SHA256:def")],
+            ..KeyConstraints::default()
+        };
+
+        assert!(constraints.permits_hops(&[hop("SHA256:def", false)], false, true));
+        assert!(constraints.permits_hops(&[hop("SHA256:def", false)], false, false));
+        assert!(!constraints.permits_hops(&[hop("SHA256:other", false)], false, true));
+        assert!(!constraints.permits_hops(&[hop("SHA256:other", false)], false, false));
+    }
+
+    #[test]
+    fn test_permits_hops_refuses_signing_on_forwarding_hop() {
+        let constraints = KeyConstraints {
+            destinations: vec![
+                destination("SHA256:jump"),
+                hop_destination(Some("SHA256:jump"), "SHA256:target"),
+            ],
+            ..KeyConstraints::default()
+        };
+
+        assert!(!constraints.permits_hops(&[hop("SHA256:jump", true)], false, true));
+        assert!(constraints.permits_hops(&[hop("SHA256:jump", true)], false, false));
+    }
+
+    #[test]
+    fn test_permits_hops_multi_hop_requires_every_hop() {
+        let constraints = KeyConstraints {
+            destinations: vec![
+                destination("SHA256:jump"),
+                hop_destination(Some("SHA256:jump"), "SHA256:target"),
+            ],
+            ..KeyConstraints::default()
+        };
+
+        let permitted = [hop("SHA256:jump", true), hop("SHA256:target", false)];
+        assert!(constraints.permits_hops(&permitted, false, true));
+
+        // The target is only permitted from the jump host
+        let from_other = [hop("SHA256:other", true), hop("SHA256:target", false)];
+        assert!(!constraints.permits_hops(&from_other, false, true));
+
+        // The jump host is only permitted as the first hop
+        let jump_twice = [
+            hop("SHA256:jump", true),
+            hop("SHA256:jump", true),
+            hop("SHA256:target", false),
+        ];
+        assert!(!constraints.permits_hops(&jump_twice, false, true));
+
+        // Only the last hop may authenticate rather than forward
+        let signed_on_jump = [hop("SHA256:jump", false), hop("SHA256:target", false)];
+        assert!(!constraints.permits_hops(&signed_on_jump, false, true));
+    }
+
+    #[test]
+    fn test_permits_hops_hides_key_not_usable_beyond_forwarded_host() {
+        let constraints = KeyConstraints {
+            destinations: vec![destination("SHA256:abc")],
+            ..KeyConstraints::default()
+        };
+
+        // The key may authenticate to the host, but not to anything the agent is forwarded to
+        // from it.
+        assert!(!constraints.permits_hops(&[hop("SHA256:abc", true)], false, false));
+        assert!(!constraints.permits_hops(&[hop("SHA256:abc", true)], false, true));
+    }
+}
@@ -226,6 +226,54 @@ pub fn framed_sign_request(blob: &[u8], data: &[u8], flags: u32) -> Vec<u8> {
     framed
 }
 
+/// Builds a framed SSH ADD_IDENTITY message (type byte 17), as sent by `ssh-add`.
+pub fn framed_add_identity(private_key: &ssh_key::PrivateKey) -> Vec<u8> {
+    use ssh_encoding::Encode as _;
+
+    let mut body = vec![17u8]; // SSH2_AGENTC_ADD_IDENTITY
+    private_key
+        .key_data()
+        .encode(&mut body)
+        .expect("key encoding should succeed");
+    write_ssh_string(&mut body, private_key.comment().as_bytes());
+
+    let mut framed = (body.len() as u32).to_be_bytes().to_vec();
+    framed.extend(body);
+    framed
+}
+
+/// Builds a framed SSH REMOVE_ALL_IDENTITIES message (type byte 19).
+pub fn framed_remove_all_identities() -> Vec<u8> {
+    let mut frame = 1u32.to_be_bytes().to_vec();
+    frame.push(19u8);
+    frame
+}
+
+/// Builds a framed SSH LOCK (type byte 22) or UNLOCK (type byte 23) message, as sent by
+/// `ssh-add -x` and `ssh-add -X`.
+pub fn framed_lock_request(unlock: bool, passphrase: &[u8]) -> Vec<u8> {
+    let mut body = vec![if unlock { 23u8 } else { 22u8 }];
+    write_ssh_string(&mut body, passphrase);
+
+    let mut framed = (body.len() as u32).to_be_bytes().to_vec();
+    framed.extend(body);
+    framed
+}
+
+/// Generates a random Ed25519 key for adding to the agent as a session key.
+pub fn session_ed25519_key() -> ssh_key::PrivateKey {
+    use ssh_key::{
+        private::{Ed25519Keypair, KeypairData},
+        rand_core::OsRng,
+    };
+
+    ssh_key::PrivateKey::new(
+        KeypairData::Ed25519(Ed25519Keypair::random(&mut OsRng)),
+        "session@example.com",
+    )
+    .expect("key generation not to fail.")
+}
+
 /// Extracts the algorithm name string from a SIGN_RESPONSE body.
 ///
 /// SIGN_RESPONSE layout (after `read_framed_response`):
@@ -5,13 +5,13 @@ use tokio::{io::AsyncWriteExt, net::windows::named_pipe::ClientOptions};
 
 mod common;
 use common::{
-    agent_with_keys, always_approving_agent, always_denying_agent,
-    framed_invalid_session_bind_extension, framed_request_identities,
-    framed_session_bind_extension, framed_sign_request, init_tracing, parse_first_key_name,
-    parse_sign_response_algorithm, read_framed_response, test_ecdsa_p256_key,
-    test_ecdsa_p256_key_blob, test_ecdsa_p384_key, test_ecdsa_p384_key_blob, test_ecdsa_p521_key,
-    test_ecdsa_p521_key_blob, test_ed25519_key, test_ed25519_key_blob, test_rsa_key,
-    test_rsa_key_blob, unsupported_dsa_key_blob, MockApprovalRequester,
+    agent_with_keys, always_approving_agent, always_denying_agent, framed_add_identity,
+    framed_invalid_session_bind_extension, framed_lock_request, framed_remove_all_identities,
+    framed_request_identities, framed_session_bind_extension, framed_sign_request, init_tracing,
+    parse_first_key_name, parse_sign_response_algorithm, read_framed_response, session_ed25519_key,
+    test_ecdsa_p256_key, test_ecdsa_p256_key_blob, test_ecdsa_p384_key, test_ecdsa_p384_key_blob,
+    test_ecdsa_p521_key, test_ecdsa_p521_key_blob, test_ed25519_key, test_ed25519_key_blob,
+    test_rsa_key, test_rsa_key_blob, unsupported_dsa_key_blob, MockApprovalRequester,
 };
 use ssh_agent::{BitwardenSSHAgent, InMemoryEncryptedKeyStore};
 
@@ -517,3 +517,118 @@ async fn test_session_bind_is_forwarding_reaches_approval_layer() {
 
     agent.stop();
 }
+
+#[serial]
+#[tokio::test(flavor = "multi_thread")]
+async fn test_added_session_key_is_listed_signs_and_is_removed() {
+    setup();
+    let mut agent = agent_with_keys(vec![test_ed25519_key()]);
+    agent.start().unwrap();
+    let session_key = session_ed25519_key();
+    let session_key_blob = session_key.public_key().to_bytes().unwrap();
+
+    let mut client = ClientOptions::new().open(PIPE_NAME).unwrap();
+    client
+        .write_all(&framed_add_identity(&session_key))
+        .await
+        .unwrap();
+    let response = read_framed_response(&mut client).await;
+    assert_eq!(response[0], 6, "expected SUCCESS type byte");
+
+    client
+        .write_all(&framed_request_identities())
+        .await
+        .unwrap();
+    let response = read_framed_response(&mut client).await;
+    assert_eq!(response[0], 12, "expected IDENTITIES_ANSWER type byte");
+    assert_eq!(
+        u32::from_be_bytes(response[1..5].try_into().unwrap()),
+        2,
+        "expected the vault key and the session key"
+    );
+
+    client
+        .write_all(&framed_sign_request(&session_key_blob, b"test data", 0))
+        .await
+        .unwrap();
+    let response = read_framed_response(&mut client).await;
+    assert_eq!(response[0], 14, "expected SIGN_RESPONSE type byte");
+
+    client
+        .write_all(&framed_remove_all_identities())
+        .await
+        .unwrap();
+    let response = read_framed_response(&mut client).await;
+    assert_eq!(response[0], 6, "expected SUCCESS type byte");
+
+    client
+        .write_all(&framed_sign_request(&session_key_blob, b"test data", 0))
+        .await
+        .unwrap();
+    let response = read_framed_response(&mut client).await;
+    assert_eq!(response[0], 5, "expected FAILURE type byte");
+
+    agent.stop();
+}
+
+#[serial]
+#[tokio::test(flavor = "multi_thread")]
+async fn test_locked_agent_lists_no_keys_and_refuses_signing() {
+    setup();
+    let mut agent = agent_with_keys(vec![test_ed25519_key()]);
+    agent.start().unwrap();
+
+    let mut client = ClientOptions::new().open(PIPE_NAME).unwrap();
+    client
+        .write_all(&framed_lock_request(false, b"secret"))
+        .await
+        .unwrap();
+    let response = read_framed_response(&mut client).await;
+    assert_eq!(response[0], 6, "expected SUCCESS type byte");
+    assert!(agent.is_locked());
+
+    client
+        .write_all(&framed_request_identities())
+        .await
+        .unwrap();
+    let response = read_framed_response(&mut client).await;
+    assert_eq!(response, vec![12, 0, 0, 0, 0], "expected no identities");
+
+    client
+        .write_all(&framed_sign_request(
+            &test_ed25519_key_blob(),
+            b"test data",
+            0,
+        ))
+        .await
+        .unwrap();
+    let response = read_framed_response(&mut client).await;
+    assert_eq!(response[0], 5, "expected FAILURE type byte");
+
+    client
+        .write_all(&framed_lock_request(true, b"wrong"))
+        .await
+        .unwrap();
+    let response = read_framed_response(&mut client).await;
+    assert_eq!(response[0], 5, "expected FAILURE type byte");
+
+    client
+        .write_all(&framed_lock_request(true, b"secret"))
+        .await
+        .unwrap();
+    let response = read_framed_response(&mut client).await;
+    assert_eq!(response[0], 6, "expected SUCCESS type byte");
+
+    client
+        .write_all(&framed_sign_request(
+            &test_ed25519_key_blob(),
+            b"test data",
+            0,
+        ))
+        .await
+        .unwrap();
+    let response = read_framed_response(&mut client).await;
+    assert_eq!(response[0], 14, "expected SIGN_RESPONSE type byte");
+
+    agent.stop();
+}
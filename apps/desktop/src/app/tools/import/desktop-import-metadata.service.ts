@@ -53,6 +53,8 @@ export class DesktopImportMetadataService
         return Loader.file;
       case "chromium":
         return Loader.chromium;
+      case "firefox":
+        return Loader.firefox;
       default:
         throw new Error(`Unknown loader from native module: ${name}`);
     }
@@ -4,7 +4,7 @@ import { Component } from "@angular/core";
 import { I18nService } from "@bitwarden/common/platform/abstractions/i18n.service";
 import { DialogRef, AsyncActionsModule, ButtonModule, DialogModule } from "@bitwarden/components";
 import type { chromium_importer } from "@bitwarden/desktop-napi";
-import { ImportMetadataServiceAbstraction } from "@bitwarden/importer-core";
+import { DataLoader, ImportMetadataServiceAbstraction, Loader } from "@bitwarden/importer-core";
 import {
   ImportComponent,
   ImporterProviders,
@@ -56,7 +56,16 @@ export class ImportDesktopComponent {
 
   private async _onLoadProfilesFromBrowser(
     browser: string,
+    loader: DataLoader,
   ): Promise<chromium_importer.ProfileInfo[]> {
+    if (loader === Loader.firefox) {
+      try {
+        return await ipc.tools.chromiumImporter.getFirefoxProfiles(browser);
+      } catch {
+        throw new Error(this.i18nService.t("errorOccurred"));
+      }
+    }
+
     // Strings shown by the native NSOpenPanel are resolved here, where the i18n
     // service lives, and threaded through to ObjC via IPC. The native side only
     // injects the resolved filesystem path it computes on its own.
@@ -100,7 +109,29 @@ export class ImportDesktopComponent {
   private async _onImportFromBrowser(
     browser: string,
     profile: string,
-  ): Promise<chromium_importer.LoginImportResult[]> {
+    loader: DataLoader,
+    primaryPassword?: string,
+  ): Promise<chromium_importer.LoginImport> {
+    if (loader === Loader.firefox) {
+      try {
+        const logins = await ipc.tools.chromiumImporter.importFirefoxLogins(
+          browser,
+          profile,
+          primaryPassword,
+        );
+        return { logins, duplicates: [] };
+      } catch (error) {
+        const rawMessage = error instanceof Error ? error.message : "";
+        throw new Error(
+          this.i18nService.t(
+            rawMessage.includes("firefoxImporterPrimaryPasswordRequired")
+              ? "firefoxImporterPrimaryPasswordRequired"
+              : "errorOccurred",
+          ),
+        );
+      }
+    }
+
     try {
       return await ipc.tools.chromiumImporter.importLogins(browser, profile);
     } catch {
@@ -16,8 +16,21 @@ const chromiumImporter = {
   importLogins: (
     browser: string,
     profileId: string,
-  ): Promise<chromium_importer.LoginImportResult[]> =>
+  ): Promise<chromium_importer.LoginImport> =>
     ipcRenderer.invoke("chromium_importer.importLogins", browser, profileId),
+  getFirefoxProfiles: (browser: string): Promise<chromium_importer.ProfileInfo[]> =>
+    ipcRenderer.invoke("chromium_importer.getFirefoxProfiles", browser),
+  importFirefoxLogins: (
+    browser: string,
+    profileId: string,
+    primaryPassword?: string,
+  ): Promise<chromium_importer.LoginImportResult[]> =>
+    ipcRenderer.invoke(
+      "chromium_importer.importFirefoxLogins",
+      browser,
+      profileId,
+      primaryPassword,
+    ),
 };
 
 export default {
@@ -180,7 +180,8 @@ describe("MainSshAgentService", () => {
           publicKey: { keyType: "Ed25519", keypair: "keypair-data" },
           processName: "ssh",
           isForwarding: false,
-          namespace: "ssh",
+          namespace: { type: "Other", namespace: "ssh" },
+          hops: [],
         },
       } as unknown as sshagent_v2.SignRequestData;
 
@@ -194,9 +195,47 @@ describe("MainSshAgentService", () => {
           processName: "ssh",
           isAgentForwarding: false,
           namespace: "ssh",
+          hops: [],
         });
       });
 
+      it("should send the host fingerprints of the hops in order", () => {
+        void capturedSignCb(null, {
+          ...mockSignData,
+          signRequest: {
+            ...mockSignData.signRequest,
+            isForwarding: true,
+            hostFingerprint: "SHA256:prod-db",
+            hops: [
+              { hostFingerprint: "SHA256:bastion", isForwarding: true },
+              { hostFingerprint: "SHA256:prod-db", isForwarding: false },
+            ],
+          },
+        } as unknown as sshagent_v2.SignRequestData);
+
+        expect(mockMessagingService.send).toHaveBeenCalledWith(
+          "sshagent.signrequest",
+          expect.objectContaining({ hops: ["SHA256:bastion", "SHA256:prod-db"] }),
+        );
+      });
+
+      it.each([
+        [{ type: "Git" }, "git"],
+        [{ type: "File" }, "file"],
+        [{ type: "Unsupported" }, "unsupported"],
+        [undefined, undefined],
+      ])("should send namespace %p as %p", (namespace, expected) => {
+        void capturedSignCb(null, {
+          ...mockSignData,
+          signRequest: { ...mockSignData.signRequest, namespace },
+        } as unknown as sshagent_v2.SignRequestData);
+
+        expect(mockMessagingService.send).toHaveBeenCalledWith(
+          "sshagent.signrequest",
+          expect.objectContaining({ namespace: expected }),
+        );
+      });
+
       it("should resolve with true when the renderer accepts", async () => {
         const signPromise = capturedSignCb(null, mockSignData);
 
@@ -166,7 +166,7 @@ export class MainSshAgentService {
       SSH_AGENT_IPC_CHANNELS.REPLACE,
       async (_, keys: { name: string; privateKey: string; cipherId: string }[]) => {
         if (this.agentStateV2 != null && this.agentStateV2.isRunning()) {
-          this.agentStateV2.replace(keys);
+          await this.agentStateV2.replace(keys);
         }
       },
     );
@@ -227,9 +227,29 @@ export class MainSshAgentService {
         requestId: id,
         processName: data.signRequest.processName,
         isAgentForwarding: data.signRequest.isForwarding,
-        namespace: data.signRequest.namespace,
+        namespace: sigNamespaceName(data.signRequest.namespace),
         hostFingerprint: data.signRequest.hostFingerprint,
+        hops: data.signRequest.hops.map((hop) => hop.hostFingerprint),
       });
     });
   }
 }
+
+/**
+ * Converts a SIG namespace to the namespace string sent to the renderer, as passed to
+ * `ssh-keygen -Y sign -n`. Malformed SSHSIG requests are reported as `unsupported`.
+ */
+function sigNamespaceName(namespace?: sshagent_v2.SIGNamespace): string | undefined {
+  switch (namespace?.type) {
+    case "Git":
+      return "git";
+    case "File":
+      return "file";
+    case "Other":
+      return namespace.namespace;
+    case "Unsupported":
+      return "unsupported";
+    default:
+      return undefined;
+  }
+}
@@ -168,6 +168,7 @@ export class SshAgentService implements OnDestroy {
           const namespace = message.namespace as string;
           const isAgentForwarding = message.isAgentForwarding as boolean;
           const hostFingerprint = message.hostFingerprint as string | undefined;
+          const hops = (message.hops as string[] | undefined) ?? [];
           if (application == "") {
             application = this.i18nService.t("unknownApplication");
           }
@@ -194,6 +195,7 @@ export class SshAgentService implements OnDestroy {
               application,
               isAgentForwarding,
               namespace,
+              hops,
             );
 
             if (await firstValueFrom(dialogRef.closed)) {
@@ -32,5 +32,16 @@ export class ChromiumImporterService {
         return await chromium_importer.importLogins(browser, profileId, isMacAppStore());
       },
     );
+
+    ipcMain.handle("chromium_importer.getFirefoxProfiles", async (event, browser: string) => {
+      return await chromium_importer.getFirefoxProfiles(browser);
+    });
+
+    ipcMain.handle(
+      "chromium_importer.importFirefoxLogins",
+      async (event, browser: string, profileId: string, primaryPassword?: string) => {
+        return await chromium_importer.importFirefoxLogins(browser, profileId, primaryPassword);
+      },
+    );
   }
 }
@@ -25,6 +25,7 @@ import {
   TypographyModule,
 } from "@bitwarden/components";
 
+import { DataLoader, Loader } from "../../metadata";
 import { ImportType } from "../../models";
 
 type ProfileOption = { id: string; name: string };
@@ -46,6 +47,17 @@ type LoginImportResult = {
   failure?: LoginImportFailure;
 };
 
+type DuplicateLogin = {
+  url: string;
+  username: string;
+};
+
+type BrowserLoginImport = {
+  logins: LoginImportResult[];
+  /** logins the browser stored more than once, which were merged into one of `logins`. */
+  duplicates?: DuplicateLogin[];
+};
+
 // FIXME(https://bitwarden.atlassian.net/browse/CL-764): Migrate to OnPush
 // eslint-disable-next-line @angular-eslint/prefer-on-push-component-change-detection
 @Component({
@@ -66,6 +78,8 @@ type LoginImportResult = {
 })
 export class ImportChromeComponent implements OnInit, OnDestroy {
   private _parentFormGroup: FormGroup;
+  protected readonly Loader = Loader;
+
   protected formGroup = this.formBuilder.group({
     profile: [
       "",
@@ -76,26 +90,39 @@ export class ImportChromeComponent implements OnInit, OnDestroy {
         updateOn: "submit",
       },
     ],
+    primaryPassword: [""],
   });
 
   profileList: ProfileOption[] = [];
 
   readonly format = input.required<ImportType>();
 
+  /** the loader the profiles and logins are read with. */
+  readonly loader = input<DataLoader>(Loader.chromium);
+
   // FIXME(https://bitwarden.atlassian.net/browse/CL-903): Migrate to Signals
   // eslint-disable-next-line @angular-eslint/prefer-signals
   @Input()
-  onLoadProfilesFromBrowser: (browser: string) => Promise<ProfileOption[]>;
+  onLoadProfilesFromBrowser: (browser: string, loader: DataLoader) => Promise<ProfileOption[]>;
 
   // FIXME(https://bitwarden.atlassian.net/browse/CL-903): Migrate to Signals
   // eslint-disable-next-line @angular-eslint/prefer-signals
   @Input()
-  onImportFromBrowser: (browser: string, profile: string) => Promise<LoginImportResult[]>;
+  onImportFromBrowser: (
+    browser: string,
+    profile: string,
+    loader: DataLoader,
+    primaryPassword?: string,
+  ) => Promise<BrowserLoginImport>;
 
   // FIXME(https://bitwarden.atlassian.net/browse/CL-903): Migrate to Signals
   // eslint-disable-next-line @angular-eslint/prefer-output-emitter-ref
   @Output() csvDataLoaded = new EventEmitter<string>();
 
+  // FIXME(https://bitwarden.atlassian.net/browse/CL-903): Migrate to Signals
+  // eslint-disable-next-line @angular-eslint/prefer-output-emitter-ref
+  @Output() duplicatesMerged = new EventEmitter<number>();
+
   // FIXME(https://bitwarden.atlassian.net/browse/CL-903): Migrate to Signals
   // eslint-disable-next-line @angular-eslint/prefer-output-emitter-ref
   @Output() error = new EventEmitter<string>();
@@ -114,7 +141,10 @@ export class ImportChromeComponent implements OnInit, OnDestroy {
     // Load profiles from browser on initialization
     if (this.onLoadProfilesFromBrowser) {
       try {
-        this.profileList = await this.onLoadProfilesFromBrowser(this.getBrowserName(this.format()));
+        this.profileList = await this.onLoadProfilesFromBrowser(
+          this.getBrowserName(this.format()),
+          this.loader(),
+        );
       } catch (error) {
         this.logService.error("Error loading profiles from browser:", error);
         const translatedMessage = this.translateValidationError(error);
@@ -135,9 +165,11 @@ export class ImportChromeComponent implements OnInit, OnDestroy {
   validateAndEmitData(): AsyncValidatorFn {
     return async () => {
       try {
-        const logins = await this.onImportFromBrowser(
+        const { logins, duplicates } = await this.onImportFromBrowser(
           this.getBrowserName(this.format()),
           this.formGroup.controls.profile.value,
+          this.loader(),
+          this.formGroup.controls.primaryPassword.value || undefined,
         );
 
         // If any of the login items has a failure return a generic error message
@@ -166,6 +198,7 @@ export class ImportChromeComponent implements OnInit, OnDestroy {
           }
         }
         const csvData = papa.unparse(chromeLogins);
+        this.duplicatesMerged.emit(duplicates?.length ?? 0);
         this.csvDataLoaded.emit(csvData);
         return null;
       } catch (error) {
@@ -200,6 +233,8 @@ export class ImportChromeComponent implements OnInit, OnDestroy {
       return "Vivaldi";
     } else if (format === "arccsv") {
       return "Arc";
+    } else if (format === "firefoxcsv") {
+      return "Firefox";
     }
     return "Chrome";
   }
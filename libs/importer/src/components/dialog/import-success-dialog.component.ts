@@ -20,6 +20,8 @@ export interface ImportSuccessDialogData {
   importResult?: ImportResult;
   /** Counts from an SDK-backed importer that submitted directly. Mutually exclusive with importResult. */
   sdkSummary?: SdkImportSummary;
+  /** Logins that were stored more than once by the browser and merged before importing. */
+  mergedDuplicates?: number;
   returnUrl?: string;
   returnLabel?: string;
 }
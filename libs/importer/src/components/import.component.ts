@@ -205,16 +205,23 @@ export class ImportComponent implements OnInit, OnDestroy, AfterViewInit {
   // FIXME(https://bitwarden.atlassian.net/browse/CL-903): Migrate to Signals
   // eslint-disable-next-line @angular-eslint/prefer-signals
   @Input()
-  onLoadProfilesFromBrowser: (browser: string) => Promise<any[]>;
+  onLoadProfilesFromBrowser: (browser: string, loader: DataLoader) => Promise<any[]>;
 
   // FIXME(https://bitwarden.atlassian.net/browse/CL-903): Migrate to Signals
   // eslint-disable-next-line @angular-eslint/prefer-signals
   @Input()
-  onImportFromBrowser: (browser: string, profile: string) => Promise<any[]>;
+  onImportFromBrowser: (
+    browser: string,
+    profile: string,
+    loader: DataLoader,
+    primaryPassword?: string,
+  ) => Promise<{ logins: any[]; duplicates?: any[] }>;
 
   protected readonly returnTo = input<string | undefined>(undefined);
 
   protected organization: Organization | undefined = undefined;
+  /** logins merged by the browser loader because the browser stored them more than once. */
+  protected mergedDuplicates = 0;
   protected destroy$ = new Subject<void>();
 
   protected readonly isCardTypeRestricted$: Observable<boolean> =
@@ -288,13 +295,18 @@ export class ImportComponent implements OnInit, OnDestroy, AfterViewInit {
   // FIXME: use the capabilities list to populate `chromiumLoader` and replace the explicit
   //        strategy check with a check for multiple loaders
   protected readonly browserImporterAvailable$ = this.importer$.pipe(
-    map((importer) => (importer?.loaders ?? []).includes(Loader.chromium)),
+    map((importer) => browserLoader(importer) !== undefined),
   );
 
-  /** emits `true` when the chromium loader is selected. */
+  /** the loader that imports directly from the browser selected by `format`, if any. */
+  protected readonly browserLoader$ = this.importer$.pipe(map(browserLoader));
+
+  /** emits `true` when a browser loader is selected. */
   protected readonly showChromiumOptions$ =
     this.formGroup.controls.chromiumLoader.valueChanges.pipe(
-      map((chromiumLoader) => chromiumLoader === Loader.chromium),
+      map(
+        (chromiumLoader) => chromiumLoader === Loader.chromium || chromiumLoader === Loader.firefox,
+      ),
     );
 
   constructor(
@@ -376,9 +388,7 @@ export class ImportComponent implements OnInit, OnDestroy, AfterViewInit {
 
           // when an importer is defined, the loader needs to be set to a value from
           // its list.
-          const loader = importer.loaders?.includes(Loader.chromium)
-            ? Loader.chromium
-            : importer.loaders?.[0];
+          const loader = browserLoader(importer) ?? importer.loaders?.[0];
           this.formGroup.controls.chromiumLoader.setValue(loader ?? Loader.file);
         },
         error: (err: unknown) => this.logService.error("an error occurred", err),
@@ -639,6 +649,9 @@ export class ImportComponent implements OnInit, OnDestroy, AfterViewInit {
   }
 
   private async doImport(importFunc: () => Promise<ImportResult>): Promise<void> {
+    const mergedDuplicates = this.mergedDuplicates;
+    this.mergedDuplicates = 0;
+
     try {
       const result = await importFunc();
 
@@ -656,6 +669,7 @@ export class ImportComponent implements OnInit, OnDestroy, AfterViewInit {
         this.dialogService.open<unknown, ImportSuccessDialogData>(ImportSuccessDialogComponent, {
           data: {
             importResult: result,
+            mergedDuplicates,
             ...returnDestination,
           },
         });
@@ -980,3 +994,9 @@ export class ImportComponent implements OnInit, OnDestroy, AfterViewInit {
     return destinations[returnTo]?.();
   }
 }
+
+/** The loader that imports directly from a browser, preferring chromium's. */
+function browserLoader(importer: ImporterCapabilities | undefined): DataLoader | undefined {
+  const loaders = importer?.loaders ?? [];
+  return [Loader.chromium, Loader.firefox].find((loader) => loaders.includes(loader));
+}
@@ -7,6 +7,7 @@ import { DataLoader, Loader } from "./data";
 export const LoaderAvailability: Record<DataLoader, ClientType[]> = deepFreeze({
   [Loader.chromium]: [ClientType.Desktop],
   [Loader.download]: [ClientType.Browser],
+  [Loader.firefox]: [ClientType.Desktop],
   [Loader.file]: [ClientType.Browser, ClientType.Desktop, ClientType.Web, ClientType.Cli],
 
   // FIXME: enable IPC importer on `ClientType.Desktop` once it's ready
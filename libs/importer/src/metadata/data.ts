@@ -6,6 +6,9 @@ export const Loader = Object.freeze({
   /** Data loaded directly from the chromium browser's data store */
   chromium: "chromium",
 
+  /** Data loaded directly from the profile of a Firefox-based browser */
+  firefox: "firefox",
+
   /** Data provided through an importer ipc channel (e.g. Bitwarden bridge) */
   ipc: "ipc",
 